@@ -55,7 +55,7 @@ fn main() {
                    , "u_init"       => "glorot_uniform".to_string()
                    , "h_bias_init"      => "zeros".to_string()
                    , "o_bias_init"      => "zeros".to_string()
-                   , "is_permut_const"    => "false".to_string()]);
+                   , "is_permut_const"    => "false".to_string()]).unwrap();
 
 
   model.info();
@@ -64,7 +64,7 @@ fn main() {
   let train_generator = AddingProblemSource::new(batch_size
                                                  , bptt_unroll
                                                  , DType::F32
-                                                 , num_train_samples);
+                                                 , num_train_samples).unwrap();
 
   // Training process
   let loss = model.fit::<AddingProblemSource, f32>(&train_generator
@@ -73,6 +73,6 @@ fn main() {
                                                    , batch_size
                                                    , Some(bptt_unroll)
                                                    , Some(&loss_indices)
-                                                   , true);
+                                                   , true).unwrap();
 }
 
@@ -38,12 +38,12 @@ fn main() {
                                      , "input_size"  => input_dims.to_string()
                                      , "output_size" => hidden_dims.to_string()
                                      , "w_init"      => "glorot_uniform".to_string()
-                                     , "b_init"      => "zeros".to_string()]);
+                                     , "b_init"      => "zeros".to_string()]).unwrap();
   model.add::<f32>("dense", hashmap!["activation"    => "linear".to_string()
                                      , "input_size"  => hidden_dims.to_string()
                                      , "output_size" => output_dims.to_string()
                                      , "w_init"      => "glorot_uniform".to_string()
-                                     , "b_init"      => "zeros".to_string()]);
+                                     , "b_init"      => "zeros".to_string()]).unwrap();
 
   // Get some nice information about our model
   model.info();
@@ -85,7 +85,7 @@ fn main() {
                                          , epochs, batch_size  // self explanatory :)
                                          , None                // BPTT interval [rnn only]
                                          , None                // Custom loss indices
-                                         , true);              // verbose
+                                         , true).unwrap();     // verbose
 
   // plot our loss on a 512x512 grid with the provided title
   plot_vec(loss, "Loss vs. Iterations", 512, 512);
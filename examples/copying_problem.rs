@@ -59,7 +59,7 @@ fn main() {
                    , "u_init"       => "glorot_uniform".to_string()
                    , "h_bias_init"      => "zeros".to_string()
                    , "o_bias_init"      => "zeros".to_string()
-                   , "is_permut_const"    => "false".to_string()]);
+                   , "is_permut_const"    => "false".to_string()]).unwrap();
 
 
   model.info();
@@ -71,7 +71,7 @@ fn main() {
                                                   , seq_size
                                                   , bptt_unroll
                                                   , data_type
-                                                  , num_train_samples);
+                                                  , num_train_samples).unwrap();
 
   // Training process
   let loss = model.fit::<CopyingProblemSource, f32>(&train_generator
@@ -80,6 +80,6 @@ fn main() {
                                                     , batch_size
                                                     , Some(bptt_unroll)
                                                     , None
-                                                    , true);
+                                                    , true).unwrap();
 
 }
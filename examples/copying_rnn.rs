@@ -54,8 +54,7 @@ fn main() {
                    , "inner_activation"  => "tanh".to_string()
                    , "outer_activation" => "ones".to_string()
                    , "w_init"         => "glorot_uniform".to_string()
-                   , "b_init"         => "glorot_uniform".to_string()
-                   , "o_bias_init"      => "zeros".to_string()]);
+                   , "b_init"         => "glorot_uniform".to_string()]).unwrap();
 
 
   model.info();
@@ -67,7 +66,7 @@ fn main() {
                                                   , seq_size
                                                   , bptt_unroll
                                                   , data_type
-                                                  , num_train_samples);
+                                                  , num_train_samples).unwrap();
 
   // Training process
   let loss = model.fit::<CopyingProblemSource, f32>(&train_generator
@@ -76,6 +75,6 @@ fn main() {
                                                     , batch_size
                                                     , Some(bptt_unroll)
                                                     , None
-                                                    , true);
+                                                    , true).unwrap();
 
 }
@@ -41,12 +41,12 @@ fn main() {
                                    , "hidden_size"       => hidden_dims.to_string()
                                    , "output_size"       => hidden_dims.to_string()
                                    , "w_init"            => "glorot_uniform".to_string()
-                                   , "b_init"            => "zeros".to_string()]);
+                                   , "b_init"            => "zeros".to_string()]).unwrap();
   model.add::<f32>("dense", hashmap!["activation"        => "linear".to_string()  // softmax is in loss
                                      , "input_size"      => hidden_dims.to_string()
                                      , "output_size"     => output_dims.to_string()
                                      , "w_init"          => "glorot_uniform".to_string()
-                                     , "b_init"          => "zeros".to_string()]);
+                                     , "b_init"          => "zeros".to_string()]).unwrap();
 
   // Get some nice information about our model
   model.info();
@@ -70,7 +70,7 @@ fn main() {
                                          , epochs, batch_size  // self explanatory :)
                                          , Some(seq_len)       // BPTT interval
                                          , None                // Custom loss indices
-                                         , true);              // verbose
+                                         , true).unwrap();     // verbose
 
   // plot our loss on a 512x512 grid with the provided title
   plot_vec(loss, "Loss vs. Iterations", 512, 512);
@@ -193,7 +193,15 @@ pub fn get_activation(name: &str, x: &Array) -> Result<Array, HALError> {
     "tanh"    => Ok(tanh(x)),
     "ones"    => Ok(ones(x)),
     "linear"  => Ok(ones(x)),
-    _         => Err(HALError::UNKNOWN),
+    _         => Err(HALError::UNKNOWN_ACTIVATION(name.to_string())),
+  }
+}
+
+/// Returns true if `name` is an activation known to `get_activation`
+pub fn is_activation(name: &str) -> bool {
+  match name {
+    "softmax" | "sigmoid" | "relu" | "lrelu" | "tanh" | "ones" | "linear" => true,
+    _                                                                   => false,
   }
 }
 
@@ -207,6 +215,6 @@ pub fn get_derivative(name: &str, x: &Array) -> Result<Array, HALError> {
     "tanh"    => Ok(tanh_derivative(x)),
     "ones"    => Ok(ones_derivative(x)),
     "linear"  => Ok(ones_derivative(x)),
-    _         => Err(HALError::UNKNOWN),
+    _         => Err(HALError::UNKNOWN_ACTIVATION(name.to_string())),
   }
 }
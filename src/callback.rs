@@ -0,0 +1,28 @@
+use model::Sequential;
+
+/// Hooks that are run from within the training loops of a `Sequential` model
+///
+/// Every hook receives mutable access to the model (and thus to its optimizer
+/// through `Sequential::get_optimizer_mut`) so that callbacks can log, modify
+/// the learning rate, checkpoint or stop training via `Sequential::stop_training`.
+/// All hooks default to doing nothing so that only the required ones need to be implemented.
+#[allow(unused_variables)]
+pub trait Callback {
+  /// Called once before the first epoch
+  fn on_train_begin(&mut self, model: &mut Sequential) {}
+
+  /// Called at the start of every epoch
+  fn on_epoch_begin(&mut self, model: &mut Sequential, epoch: u64) {}
+
+  /// Called at the end of every epoch with the losses of that epoch
+  fn on_epoch_end(&mut self, model: &mut Sequential, epoch: u64, losses: &[f32]) {}
+
+  /// Called before every minibatch
+  fn on_batch_begin(&mut self, model: &mut Sequential, epoch: u64, iter: u64) {}
+
+  /// Called after every minibatch (once the optimizer has been run) with the losses of that minibatch
+  fn on_batch_end(&mut self, model: &mut Sequential, epoch: u64, iter: u64, losses: &[f32]) {}
+
+  /// Called once training has completed with all of the losses
+  fn on_train_end(&mut self, model: &mut Sequential, losses: &[f32]) {}
+}
@@ -7,6 +7,7 @@ use std::cell::{RefCell, Cell};
 use initializations::uniform;
 use utils;
 
+use error::HALError;
 use data::{Data, DataSource, DataParams, Normalize, Shuffle};
 
 pub struct AddingProblemSource {
@@ -17,15 +18,18 @@ pub struct AddingProblemSource {
 }
 
 impl AddingProblemSource {
-  pub fn new(batch_size: u64, bptt_unroll: u64, dtype: DType, max_samples: u64) -> AddingProblemSource
+  pub fn new(batch_size: u64, bptt_unroll: u64, dtype: DType, max_samples: u64) -> Result<AddingProblemSource, HALError>
   {
-    assert!(bptt_unroll % 4 == 0, "The number of time steps has to be divisible by 4 for the adding problem");
+    if bptt_unroll % 4 != 0 {
+      return Err(HALError::invalid_param("adding problem", "bptt_unroll"
+                                         , format!("the number of time steps ({}) has to be divisible by 4", bptt_unroll)));
+    }
     let input_dims = Dim4::new(&[batch_size, 1, bptt_unroll, 1]);
     let target_dims = Dim4::new(&[batch_size, 1, bptt_unroll, 1]);
     let train_samples = 0.7 * max_samples as f32;
     let test_samples = 0.2 * max_samples as f32;
     let validation_samples = 0.1 * max_samples as f32;
-    AddingProblemSource {
+    Ok(AddingProblemSource {
       params : DataParams {
         input_dims: input_dims,
         target_dims: target_dims,
@@ -41,7 +45,7 @@ impl AddingProblemSource {
       iter: Cell::new(0),
       offset: Cell::new(0.0f32),
       bptt_unroll: bptt_unroll,
-    }
+    })
   }
 
   fn generate_input(&self, batch_size: u64, bptt_unroll: u64) -> Array {
@@ -113,6 +117,16 @@ impl DataSource for AddingProblemSource {
   fn get_validation_iter(&self, num_batch: u64) -> Option<Data> {
     Some( self.get_train_iter(num_batch))
   }
+
+  fn get_position(&self) -> (u64, u64) {
+    (self.params.current_epoch.get(), self.iter.get())
+  }
+
+  fn set_position(&self, current_epoch: u64, iter: u64) -> Result<(), HALError> {
+    self.params.current_epoch.set(current_epoch);
+    self.iter.set(iter);
+    Ok(())
+  }
 }
 
 
@@ -7,6 +7,7 @@ use std::cell::{RefCell, Cell};
 use initializations;
 use utils;
 
+use error::HALError;
 use data::{Data, DataSource, DataParams, Normalize, Shuffle};
 
 pub struct CopyingProblemSource {
@@ -19,15 +20,19 @@ pub struct CopyingProblemSource {
 
 impl CopyingProblemSource {
   pub fn new(input_size: u64, batch_size: u64, seq_size: u64
-             , bptt_unroll: u64, dtype: DType, max_samples: u64) -> CopyingProblemSource
+             , bptt_unroll: u64, dtype: DType, max_samples: u64) -> Result<CopyingProblemSource, HALError>
   {
-    assert!(bptt_unroll > 2*seq_size, "The number of time steps has to be bigger than the sequence size x2 for the copying problem");
+    if bptt_unroll <= 2*seq_size {
+      return Err(HALError::invalid_param("copying problem", "bptt_unroll"
+                                         , format!("the number of time steps ({}) has to be bigger than twice the sequence size ({})"
+                                                   , bptt_unroll, seq_size)));
+    }
     let input_dims = Dim4::new(&[batch_size, input_size, bptt_unroll, 1]);
     //let output_dims = Dim4::new(&[batch_size, output_size, bptt_unroll, 1]);
     let train_samples = 0.7 * max_samples as f32;
     let test_samples = 0.2 * max_samples as f32;
     let validation_samples = 0.1 * max_samples as f32;
-    CopyingProblemSource {
+    Ok(CopyingProblemSource {
       params : DataParams {
         input_dims: input_dims,
         target_dims: input_dims,
@@ -44,7 +49,7 @@ impl CopyingProblemSource {
       offset: Cell::new(0.0f32),
       bptt_unroll: bptt_unroll,
       seq_size: seq_size,
-    }
+    })
   }
 
   fn generate_input(&self, batch_size: u64, input_size: u64, bptt_unroll: u64, seq_size: u64) -> Array {
@@ -147,6 +152,16 @@ impl DataSource for CopyingProblemSource {
   fn get_validation_iter(&self, num_batch: u64) -> Option<Data> {
     Some( self.get_train_iter(num_batch))
   }
+
+  fn get_position(&self) -> (u64, u64) {
+    (self.params.current_epoch.get(), self.iter.get())
+  }
+
+  fn set_position(&self, current_epoch: u64, iter: u64) -> Result<(), HALError> {
+    self.params.current_epoch.set(current_epoch);
+    self.iter.set(iter);
+    Ok(())
+  }
 }
 
 
@@ -27,6 +27,7 @@ use std::time::Duration;
 use spmc::{Sender, TryRecvError, channel};
 
 use device::{DeviceManager, Device};
+use error::HALError;
 use utils;
 
 #[derive(Clone)]
@@ -275,6 +276,19 @@ impl DataSource for DataLoader {
     let mut q = &mut arc.lock().unwrap();
     q.pop_back()
   }
+
+  // NOTE: minibatches that are already buffered are not rewound
+  fn get_position(&self) -> (u64, u64) {
+    let src = self.source.clone();
+    let stex = src.lock().unwrap();
+    stex.get_position()
+  }
+
+  fn set_position(&self, current_epoch: u64, iter: u64) -> Result<(), HALError> {
+    let src = self.source.clone();
+    let stex = src.lock().unwrap();
+    stex.set_position(current_epoch, iter)
+  }
 }
 
 /// A DataSource needs to provide these basic features
@@ -283,11 +297,23 @@ impl DataSource for DataLoader {
 /// 2) It provides a train iterator that returns a minibatch
 /// 3) It provides a test iterator that returns a minibatch
 /// 4) It (optionally)provides a validation iterator that returns a minibatch
+/// 5) It (optionally) exposes its (epoch, iteration) position so that training can be resumed
 pub trait DataSource {
   fn info(&self) -> DataParams;
   fn get_train_iter(&self, num_batch: u64) -> Data;
   fn get_test_iter(&self, num_batch: u64) -> Data;
   fn get_validation_iter(&self, num_batch: u64) -> Option<Data>;
+
+  /// The (epoch, iteration) position of the source, sources that can't be
+  /// rewound simply report the start
+  fn get_position(&self) -> (u64, u64) {
+    (0, 0)
+  }
+
+  /// Rewinds the source to a position reported by `get_position`
+  fn set_position(&self, _current_epoch: u64, _iter: u64) -> Result<(), HALError> {
+    Err(HALError::invalid_param("data source", "position", "source does not support resuming".to_string()))
+  }
 }
 
 /// Trait that describes a normalization operation
@@ -3,6 +3,7 @@ use af::{Array, Dim4, DType};
 use std::cell::{RefCell, Cell};
 
 use utils;
+use error::HALError;
 use data::{Data, DataSource, DataParams, Normalize, Shuffle};
 
 pub struct SinSource {
@@ -82,4 +83,23 @@ impl DataSource for SinSource
   fn get_validation_iter(&self, num_batch: u64) -> Option<Data> {
     Some(self.get_train_iter(num_batch))
   }
+
+  fn get_position(&self) -> (u64, u64) {
+    (self.params.current_epoch.get(), self.iter.get())
+  }
+
+  fn set_position(&self, current_epoch: u64, iter: u64) -> Result<(), HALError> {
+    self.params.current_epoch.set(current_epoch);
+    self.iter.set(iter);
+
+    // replay the phase offset of every minibatch generated so far
+    let input_dims = self.params.input_dims[1];
+    let num_batch = self.params.input_dims[0];
+    let num_calls = current_epoch * (self.params.num_samples / num_batch) + iter;
+    self.offset.set(0.0f32);
+    for _ in 0..num_calls {
+      self.offset.set(self.offset.get() + 1.0/(input_dims*num_batch - 1) as f32);
+    }
+    Ok(())
+  }
 }
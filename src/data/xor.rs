@@ -6,6 +6,7 @@ use std::sync::{Arc, Mutex};
 use std::cell::{RefCell, Cell};
 
 use utils;
+use error::HALError;
 use data::{Data, DataSource, DataParams, Normalize, Shuffle};
 
 pub struct XORSource {
@@ -93,4 +94,14 @@ impl DataSource for XORSource
   fn get_validation_iter(&self, num_batch: u64) -> Option<Data> {
     Some(self.get_train_iter(num_batch))
   }
+
+  fn get_position(&self) -> (u64, u64) {
+    (self.params.current_epoch.get(), self.iter.get())
+  }
+
+  fn set_position(&self, current_epoch: u64, iter: u64) -> Result<(), HALError> {
+    self.params.current_epoch.set(current_epoch);
+    self.iter.set(iter);
+    Ok(())
+  }
 }
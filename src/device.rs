@@ -4,6 +4,8 @@ use num::Zero;
 //use std::cell::Cell;
 use std::sync::{Arc, Mutex};
 
+use error::HALError;
+
 pub type DeviceManager = Arc<DeviceManagerFactory>;
 
 #[derive(PartialEq, Clone, Copy, Debug)]
@@ -52,14 +54,23 @@ impl DeviceManagerFactory {
     })
   }
 
+  /// Returns an error if the device is not one of the available ones
+  pub fn check_device(&self, device: Device) -> Result<(), HALError>
+  {
+    match self.devices.contains(&device) {
+      true  => Ok(()),
+      false => Err(HALError::DEVICE_UNAVAILABLE(device)),
+    }
+  }
+
   pub fn swap_device(&self, device: Device)
   {
     let mut c = self.current.lock().unwrap();
     if c.backend != device.backend || c.id != device.id
     {
-      assert!(self.devices.contains(&device)
-              , "device backend = {} | available = {:?}"
-              , device.backend, self.devices);
+      if let Err(e) = self.check_device(device) {
+        panic!("{} | available = {:?}", e, self.devices);
+      }
       // println!("Swapping {}/{} to {}/{}", c.backend, c.id
       //          , device.backend, device.id);
       set_device(device);
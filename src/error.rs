@@ -1,41 +1,124 @@
+use af::Dim4;
 use std::error::Error;
 use std::fmt::{Display, Formatter};
 use std::fmt::Error as FmtError;
 
+use device::Device;
+
 #[allow(non_camel_case_types)]
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum HALError {
   ///
   /// The function returned successfully
   ///
-  SUCCESS            =   0,
+  SUCCESS,
   ///
   /// Gradient check error
   ///
-  GRADIENT_ERROR     =   1,
+  GRADIENT_ERROR,
   ///
   /// Unknown loss requested
   ///
-  UNKNOWN_LOSS       =   2,
+  UNKNOWN_LOSS(String),
+  ///
+  /// Unknown metric requested
+  ///
+  UNKNOWN_METRIC(String),
+  ///
+  /// Unknown activation requested
+  ///
+  UNKNOWN_ACTIVATION(String),
+  ///
+  /// Unknown initialization requested
+  ///
+  UNKNOWN_INITIALIZATION(String),
+  ///
+  /// Unknown optimizer requested
+  ///
+  UNKNOWN_OPTIMIZER(String),
+  ///
+  /// Unknown layer type requested
+  ///
+  UNKNOWN_LAYER(String),
+  ///
+  /// A parameter (of a layer, optimizer, data source, ...) is missing, unknown or invalid
+  ///
+  INVALID_PARAM { component: String, field: String, reason: String },
+  ///
+  /// An array did not have the expected dimensions
+  ///
+  SHAPE_MISMATCH { context: String, expected: Dim4, actual: Dim4 },
+  ///
+  /// The requested device is not available on this machine
+  ///
+  DEVICE_UNAVAILABLE(Device),
+  ///
+  /// Reading / writing / fetching a file failed
+  ///
+  IO_ERROR { path: String, reason: String },
   ///
   /// Unknown Error
   ///
-  UNKNOWN            =   999
+  UNKNOWN,
+}
+
+impl HALError {
+  /// Helper to build an INVALID_PARAM error
+  pub fn invalid_param(component: &str, field: &str, reason: String) -> HALError {
+    HALError::INVALID_PARAM {
+      component: component.to_string(),
+      field: field.to_string(),
+      reason: reason,
+    }
+  }
+
+  /// Helper to build an IO_ERROR from anything displayable (io, csv, hyper errors, ...)
+  pub fn io_error<E: Display>(path: &str, err: E) -> HALError {
+    HALError::IO_ERROR {
+      path: path.to_string(),
+      reason: err.to_string(),
+    }
+  }
 }
 
 impl Display for HALError {
   fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
-    write!(f, "{}", self.description())
+    match *self {
+      HALError::UNKNOWN_LOSS(ref name)           => write!(f, "unknown loss '{}'", name),
+      HALError::UNKNOWN_METRIC(ref name)         => write!(f, "unknown metric '{}'", name),
+      HALError::UNKNOWN_ACTIVATION(ref name)     => write!(f, "unknown activation '{}'", name),
+      HALError::UNKNOWN_INITIALIZATION(ref name) => write!(f, "unknown initialization '{}'", name),
+      HALError::UNKNOWN_OPTIMIZER(ref name)      => write!(f, "unknown optimizer '{}'", name),
+      HALError::UNKNOWN_LAYER(ref name)          => write!(f, "unknown layer type '{}'", name),
+      HALError::INVALID_PARAM { ref component, ref field, ref reason }
+        => write!(f, "invalid {} param '{}': {}", component, field, reason),
+      HALError::SHAPE_MISMATCH { ref context, ref expected, ref actual }
+        => write!(f, "shape mismatch for {}: expected {:?}, got {:?}", context, expected, actual),
+      HALError::DEVICE_UNAVAILABLE(ref device)
+        => write!(f, "device {:?} is not available", device),
+      HALError::IO_ERROR { ref path, ref reason }
+        => write!(f, "I/O error on {}: {}", path, reason),
+      _ => write!(f, "{}", self.description()),
+    }
   }
 }
 
 impl Error for HALError {
   fn description(&self) -> &str {
     match *self {
-      HALError::SUCCESS        => "Function returned successfully",
-      HALError::GRADIENT_ERROR => "Gradient check error",
-      HALError::UNKNOWN_LOSS   => "Unknown loss requested",
-      HALError::UNKNOWN        => "Unkown Error",
+      HALError::SUCCESS                   => "Function returned successfully",
+      HALError::GRADIENT_ERROR            => "Gradient check error",
+      HALError::UNKNOWN_LOSS(_)           => "Unknown loss requested",
+      HALError::UNKNOWN_METRIC(_)         => "Unknown metric requested",
+      HALError::UNKNOWN_ACTIVATION(_)     => "Unknown activation requested",
+      HALError::UNKNOWN_INITIALIZATION(_) => "Unknown initialization requested",
+      HALError::UNKNOWN_OPTIMIZER(_)      => "Unknown optimizer requested",
+      HALError::UNKNOWN_LAYER(_)          => "Unknown layer type requested",
+      HALError::INVALID_PARAM{..}         => "Invalid parameter",
+      HALError::SHAPE_MISMATCH{..}        => "Shape mismatch",
+      HALError::DEVICE_UNAVAILABLE(_)     => "Device unavailable",
+      HALError::IO_ERROR{..}              => "I/O error",
+      HALError::UNKNOWN                   => "Unkown Error",
     }
   }
 }
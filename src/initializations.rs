@@ -86,6 +86,15 @@ pub fn lecun_uniform<T: HasAfEnum>(dims: Dim4) -> Array {
 
 //TODO: Orthogonal
 
+/// Returns true if `name` is an initialization known to `get_initialization`
+pub fn is_initialization(name: &str) -> bool {
+  match name {
+    "glorot_uniform" | "glorot_normal" | "lecun_uniform"
+      | "normal" | "uniform" | "zeros" | "ones" => true,
+    _                                           => false,
+  }
+}
+
 /// A helper to retrieve an initialization based on a name and a shape
 pub fn get_initialization<T: HasAfEnum>(name: &str, dims: Dim4) -> Result<Array, HALError>
 {
@@ -97,6 +106,6 @@ pub fn get_initialization<T: HasAfEnum>(name: &str, dims: Dim4) -> Result<Array,
     "uniform"        => Ok(uniform::<T>(dims, -0.05f32, 0.05f32)), //TODO: Parameterize
     "zeros"          => Ok(zeros::<T>(dims)),
     "ones"           => Ok(ones::<T>(dims)),
-    _                => Err(HALError::UNKNOWN),
+    _                => Err(HALError::UNKNOWN_INITIALIZATION(name.to_string())),
   }
 }
@@ -0,0 +1,432 @@
+use af;
+use af::{Array, Dim4, MatProp, HasAfEnum};
+use std::sync::{Arc, Mutex};
+use std::collections::HashMap;
+
+use utils;
+use activations;
+use layer::{Layer, sequence_outputs, last_step_deltas};
+use layer::config::{LayerConfig, Parser, check_size, check_initialization};
+use error::HALError;
+use device::{Device, DeviceManager};
+use params::{Params, ParamManager, ACTGenerator, RNNIndex};
+
+/// Adaptive Computation Time around a recurrent cell (Graves, 2016)
+///
+/// At every timestep the cell ponders for a variable number of steps n = 1..N(t)
+/// on [x_t, first step flag], starting from the previous state. A halting unit
+/// h^n = sigmoid(s^n W_h + b_h) on the cell state decides when to stop: N(t) is
+/// the first step where sum_n h^n reaches 1 - epsilon (or `max_steps`). The
+/// halting probabilities p^n = h^n (n < N) & the remainder R = 1 - sum_{n<N} h^n
+/// (n = N) weigh the states & outputs of every step into the ones of the timestep:
+///
+///   s_t = sum_n p^n s^n, y_t = sum_n p^n y^n
+///
+/// The ponder cost tau * (N(t) + R(t)) is reported by `Layer::auxiliary_loss`
+/// (and thus added to the model loss) while its gradient is added in the
+/// backward pass. The cell starts every sequence from its initial state, thus
+/// this layer only works on whole sequences (see `Layer::forward_sequence`).
+///
+/// Like `layer::Bidirectional` the cell keeps its own `Params`, the params of
+/// the model hold its arrays followed by the halting unit (W_h is the last
+/// weight & b_h the last bias). The ponder count N(t) & remainder R(t) of
+/// every timestep are kept as [batch, 2] in `Params::recurrences`.
+pub struct ACT {
+  pub layer: Box<Layer>,
+  pub layer_params: Arc<Mutex<Params>>,
+  pub input_size: usize,
+  pub max_steps: usize,
+  pub ponder_cost: f32, // tau
+  pub epsilon: f32,     // the halting threshold is 1 - epsilon
+  pub return_sequences: bool,
+}
+
+/// Helper that loads the arrays of the cell from the model params
+fn load_layer(combined: &Params, cell: &mut Params)
+{
+  let (nw, nb) = (cell.weights.len(), cell.biases.len());
+  for k in 0..nw {
+    cell.weights[k] = combined.weights[k].clone();
+    cell.deltas[k] = combined.deltas[k].clone();
+  }
+  for k in 0..nb {
+    cell.biases[k] = combined.biases[k].clone();
+    cell.deltas[nw + k] = combined.deltas[nw + 1 + k].clone();
+  }
+
+  // drops the per-unroll caches of the cell (eg: of a unitary layer)
+  cell.optional = combined.optional.clone();
+  cell.training = combined.training;
+}
+
+/// Helper that stores the arrays of the cell back into the model params
+fn store_layer(combined: &mut Params, cell: &Params)
+{
+  let (nw, nb) = (cell.weights.len(), cell.biases.len());
+  for k in 0..nw {
+    combined.weights[k] = cell.weights[k].clone();
+    combined.deltas[k] = cell.deltas[k].clone();
+  }
+  for k in 0..nb {
+    combined.biases[k] = cell.biases[k].clone();
+    combined.deltas[nw + 1 + k] = cell.deltas[nw + k].clone();
+  }
+}
+
+// a pondering step of a timestep
+struct Ponder {
+  halting: Array,     // h^n
+  probability: Array, // p^n
+  running: Array,     // 1 if the sample continues after this step
+  stopping: Array,    // 1 if the sample halts at this step
+}
+
+impl ACT {
+  /// Returns the ponder counts N(t) [batch, 1] of every unrolled timestep t
+  pub fn ponder_counts(params: &Params) -> Vec<Array> {
+    params.recurrences[..params.current_unroll].iter().map(|r| af::col(r, 0)).collect()
+  }
+
+  // h^n = sigmoid(s^n W_h + b_h)
+  fn halting(&self, state: &Array, weight: &Array, bias: &Array) -> Array {
+    activations::sigmoid(&af::add(&af::matmul(state, weight, MatProp::NONE, MatProp::NONE), bias, true))
+  }
+
+  // splits the samples that did not halt yet into the ones that continue after
+  // this step & the ones that halt at it, `cumulative` is sum_{i<n} p^i
+  fn ponder(&self, halting: Array, cumulative: &Array, halted: &Array, last_step: bool) -> Ponder {
+    let active = af::sub(&1.0f32, halted, false);
+    let stopping = match last_step {
+      true  => active.clone(),
+      false => {
+        let reached = af::ge(&af::add(cumulative, &halting, false), &(1.0 - self.epsilon), false);
+        af::mul(&active, &utils::cast(&reached, halting.get_type()), false)
+      },
+    };
+    let running = af::sub(&active, &stopping, false);
+    let probability = af::add(&af::mul(&running, &halting, false)
+                              , &af::mul(&stopping, &af::sub(&1.0f32, cumulative, false), false), false);
+    Ponder { halting: halting, probability: probability, running: running, stopping: stopping }
+  }
+
+  // the [batch, 1] flag that marks the first pondering step
+  fn flag(&self, batch_size: u64, first: bool, x: &Array) -> Array {
+    utils::constant(Dim4::new(&[batch_size, 1, 1, 1]), x.get_type(), if first { 1.0f32 } else { 0.0f32 })
+  }
+
+  // sum_n p^n a^n
+  fn weigh(&self, values: &[Array], steps: &[Ponder]) -> Array {
+    values.iter().zip(steps.iter()).skip(1)
+      .fold(af::mul(&values[0], &steps[0].probability, true)
+            , |acc, (a, step)| af::add(&acc, &af::mul(a, &step.probability, true), false))
+  }
+}
+
+impl Layer for ACT
+{
+  fn forward(&self, params: Arc<Mutex<Params>>, inputs: &Array, state: Option<&Vec<Array>>) -> (Array, Option<Vec<Array>>)
+  {
+    panic!("an ACT layer ponders over the whole sequence, use forward_sequence");
+  }
+
+  fn backward(&self, params: Arc<Mutex<Params>>, delta: &Array) -> Array
+  {
+    panic!("an ACT layer ponders over the whole sequence, use backward_sequence");
+  }
+
+  fn forward_sequence(&self, params: Arc<Mutex<Params>>, inputs: &[Array]) -> Vec<Array>
+  {
+    // get a handle to the underlying params
+    let mut ltex = params.lock().unwrap();
+
+    // every sequence starts from the initial state of the cell
+    {
+      let mut ctex = self.layer_params.lock().unwrap();
+      load_layer(&ltex, &mut ctex);
+      ctex.current_unroll = 0;
+      ctex.inputs.clear();
+      ctex.outputs.clear();
+      ctex.recurrences.clear();
+      ctex.state_derivatives.clear();
+    }
+    let (weight, bias) = (ltex.weights.last().unwrap().clone(), ltex.biases.last().unwrap().clone());
+
+    let mut outputs = Vec::with_capacity(inputs.len());
+    let mut state_tm1: Option<Array> = None;
+    for x in inputs {
+      let batch_size = x.dims()[0];
+      let zeros = utils::constant(Dim4::new(&[batch_size, 1, 1, 1]), x.get_type(), 0.0f32);
+      let (mut cumulative, mut halted) = (zeros.clone(), zeros.clone());
+      let (mut count, mut remainder) = (zeros.clone(), zeros);
+      let (mut states, mut ys, mut steps) = (Vec::new(), Vec::new(), Vec::new());
+
+      // ponder until every sample of the batch halted
+      for n in 0..self.max_steps {
+        let cell_input = af::join(1, x, &self.flag(batch_size, n == 0, x));
+        let (y, s) = match states.last().or(state_tm1.as_ref()) {
+          Some(s) => self.layer.forward(self.layer_params.clone(), &cell_input, Some(&vec![s.clone()])),
+          None    => self.layer.forward(self.layer_params.clone(), &cell_input, None),
+        };
+        let s = s.expect("ACT needs a cell that returns its state")[0].clone();
+        let step = self.ponder(self.halting(&s, &weight, &bias), &cumulative, &halted, n + 1 == self.max_steps);
+
+        count = af::add(&count, &af::sub(&1.0f32, &halted, false), false);
+        remainder = af::add(&remainder, &af::mul(&step.stopping, &af::sub(&1.0f32, &cumulative, false), false), false);
+        cumulative = af::add(&cumulative, &af::mul(&step.running, &step.halting, false), false);
+        halted = af::add(&halted, &step.stopping, false);
+        states.push(s);
+        ys.push(y);
+        steps.push(step);
+
+        if af::sum_all(&halted).0 as u64 == batch_size {
+          break;
+        }
+      }
+
+      let a_t = self.weigh(&ys, &steps);
+      state_tm1 = Some(self.weigh(&states, &steps));
+
+      // parameter manager keeps the output, inputs & the ponder counts
+      let current_unroll = ltex.current_unroll;
+      let ponder = af::join(1, &count, &remainder);
+      if ltex.inputs.len() > current_unroll { // store in existing
+        ltex.inputs[current_unroll] = x.clone();
+        ltex.outputs[current_unroll] = a_t.clone();
+        ltex.recurrences[current_unroll] = ponder;
+      }else{                                  // add new
+        ltex.inputs.push(x.clone());
+        ltex.outputs.push(a_t.clone());
+        ltex.recurrences.push(ponder);
+      }
+
+      // update location in vector
+      ltex.current_unroll += 1;
+      outputs.push(a_t);
+    }
+
+    store_layer(&mut ltex, &self.layer_params.lock().unwrap());
+    sequence_outputs(outputs, self.return_sequences)
+  }
+
+  fn backward_sequence(&self, params: Arc<Mutex<Params>>, deltas: &[Array]) -> Vec<Array>
+  {
+    // get a handle to the underlying params
+    let mut ltex = params.lock().unwrap();
+    assert!(ltex.current_unroll >= deltas.len()
+            , "Cannot call backward pass without a forward pass over the sequence");
+    let deltas = match self.return_sequences {
+      true  => deltas.to_vec(),
+      false => last_step_deltas(deltas, ltex.current_unroll),
+    };
+    {
+      let mut ctex = self.layer_params.lock().unwrap();
+      load_layer(&ltex, &mut ctex);
+      ctex.state_derivatives.clear();
+    }
+
+    let (nw, nb) = (ltex.weights.len(), ltex.biases.len());
+    let (weight, bias) = (ltex.weights[nw - 1].clone(), ltex.biases[nb - 1].clone());
+    let (mut dweight, mut dbias) = (ltex.deltas[nw - 1].clone(), ltex.deltas[nw + nb - 1].clone());
+    let x = self.input_size as u64;
+
+    // the gradient of the (weighted) state of the next timestep
+    let mut dstate_t: Option<Array> = None;
+    let mut dx = Vec::with_capacity(deltas.len());
+    for t in (0..deltas.len()).rev() {
+      let delta = &deltas[t];
+      let num_steps = af::max_all(&af::col(&ltex.recurrences[t], 0)).0 as usize;
+
+      // the states & outputs of the pondering steps of timestep t
+      let (states, ys) = {
+        let ctex = self.layer_params.lock().unwrap();
+        let end = ctex.current_unroll;
+        (ctex.recurrences[end - num_steps + 1..end + 1].to_vec(), ctex.outputs[end - num_steps..end].to_vec())
+      };
+      let dstate = dstate_t.unwrap_or(utils::constant(states[0].dims(), states[0].get_type(), 0.0f32));
+
+      // recompute the halting of every step
+      let zeros = utils::constant(Dim4::new(&[delta.dims()[0], 1, 1, 1]), delta.get_type(), 0.0f32);
+      let (mut cumulative, mut halted) = (zeros.clone(), zeros);
+      let mut steps: Vec<Ponder> = Vec::with_capacity(num_steps);
+      for (n, s) in states.iter().enumerate() {
+        let step = self.ponder(self.halting(s, &weight, &bias), &cumulative, &halted, n + 1 == self.max_steps);
+        cumulative = af::add(&cumulative, &af::mul(&step.running, &step.halting, false), false);
+        halted = af::add(&halted, &step.stopping, false);
+        steps.push(step);
+      }
+
+      // dp^n = dy_t . y^n + ds_t . s^n, the remainder takes -dp^N from every
+      // earlier step & the ponder cost tau * (N + R) another -tau:
+      // dh^n = running^n * (dp^n - dp^N - tau)
+      let dprobability = ys.iter().zip(states.iter())
+        .map(|(y, s)| af::add(&af::sum(&af::mul(delta, y, false), 1), &af::sum(&af::mul(&dstate, s, false), 1), false))
+        .collect::<Vec<Array>>();
+      let dremainder = steps.iter().zip(dprobability.iter()).skip(1)
+        .fold(af::mul(&steps[0].stopping, &dprobability[0], false)
+              , |acc, (step, dp)| af::add(&acc, &af::mul(&step.stopping, dp, false), false));
+
+      // backpropagate the pondering steps in reverse through the halting unit & the cell
+      let mut dx_t = utils::constant(Dim4::new(&[delta.dims()[0], x, 1, 1]), delta.get_type(), 0.0f32);
+      let mut dcarried = utils::constant(dstate.dims(), dstate.get_type(), 0.0f32);
+      for n in (0..states.len()).rev() {
+        let step = &steps[n];
+        let dhalting = af::mul(&step.running, &af::sub(&af::sub(&dprobability[n], &dremainder, false)
+                                                       , &self.ponder_cost, false), false);
+        let dz = af::mul(&dhalting, &activations::sigmoid_derivative(&step.halting), false);
+        dweight = af::add(&dweight, &af::matmul(&states[n], &dz, MatProp::TRANS, MatProp::NONE), false);
+        dbias = af::add(&dbias, &af::sum(&dz, 0), false);
+
+        let ds = af::add(&af::add(&af::mul(&dstate, &step.probability, true)
+                                  , &af::matmul(&dz, &weight, MatProp::NONE, MatProp::TRANS), false)
+                         , &dcarried, false);
+        {
+          let mut ctex = self.layer_params.lock().unwrap();
+          if ctex.state_derivatives.len() == 0 {
+            ctex.state_derivatives.push(ds);
+          }else{
+            ctex.state_derivatives[0] = ds;
+          }
+        }
+        let dcell = self.layer.backward(self.layer_params.clone(), &af::mul(delta, &step.probability, true));
+        dx_t = af::add(&dx_t, &af::cols(&dcell, 0, x - 1), false);
+        dcarried = self.layer_params.lock().unwrap().state_derivatives[0].clone();
+      }
+
+      // the first step started from the weighted state of the previous timestep
+      dstate_t = Some(dcarried);
+      dx.push(dx_t);
+    }
+    dx.reverse();
+
+    ltex.deltas[nw - 1] = dweight;
+    ltex.deltas[nw + nb - 1] = dbias;
+    store_layer(&mut ltex, &self.layer_params.lock().unwrap());
+    ltex.current_unroll -= deltas.len();
+    dx
+  }
+
+  fn auxiliary_loss(&self, params: Arc<Mutex<Params>>) -> Option<Vec<f32>>
+  {
+    // tau * sum_{batch} (N(t) + R(t))
+    let ltex = params.lock().unwrap();
+    Some(ltex.recurrences[..ltex.current_unroll].iter()
+         .map(|r| self.ponder_cost * af::sum_all(r).0 as f32).collect())
+  }
+
+  fn return_sequences(&self) -> bool {
+    self.return_sequences
+  }
+
+  fn is_recurrent(&self) -> bool {
+    true
+  }
+}
+
+/// Config of an adaptive computation time wrapper around a recurrent cell config
+///
+/// The cell also reads the first pondering step flag (see `layer::ACT`),
+/// thus its input size is the one of the wrapper + 1
+#[derive(Clone, Debug, PartialEq)]
+pub struct ACTConfig<C: LayerConfig> {
+  pub layer: C,              // config of the cell
+  pub max_steps: usize,      // of pondering per timestep
+  pub ponder_cost: f32,      // tau
+  pub epsilon: f32,          // the halting threshold is 1 - epsilon
+  pub halting_w_init: String,
+  pub halting_b_init: String,
+}
+
+impl<C: LayerConfig> ACTConfig<C> {
+  pub fn new(layer: C) -> ACTConfig<C> {
+    ACTConfig {
+      layer: layer,
+      max_steps: 10,
+      ponder_cost: 0.01,
+      epsilon: 0.01,
+      halting_w_init: "glorot_uniform".to_string(),
+      halting_b_init: "ones".to_string(), // ponder little at first
+    }
+  }
+
+  /// Builds the config from the string params of the wrapper (without the ones of the cell)
+  pub fn from_params(layer: C, params: &HashMap<&str, String>) -> Result<ACTConfig<C>, HALError> {
+    let p = Parser::new("act", params);
+    try!(p.check_keys(&["max_steps", "ponder_cost", "epsilon", "halting_w_init", "halting_b_init"]));
+    let mut config = ACTConfig::new(layer);
+    config.max_steps = try!(p.optional("max_steps", config.max_steps));
+    config.ponder_cost = try!(p.optional("ponder_cost", config.ponder_cost));
+    config.epsilon = try!(p.optional("epsilon", config.epsilon));
+    config.halting_w_init = p.string("halting_w_init", config.halting_w_init);
+    config.halting_b_init = p.string("halting_b_init", config.halting_b_init);
+    Ok(config)
+  }
+}
+
+impl<C: LayerConfig> LayerConfig for ACTConfig<C> {
+  fn layer_type(&self) -> &'static str { "act" }
+  fn input_size(&self) -> usize { self.layer.input_size().saturating_sub(1) }
+  fn output_size(&self) -> usize { self.layer.output_size() }
+  fn return_sequences(&self) -> bool { self.layer.return_sequences() }
+  fn needs_whole_sequence(&self) -> bool { true }
+
+  fn validate(&self) -> Result<(), HALError> {
+    let layer = self.layer_type();
+    match self.layer.layer_type() {
+      "rnn" | "unitary" => try!(self.layer.validate()),
+      other => return Err(HALError::invalid_param(layer, "layer"
+                                                  , format!("needs an rnn or unitary cell, got '{}'", other))),
+    };
+    try!(check_size(layer, "input_size", self.input_size()));
+    try!(check_size(layer, "max_steps", self.max_steps));
+    if !(self.ponder_cost >= 0.0) {
+      return Err(HALError::invalid_param(layer, "ponder_cost"
+                                         , format!("needs to be at least 0, got {}", self.ponder_cost)));
+    }
+    if !(self.epsilon > 0.0 && self.epsilon < 1.0) {
+      return Err(HALError::invalid_param(layer, "epsilon"
+                                         , format!("needs to be in (0, 1), got {}", self.epsilon)));
+    }
+    try!(check_initialization(layer, "halting_w_init", &self.halting_w_init));
+    check_initialization(layer, "halting_b_init", &self.halting_b_init)
+  }
+
+  // the params of the cell (with the input size of the wrapper) along with its type
+  fn to_params(&self) -> HashMap<String, String> {
+    let mut params = self.layer.to_params();
+    params.insert("input_size".to_string(), self.input_size().to_string());
+    params.insert("layer".to_string(), self.layer.layer_type().to_string());
+    params.insert("max_steps".to_string(), self.max_steps.to_string());
+    params.insert("ponder_cost".to_string(), self.ponder_cost.to_string());
+    params.insert("epsilon".to_string(), self.epsilon.to_string());
+    params.insert("halting_w_init".to_string(), self.halting_w_init.clone());
+    params.insert("halting_b_init".to_string(), self.halting_b_init.clone());
+    params
+  }
+
+  fn build<T: HasAfEnum>(&self, param_manager: &mut ParamManager
+                         , manager: DeviceManager, device: Device) -> Box<Layer>
+  {
+    // the cell is built into its own params, which are then joined
+    // with the halting unit into the entry of the model (see `layer::ACT`)
+    let mut cells = ParamManager::default();
+    let layer = self.layer.build::<T>(&mut cells, manager.clone(), device);
+    let layer_params = cells.get_params(0);
+    {
+      // the halting unit reads the state: [h] of an rnn & [real, imaginary] of a unitary cell
+      let cell = layer_params.lock().unwrap();
+      let state_size = match self.layer.layer_type() {
+        "unitary" => cell.weights[7].dims()[1],
+        _         => cell.weights[RNNIndex::HiddenToHidden as usize].dims()[0],
+      };
+      param_manager.add_act::<T>(manager, device, &cell, state_size as usize
+                                 , &self.halting_w_init, &self.halting_b_init);
+    }
+    Box::new(ACT{layer: layer
+                 , layer_params: layer_params
+                 , input_size: self.input_size()
+                 , max_steps: self.max_steps
+                 , ponder_cost: self.ponder_cost
+                 , epsilon: self.epsilon
+                 , return_sequences: self.layer.return_sequences()})
+  }
+}
@@ -0,0 +1,349 @@
+use af;
+use af::{Array, MatProp, HasAfEnum};
+use std::sync::{Arc, Mutex};
+use std::collections::HashMap;
+
+use utils;
+use activations;
+use layer::{Layer};
+use layer::config::{LayerConfig, Parser, check_size, check_initialization, to_map};
+use error::HALError;
+use device::{Device, DeviceManager};
+use params::{Params, ParamManager, AttentionGenerator};
+
+/// Soft attention over the timesteps of a sequence
+///
+/// The inputs of every unroll are the per-timestep outputs of the preceding
+/// (recurrent) layer, i.e. its `Params::outputs`. At timestep t the current
+/// input h_t is the query that attends over h_0..h_t:
+///
+///   alpha_t = softmax_j(score(h_t, h_j)), c_t = sum_j alpha_tj * h_j
+///
+/// and the context vector c_t is the output. The score is either the scaled
+/// dot product h_t . h_j / sqrt(size) or additive: v^T tanh(h_t W_q + h_j W_k + b)
+/// with W_q, W_k & v in `Params::weights` and b in `Params::biases[0]`.
+///
+/// Without `causal` the query attends over the whole sequence h_0..h_T-1 instead,
+/// i.e. the layer behaves like an encoder & needs `forward_sequence`.
+///
+/// The attention weights alpha_t [batch, t + 1] ([batch, T] if not causal) of
+/// unroll t are kept in `Params::optional[t]` (see `Attention::attention_weights`)
+/// while the gradient that flows from the other timesteps into h_t is accumulated
+/// in `Params::state_derivatives[t]` until unroll t is backpropagated.
+pub struct Attention {
+  pub size: usize,
+  pub score: AttentionScore,
+  pub causal: bool,
+}
+
+/// Helper that stores a value of the current unroll in a per-unroll vector
+fn store(values: &mut Vec<Array>, current_unroll: usize, value: Array)
+{
+  if values.len() > current_unroll { // store in existing
+    values[current_unroll] = value;
+  }else{                             // add new
+    values.push(value);
+  }
+}
+
+impl Attention {
+  /// Returns the attention weights [batch, t + 1] of every unrolled timestep t
+  ///
+  /// `Model::predict` rolls the unrolls back, after it the weights are empty.
+  /// To inspect the weights of an inference pass run `Model::forward` with
+  /// `set_training(false)` & read them from `Sequential::get_layer_params`
+  pub fn attention_weights(params: &Params) -> Vec<Array> {
+    params.optional[..params.current_unroll].to_vec()
+  }
+
+  // scale of the dot product scores
+  fn scale(&self) -> f32 {
+    1.0 / (self.size as f32).sqrt()
+  }
+
+  // tanh(h_t W_q + h_j W_k + b) of the additive score
+  fn additive_hidden(&self, ltex: &Params, query: &Array, h: &Array) -> Array {
+    let z = af::add(&af::matmul(query, &ltex.weights[0], MatProp::NONE, MatProp::NONE)
+                    , &af::matmul(h, &ltex.weights[1], MatProp::NONE, MatProp::NONE), false);
+    af::tanh(&af::add(&z, &ltex.biases[0], true))
+  }
+
+  // the [batch, 1] scores of the memory h_j for the query
+  fn score(&self, ltex: &Params, query: &Array, h: &Array) -> Array {
+    match self.score {
+      AttentionScore::Dot      => af::mul(&af::sum(&af::mul(query, h, false), 1), &self.scale(), false),
+      AttentionScore::Additive => af::matmul(&self.additive_hidden(ltex, query, h), &ltex.weights[2]
+                                             , MatProp::NONE, MatProp::NONE),
+    }
+  }
+
+  // the attention weights [batch, len] & the context of the query over the memory
+  fn attend(&self, ltex: &Params, query: &Array, memory: &[Array]) -> (Array, Array) {
+    let scores = memory.iter().map(|h| self.score(ltex, query, h)).collect::<Vec<Array>>();
+    let alpha = activations::softmax(&scores[1..].iter().fold(scores[0].clone(), |acc, s| af::join(1, &acc, s)));
+    let context = memory.iter().enumerate()
+      .map(|(j, h)| af::mul(h, &af::col(&alpha, j as u64), true))
+      .fold(utils::constant(query.dims(), query.get_type(), 0.0f32), |acc, c| af::add(&acc, &c, false));
+    (alpha, context)
+  }
+
+  // backprops the delta of the context of query t over the memory first..last,
+  // the gradient of every h_j is accumulated in state_derivatives[j] & the one
+  // of the query is returned
+  fn backward_attend(&self, ltex: &mut Params, t: usize, first: usize, last: usize, delta: &Array) -> Array {
+    let query = ltex.inputs[t].clone();
+    let memory = ltex.inputs[first..last].to_vec();
+    let alpha = ltex.optional[t].clone();
+
+    // through the context: dalpha_j = delta . h_j, then through the softmax:
+    // dscore_j = alpha_j * (dalpha_j - sum_k alpha_k * dalpha_k)
+    let dalphas = memory.iter().map(|h| af::sum(&af::mul(delta, h, false), 1)).collect::<Vec<Array>>();
+    let dalpha = dalphas[1..].iter().fold(dalphas[0].clone(), |acc, d| af::join(1, &acc, d));
+    let dscore = af::mul(&alpha, &af::sub(&dalpha, &af::sum(&af::mul(&alpha, &dalpha, false), 1), true), false);
+
+    let mut dquery = utils::constant(query.dims(), query.get_type(), 0.0f32);
+    for (j, h) in memory.iter().enumerate() {
+      let dscore_j = af::col(&dscore, j as u64);
+      let mut dh = af::mul(delta, &af::col(&alpha, j as u64), true);
+      match self.score {
+        AttentionScore::Dot      => {
+          let dscaled = af::mul(&dscore_j, &self.scale(), false);
+          dquery = af::add(&dquery, &af::mul(h, &dscaled, true), false);
+          dh = af::add(&dh, &af::mul(&query, &dscaled, true), false);
+        },
+        AttentionScore::Additive => {
+          // dv = u^T dscore, dz = (dscore v^T) .* (1 - u^2)
+          let u = self.additive_hidden(ltex, &query, h);
+          let dz = af::mul(&af::matmul(&dscore_j, &ltex.weights[2], MatProp::NONE, MatProp::TRANS)
+                           , &activations::tanh_derivative(&u), false);
+          let dw_q = af::matmul(&query, &dz, MatProp::TRANS, MatProp::NONE);
+          let dw_k = af::matmul(h, &dz, MatProp::TRANS, MatProp::NONE);
+          let dv = af::matmul(&u, &dscore_j, MatProp::TRANS, MatProp::NONE);
+          ltex.deltas[0] = af::add(&ltex.deltas[0], &dw_q, false);
+          ltex.deltas[1] = af::add(&ltex.deltas[1], &dw_k, false);
+          ltex.deltas[2] = af::add(&ltex.deltas[2], &dv, false);
+          ltex.deltas[3] = af::add(&ltex.deltas[3], &af::sum(&dz, 0), false);
+
+          dquery = af::add(&dquery, &af::matmul(&dz, &ltex.weights[0], MatProp::NONE, MatProp::TRANS), false);
+          dh = af::add(&dh, &af::matmul(&dz, &ltex.weights[1], MatProp::NONE, MatProp::TRANS), false);
+        },
+      }
+
+      // the gradient of the other timesteps is returned by their own backward pass
+      ltex.state_derivatives[first + j] = af::add(&ltex.state_derivatives[first + j], &dh, false);
+    }
+
+    dquery
+  }
+}
+
+impl Layer for Attention
+{
+  fn forward(&self, params: Arc<Mutex<Params>>, inputs: &Array, state: Option<&Vec<Array>>) -> (Array, Option<Vec<Array>>)
+  {
+    assert!(self.causal, "a non-causal attention needs the whole sequence, use forward_sequence");
+
+    // get a handle to the underlying params
+    let mut ltex = params.lock().unwrap();
+    let current_unroll = ltex.current_unroll;
+
+    // the memory are the inputs of the previous unrolls & the current one
+    let mut memory = ltex.inputs[..current_unroll].to_vec();
+    memory.push(inputs.clone());
+    let (alpha, a_t) = self.attend(&ltex, inputs, &memory);
+
+    // parameter manager keeps the output, inputs, attention weights & the
+    // (initially empty) gradient of the later timesteps
+    store(&mut ltex.inputs, current_unroll, inputs.clone());
+    store(&mut ltex.outputs, current_unroll, a_t.clone());
+    store(&mut ltex.optional, current_unroll, alpha);
+    store(&mut ltex.state_derivatives, current_unroll
+          , utils::constant(inputs.dims(), inputs.get_type(), 0.0f32));
+
+    // update location in vector
+    ltex.current_unroll += 1;
+
+    (a_t.clone(), None)
+  }
+
+  fn backward(&self, params: Arc<Mutex<Params>>, delta: &Array) -> Array
+  {
+    assert!(self.causal, "a non-causal attention needs the whole sequence, use backward_sequence");
+
+    // get a handle to the underlying params
+    let mut ltex = params.lock().unwrap();
+    let current_unroll = ltex.current_unroll;
+    assert!(current_unroll > 0
+            , "Cannot call backward pass without at least 1 forward pass");
+
+    let t = current_unroll - 1;
+    let dquery = self.backward_attend(&mut ltex, t, 0, current_unroll, delta);
+
+    ltex.current_unroll -= 1;
+    af::add(&ltex.state_derivatives[t], &dquery, false)
+  }
+
+  fn forward_sequence(&self, params: Arc<Mutex<Params>>, inputs: &[Array]) -> Vec<Array>
+  {
+    if self.causal {
+      return inputs.iter().map(|x| self.forward(params.clone(), x, None).0).collect();
+    }
+
+    // get a handle to the underlying params
+    let mut ltex = params.lock().unwrap();
+    let first = ltex.current_unroll;
+
+    // every query attends over the whole sequence
+    let mut outputs = Vec::with_capacity(inputs.len());
+    for (i, x) in inputs.iter().enumerate() {
+      let (alpha, a_t) = self.attend(&ltex, x, inputs);
+
+      // parameter manager keeps the output, inputs, attention weights & the
+      // (initially empty) gradient of the other timesteps
+      store(&mut ltex.inputs, first + i, x.clone());
+      store(&mut ltex.outputs, first + i, a_t.clone());
+      store(&mut ltex.optional, first + i, alpha);
+      store(&mut ltex.state_derivatives, first + i
+            , utils::constant(x.dims(), x.get_type(), 0.0f32));
+      outputs.push(a_t);
+    }
+
+    // update location in vector
+    ltex.current_unroll = first + inputs.len();
+
+    outputs
+  }
+
+  fn backward_sequence(&self, params: Arc<Mutex<Params>>, deltas: &[Array]) -> Vec<Array>
+  {
+    if self.causal {
+      let mut dx: Vec<Array> = deltas.iter().rev().map(|d| self.backward(params.clone(), d)).collect();
+      dx.reverse();
+      return dx;
+    }
+
+    // get a handle to the underlying params
+    let mut ltex = params.lock().unwrap();
+    let current_unroll = ltex.current_unroll;
+    assert!(current_unroll >= deltas.len()
+            , "Cannot call backward pass without a forward pass over the sequence");
+
+    // the gradient of every input only is complete once all the queries are backpropagated
+    let first = current_unroll - deltas.len();
+    let dqueries = deltas.iter().enumerate()
+      .map(|(i, d)| self.backward_attend(&mut ltex, first + i, first, current_unroll, d))
+      .collect::<Vec<Array>>();
+
+    ltex.current_unroll = first;
+    dqueries.iter().enumerate()
+      .map(|(i, dquery)| af::add(&ltex.state_derivatives[first + i], dquery, false))
+      .collect()
+  }
+
+  fn is_recurrent(&self) -> bool {
+    true
+  }
+}
+
+/// How the query of a `layer::Attention` is scored against every timestep
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AttentionScore {
+  Dot,      // scaled dot product
+  Additive, // v^T tanh(q W_q + h W_k + b)
+}
+
+/// Config of a soft attention layer (see `layer::Attention`)
+#[derive(Clone, Debug, PartialEq)]
+pub struct AttentionConfig {
+  pub size: usize,
+  pub score: AttentionScore,
+  pub attention_size: usize, // hidden size of the additive score
+  pub causal: bool,          // only attend over the previous timesteps
+  pub w_init: String,
+  pub b_init: String,
+}
+
+impl AttentionScore {
+  /// Parses the score accepted by `Model::add` (dot or additive)
+  pub fn from_name(name: &str) -> Result<AttentionScore, HALError> {
+    match name {
+      "dot"      => Ok(AttentionScore::Dot),
+      "additive" => Ok(AttentionScore::Additive),
+      _          => Err(HALError::invalid_param("attention", "score"
+                                                , format!("unknown score '{}', expected dot or additive", name))),
+    }
+  }
+
+  fn name(&self) -> &'static str {
+    match *self {
+      AttentionScore::Dot      => "dot",
+      AttentionScore::Additive => "additive",
+    }
+  }
+}
+
+impl AttentionConfig {
+  pub fn new(size: usize, score: AttentionScore) -> AttentionConfig {
+    AttentionConfig {
+      size: size,
+      score: score,
+      attention_size: size,
+      causal: true,
+      w_init: "glorot_uniform".to_string(),
+      b_init: "zeros".to_string(),
+    }
+  }
+
+  /// Builds the config from the string params accepted by `Model::add`
+  pub fn from_params(params: &HashMap<&str, String>) -> Result<AttentionConfig, HALError> {
+    let p = Parser::new("attention", params);
+    try!(p.check_keys(&["size", "score", "attention_size", "causal", "w_init", "b_init"]));
+    let score = try!(AttentionScore::from_name(&p.string("score", "dot".to_string())));
+    let mut config = AttentionConfig::new(try!(p.required("size")), score);
+    config.attention_size = try!(p.optional("attention_size", config.attention_size));
+    config.causal = try!(p.optional("causal", config.causal));
+    config.w_init = p.string("w_init", config.w_init);
+    config.b_init = p.string("b_init", config.b_init);
+    Ok(config)
+  }
+}
+
+impl LayerConfig for AttentionConfig {
+  fn layer_type(&self) -> &'static str { "attention" }
+  fn input_size(&self) -> usize { self.size }
+  fn output_size(&self) -> usize { self.size }
+  fn needs_whole_sequence(&self) -> bool { !self.causal }
+
+  fn validate(&self) -> Result<(), HALError> {
+    let layer = self.layer_type();
+    try!(check_size(layer, "size", self.size));
+    try!(check_size(layer, "attention_size", self.attention_size));
+    try!(check_initialization(layer, "w_init", &self.w_init));
+    check_initialization(layer, "b_init", &self.b_init)
+  }
+
+  fn to_params(&self) -> HashMap<String, String> {
+    to_map(vec![("size", self.size.to_string())
+                , ("score", self.score.name().to_string())
+                , ("attention_size", self.attention_size.to_string())
+                , ("causal", self.causal.to_string())
+                , ("w_init", self.w_init.clone())
+                , ("b_init", self.b_init.clone())])
+  }
+
+  fn build<T: HasAfEnum>(&self, param_manager: &mut ParamManager
+                         , manager: DeviceManager, device: Device) -> Box<Layer>
+  {
+    let attention_size = match self.score {
+      AttentionScore::Dot      => None,
+      AttentionScore::Additive => Some(self.attention_size),
+    };
+    param_manager.add_attention::<T>(manager, device
+                                     , self.size, attention_size
+                                     , &self.w_init
+                                     , &self.b_init);
+    Box::new(Attention{size: self.size
+                       , score: self.score
+                       , causal: self.causal})
+  }
+}
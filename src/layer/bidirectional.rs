@@ -0,0 +1,298 @@
+use af;
+use af::{Array, HasAfEnum};
+use std::sync::{Arc, Mutex};
+use std::collections::HashMap;
+
+use layer::{Layer, last_step_deltas};
+use layer::config::{LayerConfig};
+use error::HALError;
+use device::{Device, DeviceManager};
+use params::{Params, ParamManager, BidirectionalGenerator};
+
+/// Runs a recurrent layer over a sequence in both directions
+///
+/// The forward copy reads the sequence in time order and the backward copy
+/// in reverse time order; their outputs at every timestep are concatenated
+/// (along the features) or summed. Both directions start every sequence from
+/// their initial state, thus this layer only works on whole sequences
+/// (see `Layer::forward_sequence`) and is not usable with RTRL.
+///
+/// Every direction keeps its own `Params`, while the params of the model
+/// (the ones the optimizer updates & that are serialized) hold the arrays of
+/// both directions: [W_fwd, W_bwd], [b_fwd, b_bwd] & the construction-time
+/// optionals [o_fwd, o_bwd]. They are mirrored into the directions on every pass.
+///
+/// Without `return_sequences` the last output merges the last output of both
+/// directions: the forward one at the last timestep & the backward one at the first.
+pub struct Bidirectional {
+  pub forward_layer: Box<Layer>,
+  pub backward_layer: Box<Layer>,
+  pub forward_params: Arc<Mutex<Params>>,
+  pub backward_params: Arc<Mutex<Params>>,
+  pub merge: BidirectionalMerge,
+  pub output_size: usize, // of every direction
+  pub return_sequences: bool,
+}
+
+/// Helper that loads the arrays of a direction (0: forward, 1: backward) from the model params
+fn load_direction(combined: &Params, direction: &mut Params, index: usize)
+{
+  let (nw, nb, no) = (direction.weights.len(), direction.biases.len(), combined.optional.len() / 2);
+  for k in 0..nw {
+    direction.weights[k] = combined.weights[index * nw + k].clone();
+    direction.deltas[k] = combined.deltas[index * nw + k].clone();
+  }
+  for k in 0..nb {
+    direction.biases[k] = combined.biases[index * nb + k].clone();
+    direction.deltas[nw + k] = combined.deltas[2 * nw + index * nb + k].clone();
+  }
+  for k in 0..no {
+    direction.optional[k] = combined.optional[index * no + k].clone();
+  }
+  direction.training = combined.training;
+}
+
+/// Helper that stores the arrays of a direction back into the model params
+fn store_direction(combined: &mut Params, direction: &Params, index: usize)
+{
+  let (nw, nb, no) = (direction.weights.len(), direction.biases.len(), combined.optional.len() / 2);
+  for k in 0..nw {
+    combined.weights[index * nw + k] = direction.weights[k].clone();
+    combined.deltas[index * nw + k] = direction.deltas[k].clone();
+  }
+  for k in 0..nb {
+    combined.biases[index * nb + k] = direction.biases[k].clone();
+    combined.deltas[2 * nw + index * nb + k] = direction.deltas[nw + k].clone();
+  }
+  for k in 0..no {
+    combined.optional[index * no + k] = direction.optional[k].clone();
+  }
+}
+
+impl Bidirectional {
+  // the params of both directions along with their index in the model params
+  fn directions(&self) -> Vec<(&Box<Layer>, &Arc<Mutex<Params>>, usize)> {
+    vec![(&self.forward_layer, &self.forward_params, 0)
+         , (&self.backward_layer, &self.backward_params, 1)]
+  }
+}
+
+impl Layer for Bidirectional
+{
+  fn forward(&self, params: Arc<Mutex<Params>>, inputs: &Array, state: Option<&Vec<Array>>) -> (Array, Option<Vec<Array>>)
+  {
+    panic!("a bidirectional layer needs the whole sequence, use forward_sequence");
+  }
+
+  fn backward(&self, params: Arc<Mutex<Params>>, delta: &Array) -> Array
+  {
+    panic!("a bidirectional layer needs the whole sequence, use backward_sequence");
+  }
+
+  fn forward_sequence(&self, params: Arc<Mutex<Params>>, inputs: &[Array]) -> Vec<Array>
+  {
+    // get a handle to the underlying params
+    let mut ltex = params.lock().unwrap();
+
+    // every sequence starts from the initial state in both directions
+    for (_, direction, index) in self.directions() {
+      let mut dtex = direction.lock().unwrap();
+      load_direction(&ltex, &mut dtex, index);
+      dtex.current_unroll = 0;
+      dtex.inputs.clear();
+      dtex.outputs.clear();
+      dtex.recurrences.clear();
+      dtex.state_derivatives.clear();
+    }
+
+    let forward: Vec<Array> = inputs.iter()
+      .map(|x| self.forward_layer.forward(self.forward_params.clone(), x, None).0).collect();
+    let mut backward: Vec<Array> = inputs.iter().rev()
+      .map(|x| self.backward_layer.forward(self.backward_params.clone(), x, None).0).collect();
+    backward.reverse();
+
+    for (_, direction, index) in self.directions() {
+      store_direction(&mut ltex, &direction.lock().unwrap(), index);
+    }
+
+    let merge = |f: &Array, b: &Array| match self.merge {
+      BidirectionalMerge::Concat => af::join(1, f, b),
+      BidirectionalMerge::Sum    => af::add(f, b, false),
+    };
+
+    // parameter manager keeps the merged outputs & inputs
+    let mut outputs = Vec::with_capacity(inputs.len());
+    for (x, (f, b)) in inputs.iter().zip(forward.iter().zip(backward.iter())) {
+      let a_t = merge(f, b);
+
+      let current_unroll = ltex.current_unroll;
+      if ltex.inputs.len() > current_unroll { // store in existing
+        ltex.inputs[current_unroll] = x.clone();
+        ltex.outputs[current_unroll] = a_t.clone();
+      }else{                                  // add new
+        ltex.inputs.push(x.clone());
+        ltex.outputs.push(a_t.clone());
+      }
+
+      // update location in vector
+      ltex.current_unroll += 1;
+      outputs.push(a_t);
+    }
+
+    match self.return_sequences {
+      true  => outputs,
+      false => vec![merge(forward.last().unwrap(), &backward[0])],
+    }
+  }
+
+  fn backward_sequence(&self, params: Arc<Mutex<Params>>, deltas: &[Array]) -> Vec<Array>
+  {
+    // get a handle to the underlying params
+    let mut ltex = params.lock().unwrap();
+    assert!(ltex.current_unroll >= deltas.len()
+            , "Cannot call backward pass without a forward pass over the sequence");
+
+    // the delta of every direction
+    let split = |delta: &Array, index: usize| match self.merge {
+      BidirectionalMerge::Concat => {
+        let first = (index * self.output_size) as u64;
+        af::cols(delta, first, first + self.output_size as u64 - 1)
+      },
+      BidirectionalMerge::Sum    => delta.clone(),
+    };
+
+    for (_, direction, index) in self.directions() {
+      let mut dtex = direction.lock().unwrap();
+      load_direction(&ltex, &mut dtex, index);
+      dtex.state_derivatives.clear();
+    }
+
+    // the deltas of every direction (in time order), without sequences only the
+    // last output of the forward direction & the first of the backward one get one
+    let (forward_deltas, backward_deltas): (Vec<Array>, Vec<Array>) = match self.return_sequences {
+      true  => (deltas.iter().map(|d| split(d, 0)).collect(), deltas.iter().map(|d| split(d, 1)).collect()),
+      false => {
+        let mut backward_deltas = last_step_deltas(&[split(&deltas[0], 1)], ltex.current_unroll);
+        backward_deltas.reverse();
+        (last_step_deltas(&[split(&deltas[0], 0)], ltex.current_unroll), backward_deltas)
+      },
+    };
+
+    // the forward direction is unrolled from the last timestep and
+    // the backward direction (which read the sequence reversed) from the first
+    let mut dx: Vec<Array> = forward_deltas.iter().rev()
+      .map(|d| self.forward_layer.backward(self.forward_params.clone(), d)).collect();
+    dx.reverse();
+    for (d, dx_t) in backward_deltas.iter().zip(dx.iter_mut()) {
+      let dx_backward = self.backward_layer.backward(self.backward_params.clone(), d);
+      *dx_t = af::add(dx_t, &dx_backward, false);
+    }
+
+    for (_, direction, index) in self.directions() {
+      store_direction(&mut ltex, &direction.lock().unwrap(), index);
+    }
+
+    ltex.current_unroll -= dx.len();
+    dx
+  }
+
+  fn return_sequences(&self) -> bool {
+    self.return_sequences
+  }
+
+  fn is_recurrent(&self) -> bool {
+    true
+  }
+}
+
+/// How the outputs of both directions of a `layer::Bidirectional` are merged
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BidirectionalMerge {
+  Concat, // [forward, backward] along the features
+  Sum,
+}
+
+/// Config of a bidirectional wrapper around a recurrent layer config
+#[derive(Clone, Debug, PartialEq)]
+pub struct BidirectionalConfig<C: LayerConfig> {
+  pub layer: C, // config of every direction
+  pub merge: BidirectionalMerge,
+}
+
+impl BidirectionalMerge {
+  /// Parses the merge mode accepted by `Model::add` (concat or sum)
+  pub fn from_name(name: &str) -> Result<BidirectionalMerge, HALError> {
+    match name {
+      "concat" => Ok(BidirectionalMerge::Concat),
+      "sum"    => Ok(BidirectionalMerge::Sum),
+      _        => Err(HALError::invalid_param("bidirectional", "merge"
+                                              , format!("unknown merge '{}', expected concat or sum", name))),
+    }
+  }
+
+  fn name(&self) -> &'static str {
+    match *self {
+      BidirectionalMerge::Concat => "concat",
+      BidirectionalMerge::Sum    => "sum",
+    }
+  }
+}
+
+impl<C: LayerConfig> BidirectionalConfig<C> {
+  pub fn new(layer: C, merge: BidirectionalMerge) -> BidirectionalConfig<C> {
+    BidirectionalConfig {
+      layer: layer,
+      merge: merge,
+    }
+  }
+}
+
+impl<C: LayerConfig> LayerConfig for BidirectionalConfig<C> {
+  fn layer_type(&self) -> &'static str { "bidirectional" }
+  fn input_size(&self) -> usize { self.layer.input_size() }
+  fn output_size(&self) -> usize {
+    match self.merge {
+      BidirectionalMerge::Concat => 2 * self.layer.output_size(),
+      BidirectionalMerge::Sum    => self.layer.output_size(),
+    }
+  }
+  fn return_sequences(&self) -> bool { self.layer.return_sequences() }
+  fn needs_whole_sequence(&self) -> bool { true }
+
+  fn validate(&self) -> Result<(), HALError> {
+    match self.layer.layer_type() {
+      "rnn" | "lstm" | "gru" | "unitary" => self.layer.validate(),
+      other => Err(HALError::invalid_param(self.layer_type(), "layer"
+                                           , format!("needs a recurrent layer, got '{}'", other))),
+    }
+  }
+
+  // the params of the wrapped layer along with its type & the merge mode
+  fn to_params(&self) -> HashMap<String, String> {
+    let mut params = self.layer.to_params();
+    params.insert("layer".to_string(), self.layer.layer_type().to_string());
+    params.insert("merge".to_string(), self.merge.name().to_string());
+    params
+  }
+
+  fn build<T: HasAfEnum>(&self, param_manager: &mut ParamManager
+                         , manager: DeviceManager, device: Device) -> Box<Layer>
+  {
+    // every direction is built into its own params, which are then
+    // joined into the single entry of the model (see `layer::Bidirectional`)
+    let mut directions = ParamManager::default();
+    let forward_layer = self.layer.build::<T>(&mut directions, manager.clone(), device);
+    let backward_layer = self.layer.build::<T>(&mut directions, manager.clone(), device);
+    let (forward_params, backward_params) = (directions.get_params(0), directions.get_params(1));
+    param_manager.add_bidirectional::<T>(manager, device
+                                         , &forward_params.lock().unwrap()
+                                         , &backward_params.lock().unwrap());
+    Box::new(Bidirectional{forward_layer: forward_layer
+                           , backward_layer: backward_layer
+                           , forward_params: forward_params
+                           , backward_params: backward_params
+                           , merge: self.merge
+                           , output_size: self.layer.output_size()
+                           , return_sequences: self.layer.return_sequences()})
+  }
+}
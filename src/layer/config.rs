@@ -0,0 +1,139 @@
+use af::HasAfEnum;
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use activations;
+use initializations;
+use error::HALError;
+use device::{Device, DeviceManager};
+use layer::Layer;
+use params::ParamManager;
+
+/// Typed construction parameters of a layer
+///
+/// A config is validated before any parameter is allocated, so that a bad
+/// value is reported as a `HALError::INVALID_PARAM` naming the offending field
+/// instead of panicking deep inside the model. `to_params` returns the
+/// string form that is used when serializing a model.
+pub trait LayerConfig {
+  /// The name of the layer type (as used by `Model::add`)
+  fn layer_type(&self) -> &'static str;
+
+  /// The number of input features of the layer
+  fn input_size(&self) -> usize;
+
+  /// The number of output features of the layer
+  fn output_size(&self) -> usize;
+
+  /// Whether the layer returns the outputs of every timestep or only the last one
+  fn return_sequences(&self) -> bool { true }
+
+  /// Whether the layer needs the whole sequence at once
+  ///
+  /// Such layers only implement `Layer::forward_sequence` & `Layer::backward_sequence`,
+  /// thus they can't be run one timestep at a time (eg: in a `Graph` or by `fit_rtrl`)
+  fn needs_whole_sequence(&self) -> bool { false }
+
+  /// Checks every field of the config
+  fn validate(&self) -> Result<(), HALError>;
+
+  /// Returns the string form of the config
+  fn to_params(&self) -> HashMap<String, String>;
+
+  /// Allocates the layer parameters in the param manager and returns the layer
+  ///
+  /// The config is expected to have been validated
+  fn build<T: HasAfEnum>(&self, param_manager: &mut ParamManager
+                         , manager: DeviceManager, device: Device) -> Box<Layer>;
+}
+
+pub fn check_size(layer: &str, field: &str, size: usize) -> Result<(), HALError> {
+  match size {
+    0 => Err(HALError::invalid_param(layer, field, "needs to be greater than 0".to_string())),
+    _ => Ok(()),
+  }
+}
+
+// a kernel needs to fit within the padded input at least once
+pub fn check_kernel(layer: &str, field: &str, kernel: usize, size: usize, padding: usize) -> Result<(), HALError> {
+  match kernel <= size + 2 * padding {
+    true  => Ok(()),
+    false => Err(HALError::invalid_param(layer, field
+                                         , format!("kernel of {} does not fit an input of {} padded by {}"
+                                                   , kernel, size, padding))),
+  }
+}
+
+pub fn check_epsilon(layer: &str, epsilon: f32) -> Result<(), HALError> {
+  match epsilon > 0.0 {
+    true  => Ok(()),
+    false => Err(HALError::invalid_param(layer, "epsilon"
+                                         , format!("needs to be greater than 0, got {}", epsilon))),
+  }
+}
+
+pub fn check_activation(layer: &str, field: &str, name: &str) -> Result<(), HALError> {
+  match activations::is_activation(name) {
+    true  => Ok(()),
+    false => Err(HALError::invalid_param(layer, field, format!("unknown activation '{}'", name))),
+  }
+}
+
+pub fn check_initialization(layer: &str, field: &str, name: &str) -> Result<(), HALError> {
+  match initializations::is_initialization(name) {
+    true  => Ok(()),
+    false => Err(HALError::invalid_param(layer, field, format!("unknown initialization '{}'", name))),
+  }
+}
+
+pub fn to_map(params: Vec<(&str, String)>) -> HashMap<String, String> {
+  params.into_iter().map(|(k, v)| (k.to_string(), v)).collect()
+}
+
+// reads typed values out of the string params of `Model::add`
+pub struct Parser<'a> {
+  layer: &'static str,
+  params: &'a HashMap<&'a str, String>,
+}
+
+impl<'a> Parser<'a> {
+  pub fn new(layer: &'static str, params: &'a HashMap<&'a str, String>) -> Parser<'a> {
+    Parser { layer: layer, params: params }
+  }
+
+  // rejects keys that the layer does not know about (eg: typos)
+  pub fn check_keys(&self, known: &[&str]) -> Result<(), HALError> {
+    let mut keys: Vec<&&str> = self.params.keys().collect();
+    keys.sort();
+    for key in keys {
+      if !known.contains(key) {
+        return Err(HALError::invalid_param(self.layer, key
+                                 , format!("unknown param, expected one of {:?}", known)));
+      }
+    }
+    Ok(())
+  }
+
+  pub fn required<T: FromStr>(&self, field: &str) -> Result<T, HALError> {
+    match self.params.get(field) {
+      Some(v) => self.parse(field, v),
+      None    => Err(HALError::invalid_param(self.layer, field, "missing required param".to_string())),
+    }
+  }
+
+  pub fn optional<T: FromStr>(&self, field: &str, default: T) -> Result<T, HALError> {
+    match self.params.get(field) {
+      Some(v) => self.parse(field, v),
+      None    => Ok(default),
+    }
+  }
+
+  pub fn string(&self, field: &str, default: String) -> String {
+    self.params.get(field).cloned().unwrap_or(default)
+  }
+
+  fn parse<T: FromStr>(&self, field: &str, value: &str) -> Result<T, HALError> {
+    value.parse::<T>().map_err(|_| HALError::invalid_param(self.layer, field
+                                                 , format!("could not parse '{}'", value)))
+  }
+}
@@ -0,0 +1,326 @@
+use af;
+use af::{Array, Dim4, Seq, ConvMode, ConvDomain, HasAfEnum};
+use std::sync::{Arc, Mutex};
+use std::collections::HashMap;
+
+use utils;
+use activations;
+use layer::{Layer};
+use layer::config::{LayerConfig, Parser, check_size, check_kernel, check_activation, check_initialization, to_map};
+use error::HALError;
+use device::{Device, DeviceManager};
+use params::{Params, ParamManager, ConvGenerator};
+
+/// A 2D convolution over [height, width, channels] images
+///
+/// Every timestep is expected to be of [batch, height * width * channels]
+/// where the features are the column major flattening of [height, width, channels]
+/// (ie: the ArrayFire image layout). The output is flattened in the same way
+/// as [output_height, output_width, filters] so that convolutions can be stacked
+/// and followed by dense layers.
+///
+/// The forward pass & the input gradient are ArrayFire convolutions (`af::convolve2`)
+/// of the zero padded images, the weight gradient correlates the images with the
+/// deltas. The weights are kept as a [kernel_height * kernel_width * channels, filters]
+/// matrix, every column being the column major flattening of a [height, width, channels]
+/// kernel, and the biases as [filters, 1].
+pub struct Conv2D {
+  pub input_dims: (usize, usize, usize), // [height, width, channels]
+  pub filters: usize,
+  pub kernel: (usize, usize),            // [height, width]
+  pub stride: (usize, usize),            // [height, width]
+  pub padding: (usize, usize),           // zeros added on each side [height, width]
+}
+
+impl Conv2D {
+  // [height, width] of the zero padded input
+  fn padded_dims(&self) -> (u64, u64) {
+    ((self.input_dims.0 + 2 * self.padding.0) as u64, (self.input_dims.1 + 2 * self.padding.1) as u64)
+  }
+
+  // [height, width] of the unstrided outputs, ie: of every position the kernel fits
+  fn valid_dims(&self) -> (u64, u64) {
+    let (padded_height, padded_width) = self.padded_dims();
+    (padded_height - self.kernel.0 as u64 + 1, padded_width - self.kernel.1 as u64 + 1)
+  }
+
+  // [height, width] of the (strided) outputs
+  fn output_dims(&self) -> (u64, u64) {
+    let (valid_height, valid_width) = self.valid_dims();
+    ((valid_height - 1) / self.stride.0 as u64 + 1, (valid_width - 1) / self.stride.1 as u64 + 1)
+  }
+
+  // the strided positions out of the unstrided outputs
+  fn strides(&self) -> [Seq; 4] {
+    let (output_height, output_width) = self.output_dims();
+    let (stride_height, stride_width) = (self.stride.0 as f64, self.stride.1 as f64);
+    [Seq::new(0.0, (output_height - 1) as f64 * stride_height, stride_height)
+     , Seq::new(0.0, (output_width - 1) as f64 * stride_width, stride_width)
+     , Seq::default(), Seq::default()]
+  }
+
+  // the input out of the padded input
+  fn unpadded(&self) -> [Seq; 4] {
+    let (height, width, _) = self.input_dims;
+    let (top, left) = self.padding;
+    [Seq::new(top as f64, (top + height - 1) as f64, 1.0)
+     , Seq::new(left as f64, (left + width - 1) as f64, 1.0)
+     , Seq::default(), Seq::default()]
+  }
+
+  // [batch, height * width * channels] -> zero padded [height, width, channels * batch] images
+  fn images(&self, inputs: &Array) -> Array {
+    let (height, width, channels) = self.input_dims;
+    let (padded_height, padded_width) = self.padded_dims();
+    let batch_size = inputs.dims()[0];
+    let images = af::moddims(&af::transpose(inputs, false)
+                             , Dim4::new(&[height as u64, width as u64, channels as u64 * batch_size, 1]));
+    let padded = utils::constant(Dim4::new(&[padded_height, padded_width, channels as u64 * batch_size, 1])
+                                 , inputs.get_type(), 0.0f32);
+    af::assign_seq(&padded, &self.unpadded(), &images)
+  }
+
+  // the kernels of a filter tiled over the batch: [kernel_height, kernel_width, channels * batch]
+  fn kernels(&self, weights: &Array, filter: usize, batch_size: u64) -> Array {
+    let kernel_dims = Dim4::new(&[self.kernel.0 as u64, self.kernel.1 as u64, self.input_dims.2 as u64, 1]);
+    af::tile(&af::moddims(&af::col(weights, filter as u64), kernel_dims), Dim4::new(&[1, 1, batch_size, 1]))
+  }
+
+  // the [height, width, 1, batch] maps of a filter tiled over the channels: [height, width, channels * batch]
+  fn tile_channels(&self, maps: &Array) -> Array {
+    let dims = maps.dims();
+    let tiled = af::tile(maps, Dim4::new(&[1, 1, self.input_dims.2 as u64, 1]));
+    af::moddims(&tiled, Dim4::new(&[dims[0], dims[1], self.input_dims.2 as u64 * dims[3], 1]))
+  }
+
+  // sums the [height, width, channels * batch] maps over the channels: [height, width, 1, batch]
+  fn sum_channels(&self, maps: &Array) -> Array {
+    let dims = maps.dims();
+    let channels = self.input_dims.2 as u64;
+    af::sum(&af::moddims(maps, Dim4::new(&[dims[0], dims[1], channels, dims[2] / channels])), 2)
+  }
+}
+
+impl Layer for Conv2D
+{
+  fn forward(&self, params: Arc<Mutex<Params>>, inputs: &Array, state: Option<&Vec<Array>>) -> (Array, Option<Vec<Array>>)
+  {
+    // get a handle to the underlying params
+    let mut ltex = params.lock().unwrap();
+    let batch_size = inputs.dims()[0];
+    let images = self.images(inputs);
+    let (kernel_height, kernel_width) = (self.kernel.0 as u64, self.kernel.1 as u64);
+    let (padded_height, padded_width) = self.padded_dims();
+    let valid = [Seq::new((kernel_height - 1) as f64, (padded_height - 1) as f64, 1.0)
+                 , Seq::new((kernel_width - 1) as f64, (padded_width - 1) as f64, 1.0)
+                 , Seq::default(), Seq::default()];
+
+    // every filter correlates the images with its kernels (ie: convolves them with the flipped
+    // kernels), the valid part of the full convolution is strided & summed over the channels
+    let maps = (0..self.filters).map(|f| {
+      let flipped = af::flip(&af::flip(&self.kernels(&ltex.weights[0], f, batch_size), 0), 1);
+      let full = af::convolve2(&images, &flipped, ConvMode::EXPAND, ConvDomain::AUTO);
+      let z = af::index(&af::index(&full, &valid), &self.strides());
+      af::add(&self.sum_channels(&z), &af::row(&ltex.biases[0], f as u64), true)
+    }).collect::<Vec<Array>>();
+
+    // [output_height, output_width, filters, batch] -> [batch, output_size]
+    let z = maps[1..].iter().fold(maps[0].clone(), |acc, m| af::join(2, &acc, m));
+    let output_size = z.elements() as u64 / batch_size;
+    let z_t = af::transpose(&af::moddims(&z, Dim4::new(&[output_size, batch_size, 1, 1])), false);
+    let a_t = activations::get_activation(&ltex.activations[0], &z_t).unwrap();
+
+    // parameter manager keeps the output & inputs
+    let current_unroll = ltex.current_unroll;
+    if ltex.inputs.len() > current_unroll { // store in existing
+      ltex.inputs[current_unroll] = inputs.clone();
+      ltex.outputs[current_unroll] = a_t.clone();
+    }else{                                  // add new
+      ltex.inputs.push(inputs.clone());
+      ltex.outputs.push(a_t.clone());
+    }
+
+    // update location in vector
+    ltex.current_unroll += 1;
+
+    (a_t.clone(), None)
+  }
+
+  fn backward(&self, params: Arc<Mutex<Params>>, delta: &Array) -> Array
+  {
+    // get a handle to the underlying params
+    let mut ltex = params.lock().unwrap();
+    let current_unroll = ltex.current_unroll;
+    assert!(current_unroll > 0
+            , "Cannot call backward pass without at least 1 forward pass");
+
+    // dz as [output_height, output_width, filters, batch], spread back onto
+    // the unstrided positions (the skipped ones get no gradient)
+    let batch_size = delta.dims()[0];
+    let dactivation = activations::get_derivative(&ltex.activations[0], &ltex.outputs[current_unroll - 1]).unwrap();
+    let (output_height, output_width) = self.output_dims();
+    let (valid_height, valid_width) = self.valid_dims();
+    let dz = af::moddims(&af::transpose(&af::mul(delta, &dactivation, false), false)
+                         , Dim4::new(&[output_height, output_width, self.filters as u64, batch_size]));
+    let dz = af::assign_seq(&utils::constant(Dim4::new(&[valid_height, valid_width, self.filters as u64, batch_size])
+                                             , delta.get_type(), 0.0f32)
+                            , &self.strides(), &dz);
+
+    let images = self.images(&ltex.inputs[current_unroll - 1]);
+    let (kernel_height, kernel_width) = (self.kernel.0 as u64, self.kernel.1 as u64);
+    let (padded_height, padded_width) = self.padded_dims();
+    let kernel_taps = [Seq::new((valid_height - 1) as f64, (padded_height - 1) as f64, 1.0)
+                       , Seq::new((valid_width - 1) as f64, (padded_width - 1) as f64, 1.0)
+                       , Seq::default(), Seq::default()];
+    let kernel_size = kernel_height * kernel_width * self.input_dims.2 as u64;
+    let mut dws = Vec::with_capacity(self.filters);
+    let mut dimages = utils::constant(images.dims(), delta.get_type(), 0.0f32);
+    for f in 0..self.filters {
+      let dmaps = self.tile_channels(&af::slices(&dz, f as u64, f as u64));
+
+      // dW: the correlation of the images with the deltas (summed over the batch)
+      let flipped = af::flip(&af::flip(&dmaps, 0), 1);
+      let correlation = af::index(&af::convolve2(&images, &flipped, ConvMode::EXPAND, ConvDomain::AUTO)
+                                  , &kernel_taps);
+      let dw = af::sum(&af::moddims(&correlation, Dim4::new(&[kernel_size, batch_size, 1, 1])), 1);
+      dws.push(dw);
+
+      // dx: the full convolution of the deltas with the kernels
+      let full = af::convolve2(&dmaps, &self.kernels(&ltex.weights[0], f, batch_size)
+                               , ConvMode::EXPAND, ConvDomain::AUTO);
+      dimages = af::add(&dimages, &full, false);
+    }
+
+    // db: the deltas summed over the positions & the batch
+    let db = af::moddims(&af::sum(&af::sum(&af::sum(&dz, 0), 1), 3), Dim4::new(&[self.filters as u64, 1, 1, 1]));
+    let dw = dws[1..].iter().fold(dws[0].clone(), |acc, d| af::join(1, &acc, d));
+    ltex.deltas[0] = af::add(&ltex.deltas[0], &dw, false);
+    ltex.deltas[1] = af::add(&ltex.deltas[1], &db, false);
+
+    ltex.current_unroll -= 1;
+
+    // crop the padding: [height, width, channels * batch] -> [batch, input_size]
+    let input_size = (self.input_dims.0 * self.input_dims.1 * self.input_dims.2) as u64;
+    af::transpose(&af::moddims(&af::index(&dimages, &self.unpadded()), Dim4::new(&[input_size, batch_size, 1, 1]))
+                  , false)
+  }
+}
+
+/// Config of a 2D convolution layer (see `layer::Conv2D` for the data layout)
+#[derive(Clone, Debug, PartialEq)]
+pub struct Conv2DConfig {
+  pub input_dims: (usize, usize, usize), // [height, width, channels]
+  pub filters: usize,
+  pub kernel: (usize, usize),            // [height, width]
+  pub stride: (usize, usize),            // [height, width]
+  pub padding: (usize, usize),           // zeros added on each side [height, width]
+  pub activation: String,
+  pub w_init: String,
+  pub b_init: String,
+}
+
+impl Conv2DConfig {
+  pub fn new(input_dims: (usize, usize, usize), filters: usize, kernel: (usize, usize)) -> Conv2DConfig {
+    Conv2DConfig {
+      input_dims: input_dims,
+      filters: filters,
+      kernel: kernel,
+      stride: (1, 1),
+      padding: (0, 0),
+      activation: "linear".to_string(),
+      w_init: "glorot_uniform".to_string(),
+      b_init: "zeros".to_string(),
+    }
+  }
+
+  /// Returns the [height, width] of every output feature map
+  pub fn output_dims(&self) -> (usize, usize) {
+    let (height, width, _) = self.input_dims;
+    ((height + 2 * self.padding.0 - self.kernel.0) / self.stride.0 + 1
+     , (width + 2 * self.padding.1 - self.kernel.1) / self.stride.1 + 1)
+  }
+
+  /// Builds the config from the string params accepted by `Model::add`
+  pub fn from_params(params: &HashMap<&str, String>) -> Result<Conv2DConfig, HALError> {
+    let p = Parser::new("conv2d", params);
+    try!(p.check_keys(&["input_height", "input_width", "input_channels", "filters"
+                        , "kernel_height", "kernel_width", "stride_height", "stride_width"
+                        , "padding_height", "padding_width", "activation", "w_init", "b_init"]));
+    let mut config = Conv2DConfig::new((try!(p.required("input_height"))
+                                        , try!(p.required("input_width"))
+                                        , try!(p.optional("input_channels", 1)))
+                                       , try!(p.required("filters"))
+                                       , (try!(p.required("kernel_height"))
+                                          , try!(p.required("kernel_width"))));
+    config.stride = (try!(p.optional("stride_height", config.stride.0))
+                     , try!(p.optional("stride_width", config.stride.1)));
+    config.padding = (try!(p.optional("padding_height", config.padding.0))
+                      , try!(p.optional("padding_width", config.padding.1)));
+    config.activation = p.string("activation", config.activation);
+    config.w_init = p.string("w_init", config.w_init);
+    config.b_init = p.string("b_init", config.b_init);
+    Ok(config)
+  }
+}
+
+impl LayerConfig for Conv2DConfig {
+  fn layer_type(&self) -> &'static str { "conv2d" }
+
+  fn input_size(&self) -> usize {
+    self.input_dims.0 * self.input_dims.1 * self.input_dims.2
+  }
+
+  fn output_size(&self) -> usize {
+    let (output_height, output_width) = self.output_dims();
+    output_height * output_width * self.filters
+  }
+
+  fn validate(&self) -> Result<(), HALError> {
+    let layer = self.layer_type();
+    try!(check_size(layer, "input_height", self.input_dims.0));
+    try!(check_size(layer, "input_width", self.input_dims.1));
+    try!(check_size(layer, "input_channels", self.input_dims.2));
+    try!(check_size(layer, "filters", self.filters));
+    try!(check_size(layer, "kernel_height", self.kernel.0));
+    try!(check_size(layer, "kernel_width", self.kernel.1));
+    try!(check_size(layer, "stride_height", self.stride.0));
+    try!(check_size(layer, "stride_width", self.stride.1));
+    try!(check_kernel(layer, "kernel_height", self.kernel.0, self.input_dims.0, self.padding.0));
+    try!(check_kernel(layer, "kernel_width", self.kernel.1, self.input_dims.1, self.padding.1));
+    try!(check_activation(layer, "activation", &self.activation));
+    try!(check_initialization(layer, "w_init", &self.w_init));
+    check_initialization(layer, "b_init", &self.b_init)
+  }
+
+  fn to_params(&self) -> HashMap<String, String> {
+    to_map(vec![("input_height", self.input_dims.0.to_string())
+                , ("input_width", self.input_dims.1.to_string())
+                , ("input_channels", self.input_dims.2.to_string())
+                , ("filters", self.filters.to_string())
+                , ("kernel_height", self.kernel.0.to_string())
+                , ("kernel_width", self.kernel.1.to_string())
+                , ("stride_height", self.stride.0.to_string())
+                , ("stride_width", self.stride.1.to_string())
+                , ("padding_height", self.padding.0.to_string())
+                , ("padding_width", self.padding.1.to_string())
+                , ("activation", self.activation.clone())
+                , ("w_init", self.w_init.clone())
+                , ("b_init", self.b_init.clone())])
+  }
+
+  fn build<T: HasAfEnum>(&self, param_manager: &mut ParamManager
+                         , manager: DeviceManager, device: Device) -> Box<Layer>
+  {
+    param_manager.add_conv2d::<T>(manager, device
+                                  , self.input_dims, self.filters
+                                  , self.kernel
+                                  , &self.activation
+                                  , &self.w_init
+                                  , &self.b_init);
+    Box::new(Conv2D{input_dims: self.input_dims
+                    , filters: self.filters
+                    , kernel: self.kernel
+                    , stride: self.stride
+                    , padding: self.padding})
+  }
+}
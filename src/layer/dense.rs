@@ -1,10 +1,14 @@
 use af;
-use af::{Array};
+use af::{Array, HasAfEnum};
 use std::sync::{Arc, Mutex};
+use std::collections::HashMap;
 
 use layer;
 use layer::{Layer};
-use params::Params;
+use layer::config::{LayerConfig, Parser, check_size, check_activation, check_initialization, to_map};
+use error::HALError;
+use device::{Device, DeviceManager};
+use params::{Params, ParamManager, DenseGenerator};
 
 pub struct Dense {
   pub input_size: usize,
@@ -58,3 +62,72 @@ impl Layer for Dense
     af::matmul(&delta_t, &ltex.weights[0], af::MatProp::NONE, af::MatProp::TRANS)
   }
 }
+
+/// Config of a fully connected layer: activation(xW + b)
+#[derive(Clone, Debug, PartialEq)]
+pub struct DenseConfig {
+  pub input_size: usize,
+  pub output_size: usize,
+  pub activation: String,
+  pub w_init: String,
+  pub b_init: String,
+}
+
+impl DenseConfig {
+  pub fn new(input_size: usize, output_size: usize) -> DenseConfig {
+    DenseConfig {
+      input_size: input_size,
+      output_size: output_size,
+      activation: "linear".to_string(),
+      w_init: "glorot_uniform".to_string(),
+      b_init: "zeros".to_string(),
+    }
+  }
+
+  /// Builds the config from the string params accepted by `Model::add`
+  pub fn from_params(params: &HashMap<&str, String>) -> Result<DenseConfig, HALError> {
+    let p = Parser::new("dense", params);
+    try!(p.check_keys(&["input_size", "output_size", "activation", "w_init", "b_init"]));
+    let mut config = DenseConfig::new(try!(p.required("input_size"))
+                                      , try!(p.required("output_size")));
+    config.activation = p.string("activation", config.activation);
+    config.w_init = p.string("w_init", config.w_init);
+    config.b_init = p.string("b_init", config.b_init);
+    Ok(config)
+  }
+}
+
+impl LayerConfig for DenseConfig {
+  fn layer_type(&self) -> &'static str { "dense" }
+  fn input_size(&self) -> usize { self.input_size }
+  fn output_size(&self) -> usize { self.output_size }
+
+  fn validate(&self) -> Result<(), HALError> {
+    let layer = self.layer_type();
+    try!(check_size(layer, "input_size", self.input_size));
+    try!(check_size(layer, "output_size", self.output_size));
+    try!(check_activation(layer, "activation", &self.activation));
+    try!(check_initialization(layer, "w_init", &self.w_init));
+    check_initialization(layer, "b_init", &self.b_init)
+  }
+
+  fn to_params(&self) -> HashMap<String, String> {
+    to_map(vec![("input_size", self.input_size.to_string())
+                , ("output_size", self.output_size.to_string())
+                , ("activation", self.activation.clone())
+                , ("w_init", self.w_init.clone())
+                , ("b_init", self.b_init.clone())])
+  }
+
+  fn build<T: HasAfEnum>(&self, param_manager: &mut ParamManager
+                         , manager: DeviceManager, device: Device) -> Box<Layer>
+  {
+    param_manager.add_dense::<T>(manager, device
+                                 , self.input_size, self.output_size
+                                 , &self.activation
+                                 , &self.w_init
+                                 , &self.b_init);
+    Box::new(Dense{input_size: self.input_size
+                   , output_size: self.output_size})
+  }
+}
@@ -0,0 +1,122 @@
+use af;
+use af::{Array, HasAfEnum};
+use std::sync::{Arc, Mutex};
+use std::collections::HashMap;
+
+use utils;
+use layer::{Layer};
+use layer::config::{LayerConfig, Parser, check_size, to_map};
+use error::HALError;
+use device::{Device, DeviceManager};
+use params::{Params, ParamManager, DropoutGenerator};
+
+/// Inverted dropout
+///
+/// In training mode (see `Params::training`) every unit is zeroed with probability
+/// `rate` and the kept units are scaled by 1 / (1 - rate), so that the layer is
+/// the identity in inference mode. The mask of unroll t is kept in `Params::optional[t]`.
+pub struct Dropout {
+  pub size: usize,
+  pub rate: f32,
+}
+
+impl Layer for Dropout
+{
+  fn forward(&self, params: Arc<Mutex<Params>>, inputs: &Array, state: Option<&Vec<Array>>) -> (Array, Option<Vec<Array>>)
+  {
+    // get a handle to the underlying params
+    let mut ltex = params.lock().unwrap();
+
+    let mask = match ltex.training && self.rate > 0.0 {
+      true  => {
+        let keep = af::gt(&af::randu::<f32>(inputs.dims()), &self.rate, false);
+        af::div(&utils::cast(&keep, inputs.get_type()), &(1.0 - self.rate), false)
+      },
+      false => utils::constant(inputs.dims(), inputs.get_type(), 1.0f32),
+    };
+    let a_t = af::mul(inputs, &mask, false);
+
+    // parameter manager keeps the output, inputs & masks
+    let current_unroll = ltex.current_unroll;
+    if ltex.inputs.len() > current_unroll { // store in existing
+      ltex.inputs[current_unroll] = inputs.clone();
+      ltex.outputs[current_unroll] = a_t.clone();
+      ltex.optional[current_unroll] = mask;
+    }else{                                  // add new
+      ltex.inputs.push(inputs.clone());
+      ltex.outputs.push(a_t.clone());
+      ltex.optional.push(mask);
+    }
+
+    // update location in vector
+    ltex.current_unroll += 1;
+
+    (a_t.clone(), None)
+  }
+
+  fn backward(&self, params: Arc<Mutex<Params>>, delta: &Array) -> Array
+  {
+    // get a handle to the underlying params
+    let mut ltex = params.lock().unwrap();
+    let current_unroll = ltex.current_unroll;
+    assert!(current_unroll > 0
+            , "Cannot call backward pass without at least 1 forward pass");
+
+    ltex.current_unroll -= 1;
+
+    // only the kept units pass their (scaled) gradient
+    af::mul(delta, &ltex.optional[current_unroll - 1], false)
+  }
+}
+
+/// Config of an (inverted) dropout layer
+#[derive(Clone, Debug, PartialEq)]
+pub struct DropoutConfig {
+  pub size: usize,
+  pub rate: f32, // probability of dropping a unit while training
+}
+
+impl DropoutConfig {
+  pub fn new(size: usize, rate: f32) -> DropoutConfig {
+    DropoutConfig {
+      size: size,
+      rate: rate,
+    }
+  }
+
+  /// Builds the config from the string params accepted by `Model::add`
+  pub fn from_params(params: &HashMap<&str, String>) -> Result<DropoutConfig, HALError> {
+    let p = Parser::new("dropout", params);
+    try!(p.check_keys(&["size", "rate"]));
+    Ok(DropoutConfig::new(try!(p.required("size"))
+                          , try!(p.required("rate"))))
+  }
+}
+
+impl LayerConfig for DropoutConfig {
+  fn layer_type(&self) -> &'static str { "dropout" }
+  fn input_size(&self) -> usize { self.size }
+  fn output_size(&self) -> usize { self.size }
+
+  fn validate(&self) -> Result<(), HALError> {
+    try!(check_size("dropout", "size", self.size));
+    match self.rate >= 0.0 && self.rate < 1.0 {
+      true  => Ok(()),
+      false => Err(HALError::invalid_param("dropout", "rate"
+                                           , format!("needs to be in [0, 1), got {}", self.rate))),
+    }
+  }
+
+  fn to_params(&self) -> HashMap<String, String> {
+    to_map(vec![("size", self.size.to_string())
+                , ("rate", self.rate.to_string())])
+  }
+
+  fn build<T: HasAfEnum>(&self, param_manager: &mut ParamManager
+                         , manager: DeviceManager, device: Device) -> Box<Layer>
+  {
+    param_manager.add_dropout::<T>(manager, device);
+    Box::new(Dropout{size: self.size
+                     , rate: self.rate})
+  }
+}
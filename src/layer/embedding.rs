@@ -0,0 +1,179 @@
+use af;
+use af::{Array, DType, Indexer, HasAfEnum};
+use std::sync::{Arc, Mutex};
+use std::collections::HashMap;
+
+use utils;
+use error::HALError;
+use layer::{Layer};
+use layer::config::{LayerConfig, Parser, check_size, check_initialization, to_map};
+use device::{Device, DeviceManager};
+use params::{Params, ParamManager, EmbeddingGenerator};
+
+/// A lookup table from integer tokens to dense vectors
+///
+/// Every timestep is expected to be of [batch, 1] holding the (integer valued)
+/// token indices, the output is the matching rows of the [vocab_size, output_size]
+/// table in `Params::weights[0]`. The table is flagged as sparse (see `Params::sparse`):
+/// the gradient is only accumulated onto the rows of the seen tokens, which are
+/// kept in `Params::touched_rows` so that the optimizers only update those rows.
+///
+/// Tokens outside of the vocabulary are rejected by `check_inputs` (ie: when
+/// fitting), a forward pass maps them to a zero vector without a gradient.
+pub struct Embedding {
+  pub vocab_size: usize,
+  pub output_size: usize,
+}
+
+/// Helper that maps the tokens to the (u32) rows of the table & a [batch, 1] mask of the valid ones
+///
+/// Invalid tokens (negative or past the vocabulary) read the first row & are masked out
+fn token_rows(inputs: &Array, vocab_size: usize) -> (Array, Array)
+{
+  let valid = af::and(&af::ge(inputs, &0.0f32, false)
+                      , &af::lt(inputs, &(vocab_size as f32), false), false);
+  let rows = utils::cast(&af::selectr(inputs, &valid, 0.0), DType::U32);
+  (rows, utils::cast(&valid, inputs.get_type()))
+}
+
+impl Layer for Embedding
+{
+  fn forward(&self, params: Arc<Mutex<Params>>, inputs: &Array, state: Option<&Vec<Array>>) -> (Array, Option<Vec<Array>>)
+  {
+    // get a handle to the underlying params
+    let mut ltex = params.lock().unwrap();
+    let (rows, valid) = token_rows(inputs, self.vocab_size);
+    let a_t = af::mul(&af::lookup(&ltex.weights[0], &rows, 0), &valid, true);
+
+    // parameter manager keeps the output & inputs
+    let current_unroll = ltex.current_unroll;
+    if ltex.inputs.len() > current_unroll { // store in existing
+      ltex.inputs[current_unroll] = inputs.clone();
+      ltex.outputs[current_unroll] = a_t.clone();
+    }else{                                  // add new
+      ltex.inputs.push(inputs.clone());
+      ltex.outputs.push(a_t.clone());
+    }
+
+    // update location in vector
+    ltex.current_unroll += 1;
+
+    (a_t.clone(), None)
+  }
+
+  fn backward(&self, params: Arc<Mutex<Params>>, delta: &Array) -> Array
+  {
+    // get a handle to the underlying params
+    let mut ltex = params.lock().unwrap();
+    let current_unroll = ltex.current_unroll;
+    assert!(current_unroll > 0
+            , "Cannot call backward pass without at least 1 forward pass");
+
+    // the deltas of the samples are sorted by token & summed per row
+    let inputs = ltex.inputs[current_unroll - 1].clone();
+    let (rows, valid) = token_rows(&inputs, self.vocab_size);
+    let (tokens, order) = af::sort_index(&rows, 0, true);
+    let (touched, sums) = af::sum_by_key(&tokens, &af::lookup(&af::mul(delta, &valid, true), &order, 0), 0);
+
+    // and only accumulated onto the touched rows of the table
+    let mut indices = Indexer::new();
+    indices.set_index(&touched, 0, None);
+    let accumulated = af::add(&af::lookup(&ltex.deltas[0], &touched, 0), &sums, false);
+    ltex.deltas[0] = af::assign_gen(&ltex.deltas[0], &indices, &accumulated);
+    ltex.touched_rows = Some(match ltex.touched_rows {
+      Some(ref seen) => af::set_union(seen, &touched, true),
+      None           => touched,
+    });
+
+    ltex.current_unroll -= 1;
+
+    // the token indices are not differentiable
+    utils::constant(inputs.dims(), delta.get_type(), 0.0f32)
+  }
+
+  fn check_inputs(&self, inputs: &Array) -> Result<(), HALError>
+  {
+    let (min, max) = (af::min_all(inputs).0, af::max_all(inputs).0);
+    if min < 0.0 || max >= self.vocab_size as f64 {
+      return Err(HALError::invalid_param("embedding", "inputs"
+                                         , format!("tokens need to be in [0, {}), got [{}, {}]"
+                                                   , self.vocab_size, min, max)));
+    }
+    match af::sum_all(&af::neq(inputs, &af::floor(inputs), false)).0 > 0.0 {
+      true  => Err(HALError::invalid_param("embedding", "inputs", "tokens need to be integers".to_string())),
+      false => Ok(()),
+    }
+  }
+}
+
+/// Config of an embedding layer (see `layer::Embedding`)
+#[derive(Clone, Debug, PartialEq)]
+pub struct EmbeddingConfig {
+  pub vocab_size: usize,
+  pub output_size: usize,
+  pub w_init: String,
+  pub pretrained: Option<Vec<f32>>, // column major [vocab_size, output_size] table, replaces w_init
+}
+
+impl EmbeddingConfig {
+  pub fn new(vocab_size: usize, output_size: usize) -> EmbeddingConfig {
+    EmbeddingConfig {
+      vocab_size: vocab_size,
+      output_size: output_size,
+      w_init: "uniform".to_string(),
+      pretrained: None,
+    }
+  }
+
+  /// Builds the config from the string params accepted by `Model::add`
+  ///
+  /// A pretrained table can only be provided through the typed config
+  pub fn from_params(params: &HashMap<&str, String>) -> Result<EmbeddingConfig, HALError> {
+    let p = Parser::new("embedding", params);
+    try!(p.check_keys(&["vocab_size", "output_size", "w_init"]));
+    let mut config = EmbeddingConfig::new(try!(p.required("vocab_size"))
+                                          , try!(p.required("output_size")));
+    config.w_init = p.string("w_init", config.w_init);
+    Ok(config)
+  }
+}
+
+impl LayerConfig for EmbeddingConfig {
+  fn layer_type(&self) -> &'static str { "embedding" }
+  fn input_size(&self) -> usize { 1 }
+  fn output_size(&self) -> usize { self.output_size }
+
+  fn validate(&self) -> Result<(), HALError> {
+    let layer = self.layer_type();
+    try!(check_size(layer, "vocab_size", self.vocab_size));
+    try!(check_size(layer, "output_size", self.output_size));
+    try!(check_initialization(layer, "w_init", &self.w_init));
+    match self.pretrained {
+      Some(ref table) if table.len() != self.vocab_size * self.output_size => {
+        Err(HALError::invalid_param(layer, "pretrained"
+                                    , format!("expected {} values for a [{}, {}] table, got {}"
+                                              , self.vocab_size * self.output_size
+                                              , self.vocab_size, self.output_size, table.len())))
+      },
+      _ => Ok(()),
+    }
+  }
+
+  // the pretrained table is saved along with the weights
+  fn to_params(&self) -> HashMap<String, String> {
+    to_map(vec![("vocab_size", self.vocab_size.to_string())
+                , ("output_size", self.output_size.to_string())
+                , ("w_init", self.w_init.clone())])
+  }
+
+  fn build<T: HasAfEnum>(&self, param_manager: &mut ParamManager
+                         , manager: DeviceManager, device: Device) -> Box<Layer>
+  {
+    param_manager.add_embedding::<T>(manager, device
+                                     , self.vocab_size, self.output_size
+                                     , &self.w_init
+                                     , self.pretrained.as_ref().map(|t| &t[..]));
+    Box::new(Embedding{vocab_size: self.vocab_size
+                       , output_size: self.output_size})
+  }
+}
@@ -0,0 +1,294 @@
+use af;
+use af::{Array, Dim4, MatProp, HasAfEnum};
+use std::sync::{Arc, Mutex};
+use std::collections::HashMap;
+
+use utils;
+use layer;
+use activations;
+use error::HALError;
+use device::{Device, DeviceManager};
+use params::{Params, ParamManager, FullUnitaryGenerator};
+use layer::{Layer, RecurrentLayer};
+use layer::config::{LayerConfig, Parser, check_size, check_activation, check_initialization, to_map};
+
+/// A full-capacity unitary RNN (Wisdom et al., 2016)
+///
+/// Unlike `layer::Unitary`, whose recurrent matrix is a product of structured
+/// unitary factors, the recurrent matrix W is an arbitrary [N, N] unitary:
+///
+///   z_t = h_{t-1} W + x_t V, h_t = modReLU(z_t, b), o_t = outer_activation([Re h_t, Im h_t] U + b_o)
+///
+/// with modReLU(z, b) = z * relu(|z| + b) / |z|. Complex arrays are kept as
+/// [real, imaginary] along the features, i.e. W is [N, 2N], V is [input, 2N]
+/// & the state is [batch, 2N]. W is flagged as unitary (see `Params::unitary`),
+/// thus the optimizers update it with a Cayley step along the Stiefel manifold
+/// (see `optimizer::unitary_update`) so that it stays unitary.
+pub struct FullUnitary {
+  pub input_size: usize,
+  pub hidden_size: usize,
+  pub output_size: usize,
+  pub return_sequences: bool,
+}
+
+// indices in Params::weights, the unitary matrix has to be first
+const RECURRENT: usize = 0;
+const INPUT: usize = 1;
+const OUTPUT: usize = 2;
+
+// indices in Params::biases
+const MOD_BIAS: usize = 0;
+const OUTPUT_BIAS: usize = 1;
+
+// keeps |z| away from 0
+const MOD_EPSILON: f32 = 1e-6;
+
+impl RecurrentLayer for FullUnitary {
+  fn state_size(self) -> usize {
+    2 * self.hidden_size
+  }
+}
+
+impl FullUnitary
+{
+  // the real & imaginary halves of a [.., 2N] array
+  fn split(&self, a: &Array) -> (Array, Array) {
+    let n = self.hidden_size as u64;
+    (af::cols(a, 0, n - 1), af::cols(a, n, 2 * n - 1))
+  }
+
+  // z_t = h_{t-1} W + x_t V as its real & imaginary parts
+  fn preactivation(&self, ltex: &Params, inputs: &Array, h_tm1: &Array) -> (Array, Array) {
+    let mm = |a: &Array, b: &Array| af::matmul(a, b, MatProp::NONE, MatProp::NONE);
+    let (a, b) = self.split(h_tm1);
+    let (p, q) = self.split(&ltex.weights[RECURRENT]);
+    let (vr, vi) = self.split(&ltex.weights[INPUT]);
+    let zr = af::add(&af::sub(&mm(&a, &p), &mm(&b, &q), false), &mm(inputs, &vr), false);
+    let zi = af::add(&af::add(&mm(&a, &q), &mm(&b, &p), false), &mm(inputs, &vi), false);
+    (zr, zi)
+  }
+
+  // |z|
+  fn modulus(&self, zr: &Array, zi: &Array) -> Array {
+    af::sqrt(&af::add(&af::add(&af::mul(zr, zr, false), &af::mul(zi, zi, false), false), &MOD_EPSILON, false))
+  }
+}
+
+impl Layer for FullUnitary
+{
+  fn forward(&self, params: Arc<Mutex<Params>>, inputs: &Array, state: Option<&Vec<Array>>) -> (Array, Option<Vec<Array>>)
+  {
+    // get a handle to the underlying params
+    let mut ltex = params.lock().unwrap();
+    let current_unroll = ltex.current_unroll;
+
+    // recurrences[t] holds the state of unroll t - 1.
+    // If a state is provided it overrides the current recurrence, otherwise
+    // the last state of the previous unroll is carried over (at t = 0)
+    let batch_size = inputs.dims()[0];
+    let zero_state = || utils::constant(Dim4::new(&[batch_size, 2 * self.hidden_size as u64, 1, 1])
+                                        , inputs.get_type(), 0.0f32);
+    let h_tm1 = match state {
+      Some(init_state) => init_state[0].clone(),
+      None             => match ltex.recurrences.len() {
+        0 => zero_state(),
+        _ => match current_unroll {
+          0 => {
+            let carried = ltex.recurrences.last().unwrap().clone();
+            match carried.dims()[0] == batch_size {
+              true  => carried,
+              false => zero_state(),
+            }
+          },
+          _ => ltex.recurrences[current_unroll].clone(),
+        },
+      },
+    };
+
+    if ltex.recurrences.len() > current_unroll {
+      ltex.recurrences[current_unroll] = h_tm1.clone();
+    }else{
+      ltex.recurrences.push(h_tm1.clone());
+    }
+
+    // h_t = z_t * relu(|z_t| + b) / |z_t|
+    let (zr, zi) = self.preactivation(&ltex, inputs, &h_tm1);
+    let modulus = self.modulus(&zr, &zi);
+    let scale = af::div(&activations::relu(&af::add(&modulus, &ltex.biases[MOD_BIAS], true)), &modulus, false);
+    let h_t = af::join(1, &af::mul(&zr, &scale, false), &af::mul(&zi, &scale, false));
+
+    let o_t = layer::linear(&h_t, &ltex.weights[OUTPUT], Some(&ltex.biases[OUTPUT_BIAS]), &ltex.activations[0]);
+
+    // parameter manager keeps the output, inputs & states
+    if ltex.inputs.len() > current_unroll { // store in existing
+      ltex.inputs[current_unroll] = inputs.clone();
+      ltex.outputs[current_unroll] = o_t.clone();
+      ltex.recurrences[current_unroll + 1] = h_t.clone();
+    }else{                                  // add new
+      ltex.inputs.push(inputs.clone());
+      ltex.outputs.push(o_t.clone());
+      ltex.recurrences.push(h_t.clone());
+    }
+
+    // update location in vector
+    ltex.current_unroll += 1;
+
+    (o_t, Some(vec![h_t]))
+  }
+
+  fn backward(&self, params: Arc<Mutex<Params>>, delta: &Array) -> Array
+  {
+    // get a handle to the underlying params
+    let mut ltex = params.lock().unwrap();
+    let current_unroll = ltex.current_unroll;
+    assert!(current_unroll > 0
+            , "Cannot call backward pass without at least 1 forward pass");
+
+    let x_t = ltex.inputs[current_unroll - 1].clone();
+    let h_tm1 = ltex.recurrences[current_unroll - 1].clone();
+    let h_t = ltex.recurrences[current_unroll].clone();
+
+    // check to see if we already have a state derivative, else add one
+    if ltex.state_derivatives.len() == 0 {
+      ltex.state_derivatives.push(utils::constant(h_t.dims(), h_t.get_type(), 0.0f32));
+    }
+
+    // output: o_t = outer_activation(h_t U + b_o)
+    let (dz_o, du, db_o) = layer::linear_backward(delta, &h_t, &ltex.outputs[current_unroll - 1], &ltex.activations[0]);
+    let dh = af::add(&af::matmul(&dz_o, &ltex.weights[OUTPUT], MatProp::NONE, MatProp::TRANS)
+                     , &ltex.state_derivatives[0], false);
+    let (dh_re, dh_im) = self.split(&dh);
+
+    // modReLU: h = z * s with s = m / |z| & m = relu(|z| + b)
+    // ds = dh_re . zr + dh_im . zi, d|z| = ds * (m' / |z| - m / |z|^2), db = ds * m' / |z|
+    let (zr, zi) = self.preactivation(&ltex, &x_t, &h_tm1);
+    let modulus = self.modulus(&zr, &zi);
+    let m = activations::relu(&af::add(&modulus, &ltex.biases[MOD_BIAS], true));
+    let dm = activations::relu_derivative(&m);
+    let scale = af::div(&m, &modulus, false);
+    let dscale = af::add(&af::mul(&dh_re, &zr, false), &af::mul(&dh_im, &zi, false), false);
+    let dmodulus = af::mul(&dscale, &af::sub(&af::div(&dm, &modulus, false)
+                                             , &af::div(&scale, &modulus, false), false), false);
+    let dmod_bias = af::sum(&af::mul(&dscale, &af::div(&dm, &modulus, false), false), 0);
+    let dzr = af::add(&af::mul(&dh_re, &scale, false), &af::mul(&dmodulus, &af::div(&zr, &modulus, false), false), false);
+    let dzi = af::add(&af::mul(&dh_im, &scale, false), &af::mul(&dmodulus, &af::div(&zi, &modulus, false), false), false);
+
+    // z = h_{t-1} W + x V: zr = a P - b Q + x Vr, zi = a Q + b P + x Vi
+    let tmm = |a: &Array, b: &Array| af::matmul(a, b, MatProp::TRANS, MatProp::NONE);
+    let mmt = |a: &Array, b: &Array| af::matmul(a, b, MatProp::NONE, MatProp::TRANS);
+    let (a, b) = self.split(&h_tm1);
+    let (p, q) = self.split(&ltex.weights[RECURRENT]);
+    let (vr, vi) = self.split(&ltex.weights[INPUT]);
+    let dw = af::join(1, &af::add(&tmm(&a, &dzr), &tmm(&b, &dzi), false)
+                      , &af::sub(&tmm(&a, &dzi), &tmm(&b, &dzr), false));
+    let dv = af::join(1, &tmm(&x_t, &dzr), &tmm(&x_t, &dzi));
+    let dh_tm1 = af::join(1, &af::add(&mmt(&dzr, &p), &mmt(&dzi, &q), false)
+                          , &af::sub(&mmt(&dzi, &p), &mmt(&dzr, &q), false));
+
+    // push in the appropriate gradients
+    let num_weights = ltex.weights.len();
+    ltex.deltas[RECURRENT] = af::add(&ltex.deltas[RECURRENT], &dw, false);
+    ltex.deltas[INPUT] = af::add(&ltex.deltas[INPUT], &dv, false);
+    ltex.deltas[OUTPUT] = af::add(&ltex.deltas[OUTPUT], &du, false);
+    ltex.deltas[num_weights + MOD_BIAS] = af::add(&ltex.deltas[num_weights + MOD_BIAS], &dmod_bias, false);
+    ltex.deltas[num_weights + OUTPUT_BIAS] = af::add(&ltex.deltas[num_weights + OUTPUT_BIAS], &db_o, false);
+
+    // carry the state derivative to the previous unroll
+    ltex.state_derivatives[0] = dh_tm1;
+
+    // update location in vector
+    ltex.current_unroll -= 1;
+
+    af::add(&mmt(&dzr, &vr), &mmt(&dzi, &vi), false)
+  }
+
+  fn return_sequences(&self) -> bool {
+    self.return_sequences
+  }
+
+  fn is_recurrent(&self) -> bool {
+    true
+  }
+}
+
+/// Config of a full-capacity unitary RNN (see `layer::FullUnitary`)
+#[derive(Clone, Debug, PartialEq)]
+pub struct FullUnitaryConfig {
+  pub input_size: usize,
+  pub hidden_size: usize,       // complex units, the state has 2 * hidden_size features
+  pub output_size: usize,
+  pub outer_activation: String,
+  pub w_init: String,           // input to hidden & hidden to output matrices
+  pub b_init: String,
+  pub return_sequences: bool,   // only output the last timestep if false
+}
+
+impl FullUnitaryConfig {
+  pub fn new(input_size: usize, hidden_size: usize, output_size: usize) -> FullUnitaryConfig {
+    FullUnitaryConfig {
+      input_size: input_size,
+      hidden_size: hidden_size,
+      output_size: output_size,
+      outer_activation: "linear".to_string(),
+      w_init: "glorot_uniform".to_string(),
+      b_init: "zeros".to_string(),
+      return_sequences: true,
+    }
+  }
+
+  /// Builds the config from the string params accepted by `Model::add`
+  pub fn from_params(params: &HashMap<&str, String>) -> Result<FullUnitaryConfig, HALError> {
+    let p = Parser::new("full_unitary", params);
+    try!(p.check_keys(&["input_size", "hidden_size", "output_size"
+                        , "outer_activation", "w_init", "b_init", "return_sequences"]));
+    let mut config = FullUnitaryConfig::new(try!(p.required("input_size"))
+                                            , try!(p.required("hidden_size"))
+                                            , try!(p.required("output_size")));
+    config.outer_activation = p.string("outer_activation", config.outer_activation);
+    config.w_init = p.string("w_init", config.w_init);
+    config.b_init = p.string("b_init", config.b_init);
+    config.return_sequences = try!(p.optional("return_sequences", config.return_sequences));
+    Ok(config)
+  }
+}
+
+impl LayerConfig for FullUnitaryConfig {
+  fn layer_type(&self) -> &'static str { "full_unitary" }
+  fn input_size(&self) -> usize { self.input_size }
+  fn output_size(&self) -> usize { self.output_size }
+  fn return_sequences(&self) -> bool { self.return_sequences }
+
+  fn validate(&self) -> Result<(), HALError> {
+    let layer = self.layer_type();
+    try!(check_size(layer, "input_size", self.input_size));
+    try!(check_size(layer, "hidden_size", self.hidden_size));
+    try!(check_size(layer, "output_size", self.output_size));
+    try!(check_activation(layer, "outer_activation", &self.outer_activation));
+    try!(check_initialization(layer, "w_init", &self.w_init));
+    check_initialization(layer, "b_init", &self.b_init)
+  }
+
+  fn to_params(&self) -> HashMap<String, String> {
+    to_map(vec![("input_size", self.input_size.to_string())
+                , ("hidden_size", self.hidden_size.to_string())
+                , ("output_size", self.output_size.to_string())
+                , ("outer_activation", self.outer_activation.clone())
+                , ("w_init", self.w_init.clone())
+                , ("b_init", self.b_init.clone())
+                , ("return_sequences", self.return_sequences.to_string())])
+  }
+
+  fn build<T: HasAfEnum>(&self, param_manager: &mut ParamManager
+                         , manager: DeviceManager, device: Device) -> Box<Layer>
+  {
+    param_manager.add_full_unitary::<T>(manager, device
+                                        , self.input_size, self.hidden_size, self.output_size
+                                        , &self.outer_activation
+                                        , &self.w_init
+                                        , &self.b_init);
+    Box::new(FullUnitary{input_size: self.input_size
+                         , hidden_size: self.hidden_size
+                         , output_size: self.output_size
+                         , return_sequences: self.return_sequences})
+  }
+}
@@ -0,0 +1,309 @@
+use af;
+use af::{Array, Dim4, MatProp, HasAfEnum};
+use std::sync::{Arc, Mutex};
+use std::collections::HashMap;
+
+use utils;
+use activations;
+use error::HALError;
+use device::{Device, DeviceManager};
+use params::{GRUIndex, Params, ParamManager, GRUGenerator};
+use layer::{Layer, RecurrentLayer};
+use layer::config::{LayerConfig, Parser, check_size, check_activation, check_initialization, to_map};
+
+pub struct GRU {
+  pub input_size: usize,
+  pub output_size: usize,
+  pub return_sequences: bool,
+}
+
+impl RecurrentLayer for GRU {
+  fn state_size(self) -> usize {
+    self.output_size
+  }
+}
+
+/// Helper to return the gate located at `index` from the joined [z, r, hh] array
+fn gate(gates: &Array, index: GRUIndex) -> Array {
+  let n = gates.dims()[1] / 3;
+  let offset = index as u64 * n;
+  af::cols(gates, offset, offset + n - 1)
+}
+
+impl GRU
+{
+  /// Helper to join the gate parameters so that a single matmul can be used
+  ///
+  /// Returns the input weights [W_z W_r W_h], the recurrent weights of the
+  /// update & reset gates [U_z U_r], the candidate recurrent weight U_h and
+  /// the biases [b_z; b_r; b_h]
+  fn joined_params(&self, ltex: &Params) -> (Array, Array, Array, Array) {
+    let offset = 3; // the offset from weights --> recurrent weights
+    let w = af::join_many(1, vec![&ltex.weights[GRUIndex::Update as usize]
+                                  , &ltex.weights[GRUIndex::Reset as usize]
+                                  , &ltex.weights[GRUIndex::Hidden as usize]]);
+    let u_zr = af::join(1, &ltex.weights[GRUIndex::Update as usize + offset]
+                        , &ltex.weights[GRUIndex::Reset as usize + offset]);
+    let u_h = ltex.weights[GRUIndex::Hidden as usize + offset].clone();
+    let b = af::join_many(0, vec![&ltex.biases[GRUIndex::Update as usize]
+                                  , &ltex.biases[GRUIndex::Reset as usize]
+                                  , &ltex.biases[GRUIndex::Hidden as usize]]);
+    (w, u_zr, u_h, b)
+  }
+}
+
+impl Layer for GRU
+{
+  fn forward(&self, params: Arc<Mutex<Params>>, inputs: &Array, state: Option<&Vec<Array>>) -> (Array, Option<Vec<Array>>)
+  {
+    // get a handle to the underlying params
+    let mut ltex = params.lock().unwrap();
+    let current_unroll = ltex.current_unroll;
+    let output_size = ltex.weights[GRUIndex::Update as usize].dims()[1];
+
+    // recurrences[t] holds h_{t-1} for unroll t.
+    // If a state is provided it overrides the current recurrence, otherwise
+    // the last state of the previous unroll is carried over (at t = 0)
+    let init_h_dims = Dim4::new(&[inputs.dims()[0], output_size, 1, 1]);
+    let h_tm1 = match state {
+      Some(init_state) => init_state[0].clone(), // only one recurrence for a GRU
+      None             => match ltex.recurrences.len() {
+        0 => utils::constant(init_h_dims, inputs.get_type(), 0.0f32),
+        _ => match current_unroll {
+          0 => {
+            let carried = ltex.recurrences.last().unwrap().clone();
+            match carried.dims()[0] == init_h_dims[0] {
+              true  => carried,
+              false => utils::constant(init_h_dims, inputs.get_type(), 0.0f32),
+            }
+          },
+          _ => ltex.recurrences[current_unroll].clone(),
+        },
+      },
+    };
+
+    if ltex.recurrences.len() > current_unroll {
+      ltex.recurrences[current_unroll] = h_tm1.clone();
+    }else{
+      ltex.recurrences.push(h_tm1.clone());
+    }
+
+    // [a(z,r,h)_x] = x_t*W + b
+    // [a(z,r)_h]   = h_{t-1}*[U_z U_r]
+    let (w, u_zr, u_h, b) = self.joined_params(&ltex);
+    let n = output_size;
+    let wx = af::transpose(&af::add(&af::transpose(&af::matmul(inputs, &w, MatProp::NONE, MatProp::NONE), false)
+                                    , &b, true), false);
+    let a_zr = af::add(&af::cols(&wx, 0, 2*n - 1)
+                       , &af::matmul(&h_tm1, &u_zr, MatProp::NONE, MatProp::NONE)
+                       , false);
+
+    // z_t  = inner_activation(a_z)
+    // r_t  = inner_activation(a_r)
+    // hh_t = outer_activation(x_t*W_h + (r_t .* h_{t-1})*U_h + b_h)
+    let inner_activation = ltex.activations[0].clone();
+    let outer_activation = ltex.activations[1].clone();
+    let z_t = activations::get_activation(&inner_activation, &af::cols(&a_zr, 0, n - 1)).unwrap();
+    let r_t = activations::get_activation(&inner_activation, &af::cols(&a_zr, n, 2*n - 1)).unwrap();
+    let rh_tm1 = af::mul(&r_t, &h_tm1, false);
+    let a_h = af::add(&af::cols(&wx, 2*n, 3*n - 1)
+                      , &af::matmul(&rh_tm1, &u_h, MatProp::NONE, MatProp::NONE)
+                      , false);
+    let hh_t = activations::get_activation(&outer_activation, &a_h).unwrap();
+
+    // h_t = (1 - z_t) .* h_{t-1} + z_t .* hh_t
+    let h_t = af::add(&h_tm1, &af::mul(&z_t, &af::sub(&hh_t, &h_tm1, false), false), false);
+
+    // parameter manager keeps the inputs, outputs, states & activated gates
+    let gates = af::join_many(1, vec![&z_t, &r_t, &hh_t]);
+    if ltex.inputs.len() > current_unroll { // store in existing
+      ltex.inputs[current_unroll] = inputs.clone();
+      ltex.outputs[current_unroll] = h_t.clone();
+      ltex.optional[current_unroll] = gates;
+      ltex.recurrences[current_unroll + 1] = h_t.clone();
+    }else{                                  // add new
+      ltex.inputs.push(inputs.clone());
+      ltex.outputs.push(h_t.clone());
+      ltex.optional.push(gates);
+      ltex.recurrences.push(h_t.clone());
+    }
+
+    // update location in vector
+    ltex.current_unroll += 1;
+
+    (h_t.clone(), Some(vec![h_t.clone()])) // clone just increases the ref count
+  }
+
+  fn backward(&self, params: Arc<Mutex<Params>>, delta: &Array) -> Array
+  {
+    // get a handle to the underlying params
+    let mut ltex = params.lock().unwrap();
+    let current_unroll = ltex.current_unroll;
+    assert!(current_unroll > 0
+            , "Cannot call backward pass without at least 1 forward pass");
+
+    // check to see if we already have a state derivative, else add one
+    if ltex.state_derivatives.len() == 0 {
+      let h_size = delta.dims();
+      let h_type = delta.get_type();
+      ltex.state_derivatives.push(utils::constant(h_size, h_type, 0.0f32));
+    }
+
+    let inner_activation = ltex.activations[0].clone();
+    let outer_activation = ltex.activations[1].clone();
+    let gates = ltex.optional[current_unroll - 1].clone();
+    let z_t = gate(&gates, GRUIndex::Update);
+    let r_t = gate(&gates, GRUIndex::Reset);
+    let hh_t = gate(&gates, GRUIndex::Hidden);
+    let h_tm1 = ltex.recurrences[current_unroll - 1].clone();
+    let rh_tm1 = af::mul(&r_t, &h_tm1, false);
+    let (w, u_zr, u_h, _) = self.joined_params(&ltex);
+
+    // dh_t    = delta_t + dh_{t+1}
+    // da_h    = dh_t .* z_t .* outer_activation'(hh_t)
+    // d(rh)   = da_h * U_h^T
+    // da_r    = d(rh) .* h_{t-1} .* inner_activation'(r_t)
+    // da_z    = dh_t .* (hh_t - h_{t-1}) .* inner_activation'(z_t)
+    let dh_t = af::add(delta, &ltex.state_derivatives[0], false);
+    let da_h = af::mul(&af::mul(&dh_t, &z_t, false)
+                       , &activations::get_derivative(&outer_activation, &hh_t).unwrap(), false);
+    let drh = af::matmul(&da_h, &u_h, MatProp::NONE, MatProp::TRANS);
+    let da_r = af::mul(&af::mul(&drh, &h_tm1, false)
+                       , &activations::get_derivative(&inner_activation, &r_t).unwrap(), false);
+    let da_z = af::mul(&af::mul(&dh_t, &af::sub(&hh_t, &h_tm1, false), false)
+                       , &activations::get_derivative(&inner_activation, &z_t).unwrap(), false);
+    let da_zr = af::join(1, &da_z, &da_r);
+    let da = af::join_many(1, vec![&da_z, &da_r, &da_h]);
+
+    // dW     = x_t^T * [da_z, da_r, da_h]
+    // dU_zr  = h_{t-1}^T * [da_z, da_r]
+    // dU_h   = (r_t .* h_{t-1})^T * da_h
+    // db     = sum_{batch} [da_z, da_r, da_h]
+    let n = h_tm1.dims()[1];
+    let dw = af::matmul(&ltex.inputs[current_unroll - 1], &da, MatProp::TRANS, MatProp::NONE);
+    let du_zr = af::matmul(&h_tm1, &da_zr, MatProp::TRANS, MatProp::NONE);
+    let du_h = af::matmul(&rh_tm1, &da_h, MatProp::TRANS, MatProp::NONE);
+    let db = af::transpose(&af::sum(&da, 0), false);
+
+    // push in the appropriate gradients [W_z, W_r, W_h, U_z, U_r, U_h, b_z, b_r, b_h]
+    let offset = 3;
+    for g in 0..3 {
+      let (first, last) = (g as u64 * n, (g as u64 + 1) * n - 1);
+      let du = match g {
+        2 => du_h.clone(),
+        _ => af::cols(&du_zr, first, last),
+      };
+      ltex.deltas[g] = af::add(&ltex.deltas[g], &af::cols(&dw, first, last), false);
+      ltex.deltas[g + offset] = af::add(&ltex.deltas[g + offset], &du, false);
+      ltex.deltas[g + 2*offset] = af::add(&ltex.deltas[g + 2*offset], &af::rows(&db, first, last), false);
+    }
+
+    // dh_{t-1} = dh_t .* (1 - z_t) + d(rh) .* r_t + [da_z, da_r] * [U_z U_r]^T
+    let dh_direct = af::sub(&dh_t, &af::mul(&dh_t, &z_t, false), false);
+    ltex.state_derivatives[0] = af::add(&af::add(&dh_direct, &af::mul(&drh, &r_t, false), false)
+                                        , &af::matmul(&da_zr, &u_zr, MatProp::NONE, MatProp::TRANS)
+                                        , false);
+
+    // update location in vector
+    ltex.current_unroll -= 1;
+
+    // delta_{t-1} = [da_z, da_r, da_h] * W^T
+    af::matmul(&da, &w, MatProp::NONE, MatProp::TRANS)
+  }
+
+  fn return_sequences(&self) -> bool {
+    self.return_sequences
+  }
+
+  fn is_recurrent(&self) -> bool {
+    true
+  }
+}
+
+/// Config of a GRU layer
+#[derive(Clone, Debug, PartialEq)]
+pub struct GRUConfig {
+  pub input_size: usize,
+  pub output_size: usize,
+  pub inner_activation: String,
+  pub outer_activation: String,
+  pub w_init: String,
+  pub w_recurrent_init: String,
+  pub b_init: String,
+  pub return_sequences: bool,
+}
+
+impl GRUConfig {
+  pub fn new(input_size: usize, output_size: usize) -> GRUConfig {
+    GRUConfig {
+      input_size: input_size,
+      output_size: output_size,
+      inner_activation: "sigmoid".to_string(),
+      outer_activation: "tanh".to_string(),
+      w_init: "glorot_uniform".to_string(),
+      w_recurrent_init: "glorot_uniform".to_string(),
+      b_init: "zeros".to_string(),
+      return_sequences: true,
+    }
+  }
+
+  /// Builds the config from the string params accepted by `Model::add`
+  pub fn from_params(params: &HashMap<&str, String>) -> Result<GRUConfig, HALError> {
+    let p = Parser::new("gru", params);
+    try!(p.check_keys(&["input_size", "output_size", "inner_activation", "outer_activation"
+                        , "w_init", "w_recurrent_init", "b_init", "return_sequences"]));
+    let mut config = GRUConfig::new(try!(p.required("input_size"))
+                                    , try!(p.required("output_size")));
+    config.inner_activation = p.string("inner_activation", config.inner_activation);
+    config.outer_activation = p.string("outer_activation", config.outer_activation);
+    config.w_init = p.string("w_init", config.w_init);
+    config.w_recurrent_init = p.string("w_recurrent_init", config.w_recurrent_init);
+    config.b_init = p.string("b_init", config.b_init);
+    config.return_sequences = try!(p.optional("return_sequences", config.return_sequences));
+    Ok(config)
+  }
+}
+
+impl LayerConfig for GRUConfig {
+  fn layer_type(&self) -> &'static str { "gru" }
+  fn input_size(&self) -> usize { self.input_size }
+  fn output_size(&self) -> usize { self.output_size }
+  fn return_sequences(&self) -> bool { self.return_sequences }
+
+  fn validate(&self) -> Result<(), HALError> {
+    let layer = self.layer_type();
+    try!(check_size(layer, "input_size", self.input_size));
+    try!(check_size(layer, "output_size", self.output_size));
+    try!(check_activation(layer, "inner_activation", &self.inner_activation));
+    try!(check_activation(layer, "outer_activation", &self.outer_activation));
+    try!(check_initialization(layer, "w_init", &self.w_init));
+    try!(check_initialization(layer, "w_recurrent_init", &self.w_recurrent_init));
+    check_initialization(layer, "b_init", &self.b_init)
+  }
+
+  fn to_params(&self) -> HashMap<String, String> {
+    to_map(vec![("input_size", self.input_size.to_string())
+                , ("output_size", self.output_size.to_string())
+                , ("inner_activation", self.inner_activation.clone())
+                , ("outer_activation", self.outer_activation.clone())
+                , ("w_init", self.w_init.clone())
+                , ("w_recurrent_init", self.w_recurrent_init.clone())
+                , ("b_init", self.b_init.clone())
+                , ("return_sequences", self.return_sequences.to_string())])
+  }
+
+  fn build<T: HasAfEnum>(&self, param_manager: &mut ParamManager
+                         , manager: DeviceManager, device: Device) -> Box<Layer>
+  {
+    param_manager.add_gru::<T>(manager, device
+                               , self.input_size, self.output_size
+                               , &self.inner_activation
+                               , &self.outer_activation
+                               , &self.w_init
+                               , &self.w_recurrent_init
+                               , &self.b_init);
+    Box::new(GRU{input_size: self.input_size
+                 , output_size: self.output_size
+                 , return_sequences: self.return_sequences})
+  }
+}
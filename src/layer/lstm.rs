@@ -1,168 +1,446 @@
 use af;
-use af::{Dim4, Array};
-use af::MatProp;
+use af::{Array, Dim4, MatProp, HasAfEnum};
+use std::sync::{Arc, Mutex};
+use std::collections::HashMap;
 
+use utils;
+use layer;
 use activations;
-use initializations;
-use params::{LSTMIndex, Input, Params};
+use error::HALError;
+use device::{Device, DeviceManager};
+use params::{LSTMIndex, Params, ParamManager, LSTMGenerator};
+use layer::{Layer, RecurrentLayer, RTRL};
+use layer::config::{LayerConfig, Parser, check_size, check_activation, check_initialization, to_map};
 
 pub struct LSTM {
   pub input_size: usize,
   pub output_size: usize,
-  pub max_seq_size: usize,
   pub return_sequences: bool,
 }
 
-pub enum ActivationIndex {
-  Inner,
-  Outer,
+impl RecurrentLayer for LSTM {
+  fn state_size(self) -> usize {
+    self.output_size
+  }
+}
+
+/// Helper to split a [h_t, c_t] recurrence into its cell output and cell memory
+fn split_state(state: &Array) -> (Array, Array) {
+  let n = state.dims()[1] / 2;
+  (af::cols(state, 0, n - 1), af::cols(state, n, 2*n - 1))
+}
+
+/// Helper to return the gate located at `index` from the joined [i, f, o, ct] array
+fn gate(gates: &Array, index: LSTMIndex) -> Array {
+  let n = gates.dims()[1] / 4;
+  let offset = index as u64 * n;
+  af::cols(gates, offset, offset + n - 1)
+}
+
+impl LSTM
+{
+  /// Helper to join the four gate parameters so that a single matmul can be used
+  ///
+  /// Weights are joined column wise [W_i W_f W_o W_ct] and biases row wise
+  fn joined_params(&self, ltex: &Params) -> (Array, Array, Array) {
+    let offset = 4; // the offset from weights --> recurrent weights
+    let w = af::join_many(1, vec![&ltex.weights[LSTMIndex::Input as usize]
+                                  , &ltex.weights[LSTMIndex::Forget as usize]
+                                  , &ltex.weights[LSTMIndex::Output as usize]
+                                  , &ltex.weights[LSTMIndex::CellTilda as usize]]);
+    let u = af::join_many(1, vec![&ltex.weights[LSTMIndex::Input as usize + offset]
+                                  , &ltex.weights[LSTMIndex::Forget as usize + offset]
+                                  , &ltex.weights[LSTMIndex::Output as usize + offset]
+                                  , &ltex.weights[LSTMIndex::CellTilda as usize + offset]]);
+    let b = af::join_many(0, vec![&ltex.biases[LSTMIndex::Input as usize]
+                                  , &ltex.biases[LSTMIndex::Forget as usize]
+                                  , &ltex.biases[LSTMIndex::Output as usize]
+                                  , &ltex.biases[LSTMIndex::CellTilda as usize]]);
+    (w, u, b)
+  }
+
+  /// Helper to split the joined gradients per gate and add them to the deltas
+  ///
+  /// Deltas are ordered as [W_i, W_f, W_o, W_ct, U_i, .., U_ct, b_i, .., b_ct]
+  fn accumulate_deltas(&self, ltex: &mut Params, dw: &Array, du: &Array, db: &Array) {
+    let n = ltex.weights[LSTMIndex::Input as usize].dims()[1];
+    let offset = 4;
+    for g in 0..4 {
+      let (first, last) = (g as u64 * n, (g as u64 + 1) * n - 1);
+      ltex.deltas[g] = af::add(&ltex.deltas[g], &af::cols(dw, first, last), false);
+      ltex.deltas[g + offset] = af::add(&ltex.deltas[g + offset], &af::cols(du, first, last), false);
+      ltex.deltas[g + 2*offset] = af::add(&ltex.deltas[g + 2*offset], &af::rows(db, first, last), false);
+    }
+  }
 }
 
-impl RTRL for LSTM {
-  pub fn rtrl(&self, delta: &Array, params: &mut Params) -> Array
+impl Layer for LSTM
+{
+  fn forward(&self, params: Arc<Mutex<Params>>, inputs: &Array, state: Option<&Vec<Array>>) -> (Array, Option<Vec<Array>>)
   {
-    let inner_activation = params.activation[0];
-    let outer_activation = params.activation[1];
-    let i_t  = params.recurrences[LSTMIndex::Input];
-    let f_t  = params.recurrences[LSTMIndex::Forget];
-    let o_t  = params.recurrences[LSTMIndex::Output];
-    let ct_t = params.recurrences[LSTMIndex::CellTilda];
-    let c_t  = params.recurrences[LSTMIndex::Cell];
-    let h_t  = params.recurrences[LSTMIndex::CellOutput];
-
-    let inputs = params.inputs.last().unwrap();
-    let mut derivatives = params.optional.pop().unwrap();
-    let mut dW_tm1 = derivatives[0];
-    let mut dU_tm1 = derivatives[1];
-    let mut db_tm1 = derivatives[2];
-
-    // e_t = o_t * outer_activation'(c_t) * delta
-    let e_t = af::mul(&af::mul(&o_t, &activations::get_derivative(outer_activation, &c_t).unwrap()).unwrap()
-                               , delta).unwrap();
-
-    // compute their derivatives [diff(z_i), diff(z_f), diff(z_ct)]
-    let dz = vec![&activations::get_derivative(inner_activation, &i_t).unwrap()
-                  , &activations::get_derivative(inner_activation, &f_t).unwrap()
-                  , &activations::get_derivative(outer_activation, &ct_t).unwrap()];
-    let ct_ctm1_it = vec![&ct_t, &c_tm1, &i_t];
-
-    // [Ct_t; C_{t-1}; i_t] * dz
-    let dzprod = af::mul(&af::join_many(0, ct_ctm1_it).unwrap()
-                         , af::join_many(0, dz).unwrap(), false).unwrap();
-
-    // dC_t/dWi  = (dC_{t-1}/dWi  * f_t) + ct_t  * inner_activation(z_i) * x_t
-    // dC_t/dWf  = (dC_{t-1}/dWf  * f_t) + c_tm1 * inner_activation(z_f) * x_t
-    // dC_t/dWct = (dC_{t-1}/dWct * f_t) + i_t   * outer_activation(Ct)  * x_t
-    let w_lhs = af::mul(dW_tm1, &f_t, true).unwrap(); // dC_{t-1}/dW * f_t
-    let w_rhs = af::mul(&dzprod, &inputs.data, true).unwrap();
-    dW_tm1 = af::add(&w_lhs, &w_rhs, false).unwrap();
-
-    // dC_t/dUi = (dC_{t-1}/dUi * f_t) + ct_t * inner_activation(z_i) * h_{t-1}
-    // dC_t/dUf = (dC_{t-1}/dUf * f_t) + c_tm1 * inner_activation(z_f) * h_{t-1}
-    // dC_t/dUct = (dC_{t-1}/dUct * f_t) + outer_activation(Ct) * x_t * h_{t-1}
-    let u_lhs = af::mul(dU_tm1, &f_t, true).unwrap(); // dC_{t-1}/dU * f_t
-    let u_rhs = af::mul(&dzprod, &recurrences.data, true).unwrap();
-    dU_tm1 = af::add(&u_lhs, &u_rhs, false).unwrap();
-
-    // dC_t/dbi = (dC_{t-1}/dbi * f_t) + ct_t * inner_activation(z_i)
-    // dC_t/dbf = (dC_{t-1}/dbf * f_t) + c_tm1 * inner_activation(z_f)
-    // dC_t/dbct = (dC_{t-1}/dbct * f_t) + outer_activation(Ct)
-    let b_lhs = af::mul(db_tm1, &f_t, true).unwrap(); // dC_{t-1}/db * f_t
-    params.optional[2] = af::add(&b_lhs, &dzprod, false).unwrap(); //db_{t-1}
+    // get a handle to the underlying params
+    let mut ltex = params.lock().unwrap();
+    let current_unroll = ltex.current_unroll;
+    let output_size = ltex.weights[LSTMIndex::Input as usize].dims()[1];
+    let init_dims = Dim4::new(&[inputs.dims()[0], output_size, 1, 1]);
+
+    // recurrences[t] holds the joined [h_{t-1}, c_{t-1}] state for unroll t.
+    // If a state is provided it overrides the current recurrence, otherwise
+    // the last state of the previous unroll is carried over (at t = 0)
+    let state_tm1 = match state {
+      Some(init_state) => {
+        let h = init_state[0].clone();
+        let c = match init_state.len() {
+          1 => utils::constant(h.dims(), h.get_type(), 0.0f32),
+          _ => init_state[1].clone(),
+        };
+        af::join(1, &h, &c)
+      },
+      None             => match ltex.recurrences.len() {
+        0 => {
+          let zero_dims = Dim4::new(&[init_dims[0], 2*output_size, 1, 1]);
+          utils::constant(zero_dims, inputs.get_type(), 0.0f32)
+        },
+        _ => match current_unroll {
+          0 => {
+            let carried = ltex.recurrences.last().unwrap().clone();
+            match carried.dims()[0] == init_dims[0] {
+              true  => carried,
+              false => {
+                let zero_dims = Dim4::new(&[init_dims[0], 2*output_size, 1, 1]);
+                utils::constant(zero_dims, inputs.get_type(), 0.0f32)
+              },
+            }
+          },
+          _ => ltex.recurrences[current_unroll].clone(),
+        },
+      },
+    };
+
+    if ltex.recurrences.len() > current_unroll {
+      ltex.recurrences[current_unroll] = state_tm1.clone();
+    }else{
+      ltex.recurrences.push(state_tm1.clone());
+    }
+    let (h_tm1, c_tm1) = split_state(&state_tm1);
+
+    // [z(i,f,o,ct)_t] = x_t*W + h_{t-1}*U + b
+    let (w, u, b) = self.joined_params(&ltex);
+    let wx_uh = af::add(&af::matmul(inputs, &w, MatProp::NONE, MatProp::NONE)
+                        , &af::matmul(&h_tm1, &u, MatProp::NONE, MatProp::NONE)
+                        , false);
+    let z_t = af::transpose(&af::add(&af::transpose(&wx_uh, false), &b, true), false);
+
+    let inner_activation = ltex.activations[0].clone();
+    let outer_activation = ltex.activations[1].clone();
+    let i_t  = activations::get_activation(&inner_activation, &gate(&z_t, LSTMIndex::Input)).unwrap();
+    let f_t  = activations::get_activation(&inner_activation, &gate(&z_t, LSTMIndex::Forget)).unwrap();
+    let o_t  = activations::get_activation(&inner_activation, &gate(&z_t, LSTMIndex::Output)).unwrap();
+    let ct_t = activations::get_activation(&outer_activation, &gate(&z_t, LSTMIndex::CellTilda)).unwrap();
+
+    // c_t = i_t * ct_t + f_t * c_{t-1}
+    // h_t = o_t * outer_activation(c_t)
+    let c_t = af::add(&af::mul(&i_t, &ct_t, false)
+                      , &af::mul(&f_t, &c_tm1, false)
+                      , false);
+    let h_t = af::mul(&o_t, &activations::get_activation(&outer_activation, &c_t).unwrap(), false);
+
+    // parameter manager keeps the inputs, outputs, states & activated gates
+    let gates = af::join_many(1, vec![&i_t, &f_t, &o_t, &ct_t]);
+    let state_t = af::join(1, &h_t, &c_t);
+    if ltex.inputs.len() > current_unroll { // store in existing
+      ltex.inputs[current_unroll] = inputs.clone();
+      ltex.outputs[current_unroll] = h_t.clone();
+      ltex.optional[current_unroll] = gates;
+    }else{                                  // add new
+      ltex.inputs.push(inputs.clone());
+      ltex.outputs.push(h_t.clone());
+      ltex.optional.push(gates);
+    }
+
+    if ltex.recurrences.len() > current_unroll + 1 {
+      ltex.recurrences[current_unroll + 1] = state_t;
+    }else{
+      ltex.recurrences.push(state_t);
+    }
+
+    // update location in vector
+    ltex.current_unroll += 1;
+
+    (h_t.clone(), Some(vec![h_t.clone(), c_t.clone()])) // clone just increases the ref count
+  }
+
+  fn backward(&self, params: Arc<Mutex<Params>>, delta: &Array) -> Array
+  {
+    // get a handle to the underlying params
+    let mut ltex = params.lock().unwrap();
+    let current_unroll = ltex.current_unroll;
+    assert!(current_unroll > 0
+            , "Cannot call backward pass without at least 1 forward pass");
+
+    // check to see if we already have the state derivatives [dh, dc], else add them
+    let n = ltex.weights[LSTMIndex::Input as usize].dims()[1];
+    if ltex.state_derivatives.len() == 0 {
+      let s_dims = Dim4::new(&[delta.dims()[0], n, 1, 1]);
+      let s_type = delta.get_type();
+      ltex.state_derivatives.push(utils::constant(s_dims, s_type, 0.0f32));
+      ltex.state_derivatives.push(utils::constant(s_dims, s_type, 0.0f32));
+    }
+
+    let inner_activation = ltex.activations[0].clone();
+    let outer_activation = ltex.activations[1].clone();
+    let gates = ltex.optional[current_unroll - 1].clone();
+    let i_t  = gate(&gates, LSTMIndex::Input);
+    let f_t  = gate(&gates, LSTMIndex::Forget);
+    let o_t  = gate(&gates, LSTMIndex::Output);
+    let ct_t = gate(&gates, LSTMIndex::CellTilda);
+    let (h_tm1, c_tm1) = split_state(&ltex.recurrences[current_unroll - 1]);
+    let (_, c_t) = split_state(&ltex.recurrences[current_unroll]);
+    let tc_t = activations::get_activation(&outer_activation, &c_t).unwrap();
+
+    // dh_t  = delta_t + dh_{t+1}
+    // do_t  = dh_t .* outer_activation(c_t)
+    // dc_t  = dh_t .* o_t .* outer_activation'(c_t) + dc_{t+1}
+    // di_t  = dc_t .* ct_t
+    // df_t  = dc_t .* c_{t-1}
+    // dct_t = dc_t .* i_t
+    let dh_t = af::add(delta, &ltex.state_derivatives[0], false);
+    let do_t = af::mul(&dh_t, &tc_t, false);
+    let dc_t = af::add(&af::mul(&af::mul(&dh_t, &o_t, false)
+                                , &activations::get_derivative(&outer_activation, &tc_t).unwrap()
+                                , false)
+                       , &ltex.state_derivatives[1], false);
+    let di_t = af::mul(&dc_t, &ct_t, false);
+    let df_t = af::mul(&dc_t, &c_tm1, false);
+    let dct_t = af::mul(&dc_t, &i_t, false);
+
+    // push the gate deltas through their activations: dz = [dz_i, dz_f, dz_o, dz_ct]
+    let dz_i = af::mul(&di_t, &activations::get_derivative(&inner_activation, &i_t).unwrap(), false);
+    let dz_f = af::mul(&df_t, &activations::get_derivative(&inner_activation, &f_t).unwrap(), false);
+    let dz_o = af::mul(&do_t, &activations::get_derivative(&inner_activation, &o_t).unwrap(), false);
+    let dz_ct = af::mul(&dct_t, &activations::get_derivative(&outer_activation, &ct_t).unwrap(), false);
+    let dz = af::join_many(1, vec![&dz_i, &dz_f, &dz_o, &dz_ct]);
+
+    // dW = x_t^T * dz
+    // dU = h_{t-1}^T * dz
+    // db = sum_{batch} dz
+    let dw = af::matmul(&ltex.inputs[current_unroll - 1], &dz, MatProp::TRANS, MatProp::NONE);
+    let du = af::matmul(&h_tm1, &dz, MatProp::TRANS, MatProp::NONE);
+    let db = af::transpose(&af::sum(&dz, 0), false);
+
+    // push in the appropriate gradients
+    self.accumulate_deltas(&mut ltex, &dw, &du, &db);
+
+    // dh_{t-1} = dz * U^T
+    // dc_{t-1} = dc_t .* f_t
+    let (w, u, _) = self.joined_params(&ltex);
+    ltex.state_derivatives[0] = af::matmul(&dz, &u, MatProp::NONE, MatProp::TRANS);
+    ltex.state_derivatives[1] = af::mul(&dc_t, &f_t, false);
+
+    // update location in vector
+    ltex.current_unroll -= 1;
+
+    // delta_{t-1} = dz * W^T
+    af::matmul(&dz, &w, MatProp::NONE, MatProp::TRANS)
+  }
+
+  fn as_rtrl(&self) -> Option<&RTRL> {
+    Some(self)
+  }
+
+  fn return_sequences(&self) -> bool {
+    self.return_sequences
+  }
+
+  fn is_recurrent(&self) -> bool {
+    true
   }
 }
 
-impl Layer for LSTM {
-  fn forward(&self, params: &mut Params, inputs: &Input, train: bool) -> Input
+impl RTRL for LSTM
+{
+  fn rtrl(&self, params: Arc<Mutex<Params>>, delta: &Array) -> Array
   {
-    assert!(inputs.data.dims().unwrap()[2] == 1); // only planar data here
-
-    let h_tm1 = params.recurrences[LSTMIndex::CellOutput].last().unwrap();  // cell output @ t-1
-    let c_tm1 = params.recurrences[LSTMIndex::Cell].last().unwrap();        // cell memory @ t-1
-    let inner_activation = params.activations[0];
-    let outer_activation = params.activations[1];
-
-    // forward pass in a batch for performance
-    let weights_ref    = vec![&params.weights[LSTMIndex::Input]
-                              , &params.weights[LSTMIndex::Forget]
-                              , &params.weights[LSTMIndex::Output]
-                              , &params.weights[LSTMIndex::CellTilda]];
-    let offset = 4; // the offset from weights --> recurrent weights
-    let recurrents_ref = vec![&params.weights[LSTMIndex::Input as usize + offset]
-                              , &params.weights[LSTMIndex::Forget as usize + offset]
-                              , &params.weights[LSTMIndex::Output as usize + offset]
-                              , &params.weights[LSTMIndex::CellTilda as usize + offset]];
-    let bias_ref       = vec![&params.biases[LSTMIndex::Input]
-                              , &params.biases[LSTMIndex::Forget]
-                              , &params.biases[LSTMIndex::Output]
-                              , &params.biases[LSTMIndex::CellTilda]];
-
-    // [z(i,f,o,ct)_t] = W*x + U*h_tm1 + b
-    let z_t = af::add(&af::add(&af::matmul(&af::join_many(0, weights_ref).unwrap(), inputs.data).unwrap()
-                               , &af::matmul(&af::join_many(0, recurrents_ref).unwrap(), &h_tm1).unwrap(), false).unwrap()
-                      , &af::join_many(0, bias_ref).unwrap(), true).unwrap();
-    let i_t   = activations::get_activation(inner_activation, &af::rows(&z_t, 0, 0).unwrap());
-    let f_t   = activations::get_activation(inner_activation, &af::rows(&z_t, 1, 1).unwrap());
-    let o_t   = activations::get_activation(inner_activation, &af::rows(&z_t, 2, 2).unwrap());
-    let ct_t  = activations::get_activation(inner_activation, &af::rows(&z_t, 3, 3).unwrap());
-
-    // C_{t} = i_{t} * Ct_{t} + f_{t} * C_{t-1}
-    // h_{t} = o_{t} * outer_activation(C_{t})
-    let c_t = af::add(&af::mul(&i_t, &ct_t, false).unwrap()
-                      , &af::mul(&f_t, &c_tm1, false).unwrap()
-                      , false).unwrap();
-    let h_t = af::mul(&o_t, &activations::get_activation(outer_activation, &c_t).unwrap(), false).unwrap();
-
-    if train { // store the outputs & recurrences in the parameter manager
-      params.inputs.push(inputs.clone());
-      params.outputs.push(h_t.clone());
-      params.recurrences[LSTMIndex::Input].push(i_t.clone());
-      params.recurrences[LSTMIndex::Forget].push(f_t.clone());
-      params.recurrences[LSTMIndex::Output].push(o_t.clone());
-      params.recurrences[LSTMIndex::CellTilda].push(ct_t.clone());
-      params.recurrences[LSTMIndex::Cell].push(c_t.clone()); // TODO: we need c{t-1}
-      params.recurrences[LSTMIndex::CellOutput].push(h_t.clone());
+    // get a handle to the underlying params
+    let mut ltex = params.lock().unwrap();
+    let current_unroll = ltex.current_unroll;
+    assert!(current_unroll > 0
+            , "Cannot call rtrl without at least 1 forward pass");
+
+    let x_t = ltex.inputs[current_unroll - 1].clone();
+    let batch_size = x_t.dims()[0];
+    let dtype = x_t.get_type();
+    let (w, u, b) = self.joined_params(&ltex);
+    let n = u.dims()[0];
+
+    // (re)allocate the sensitivities if required:
+    // [dh/dW, dh/dU, dh/db, dc/dW, dc/dU, dc/db] where W, U & b are the joined gate params
+    let num_sensitivities = 3;
+    if ltex.state_derivatives.len() != 2*num_sensitivities
+      || ltex.state_derivatives[0].dims()[0] != batch_size
+    {
+      let param_sizes = vec![w.elements() as u64, u.elements() as u64, b.elements() as u64];
+      ltex.state_derivatives = param_sizes.iter().chain(param_sizes.iter()).map(|k| {
+        utils::constant(Dim4::new(&[batch_size, n, *k, 1]), dtype, 0.0f32)
+      }).collect();
     }
 
-    if self.return_sequences {
-      Input { data: af::join_many(1, vec![&h_t, &c_t]).unwrap() // join on col
-              , activation: self.outer_activation }
-    }else {
-      Input { data: h_t.clone()
-              , activation: self.outer_activation }
+    let inner_activation = ltex.activations[0].clone();
+    let outer_activation = ltex.activations[1].clone();
+    let gates = ltex.optional[current_unroll - 1].clone();
+    let i_t  = gate(&gates, LSTMIndex::Input);
+    let f_t  = gate(&gates, LSTMIndex::Forget);
+    let o_t  = gate(&gates, LSTMIndex::Output);
+    let ct_t = gate(&gates, LSTMIndex::CellTilda);
+    let (h_tm1, c_tm1) = split_state(&ltex.recurrences[current_unroll - 1]);
+    let (_, c_t) = split_state(&ltex.recurrences[current_unroll]);
+    let tc_t = activations::get_activation(&outer_activation, &c_t).unwrap();
+    let dtc_t = activations::get_derivative(&outer_activation, &tc_t).unwrap();
+
+    let di_dz = activations::get_derivative(&inner_activation, &i_t).unwrap();
+    let df_dz = activations::get_derivative(&inner_activation, &f_t).unwrap();
+    let do_dz = activations::get_derivative(&inner_activation, &o_t).unwrap();
+    let dct_dz = activations::get_derivative(&outer_activation, &ct_t).unwrap();
+
+    let direct = vec![layer::linear_sensitivity(&x_t, 4*n)
+                      , layer::linear_sensitivity(&h_tm1, 4*n)
+                      , layer::bias_sensitivity(batch_size, 4*n, dtype)];
+    for (k, d) in direct.iter().enumerate() {
+      // dz/dp = d(xW + h_{t-1}U + b)/dp + dh_{t-1}/dp * U
+      let dz = af::add(d, &layer::sensitivity_matmul(&ltex.state_derivatives[k], &u), false);
+      let block = |index: LSTMIndex| {
+        let offset = index as u64 * n;
+        af::cols(&dz, offset, offset + n - 1)
+      };
+      let di = layer::scale_sensitivity(&di_dz, &block(LSTMIndex::Input));
+      let df = layer::scale_sensitivity(&df_dz, &block(LSTMIndex::Forget));
+      let d_o = layer::scale_sensitivity(&do_dz, &block(LSTMIndex::Output));
+      let dct = layer::scale_sensitivity(&dct_dz, &block(LSTMIndex::CellTilda));
+
+      // dc_t/dp = di .* ct_t + i_t .* dct + df .* c_{t-1} + f_t .* dc_{t-1}/dp
+      // dh_t/dp = do .* outer_activation(c_t) + o_t .* outer_activation'(c_t) .* dc_t/dp
+      let dc = af::add(&af::add(&layer::scale_sensitivity(&ct_t, &di)
+                                , &layer::scale_sensitivity(&i_t, &dct), false)
+                       , &af::add(&layer::scale_sensitivity(&c_tm1, &df)
+                                  , &layer::scale_sensitivity(&f_t, &ltex.state_derivatives[k + num_sensitivities])
+                                  , false)
+                       , false);
+      let dh = af::add(&layer::scale_sensitivity(&tc_t, &d_o)
+                       , &layer::scale_sensitivity(&af::mul(&o_t, &dtc_t, false), &dc)
+                       , false);
+      ltex.state_derivatives[k] = dh;
+      ltex.state_derivatives[k + num_sensitivities] = dc;
     }
+
+    // dL/dp = sum_{batch} delta_t . dh_t/dp
+    let dw = layer::sensitivity_grad(delta, &ltex.state_derivatives[0], w.dims());
+    let du = layer::sensitivity_grad(delta, &ltex.state_derivatives[1], u.dims());
+    let db = layer::sensitivity_grad(delta, &ltex.state_derivatives[2], b.dims());
+    self.accumulate_deltas(&mut ltex, &dw, &du, &db);
+
+    // update location in vector
+    ltex.current_unroll -= 1;
+
+    // instantaneous delta_{t-1} = dz * W^T
+    let dc_t = af::mul(&af::mul(delta, &o_t, false), &dtc_t, false);
+    let dz = af::join_many(1, vec![&af::mul(&af::mul(&dc_t, &ct_t, false), &di_dz, false)
+                                   , &af::mul(&af::mul(&dc_t, &c_tm1, false), &df_dz, false)
+                                   , &af::mul(&af::mul(delta, &tc_t, false), &do_dz, false)
+                                   , &af::mul(&af::mul(&dc_t, &i_t, false), &dct_dz, false)]);
+    af::matmul(&dz, &w, MatProp::NONE, MatProp::TRANS)
   }
+}
 
-  fn backward(&self, params: &mut Params, delta: &Array) -> Array {
-    let inner_activation = params.activations[0];
-    let outer_activation = params.activations[1];
-    let o_t = params.recurrences[LSTMIndex::Output].last().unwrap();
-    let c_t = params.recurrences[LSTMIndex::Cell].last().unwrap();
-
-    // delta_t     = (transpose(W_{t+1}) * d_{l+1}) .* dActivation(z)
-    // delta_{t-1} = (transpose(W_{t}) * d_{l})
-    params.deltas = vec![af::mul(delta, &activations::get_derivative(&params.activations[0]
-                                                                                , &params.outputs[0].data).unwrap(), false).unwrap()];
-
-    //TODO: redundant?
-    // d_h = inner_activation'(z_o)  * outer_activation(c_t) * delta
-    let d_h = af::mul(&af::mul(&activations::get_derivative(inner_activation, &o_t).unwrap()
-                               , &activations::get_activation(outer_activation, &c_t).unwrap()).unwrap()
-                      , delta).unwrap();
-    let d_i = self.rtrl(delta, &d_h, &mut params); // input gate delta is returned
-
-    // cleanup members because this layer's backprop is done
-    params.recurrences[LSTMIndex::Cell].pop();
-    params.recurrences[LSTMIndex::CellOutput].pop();
-    params.recurrences[LSTMIndex::CellTilda].pop();
-    params.recurrences[LSTMIndex::Forget].pop();
-    params.recurrences[LSTMIndex::Input].pop();
-    params.recurrences[LSTMIndex::Output].pop();
-
-    let activation_prev = activations::get_activation(self.inputs.activation[0], &self.inputs.data[DataIndex::Input]).unwrap();
-    let d_activation_prev = activations::get_derivative(self.inputs.activation[0], &activation_prev).unwrap();
-    let delta_prev = af::mul(&af::matmul(&params.weights[0], delta, af::MatProp::TRANS, af::MatProp::NONE).unwrap()
-                             , &d_activation_prev, false).unwrap();
-    delta_prev
+/// Config of an LSTM layer
+#[derive(Clone, Debug, PartialEq)]
+pub struct LSTMConfig {
+  pub input_size: usize,
+  pub output_size: usize,
+  pub inner_activation: String,
+  pub outer_activation: String,
+  pub w_init: String,
+  pub w_recurrent_init: String,
+  pub forget_b_init: String,
+  pub b_init: String,
+  pub return_sequences: bool,
+}
+
+impl LSTMConfig {
+  pub fn new(input_size: usize, output_size: usize) -> LSTMConfig {
+    LSTMConfig {
+      input_size: input_size,
+      output_size: output_size,
+      inner_activation: "sigmoid".to_string(),
+      outer_activation: "tanh".to_string(),
+      w_init: "glorot_uniform".to_string(),
+      w_recurrent_init: "glorot_uniform".to_string(),
+      forget_b_init: "ones".to_string(),
+      b_init: "zeros".to_string(),
+      return_sequences: true,
+    }
+  }
+
+  /// Builds the config from the string params accepted by `Model::add`
+  pub fn from_params(params: &HashMap<&str, String>) -> Result<LSTMConfig, HALError> {
+    let p = Parser::new("lstm", params);
+    try!(p.check_keys(&["input_size", "output_size", "inner_activation", "outer_activation"
+                        , "w_init", "w_recurrent_init", "forget_b_init", "b_init", "return_sequences"]));
+    let mut config = LSTMConfig::new(try!(p.required("input_size"))
+                                     , try!(p.required("output_size")));
+    config.inner_activation = p.string("inner_activation", config.inner_activation);
+    config.outer_activation = p.string("outer_activation", config.outer_activation);
+    config.w_init = p.string("w_init", config.w_init);
+    config.w_recurrent_init = p.string("w_recurrent_init", config.w_recurrent_init);
+    config.forget_b_init = p.string("forget_b_init", config.forget_b_init);
+    config.b_init = p.string("b_init", config.b_init);
+    config.return_sequences = try!(p.optional("return_sequences", config.return_sequences));
+    Ok(config)
+  }
+}
+
+impl LayerConfig for LSTMConfig {
+  fn layer_type(&self) -> &'static str { "lstm" }
+  fn input_size(&self) -> usize { self.input_size }
+  fn output_size(&self) -> usize { self.output_size }
+  fn return_sequences(&self) -> bool { self.return_sequences }
+
+  fn validate(&self) -> Result<(), HALError> {
+    let layer = self.layer_type();
+    try!(check_size(layer, "input_size", self.input_size));
+    try!(check_size(layer, "output_size", self.output_size));
+    try!(check_activation(layer, "inner_activation", &self.inner_activation));
+    try!(check_activation(layer, "outer_activation", &self.outer_activation));
+    try!(check_initialization(layer, "w_init", &self.w_init));
+    try!(check_initialization(layer, "w_recurrent_init", &self.w_recurrent_init));
+    try!(check_initialization(layer, "forget_b_init", &self.forget_b_init));
+    check_initialization(layer, "b_init", &self.b_init)
+  }
+
+  fn to_params(&self) -> HashMap<String, String> {
+    to_map(vec![("input_size", self.input_size.to_string())
+                , ("output_size", self.output_size.to_string())
+                , ("inner_activation", self.inner_activation.clone())
+                , ("outer_activation", self.outer_activation.clone())
+                , ("w_init", self.w_init.clone())
+                , ("w_recurrent_init", self.w_recurrent_init.clone())
+                , ("forget_b_init", self.forget_b_init.clone())
+                , ("b_init", self.b_init.clone())
+                , ("return_sequences", self.return_sequences.to_string())])
+  }
+
+  fn build<T: HasAfEnum>(&self, param_manager: &mut ParamManager
+                         , manager: DeviceManager, device: Device) -> Box<Layer>
+  {
+    param_manager.add_lstm::<T>(manager, device
+                                , self.input_size, self.output_size
+                                , &self.inner_activation
+                                , &self.outer_activation
+                                , &self.w_init
+                                , &self.w_recurrent_init
+                                , &self.forget_b_init
+                                , &self.b_init);
+    Box::new(LSTM{input_size: self.input_size
+                  , output_size: self.output_size
+                  , return_sequences: self.return_sequences})
   }
 }
@@ -1,36 +1,139 @@
-pub use self::dense::Dense;
+pub use self::dense::{Dense, DenseConfig};
 mod dense;
-pub use self::unitary::Unitary;
+pub use self::unitary::{Unitary, UnitaryConfig};
 mod unitary;
 
-pub use self::rnn::RNN;
+pub use self::rnn::{RNN, RNNConfig};
 mod rnn;
 
-// pub use self::lstm::LSTM;
-// mod lstm;
+pub use self::lstm::{LSTM, LSTMConfig};
+mod lstm;
+
+pub use self::gru::{GRU, GRUConfig};
+mod gru;
+
+pub use self::conv2d::{Conv2D, Conv2DConfig};
+mod conv2d;
+
+pub use self::pooling::{MaxPool2D, AvgPool2D, GlobalAvgPool2D
+                        , PoolType, Pool2DConfig, GlobalAvgPool2DConfig};
+mod pooling;
+
+pub use self::dropout::{Dropout, DropoutConfig};
+mod dropout;
+
+pub use self::normalization::{BatchNorm, LayerNorm, BatchNormConfig, LayerNormConfig};
+mod normalization;
+
+pub use self::embedding::{Embedding, EmbeddingConfig};
+mod embedding;
+
+pub use self::bidirectional::{Bidirectional, BidirectionalMerge, BidirectionalConfig};
+mod bidirectional;
+
+pub use self::attention::{Attention, AttentionScore, AttentionConfig};
+mod attention;
+
+pub use self::transformer::{TransformerEncoder, TransformerEncoderConfig, positional_encoding};
+mod transformer;
+
+pub use self::ntm::{NTM, NTMController, NTMConfig};
+mod ntm;
+
+pub use self::act::{ACT, ACTConfig};
+mod act;
+
+pub use self::full_unitary::{FullUnitary, FullUnitaryConfig};
+mod full_unitary;
+
+pub use self::config::LayerConfig;
+mod config;
 
 use af;
-use af::{Array, MatProp};
+use af::{Array, Dim4, DType, MatProp, Indexer};
 use params::Params;
+use error::HALError;
 use std::sync::{Arc, Mutex};
 
+use utils;
 use activations;
 
 pub trait Layer {
   fn forward(&self, params: Arc<Mutex<Params>>, inputs: &Array, state: Option<&Vec<Array>>) -> (Array, Option<Vec<Array>>);
   fn backward(&self, params: Arc<Mutex<Params>>, delta: &Array) -> Array;
+
+  /// Runs the forward pass over a whole sequence (one array per timestep)
+  ///
+  /// Layers that need to see the whole sequence (eg: `Bidirectional`) override this.
+  /// Only the last output is returned if the layer does not return sequences
+  fn forward_sequence(&self, params: Arc<Mutex<Params>>, inputs: &[Array]) -> Vec<Array> {
+    let outputs = inputs.iter().map(|x| self.forward(params.clone(), x, None).0).collect();
+    sequence_outputs(outputs, self.return_sequences())
+  }
+
+  /// Runs the backward pass over the deltas of a whole sequence (in time order)
+  ///
+  /// Returns the deltas of the inputs (in time order). A layer that does not
+  /// return sequences gets the single delta of its last output
+  fn backward_sequence(&self, params: Arc<Mutex<Params>>, deltas: &[Array]) -> Vec<Array> {
+    let deltas = match self.return_sequences() {
+      true  => deltas.to_vec(),
+      false => last_step_deltas(deltas, params.lock().unwrap().current_unroll),
+    };
+    let mut dx: Vec<Array> = deltas.iter().rev().map(|d| self.backward(params.clone(), d)).collect();
+    dx.reverse();
+    dx
+  }
+
+  /// Whether the layer returns the outputs of every timestep or only the last one
+  ///
+  /// Only the last output of a layer that does not return sequences flows to
+  /// the next layer (& the loss), eg: for sequence classifiers
+  fn return_sequences(&self) -> bool {
+    true
+  }
+
+  /// Whether the output of a timestep depends on the other timesteps
+  ///
+  /// True for the recurrent cells & the layers that mix the whole sequence (eg: `Attention`),
+  /// such layers need to implement `RTRL` to be trained one timestep at a time
+  fn is_recurrent(&self) -> bool {
+    false
+  }
+
+  /// Validates the model inputs of a timestep before they are fed to the layer
+  ///
+  /// Only the layers that are fed the model inputs directly are checked
+  /// (eg: the token ids of `Embedding`), the models call this when fitting
+  fn check_inputs(&self, _inputs: &Array) -> Result<(), HALError> {
+    Ok(())
+  }
+
+  /// Returns the auxiliary loss of every timestep of the last forward pass (if it has one)
+  ///
+  /// The model adds it to the loss it reports (eg: the ponder cost of `ACT`),
+  /// the layer adds its gradient in `backward_sequence`
+  fn auxiliary_loss(&self, _params: Arc<Mutex<Params>>) -> Option<Vec<f32>> {
+    None
+  }
+
+  /// Returns the RTRL implementation of the layer (if it has one)
+  fn as_rtrl(&self) -> Option<&RTRL> {
+    None
+  }
 }
 
 pub trait RecurrentLayer {
   fn state_size(self) -> usize;
 }
 
+/// Real-time recurrent learning
+///
+/// Consumes the last forward step (like `Layer::backward`), but instead of
+/// waiting for future state derivatives it carries the sensitivities of the
+/// state w.r.t. every parameter in `Params::state_derivatives`. This allows the
+/// gradients to be computed (and applied) at every timestep with constant memory.
 pub trait RTRL{
-  // fn rtrl(&self, dW_tm1: &mut Array  // previous W derivatives for [I, F, Ct]
-  //         , dU_tm1: &mut Array   // previous U derivatives for [I, F, Ct]
-  //         , db_tm1: &mut Array   // previous b derivatives for [I, F, Ct]
-  //         , z_t: &Array          // current time activation
-  //         , inputs: &Array);     // x_t & h_{t-1}
   fn rtrl(&self, params: Arc<Mutex<Params>>, delta: &Array) -> Array;
 }
 
@@ -69,3 +172,154 @@ pub fn linear_backward(delta: &Array, input: &Array
   let db = af::transpose(&af::sum(&delta_t, 0), false); // delta_b = sum_{batch}delta
   return (delta_t.clone(), dw.clone(), db.clone())
 }
+
+
+/// Helper that layer normalizes every row of z: gain * (z - mean) / sqrt(var + epsilon) + bias
+///
+/// The statistics are taken over the features of every sample; gain & bias are [1, features]
+pub fn layer_norm(z: &Array, gain: &Array, bias: &Array, epsilon: f32) -> Array
+{
+  let (zhat, _) = layer_norm_moments(z, epsilon);
+  af::add(&af::mul(&zhat, gain, true), bias, true)
+}
+
+/// Helper that computes the backward operation of `layer_norm` and returns dz, dgain, dbias
+pub fn layer_norm_backward(delta: &Array, z: &Array, gain: &Array, epsilon: f32) -> (Array, Array, Array)
+{
+  // dz = (dzhat - mean(dzhat) - zhat .* mean(dzhat .* zhat)) / std [means over the features]
+  let (zhat, std) = layer_norm_moments(z, epsilon);
+  let dzhat = af::mul(delta, gain, true);
+  let centered = af::sub(&dzhat, &af::mean(&dzhat, 1), true);
+  let projection = af::mul(&zhat, &af::mean(&af::mul(&dzhat, &zhat, false), 1), true);
+  let dz = af::div(&af::sub(&centered, &projection, false), &std, true);
+  let dgain = af::sum(&af::mul(delta, &zhat, false), 0);
+  let dbias = af::sum(delta, 0);
+  (dz, dgain, dbias)
+}
+
+/// Helper that returns the normalized z and the [batch, 1] standard deviations used
+fn layer_norm_moments(z: &Array, epsilon: f32) -> (Array, Array)
+{
+  let centered = af::sub(z, &af::mean(z, 1), true);
+  let var = af::mean(&af::mul(&centered, &centered, false), 1);
+  let std = af::sqrt(&af::add(&var, &epsilon, false));
+  (af::div(&centered, &std, true), std)
+}
+
+/// Helper that returns the direct sensitivity of xP w.r.t. P
+///
+/// The result is of [batch, output_size, rows(P) * output_size] where the
+/// last dimension is the column major flattened index of P
+pub fn linear_sensitivity(input: &Array, output_size: u64) -> Array
+{
+  let idims = input.dims();
+  let (batch_size, input_size) = (idims[0], idims[1]);
+
+  // d(xP)_{b,j} / dP_{a,c} = x_{b,a} * I_{j,c}
+  let x = af::tile(&af::moddims(input, Dim4::new(&[batch_size, 1, input_size, 1]))
+                   , Dim4::new(&[1, output_size, 1, output_size]));
+  let eye = af::tile(&af::moddims(&identity(output_size, input.get_type())
+                                  , Dim4::new(&[1, output_size, 1, output_size]))
+                     , Dim4::new(&[batch_size, 1, input_size, 1]));
+  af::moddims(&af::mul(&x, &eye, false)
+              , Dim4::new(&[batch_size, output_size, input_size * output_size, 1]))
+}
+
+/// Helper that returns the direct sensitivity of x + b w.r.t. b
+///
+/// The result is of [batch, output_size, output_size]
+pub fn bias_sensitivity(batch_size: u64, output_size: u64, dtype: DType) -> Array
+{
+  af::tile(&af::moddims(&identity(output_size, dtype)
+                        , Dim4::new(&[1, output_size, output_size, 1]))
+           , Dim4::new(&[batch_size, 1, 1, 1]))
+}
+
+/// Helper that computes S * P for every parameter slice of a sensitivity S
+///
+/// S is of [batch, n, K] and P of [n, m]; the result is of [batch, m, K]
+pub fn sensitivity_matmul(sensitivity: &Array, weight: &Array) -> Array
+{
+  let sdims = sensitivity.dims();
+  let m = weight.dims()[1];
+  let flat = af::moddims(&af::reorder(sensitivity, Dim4::new(&[0, 2, 1, 3]))
+                         , Dim4::new(&[sdims[0] * sdims[2], sdims[1], 1, 1]));
+  let prod = af::matmul(&flat, weight, MatProp::NONE, MatProp::NONE);
+  af::reorder(&af::moddims(&prod, Dim4::new(&[sdims[0], sdims[2], m, 1]))
+              , Dim4::new(&[0, 2, 1, 3]))
+}
+
+/// Helper that elementwise scales every parameter slice of a sensitivity
+///
+/// `scale` is of [batch, n] and the sensitivity of [batch, n, K]
+pub fn scale_sensitivity(scale: &Array, sensitivity: &Array) -> Array
+{
+  let num_params = sensitivity.dims()[2];
+  af::mul(&af::tile(scale, Dim4::new(&[1, 1, num_params, 1])), sensitivity, false)
+}
+
+/// Helper that reduces a sensitivity to the gradient of a parameter
+///
+/// dL/dP = sum_{batch, n} delta .* S reshaped to the parameter dims
+pub fn sensitivity_grad(delta: &Array, sensitivity: &Array, param_dims: Dim4) -> Array
+{
+  let grad = af::sum(&af::sum(&scale_sensitivity(delta, sensitivity), 0), 1);
+  af::moddims(&grad, param_dims)
+}
+
+/// Helper that gathers the input features read by a sliding window
+///
+/// `indices` holds the feature read by every tap (see `layer::MaxPool2D`).
+/// The result is of [batch, taps]
+pub fn unfold_patches(input: &Array, indices: &Array) -> Array
+{
+  af::lookup(input, indices, 1)
+}
+
+/// Helper that sums the gradients of the taps back onto the input features
+///
+/// This is the transpose of `unfold_patches`: the taps are sorted by the feature
+/// they read, the runs of equal features are summed & scattered onto dx
+pub fn fold_patches(dpatches: &Array, indices: &Array, input_size: u64) -> Array
+{
+  let batch_size = dpatches.dims()[0];
+  let num_taps = indices.elements() as u64;
+  let (features, order) = af::sort_index(&af::moddims(indices, Dim4::new(&[num_taps, 1, 1, 1])), 0, true);
+  let sorted = af::transpose(&af::lookup(dpatches, &order, 1), false);
+  let (read, sums) = af::sum_by_key(&features, &sorted, 0);
+
+  let mut rows = Indexer::new();
+  rows.set_index(&read, 0, None);
+  let dx = af::assign_gen(&utils::constant(Dim4::new(&[input_size, batch_size, 1, 1])
+                                           , dpatches.get_type(), 0.0f32)
+                          , &rows, &sums);
+  af::transpose(&dx, false)
+}
+
+/// Helper that keeps the outputs a layer returns: every timestep or only the last one
+fn sequence_outputs(outputs: Vec<Array>, return_sequences: bool) -> Vec<Array>
+{
+  match return_sequences {
+    true  => outputs,
+    false => outputs.last().into_iter().cloned().collect(),
+  }
+}
+
+/// Helper that spreads the delta of the last output of a layer over the `seq_len` timesteps
+///
+/// The earlier timesteps of a layer that does not return sequences get a zero delta
+fn last_step_deltas(deltas: &[Array], seq_len: usize) -> Vec<Array>
+{
+  assert!(deltas.len() == 1 && seq_len > 0
+          , "a layer returning its last output expects a single delta after a forward pass");
+  let zeros = utils::constant(deltas[0].dims(), deltas[0].get_type(), 0.0f32);
+  let mut spread = vec![zeros; seq_len - 1];
+  spread.push(deltas[0].clone());
+  spread
+}
+
+/// Helper to build an [n x n] identity of the provided type
+fn identity(n: u64, dtype: DType) -> Array
+{
+  af::diag_create(&utils::constant(Dim4::new(&[n, 1, 1, 1]), dtype, 1.0f32), 0)
+}
@@ -0,0 +1,305 @@
+use af;
+use af::{Array, HasAfEnum};
+use std::sync::{Arc, Mutex};
+use std::collections::HashMap;
+
+use layer;
+use layer::{Layer};
+use layer::config::{LayerConfig, Parser, check_size, check_epsilon, check_initialization, to_map};
+use error::HALError;
+use device::{Device, DeviceManager};
+use params::{Params, ParamManager, NormGenerator};
+
+/// Batch normalization: gamma * (x - mean) / sqrt(var + epsilon) + beta
+///
+/// In training mode (see `Params::training`) the mean & (biased) variance of
+/// every feature are taken over the batch and folded into the running
+/// statistics as running = momentum * running + (1 - momentum) * batch.
+/// In inference mode the running statistics are used instead.
+///
+/// gamma & beta are `Params::weights[0]` & `Params::biases[0]` while the running
+/// mean & variance are kept in `Params::optional[0]` & `Params::optional[1]`.
+pub struct BatchNorm {
+  pub size: usize,
+  pub momentum: f32,
+  pub epsilon: f32,
+}
+
+/// Layer normalization: gain * (x - mean) / sqrt(var + epsilon) + bias
+///
+/// Unlike `BatchNorm` the mean & variance are taken over the features of every
+/// sample, thus the layer behaves the same while training and at inference.
+/// gain & bias are `Params::weights[0]` & `Params::biases[0]`.
+pub struct LayerNorm {
+  pub size: usize,
+  pub epsilon: f32,
+}
+
+/// Helper that returns the mean & biased variance of every feature over the batch
+fn batch_moments(inputs: &Array) -> (Array, Array)
+{
+  let mean = af::mean(inputs, 0);
+  let centered = af::sub(inputs, &mean, true);
+  let var = af::mean(&af::mul(&centered, &centered, false), 0);
+  (mean, var)
+}
+
+/// Helper that returns the normalized input and the standard deviation used
+fn normalize(inputs: &Array, mean: &Array, var: &Array, epsilon: f32) -> (Array, Array)
+{
+  let std = af::sqrt(&af::add(var, &epsilon, false));
+  (af::div(&af::sub(inputs, mean, true), &std, true), std)
+}
+
+impl BatchNorm {
+  // the statistics that normalize the inputs in the current mode
+  fn moments(&self, ltex: &Params, inputs: &Array) -> (Array, Array) {
+    match ltex.training {
+      true  => batch_moments(inputs),
+      false => (ltex.optional[0].clone(), ltex.optional[1].clone()),
+    }
+  }
+}
+
+impl Layer for BatchNorm
+{
+  fn forward(&self, params: Arc<Mutex<Params>>, inputs: &Array, state: Option<&Vec<Array>>) -> (Array, Option<Vec<Array>>)
+  {
+    // get a handle to the underlying params
+    let mut ltex = params.lock().unwrap();
+    let (mean, var) = self.moments(&ltex, inputs);
+    if ltex.training {
+      ltex.optional[0] = af::add(&af::mul(&ltex.optional[0], &self.momentum, false)
+                                 , &af::mul(&mean, &(1.0 - self.momentum), false), false);
+      ltex.optional[1] = af::add(&af::mul(&ltex.optional[1], &self.momentum, false)
+                                 , &af::mul(&var, &(1.0 - self.momentum), false), false);
+    }
+
+    let (xhat, _) = normalize(inputs, &mean, &var, self.epsilon);
+    let a_t = af::add(&af::mul(&xhat, &ltex.weights[0], true), &ltex.biases[0], true);
+
+    // parameter manager keeps the output & inputs
+    let current_unroll = ltex.current_unroll;
+    if ltex.inputs.len() > current_unroll { // store in existing
+      ltex.inputs[current_unroll] = inputs.clone();
+      ltex.outputs[current_unroll] = a_t.clone();
+    }else{                                  // add new
+      ltex.inputs.push(inputs.clone());
+      ltex.outputs.push(a_t.clone());
+    }
+
+    // update location in vector
+    ltex.current_unroll += 1;
+
+    (a_t.clone(), None)
+  }
+
+  fn backward(&self, params: Arc<Mutex<Params>>, delta: &Array) -> Array
+  {
+    // get a handle to the underlying params
+    let mut ltex = params.lock().unwrap();
+    let current_unroll = ltex.current_unroll;
+    assert!(current_unroll > 0
+            , "Cannot call backward pass without at least 1 forward pass");
+
+    // the batch statistics are recomputed from the stored input
+    let inputs = ltex.inputs[current_unroll - 1].clone();
+    let (mean, var) = self.moments(&ltex, &inputs);
+    let (xhat, std) = normalize(&inputs, &mean, &var, self.epsilon);
+
+    // dgamma = sum_{batch} delta .* xhat, dbeta = sum_{batch} delta
+    let dgamma = af::sum(&af::mul(delta, &xhat, false), 0);
+    let dbeta = af::sum(delta, 0);
+    ltex.deltas[0] = af::add(&ltex.deltas[0], &dgamma, false);
+    ltex.deltas[1] = af::add(&ltex.deltas[1], &dbeta, false);
+
+    let dxhat = af::mul(delta, &ltex.weights[0], true);
+    ltex.current_unroll -= 1;
+
+    match ltex.training {
+      // the batch statistics depend on every input of the batch:
+      // dx = (dxhat - mean(dxhat) - xhat .* mean(dxhat .* xhat)) / std
+      true  => {
+        let centered = af::sub(&dxhat, &af::mean(&dxhat, 0), true);
+        let projection = af::mul(&xhat, &af::mean(&af::mul(&dxhat, &xhat, false), 0), true);
+        af::div(&af::sub(&centered, &projection, false), &std, true)
+      },
+      false => af::div(&dxhat, &std, true),
+    }
+  }
+}
+
+impl Layer for LayerNorm
+{
+  fn forward(&self, params: Arc<Mutex<Params>>, inputs: &Array, state: Option<&Vec<Array>>) -> (Array, Option<Vec<Array>>)
+  {
+    // get a handle to the underlying params
+    let mut ltex = params.lock().unwrap();
+    let a_t = layer::layer_norm(inputs, &ltex.weights[0], &ltex.biases[0], self.epsilon);
+
+    // parameter manager keeps the output & inputs
+    let current_unroll = ltex.current_unroll;
+    if ltex.inputs.len() > current_unroll { // store in existing
+      ltex.inputs[current_unroll] = inputs.clone();
+      ltex.outputs[current_unroll] = a_t.clone();
+    }else{                                  // add new
+      ltex.inputs.push(inputs.clone());
+      ltex.outputs.push(a_t.clone());
+    }
+
+    // update location in vector
+    ltex.current_unroll += 1;
+
+    (a_t.clone(), None)
+  }
+
+  fn backward(&self, params: Arc<Mutex<Params>>, delta: &Array) -> Array
+  {
+    // get a handle to the underlying params
+    let mut ltex = params.lock().unwrap();
+    let current_unroll = ltex.current_unroll;
+    assert!(current_unroll > 0
+            , "Cannot call backward pass without at least 1 forward pass");
+
+    let (dx, dgain, dbias) = layer::layer_norm_backward(delta, &ltex.inputs[current_unroll - 1]
+                                                        , &ltex.weights[0], self.epsilon);
+    ltex.deltas[0] = af::add(&ltex.deltas[0], &dgain, false);
+    ltex.deltas[1] = af::add(&ltex.deltas[1], &dbias, false);
+
+    ltex.current_unroll -= 1;
+    dx
+  }
+}
+
+/// Config of a batch normalization layer
+#[derive(Clone, Debug, PartialEq)]
+pub struct BatchNormConfig {
+  pub size: usize,
+  pub momentum: f32, // weight of the old running statistics
+  pub epsilon: f32,  // added to the variance for numerical stability
+  pub gamma_init: String,
+  pub beta_init: String,
+}
+
+/// Config of a layer normalization layer
+#[derive(Clone, Debug, PartialEq)]
+pub struct LayerNormConfig {
+  pub size: usize,
+  pub epsilon: f32, // added to the variance for numerical stability
+  pub gain_init: String,
+  pub bias_init: String,
+}
+
+impl BatchNormConfig {
+  pub fn new(size: usize) -> BatchNormConfig {
+    BatchNormConfig {
+      size: size,
+      momentum: 0.9,
+      epsilon: 1e-5,
+      gamma_init: "ones".to_string(),
+      beta_init: "zeros".to_string(),
+    }
+  }
+
+  /// Builds the config from the string params accepted by `Model::add`
+  pub fn from_params(params: &HashMap<&str, String>) -> Result<BatchNormConfig, HALError> {
+    let p = Parser::new("batch_norm", params);
+    try!(p.check_keys(&["size", "momentum", "epsilon", "gamma_init", "beta_init"]));
+    let mut config = BatchNormConfig::new(try!(p.required("size")));
+    config.momentum = try!(p.optional("momentum", config.momentum));
+    config.epsilon = try!(p.optional("epsilon", config.epsilon));
+    config.gamma_init = p.string("gamma_init", config.gamma_init);
+    config.beta_init = p.string("beta_init", config.beta_init);
+    Ok(config)
+  }
+}
+
+impl LayerConfig for BatchNormConfig {
+  fn layer_type(&self) -> &'static str { "batch_norm" }
+  fn input_size(&self) -> usize { self.size }
+  fn output_size(&self) -> usize { self.size }
+
+  fn validate(&self) -> Result<(), HALError> {
+    let layer = self.layer_type();
+    try!(check_size(layer, "size", self.size));
+    if !(self.momentum >= 0.0 && self.momentum < 1.0) {
+      return Err(HALError::invalid_param(layer, "momentum"
+                                         , format!("needs to be in [0, 1), got {}", self.momentum)));
+    }
+    try!(check_epsilon(layer, self.epsilon));
+    try!(check_initialization(layer, "gamma_init", &self.gamma_init));
+    check_initialization(layer, "beta_init", &self.beta_init)
+  }
+
+  fn to_params(&self) -> HashMap<String, String> {
+    to_map(vec![("size", self.size.to_string())
+                , ("momentum", self.momentum.to_string())
+                , ("epsilon", self.epsilon.to_string())
+                , ("gamma_init", self.gamma_init.clone())
+                , ("beta_init", self.beta_init.clone())])
+  }
+
+  fn build<T: HasAfEnum>(&self, param_manager: &mut ParamManager
+                         , manager: DeviceManager, device: Device) -> Box<Layer>
+  {
+    param_manager.add_batch_norm::<T>(manager, device, self.size
+                                      , &self.gamma_init
+                                      , &self.beta_init);
+    Box::new(BatchNorm{size: self.size
+                       , momentum: self.momentum
+                       , epsilon: self.epsilon})
+  }
+}
+
+impl LayerNormConfig {
+  pub fn new(size: usize) -> LayerNormConfig {
+    LayerNormConfig {
+      size: size,
+      epsilon: 1e-5,
+      gain_init: "ones".to_string(),
+      bias_init: "zeros".to_string(),
+    }
+  }
+
+  /// Builds the config from the string params accepted by `Model::add`
+  pub fn from_params(params: &HashMap<&str, String>) -> Result<LayerNormConfig, HALError> {
+    let p = Parser::new("layer_norm", params);
+    try!(p.check_keys(&["size", "epsilon", "gain_init", "bias_init"]));
+    let mut config = LayerNormConfig::new(try!(p.required("size")));
+    config.epsilon = try!(p.optional("epsilon", config.epsilon));
+    config.gain_init = p.string("gain_init", config.gain_init);
+    config.bias_init = p.string("bias_init", config.bias_init);
+    Ok(config)
+  }
+}
+
+impl LayerConfig for LayerNormConfig {
+  fn layer_type(&self) -> &'static str { "layer_norm" }
+  fn input_size(&self) -> usize { self.size }
+  fn output_size(&self) -> usize { self.size }
+
+  fn validate(&self) -> Result<(), HALError> {
+    let layer = self.layer_type();
+    try!(check_size(layer, "size", self.size));
+    try!(check_epsilon(layer, self.epsilon));
+    try!(check_initialization(layer, "gain_init", &self.gain_init));
+    check_initialization(layer, "bias_init", &self.bias_init)
+  }
+
+  fn to_params(&self) -> HashMap<String, String> {
+    to_map(vec![("size", self.size.to_string())
+                , ("epsilon", self.epsilon.to_string())
+                , ("gain_init", self.gain_init.clone())
+                , ("bias_init", self.bias_init.clone())])
+  }
+
+  fn build<T: HasAfEnum>(&self, param_manager: &mut ParamManager
+                         , manager: DeviceManager, device: Device) -> Box<Layer>
+  {
+    param_manager.add_layer_norm::<T>(manager, device, self.size
+                                      , &self.gain_init
+                                      , &self.bias_init);
+    Box::new(LayerNorm{size: self.size
+                       , epsilon: self.epsilon})
+  }
+}
@@ -0,0 +1,564 @@
+use af;
+use af::{Array, Dim4, DType, MatProp, HasAfEnum};
+use std::sync::{Arc, Mutex};
+use std::collections::HashMap;
+
+use utils;
+use layer;
+use activations;
+use error::HALError;
+use device::{Device, DeviceManager};
+use params::{Params, ParamManager, NTMGenerator};
+use layer::{Layer, RecurrentLayer};
+use layer::config::{LayerConfig, Parser, check_size, check_activation, check_initialization, to_map};
+
+/// A Neural Turing Machine with one read & one write head
+///
+/// At every timestep the controller reads [x_t, r_{t-1}] (and its previous
+/// state h_{t-1} for an RNN controller):
+///
+///   h_t = tanh([x_t, r_{t-1}] W_c + h_{t-1} U_c + b_c)
+///
+/// and emits the params of both heads, p_t = h_t W_p + b_p, from which every head
+/// addresses the memory M_{t-1} [memory_size slots of memory_width]:
+///
+///   content:  w^c = softmax(beta * cosine(k, M_{t-1}))
+///   gate:     w^g = g * w^c + (1 - g) * w_{t-1}
+///   shift:    w^s = sum_j s_j * roll(w^g, j - 1), j = 0, 1, 2
+///   sharpen:  w = (w^s)^gamma / sum (w^s)^gamma
+///
+/// with k linear, beta = softplus, g = sigmoid, s = softmax & gamma = 1 + softplus.
+/// The read head returns r_t = sum_n w^r_n M_{t-1}[n], the write head then erases
+/// & adds: M_t = M_{t-1} .* (1 - w^w e^T) + w^w a^T with e = sigmoid & a = tanh.
+/// The output is o_t = outer_activation([h_t, r_t] W_o + b_o).
+///
+/// The whole state (M_t, w^r_t, w^w_t, r_t, h_t) is packed into a single
+/// [batch, state size] array in `Params::recurrences`. Without a carried over
+/// state the machine starts from an empty memory with both heads on the first slot.
+pub struct NTM {
+  pub input_size: usize,
+  pub output_size: usize,
+  pub controller: NTMController,
+  pub controller_size: usize,
+  pub memory_size: usize,     // number of memory slots
+  pub memory_width: usize,    // size of every slot
+  pub return_sequences: bool, // only the last output is returned if false
+}
+
+// indices in Params::weights, the recurrent controller weight is last
+const CONTROLLER: usize = 0;
+const HEADS: usize = 1;
+const OUTPUT: usize = 2;
+const CONTROLLER_RECURRENT: usize = 3;
+
+// indices in Params::biases
+const CONTROLLER_BIAS: usize = 0;
+const HEADS_BIAS: usize = 1;
+const OUTPUT_BIAS: usize = 2;
+
+// keeps the norms of the cosine similarity away from 0
+const NORM_EPSILON: f32 = 1e-6;
+
+/// Helper that returns the [batch, 1, slots] form of a [batch, slots] weighting
+fn to_slots(w: &Array) -> Array {
+  let dims = w.dims();
+  af::moddims(w, Dim4::new(&[dims[0], 1, dims[1], 1]))
+}
+
+/// Helper that returns the [batch, slots] form of a [batch, 1, slots] array
+fn from_slots(a: &Array) -> Array {
+  let dims = a.dims();
+  af::moddims(a, Dim4::new(&[dims[0], dims[2], 1, 1]))
+}
+
+/// Helper that returns sqrt(sum(x^2) + epsilon) over the features
+fn norm(x: &Array) -> Array {
+  af::sqrt(&af::add(&af::sum(&af::mul(x, x, false), 1), &NORM_EPSILON, false))
+}
+
+/// log(1 + exp(x)), its derivative is sigmoid(x)
+fn softplus(x: &Array) -> Array {
+  af::log(&af::add(&af::exp(x), &1.0f32, false))
+}
+
+/// Helper that circularly rolls a weighting by `offset` slots
+fn roll(w: &Array, offset: i32) -> Array {
+  af::shift(w, &[0, offset, 0, 0])
+}
+
+// the recurrent state of the machine
+struct State {
+  memory: Array,        // [batch, memory_width, memory_size]
+  read_weights: Array,  // [batch, memory_size]
+  write_weights: Array, // [batch, memory_size]
+  read: Array,          // [batch, memory_width]
+  hidden: Array,        // [batch, controller_size]
+}
+
+// the addressing of a head along with what its backward pass needs
+struct Addressing {
+  key: Array,
+  strength: Array,
+  gate: Array,
+  shift: Array,
+  sharpen: Array,
+  similarity: Array,
+  content: Array,
+  gated: Array,
+  shifted: Array,
+  powered: Array,
+  weights: Array,
+}
+
+// the intermediate values of a timestep
+struct Step {
+  controller_input: Array, // [x_t, r_{t-1}]
+  heads: Array,            // p_t (not activated)
+  reading: Addressing,
+  writing: Addressing,
+  erase: Array,
+  add: Array,
+  joined: Array,           // [h_t, r_t]
+  output: Array,
+  state: State,
+}
+
+impl RecurrentLayer for NTM {
+  fn state_size(self) -> usize {
+    let (n, m) = (self.memory_size, self.memory_width);
+    n * m + 2 * n + m + self.controller_size
+  }
+}
+
+impl NTM
+{
+  // the number of addressing params of a head: [k, beta, g, s, gamma],
+  // p_t holds those of the read & write heads followed by [e, a]
+  fn head_size(&self) -> u64 {
+    (self.memory_width + 6) as u64
+  }
+
+  fn pack(&self, state: &State) -> Array {
+    let batch_size = state.memory.dims()[0];
+    let flat = Dim4::new(&[batch_size, (self.memory_size * self.memory_width) as u64, 1, 1]);
+    af::join_many(1, vec![&af::moddims(&state.memory, flat), &state.read_weights, &state.write_weights
+                          , &state.read, &state.hidden])
+  }
+
+  fn unpack(&self, packed: &Array) -> State {
+    let (n, m, h) = (self.memory_size as u64, self.memory_width as u64, self.controller_size as u64);
+    let dims = Dim4::new(&[packed.dims()[0], m, n, 1]);
+    State {
+      memory: af::moddims(&af::cols(packed, 0, n * m - 1), dims),
+      read_weights: af::cols(packed, n * m, n * m + n - 1),
+      write_weights: af::cols(packed, n * m + n, n * m + 2 * n - 1),
+      read: af::cols(packed, n * m + 2 * n, n * m + 2 * n + m - 1),
+      hidden: af::cols(packed, n * m + 2 * n + m, n * m + 2 * n + m + h - 1),
+    }
+  }
+
+  // an empty memory with both heads focused on the first slot
+  fn initial_state(&self, batch_size: u64, dtype: DType) -> Array {
+    let (n, m) = (self.memory_size as u64, self.memory_width as u64);
+    let zeros = |cols: u64| utils::constant(Dim4::new(&[batch_size, cols, 1, 1]), dtype, 0.0f32);
+    let focused = match n {
+      1 => utils::constant(Dim4::new(&[batch_size, 1, 1, 1]), dtype, 1.0f32),
+      _ => af::join(1, &utils::constant(Dim4::new(&[batch_size, 1, 1, 1]), dtype, 1.0f32), &zeros(n - 1)),
+    };
+    af::join_many(1, vec![&zeros(n * m), &focused, &focused, &zeros(m), &zeros(self.controller_size as u64)])
+  }
+
+  // addresses the memory with the head params starting at column `offset` of p_t
+  fn address(&self, memory: &Array, w_tm1: &Array, heads: &Array, offset: u64) -> Addressing {
+    let m = self.memory_width as u64;
+    let key = af::cols(heads, offset, offset + m - 1);
+    let strength = softplus(&af::col(heads, offset + m));
+    let gate = activations::sigmoid(&af::col(heads, offset + m + 1));
+    let shift = activations::softmax(&af::cols(heads, offset + m + 2, offset + m + 4));
+    let sharpen = af::add(&softplus(&af::col(heads, offset + m + 5)), &1.0f32, false);
+
+    let dot = from_slots(&af::sum(&af::mul(memory, &key, true), 1));
+    let similarity = af::div(&dot, &af::mul(&norm(&key), &from_slots(&norm(memory)), true), false);
+    let content = activations::softmax(&af::mul(&similarity, &strength, true));
+    let gated = af::add(&af::mul(&content, &gate, true)
+                        , &af::mul(w_tm1, &af::sub(&1.0f32, &gate, false), true), false);
+    let shifted = (0..3).map(|j| af::mul(&roll(&gated, j as i32 - 1), &af::col(&shift, j), true))
+      .fold(utils::constant(gated.dims(), gated.get_type(), 0.0f32), |acc, s| af::add(&acc, &s, false));
+    let powered = af::exp(&af::mul(&af::log(&shifted), &sharpen, true));
+    let weights = af::div(&powered, &af::sum(&powered, 1), true);
+
+    Addressing { key: key, strength: strength, gate: gate, shift: shift, sharpen: sharpen
+                 , similarity: similarity, content: content, gated: gated, shifted: shifted
+                 , powered: powered, weights: weights }
+  }
+
+  // backpropagates dw through the addressing of a head,
+  // returns the deltas of its (not activated) params, of w_{t-1} & of the memory
+  fn address_backward(&self, a: &Addressing, memory: &Array, w_tm1: &Array
+                      , heads: &Array, offset: u64, dweights: &Array) -> (Array, Array, Array)
+  {
+    let m = self.memory_width as u64;
+
+    // sharpen: w = u / sum(u), u = (w^s)^gamma
+    let dpowered = af::div(&af::sub(dweights, &af::sum(&af::mul(dweights, &a.weights, false), 1), true)
+                           , &af::sum(&a.powered, 1), true);
+    let dpowered_u = af::mul(&dpowered, &a.powered, false);
+    let dsharpen = af::sum(&af::mul(&dpowered_u, &af::log(&a.shifted), false), 1);
+    let dshifted = af::mul(&af::div(&dpowered_u, &a.shifted, false), &a.sharpen, true);
+
+    // shift: w^s = sum_j s_j * roll(w^g, j - 1)
+    let dshifts = (0..3).map(|j| af::sum(&af::mul(&dshifted, &roll(&a.gated, j as i32 - 1), false), 1))
+      .collect::<Vec<Array>>();
+    let dshift = af::join_many(1, dshifts.iter().collect());
+    let dgated = (0..3).map(|j| af::mul(&roll(&dshifted, 1 - j as i32), &af::col(&a.shift, j), true))
+      .fold(utils::constant(dshifted.dims(), dshifted.get_type(), 0.0f32), |acc, d| af::add(&acc, &d, false));
+
+    // gate: w^g = g * w^c + (1 - g) * w_{t-1}
+    let dgate = af::sum(&af::mul(&dgated, &af::sub(&a.content, w_tm1, false), false), 1);
+    let dcontent = af::mul(&dgated, &a.gate, true);
+    let dw_tm1 = af::mul(&dgated, &af::sub(&1.0f32, &a.gate, false), true);
+
+    // content: w^c = softmax(beta * K)
+    let dz = af::mul(&a.content, &af::sub(&dcontent, &af::sum(&af::mul(&a.content, &dcontent, false), 1), true), false);
+    let dstrength = af::sum(&af::mul(&dz, &a.similarity, false), 1);
+    let dsimilarity = af::mul(&dz, &a.strength, true);
+
+    // cosine: K_n = k . M_n / (|k| |M_n|)
+    // dk   = sum_n dK_n / (|k| |M_n|) M_n - k sum_n dK_n K_n / |k|^2
+    // dM_n = dK_n / (|k| |M_n|) k - M_n dK_n K_n / |M_n|^2
+    let (key_norm, memory_norm) = (norm(&a.key), from_slots(&norm(memory)));
+    let scaled = af::div(&dsimilarity, &af::mul(&key_norm, &memory_norm, true), false);
+    let projected = af::mul(&dsimilarity, &a.similarity, false);
+    let dkey = af::sub(&af::sum(&af::mul(memory, &to_slots(&scaled), true), 2)
+                       , &af::mul(&a.key, &af::div(&af::sum(&projected, 1), &af::mul(&key_norm, &key_norm, false), false), true)
+                       , false);
+    let dmemory = af::sub(&af::mul(&to_slots(&scaled), &a.key, true)
+                          , &af::mul(memory, &to_slots(&af::div(&projected, &af::mul(&memory_norm, &memory_norm, false), false)), true)
+                          , false);
+
+    // through the activations of the head params
+    let dheads = af::join_many(1, vec![&dkey
+                                       , &af::mul(&dstrength, &activations::sigmoid(&af::col(heads, offset + m)), false)
+                                       , &af::mul(&dgate, &activations::sigmoid_derivative(&a.gate), false)
+                                       , &af::mul(&a.shift, &af::sub(&dshift, &af::sum(&af::mul(&a.shift, &dshift, false), 1), true), false)
+                                       , &af::mul(&dsharpen, &activations::sigmoid(&af::col(heads, offset + m + 5)), false)]);
+    (dheads, dw_tm1, dmemory)
+  }
+
+  // runs a timestep from the previous state
+  fn step(&self, ltex: &Params, inputs: &Array, prev: &State) -> Step {
+    // controller
+    let controller_input = af::join(1, inputs, &prev.read);
+    let z = af::transpose(&af::add(&af::transpose(&af::matmul(&controller_input, &ltex.weights[CONTROLLER]
+                                                              , MatProp::NONE, MatProp::NONE), false)
+                                   , &ltex.biases[CONTROLLER_BIAS], true), false);
+    let hidden = match self.controller {
+      NTMController::Dense => af::tanh(&z),
+      NTMController::RNN   => af::tanh(&af::add(&z, &af::matmul(&prev.hidden, &ltex.weights[CONTROLLER_RECURRENT]
+                                                                , MatProp::NONE, MatProp::NONE), false)),
+    };
+
+    // heads: read from M_{t-1}, then erase & add
+    let heads = layer::linear(&hidden, &ltex.weights[HEADS], Some(&ltex.biases[HEADS_BIAS]), "linear");
+    let (m, head_size) = (self.memory_width as u64, self.head_size());
+    let reading = self.address(&prev.memory, &prev.read_weights, &heads, 0);
+    let writing = self.address(&prev.memory, &prev.write_weights, &heads, head_size);
+    let erase = activations::sigmoid(&af::cols(&heads, 2 * head_size, 2 * head_size + m - 1));
+    let add = af::tanh(&af::cols(&heads, 2 * head_size + m, 2 * head_size + 2 * m - 1));
+
+    let read = af::sum(&af::mul(&prev.memory, &to_slots(&reading.weights), true), 2);
+    let write_slots = to_slots(&writing.weights);
+    let memory = af::add(&af::sub(&prev.memory, &af::mul(&prev.memory, &af::mul(&write_slots, &erase, true), false), false)
+                         , &af::mul(&write_slots, &add, true), false);
+
+    let joined = af::join(1, &hidden, &read);
+    let output = layer::linear(&joined, &ltex.weights[OUTPUT], Some(&ltex.biases[OUTPUT_BIAS]), &ltex.activations[0]);
+    let state = State { memory: memory, read_weights: reading.weights.clone(), write_weights: writing.weights.clone()
+                        , read: read, hidden: hidden };
+    Step { controller_input: controller_input, heads: heads, reading: reading, writing: writing
+           , erase: erase, add: add, joined: joined, output: output, state: state }
+  }
+}
+
+impl Layer for NTM
+{
+  fn forward(&self, params: Arc<Mutex<Params>>, inputs: &Array, state: Option<&Vec<Array>>) -> (Array, Option<Vec<Array>>)
+  {
+    // get a handle to the underlying params
+    let mut ltex = params.lock().unwrap();
+    let current_unroll = ltex.current_unroll;
+
+    // recurrences[t] holds the packed state of unroll t - 1.
+    // If a state is provided it overrides the current recurrence, otherwise
+    // the last state of the previous unroll is carried over (at t = 0)
+    let batch_size = inputs.dims()[0];
+    let packed_tm1 = match state {
+      Some(init_state) => init_state[0].clone(),
+      None             => match ltex.recurrences.len() {
+        0 => self.initial_state(batch_size, inputs.get_type()),
+        _ => match current_unroll {
+          0 => {
+            let carried = ltex.recurrences.last().unwrap().clone();
+            match carried.dims()[0] == batch_size {
+              true  => carried,
+              false => self.initial_state(batch_size, inputs.get_type()),
+            }
+          },
+          _ => ltex.recurrences[current_unroll].clone(),
+        },
+      },
+    };
+
+    if ltex.recurrences.len() > current_unroll {
+      ltex.recurrences[current_unroll] = packed_tm1.clone();
+    }else{
+      ltex.recurrences.push(packed_tm1.clone());
+    }
+
+    let step = self.step(&ltex, inputs, &self.unpack(&packed_tm1));
+    let packed_t = self.pack(&step.state);
+
+    // parameter manager keeps the inputs, outputs & states
+    if ltex.inputs.len() > current_unroll { // store in existing
+      ltex.inputs[current_unroll] = inputs.clone();
+      ltex.outputs[current_unroll] = step.output.clone();
+      ltex.recurrences[current_unroll + 1] = packed_t.clone();
+    }else{                                  // add new
+      ltex.inputs.push(inputs.clone());
+      ltex.outputs.push(step.output.clone());
+      ltex.recurrences.push(packed_t.clone());
+    }
+
+    // update location in vector
+    ltex.current_unroll += 1;
+
+    (step.output, Some(vec![packed_t]))
+  }
+
+  fn backward(&self, params: Arc<Mutex<Params>>, delta: &Array) -> Array
+  {
+    // get a handle to the underlying params
+    let mut ltex = params.lock().unwrap();
+    let current_unroll = ltex.current_unroll;
+    assert!(current_unroll > 0
+            , "Cannot call backward pass without at least 1 forward pass");
+
+    // the intermediate values are recomputed from the previous state
+    let packed_tm1 = ltex.recurrences[current_unroll - 1].clone();
+    let prev = self.unpack(&packed_tm1);
+    let step = self.step(&ltex, &ltex.inputs[current_unroll - 1], &prev);
+
+    // check to see if we already have a state derivative, else add one
+    if ltex.state_derivatives.len() == 0 {
+      ltex.state_derivatives.push(utils::constant(packed_tm1.dims(), packed_tm1.get_type(), 0.0f32));
+    }
+    let dstate = self.unpack(&ltex.state_derivatives[0]);
+    let num_weights = ltex.weights.len();
+    let mut grads = ltex.deltas.clone();
+    let accumulate = |grads: &mut Vec<Array>, index: usize, grad: &Array| {
+      grads[index] = af::add(&grads[index], grad, false);
+    };
+
+    // output: o_t = outer_activation([h_t, r_t] W_o + b_o)
+    let (dz_o, dw_o, db_o) = layer::linear_backward(delta, &step.joined, &step.output, &ltex.activations[0]);
+    accumulate(&mut grads, OUTPUT, &dw_o);
+    accumulate(&mut grads, num_weights + OUTPUT_BIAS, &db_o);
+    let djoined = af::matmul(&dz_o, &ltex.weights[OUTPUT], MatProp::NONE, MatProp::TRANS);
+    let h = self.controller_size as u64;
+    let dhidden = af::add(&af::cols(&djoined, 0, h - 1), &dstate.hidden, false);
+    let dread = af::add(&af::cols(&djoined, h, h + self.memory_width as u64 - 1), &dstate.read, false);
+
+    // write: M_t = M_{t-1} .* (1 - w e^T) + w a^T
+    let dmemory_t = dstate.memory.clone();
+    let write_slots = to_slots(&step.writing.weights);
+    let mut dmemory = af::sub(&dmemory_t, &af::mul(&dmemory_t, &af::mul(&write_slots, &step.erase, true), false), false);
+    let dwrite = af::add(&from_slots(&af::sum(&af::mul(&dmemory_t, &af::sub(&step.add, &af::mul(&prev.memory, &step.erase, true)
+                                                                              , true), false), 1))
+                         , &dstate.write_weights, false);
+    let derase = af::mul(&af::sum(&af::mul(&af::mul(&dmemory_t, &prev.memory, false), &write_slots, true), 2)
+                         , &-1.0f32, false);
+    let dadd = af::sum(&af::mul(&dmemory_t, &write_slots, true), 2);
+
+    // read: r_t = sum_n w_n M_{t-1}[n]
+    let dread_weights = af::add(&from_slots(&af::sum(&af::mul(&prev.memory, &dread, true), 1)), &dstate.read_weights, false);
+    dmemory = af::add(&dmemory, &af::mul(&to_slots(&step.reading.weights), &dread, true), false);
+
+    // addressing of both heads
+    let head_size = self.head_size();
+    let (dreading, dread_weights_tm1, dmemory_read) = self.address_backward(&step.reading, &prev.memory, &prev.read_weights
+                                                                            , &step.heads, 0, &dread_weights);
+    let (dwriting, dwrite_weights_tm1, dmemory_write) = self.address_backward(&step.writing, &prev.memory, &prev.write_weights
+                                                                              , &step.heads, head_size, &dwrite);
+    dmemory = af::add(&af::add(&dmemory, &dmemory_read, false), &dmemory_write, false);
+    let dheads = af::join_many(1, vec![&dreading, &dwriting
+                                       , &af::mul(&derase, &activations::sigmoid_derivative(&step.erase), false)
+                                       , &af::mul(&dadd, &activations::tanh_derivative(&step.add), false)]);
+    let dw_p = af::matmul(&step.state.hidden, &dheads, MatProp::TRANS, MatProp::NONE);
+    accumulate(&mut grads, HEADS, &dw_p);
+    accumulate(&mut grads, num_weights + HEADS_BIAS, &af::transpose(&af::sum(&dheads, 0), false));
+    let dhidden = af::add(&dhidden, &af::matmul(&dheads, &ltex.weights[HEADS], MatProp::NONE, MatProp::TRANS), false);
+
+    // controller: h_t = tanh([x_t, r_{t-1}] W_c + h_{t-1} U_c + b_c)
+    let dz = af::mul(&dhidden, &activations::tanh_derivative(&step.state.hidden), false);
+    accumulate(&mut grads, CONTROLLER, &af::matmul(&step.controller_input, &dz, MatProp::TRANS, MatProp::NONE));
+    accumulate(&mut grads, num_weights + CONTROLLER_BIAS, &af::transpose(&af::sum(&dz, 0), false));
+    let dhidden_tm1 = match self.controller {
+      NTMController::Dense => utils::constant(prev.hidden.dims(), prev.hidden.get_type(), 0.0f32),
+      NTMController::RNN   => {
+        accumulate(&mut grads, CONTROLLER_RECURRENT, &af::matmul(&prev.hidden, &dz, MatProp::TRANS, MatProp::NONE));
+        af::matmul(&dz, &ltex.weights[CONTROLLER_RECURRENT], MatProp::NONE, MatProp::TRANS)
+      },
+    };
+    let dcontroller_input = af::matmul(&dz, &ltex.weights[CONTROLLER], MatProp::NONE, MatProp::TRANS);
+    let x = self.input_size as u64;
+    let dread_tm1 = af::cols(&dcontroller_input, x, x + self.memory_width as u64 - 1);
+
+    // carry the state derivative to the previous unroll
+    let dprev = State { memory: dmemory, read_weights: dread_weights_tm1, write_weights: dwrite_weights_tm1
+                        , read: dread_tm1, hidden: dhidden_tm1 };
+    ltex.state_derivatives[0] = self.pack(&dprev);
+    ltex.deltas = grads;
+
+    // update location in vector
+    ltex.current_unroll -= 1;
+
+    af::cols(&dcontroller_input, 0, x - 1)
+  }
+
+  fn return_sequences(&self) -> bool {
+    self.return_sequences
+  }
+
+  fn is_recurrent(&self) -> bool {
+    true
+  }
+}
+
+/// The controller of a `layer::NTM`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum NTMController {
+  Dense, // feed-forward
+  RNN,   // vanilla recurrent
+}
+
+/// Config of a Neural Turing Machine (see `layer::NTM`)
+#[derive(Clone, Debug, PartialEq)]
+pub struct NTMConfig {
+  pub input_size: usize,
+  pub output_size: usize,
+  pub controller: NTMController,
+  pub controller_size: usize,
+  pub memory_size: usize,  // number of memory slots
+  pub memory_width: usize, // size of every slot
+  pub outer_activation: String,
+  pub w_init: String,
+  pub b_init: String,
+  pub return_sequences: bool, // only output the last timestep if false
+}
+
+impl NTMController {
+  /// Parses the controller accepted by `Model::add` (dense or rnn)
+  pub fn from_name(name: &str) -> Result<NTMController, HALError> {
+    match name {
+      "dense" => Ok(NTMController::Dense),
+      "rnn"   => Ok(NTMController::RNN),
+      _       => Err(HALError::invalid_param("ntm", "controller"
+                                             , format!("unknown controller '{}', expected dense or rnn", name))),
+    }
+  }
+
+  fn name(&self) -> &'static str {
+    match *self {
+      NTMController::Dense => "dense",
+      NTMController::RNN   => "rnn",
+    }
+  }
+}
+
+impl NTMConfig {
+  pub fn new(input_size: usize, output_size: usize, memory_size: usize, memory_width: usize) -> NTMConfig {
+    NTMConfig {
+      input_size: input_size,
+      output_size: output_size,
+      controller: NTMController::Dense,
+      controller_size: 100,
+      memory_size: memory_size,
+      memory_width: memory_width,
+      outer_activation: "linear".to_string(),
+      w_init: "glorot_uniform".to_string(),
+      b_init: "zeros".to_string(),
+      return_sequences: true,
+    }
+  }
+
+  /// Builds the config from the string params accepted by `Model::add`
+  pub fn from_params(params: &HashMap<&str, String>) -> Result<NTMConfig, HALError> {
+    let p = Parser::new("ntm", params);
+    try!(p.check_keys(&["input_size", "output_size", "controller", "controller_size"
+                        , "memory_size", "memory_width", "outer_activation", "w_init", "b_init"
+                        , "return_sequences"]));
+    let mut config = NTMConfig::new(try!(p.required("input_size"))
+                                    , try!(p.required("output_size"))
+                                    , try!(p.required("memory_size"))
+                                    , try!(p.required("memory_width")));
+    config.controller = try!(NTMController::from_name(&p.string("controller", config.controller.name().to_string())));
+    config.controller_size = try!(p.optional("controller_size", config.controller_size));
+    config.outer_activation = p.string("outer_activation", config.outer_activation);
+    config.w_init = p.string("w_init", config.w_init);
+    config.b_init = p.string("b_init", config.b_init);
+    config.return_sequences = try!(p.optional("return_sequences", config.return_sequences));
+    Ok(config)
+  }
+}
+
+impl LayerConfig for NTMConfig {
+  fn layer_type(&self) -> &'static str { "ntm" }
+  fn input_size(&self) -> usize { self.input_size }
+  fn output_size(&self) -> usize { self.output_size }
+  fn return_sequences(&self) -> bool { self.return_sequences }
+
+  fn validate(&self) -> Result<(), HALError> {
+    let layer = self.layer_type();
+    try!(check_size(layer, "input_size", self.input_size));
+    try!(check_size(layer, "output_size", self.output_size));
+    try!(check_size(layer, "controller_size", self.controller_size));
+    try!(check_size(layer, "memory_size", self.memory_size));
+    try!(check_size(layer, "memory_width", self.memory_width));
+    try!(check_activation(layer, "outer_activation", &self.outer_activation));
+    try!(check_initialization(layer, "w_init", &self.w_init));
+    check_initialization(layer, "b_init", &self.b_init)
+  }
+
+  fn to_params(&self) -> HashMap<String, String> {
+    to_map(vec![("input_size", self.input_size.to_string())
+                , ("output_size", self.output_size.to_string())
+                , ("controller", self.controller.name().to_string())
+                , ("controller_size", self.controller_size.to_string())
+                , ("memory_size", self.memory_size.to_string())
+                , ("memory_width", self.memory_width.to_string())
+                , ("outer_activation", self.outer_activation.clone())
+                , ("w_init", self.w_init.clone())
+                , ("b_init", self.b_init.clone())
+                , ("return_sequences", self.return_sequences.to_string())])
+  }
+
+  fn build<T: HasAfEnum>(&self, param_manager: &mut ParamManager
+                         , manager: DeviceManager, device: Device) -> Box<Layer>
+  {
+    param_manager.add_ntm::<T>(manager, device
+                               , self.input_size, self.output_size
+                               , self.controller_size
+                               , self.controller == NTMController::RNN
+                               , self.memory_size, self.memory_width
+                               , &self.outer_activation
+                               , &self.w_init
+                               , &self.b_init);
+    Box::new(NTM{input_size: self.input_size
+                 , output_size: self.output_size
+                 , controller: self.controller
+                 , controller_size: self.controller_size
+                 , memory_size: self.memory_size
+                 , memory_width: self.memory_width
+                 , return_sequences: self.return_sequences})
+  }
+}
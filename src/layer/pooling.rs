@@ -0,0 +1,344 @@
+use af;
+use af::{Array, Dim4, HasAfEnum};
+use std::sync::{Arc, Mutex};
+use std::collections::HashMap;
+
+use utils;
+use layer;
+use layer::{Layer};
+use layer::config::{LayerConfig, Parser, check_size, check_kernel, to_map};
+use error::HALError;
+use device::{Device, DeviceManager};
+use params::{Params, ParamManager, PoolGenerator};
+
+/// Max pooling over the windows of [height, width, channels] images
+///
+/// Uses the same data layout as `layer::Conv2D`; every channel is pooled
+/// separately so the output is [output_height, output_width, channels].
+/// `Params::optional[0]` holds the window indices and `Params::optional[1 + t]`
+/// the argmax of every window for unroll t (used to route the gradients).
+pub struct MaxPool2D {
+  pub input_size: usize,
+  pub output_size: usize,
+}
+
+/// Average pooling over the windows of [height, width, channels] images
+///
+/// Uses the same data layout as `layer::MaxPool2D`.
+pub struct AvgPool2D {
+  pub input_size: usize,
+  pub output_size: usize,
+}
+
+/// Average of every channel of [height, width, channels] images
+///
+/// The output is of [batch, channels] (ie: `output_size` is the number of channels).
+pub struct GlobalAvgPool2D {
+  pub input_size: usize,
+  pub output_size: usize,
+}
+
+/// Helper that gathers the windows of an input into [batch, outputs, window_size]
+fn windows(input: &Array, indices: &Array, output_size: usize) -> Array
+{
+  let window_size = indices.elements() as u64 / output_size as u64;
+  af::moddims(&layer::unfold_patches(input, indices)
+              , Dim4::new(&[input.dims()[0], output_size as u64, window_size, 1]))
+}
+
+/// Helper that stores the input & output of the current unroll
+fn store_unroll(ltex: &mut Params, inputs: &Array, outputs: &Array)
+{
+  let current_unroll = ltex.current_unroll;
+  if ltex.inputs.len() > current_unroll { // store in existing
+    ltex.inputs[current_unroll] = inputs.clone();
+    ltex.outputs[current_unroll] = outputs.clone();
+  }else{                                  // add new
+    ltex.inputs.push(inputs.clone());
+    ltex.outputs.push(outputs.clone());
+  }
+
+  // update location in vector
+  ltex.current_unroll += 1;
+}
+
+impl Layer for MaxPool2D
+{
+  fn forward(&self, params: Arc<Mutex<Params>>, inputs: &Array, state: Option<&Vec<Array>>) -> (Array, Option<Vec<Array>>)
+  {
+    // get a handle to the underlying params
+    let mut ltex = params.lock().unwrap();
+    let (a_t, argmax) = af::imax(&windows(inputs, &ltex.optional[0], self.output_size), 2);
+
+    // the argmax of this unroll lives after the window indices
+    let current_unroll = ltex.current_unroll;
+    if ltex.optional.len() > current_unroll + 1 {
+      ltex.optional[current_unroll + 1] = argmax;
+    }else{
+      ltex.optional.push(argmax);
+    }
+
+    store_unroll(&mut ltex, inputs, &a_t);
+    (a_t.clone(), None)
+  }
+
+  fn backward(&self, params: Arc<Mutex<Params>>, delta: &Array) -> Array
+  {
+    // get a handle to the underlying params
+    let mut ltex = params.lock().unwrap();
+    let current_unroll = ltex.current_unroll;
+    assert!(current_unroll > 0
+            , "Cannot call backward pass without at least 1 forward pass");
+
+    // only the max of every window receives the gradient
+    let batch_size = delta.dims()[0];
+    let window_size = ltex.optional[0].elements() as u64 / self.output_size as u64;
+    let window_dims = Dim4::new(&[batch_size, self.output_size as u64, window_size, 1]);
+    let taps = af::range::<u32>(window_dims, 2);
+    let argmax = af::tile(&ltex.optional[current_unroll], Dim4::new(&[1, 1, window_size, 1]));
+    let mask = utils::cast(&af::eq(&taps, &argmax, false), delta.get_type());
+    let dwindows = af::mul(&af::tile(delta, Dim4::new(&[1, 1, window_size, 1])), &mask, false);
+
+    ltex.current_unroll -= 1;
+
+    let num_taps = self.output_size as u64 * window_size;
+    layer::fold_patches(&af::moddims(&dwindows, Dim4::new(&[batch_size, num_taps, 1, 1]))
+                        , &ltex.optional[0], self.input_size as u64)
+  }
+}
+
+impl Layer for AvgPool2D
+{
+  fn forward(&self, params: Arc<Mutex<Params>>, inputs: &Array, state: Option<&Vec<Array>>) -> (Array, Option<Vec<Array>>)
+  {
+    // get a handle to the underlying params
+    let mut ltex = params.lock().unwrap();
+    let window_size = ltex.optional[0].elements() as u64 / self.output_size as u64;
+    let a_t = af::div(&af::sum(&windows(inputs, &ltex.optional[0], self.output_size), 2)
+                      , &(window_size as f32), false);
+
+    store_unroll(&mut ltex, inputs, &a_t);
+    (a_t.clone(), None)
+  }
+
+  fn backward(&self, params: Arc<Mutex<Params>>, delta: &Array) -> Array
+  {
+    // get a handle to the underlying params
+    let mut ltex = params.lock().unwrap();
+    let current_unroll = ltex.current_unroll;
+    assert!(current_unroll > 0
+            , "Cannot call backward pass without at least 1 forward pass");
+
+    // every tap of a window receives an equal share of the gradient
+    let window_size = ltex.optional[0].elements() as u64 / self.output_size as u64;
+    let dwindows = af::div(&af::tile(delta, Dim4::new(&[1, window_size, 1, 1]))
+                           , &(window_size as f32), false);
+
+    ltex.current_unroll -= 1;
+
+    // tiling along the columns already gives the [outputs, window_size] tap order
+    layer::fold_patches(&dwindows, &ltex.optional[0], self.input_size as u64)
+  }
+}
+
+impl Layer for GlobalAvgPool2D
+{
+  fn forward(&self, params: Arc<Mutex<Params>>, inputs: &Array, state: Option<&Vec<Array>>) -> (Array, Option<Vec<Array>>)
+  {
+    // get a handle to the underlying params
+    let mut ltex = params.lock().unwrap();
+
+    // [batch, height * width * channels] -> [batch, height * width, channels]
+    let batch_size = inputs.dims()[0];
+    let num_positions = (self.input_size / self.output_size) as u64;
+    let positions = af::moddims(inputs, Dim4::new(&[batch_size, num_positions, self.output_size as u64, 1]));
+    let a_t = af::moddims(&af::div(&af::sum(&positions, 1), &(num_positions as f32), false)
+                          , Dim4::new(&[batch_size, self.output_size as u64, 1, 1]));
+
+    store_unroll(&mut ltex, inputs, &a_t);
+    (a_t.clone(), None)
+  }
+
+  fn backward(&self, params: Arc<Mutex<Params>>, delta: &Array) -> Array
+  {
+    // get a handle to the underlying params
+    let mut ltex = params.lock().unwrap();
+    let current_unroll = ltex.current_unroll;
+    assert!(current_unroll > 0
+            , "Cannot call backward pass without at least 1 forward pass");
+
+    // every position of a channel receives an equal share of the gradient
+    let batch_size = delta.dims()[0];
+    let num_positions = (self.input_size / self.output_size) as u64;
+    let channels = af::moddims(delta, Dim4::new(&[batch_size, 1, self.output_size as u64, 1]));
+    let dpositions = af::div(&af::tile(&channels, Dim4::new(&[1, num_positions, 1, 1]))
+                             , &(num_positions as f32), false);
+
+    ltex.current_unroll -= 1;
+
+    af::moddims(&dpositions, Dim4::new(&[batch_size, self.input_size as u64, 1, 1]))
+  }
+}
+
+/// The reduction applied over every window of a pooling layer
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PoolType {
+  Max,
+  Average,
+}
+
+/// Config of a max / average pooling layer (see `layer::MaxPool2D` for the data layout)
+#[derive(Clone, Debug, PartialEq)]
+pub struct Pool2DConfig {
+  pub pool_type: PoolType,
+  pub input_dims: (usize, usize, usize), // [height, width, channels]
+  pub window: (usize, usize),            // [height, width]
+  pub stride: (usize, usize),            // [height, width]
+}
+
+/// Config of a global average pooling layer
+#[derive(Clone, Debug, PartialEq)]
+pub struct GlobalAvgPool2DConfig {
+  pub input_dims: (usize, usize, usize), // [height, width, channels]
+}
+
+impl PoolType {
+  fn layer_type(&self) -> &'static str {
+    match *self {
+      PoolType::Max     => "max_pool2d",
+      PoolType::Average => "avg_pool2d",
+    }
+  }
+}
+
+impl Pool2DConfig {
+  /// Non overlapping windows by default (ie: the stride is the window size)
+  pub fn new(pool_type: PoolType, input_dims: (usize, usize, usize), window: (usize, usize)) -> Pool2DConfig {
+    Pool2DConfig {
+      pool_type: pool_type,
+      input_dims: input_dims,
+      window: window,
+      stride: window,
+    }
+  }
+
+  /// Returns the [height, width] of every pooled channel
+  pub fn output_dims(&self) -> (usize, usize) {
+    let (height, width, _) = self.input_dims;
+    ((height - self.window.0) / self.stride.0 + 1
+     , (width - self.window.1) / self.stride.1 + 1)
+  }
+
+  /// Builds the config from the string params accepted by `Model::add`
+  pub fn from_params(pool_type: PoolType, params: &HashMap<&str, String>) -> Result<Pool2DConfig, HALError> {
+    let p = Parser::new(pool_type.layer_type(), params);
+    try!(p.check_keys(&["input_height", "input_width", "input_channels"
+                        , "window_height", "window_width", "stride_height", "stride_width"]));
+    let mut config = Pool2DConfig::new(pool_type
+                                       , (try!(p.required("input_height"))
+                                          , try!(p.required("input_width"))
+                                          , try!(p.optional("input_channels", 1)))
+                                       , (try!(p.required("window_height"))
+                                          , try!(p.required("window_width"))));
+    config.stride = (try!(p.optional("stride_height", config.stride.0))
+                     , try!(p.optional("stride_width", config.stride.1)));
+    Ok(config)
+  }
+}
+
+impl LayerConfig for Pool2DConfig {
+  fn layer_type(&self) -> &'static str { self.pool_type.layer_type() }
+
+  fn input_size(&self) -> usize {
+    self.input_dims.0 * self.input_dims.1 * self.input_dims.2
+  }
+
+  fn output_size(&self) -> usize {
+    let (output_height, output_width) = self.output_dims();
+    output_height * output_width * self.input_dims.2
+  }
+
+  fn validate(&self) -> Result<(), HALError> {
+    let layer = self.layer_type();
+    try!(check_size(layer, "input_height", self.input_dims.0));
+    try!(check_size(layer, "input_width", self.input_dims.1));
+    try!(check_size(layer, "input_channels", self.input_dims.2));
+    try!(check_size(layer, "window_height", self.window.0));
+    try!(check_size(layer, "window_width", self.window.1));
+    try!(check_size(layer, "stride_height", self.stride.0));
+    try!(check_size(layer, "stride_width", self.stride.1));
+    try!(check_kernel(layer, "window_height", self.window.0, self.input_dims.0, 0));
+    check_kernel(layer, "window_width", self.window.1, self.input_dims.1, 0)
+  }
+
+  fn to_params(&self) -> HashMap<String, String> {
+    to_map(vec![("input_height", self.input_dims.0.to_string())
+                , ("input_width", self.input_dims.1.to_string())
+                , ("input_channels", self.input_dims.2.to_string())
+                , ("window_height", self.window.0.to_string())
+                , ("window_width", self.window.1.to_string())
+                , ("stride_height", self.stride.0.to_string())
+                , ("stride_width", self.stride.1.to_string())])
+  }
+
+  fn build<T: HasAfEnum>(&self, param_manager: &mut ParamManager
+                         , manager: DeviceManager, device: Device) -> Box<Layer>
+  {
+    param_manager.add_pool2d::<T>(manager, device, self.layer_type()
+                                  , self.input_dims, self.window, self.stride);
+    match self.pool_type {
+      PoolType::Max     => Box::new(MaxPool2D{input_size: self.input_size()
+                                              , output_size: self.output_size()}),
+      PoolType::Average => Box::new(AvgPool2D{input_size: self.input_size()
+                                              , output_size: self.output_size()}),
+    }
+  }
+}
+
+impl GlobalAvgPool2DConfig {
+  pub fn new(input_dims: (usize, usize, usize)) -> GlobalAvgPool2DConfig {
+    GlobalAvgPool2DConfig {
+      input_dims: input_dims,
+    }
+  }
+
+  /// Builds the config from the string params accepted by `Model::add`
+  pub fn from_params(params: &HashMap<&str, String>) -> Result<GlobalAvgPool2DConfig, HALError> {
+    let p = Parser::new("global_avg_pool2d", params);
+    try!(p.check_keys(&["input_height", "input_width", "input_channels"]));
+    Ok(GlobalAvgPool2DConfig::new((try!(p.required("input_height"))
+                                   , try!(p.required("input_width"))
+                                   , try!(p.optional("input_channels", 1)))))
+  }
+}
+
+impl LayerConfig for GlobalAvgPool2DConfig {
+  fn layer_type(&self) -> &'static str { "global_avg_pool2d" }
+
+  fn input_size(&self) -> usize {
+    self.input_dims.0 * self.input_dims.1 * self.input_dims.2
+  }
+
+  fn output_size(&self) -> usize { self.input_dims.2 }
+
+  fn validate(&self) -> Result<(), HALError> {
+    let layer = self.layer_type();
+    try!(check_size(layer, "input_height", self.input_dims.0));
+    try!(check_size(layer, "input_width", self.input_dims.1));
+    check_size(layer, "input_channels", self.input_dims.2)
+  }
+
+  fn to_params(&self) -> HashMap<String, String> {
+    to_map(vec![("input_height", self.input_dims.0.to_string())
+                , ("input_width", self.input_dims.1.to_string())
+                , ("input_channels", self.input_dims.2.to_string())])
+  }
+
+  fn build<T: HasAfEnum>(&self, param_manager: &mut ParamManager
+                         , manager: DeviceManager, device: Device) -> Box<Layer>
+  {
+    param_manager.add_global_pool2d::<T>(manager, device, self.layer_type());
+    Box::new(GlobalAvgPool2D{input_size: self.input_size()
+                             , output_size: self.output_size()})
+  }
+}
@@ -1,19 +1,36 @@
 use af;
-use af::{Array, Dim4, MatProp};
+use af::{Array, Dim4, MatProp, HasAfEnum};
 use std::sync::{Arc, Mutex};
+use std::collections::HashMap;
 
 use utils;
 use layer;
 use activations;
-use params::{Params, RNNIndex};
-use layer::{Layer, RecurrentLayer};
+use error::HALError;
+use device::{Device, DeviceManager};
+use params::{Params, RNNIndex, ParamManager, RNNGenerator};
+use layer::{Layer, RecurrentLayer, RTRL};
+use layer::config::{LayerConfig, Parser, check_size, check_activation, check_initialization, to_map};
 
+/// A vanilla RNN: a_t = inner_activation(x_t W + a_{t-1} U + b), o_t = outer_activation(a_t V + b_v)
+///
+/// With `layer_norm` the pre-activation of the hidden state is layer normalized
+/// (see `layer::layer_norm`) before the inner activation; the gain & bias are
+/// kept after the regular biases in `Params::biases`. Layer normalized RNNs
+/// do not implement RTRL, thus `Sequential::fit_rtrl` rejects them.
 pub struct RNN {
   pub input_size: usize,
   pub hidden_size: usize,
   pub output_size: usize,
+  pub layer_norm: bool,
+  pub return_sequences: bool,
 }
 
+// indices of the layer norm gain & bias in Params::biases
+const LAYER_NORM_GAIN: usize = 2;
+const LAYER_NORM_BIAS: usize = 3;
+const LAYER_NORM_EPSILON: f32 = 1e-5;
+
 impl RecurrentLayer for RNN {
   fn state_size(self) -> usize {
     self.output_size
@@ -23,9 +40,29 @@ impl RecurrentLayer for RNN {
 impl RNN
 {
   /// A helper to do a large matmul if possible
+  ///
+  /// If `layer_norm` [gain, bias] is provided the pre-activation is layer
+  /// normalized before applying the hidden activation
   fn optimized_state_calc(&self, input: &Array, a_tm1: &Array
                           , weight_i2h: &Array, weight_h2h: &Array
-                          , hidden_bias: &Array, hidden_activation: &String) -> Array
+                          , hidden_bias: &Array, layer_norm: Option<(&Array, &Array)>
+                          , hidden_activation: &str) -> Array
+  {
+    match layer_norm {
+      Some((gain, bias)) => {
+        let z_t = self.state_projection(input, a_tm1, weight_i2h, weight_h2h, hidden_bias, "linear");
+        activations::get_activation(hidden_activation
+                                    , &layer::layer_norm(&z_t, gain, bias, LAYER_NORM_EPSILON)).unwrap()
+      },
+      None               => self.state_projection(input, a_tm1, weight_i2h, weight_h2h
+                                                  , hidden_bias, hidden_activation),
+    }
+  }
+
+  /// Computes activation(xW + a_{t-1}U + b) with a single matmul if possible
+  fn state_projection(&self, input: &Array, a_tm1: &Array
+                      , weight_i2h: &Array, weight_h2h: &Array
+                      , hidden_bias: &Array, activation: &str) -> Array
   {
     let idims = weight_i2h.dims();
     let hdims = weight_h2h.dims();
@@ -39,7 +76,7 @@ impl RNN
         layer::linear(&af::join_many(1, is_vec)
                       , &af::join_many(0, wu_vec)
                       , Some(hidden_bias)
-                      , hidden_activation)
+                      , activation)
       },
 
       false => {
@@ -53,7 +90,7 @@ impl RNN
         // this helps use the linear projection operator
         let wx_p_b = af::add(&af::transpose(&wx, false)
                              , hidden_bias, true);
-        layer::linear(a_tm1, weight_h2h, Some(&wx_p_b), hidden_activation)
+        layer::linear(a_tm1, weight_h2h, Some(&wx_p_b), activation)
       }
     }
   }
@@ -69,8 +106,9 @@ impl Layer for RNN
     let current_unroll = ltex.current_unroll;
 
     // set a_{t-1} to the given state (if provided)
-    // if state is provided ensure to save it as the last recurrence
-    //   --> Note: this will override the last recurrence if it exists
+    // if state is provided ensure to save it as the current recurrence
+    //   --> Note: this will override the current recurrence if it exists
+    // if it is not provided carry over the last state of the previous unroll
     // also handle case where time = 0 for both cases
     let atm1 = match state {
       Some(init_state)  => match ltex.recurrences.len() {
@@ -80,8 +118,7 @@ impl Layer for RNN
         },
 
         _ => {
-          let rlen = ltex.recurrences.len();
-          ltex.recurrences[rlen - 1] = init_state[0].clone();
+          ltex.recurrences[current_unroll] = init_state[0].clone();
           init_state[0].clone() // only one recurrence for vanilla RNN
         },
       },
@@ -94,16 +131,37 @@ impl Layer for RNN
           ltex.recurrences.push(zero_state.clone());
           zero_state
         },
-        _ => ltex.recurrences.last().unwrap().clone()
+        _ => {
+          if current_unroll == 0 {
+            // carry the last state over unless the batch size changed
+            let carried = ltex.recurrences.last().unwrap().clone();
+            ltex.recurrences[0] = match carried.dims()[0] == inputs.dims()[0] {
+              true  => carried,
+              false => {
+                let output_size = ltex.weights[RNNIndex::HiddenToHidden as usize].dims()[0];
+                let zero_dims = Dim4::new(&[inputs.dims()[0], output_size, 1, 1]);
+                utils::constant(zero_dims, inputs.get_type(), 0f32)
+              },
+            };
+          }
+          ltex.recurrences[current_unroll].clone()
+        },
       }
     };
 
     // compute the current state a_t in an optimized fashion [if possible]
-    let a_t = self.optimized_state_calc(inputs, &atm1
-                                        , &ltex.weights[RNNIndex::InputToHidden as usize]
-                                        , &ltex.weights[RNNIndex::HiddenToHidden as usize]
-                                        , &ltex.biases[RNNIndex::InputToHidden as usize]
-                                        , &ltex.activations[0]);
+    let a_t = {
+      let layer_norm = match self.layer_norm {
+        true  => Some((&ltex.biases[LAYER_NORM_GAIN], &ltex.biases[LAYER_NORM_BIAS])),
+        false => None,
+      };
+      self.optimized_state_calc(inputs, &atm1
+                                , &ltex.weights[RNNIndex::InputToHidden as usize]
+                                , &ltex.weights[RNNIndex::HiddenToHidden as usize]
+                                , &ltex.biases[RNNIndex::InputToHidden as usize]
+                                , layer_norm
+                                , &ltex.activations[0])
+    };
 
     // calculate the output projection
     // v_t = V*a_t + b_v
@@ -163,8 +221,24 @@ impl Layer for RNN
                                          , &ltex.recurrences[current_unroll]).unwrap();
 
     // update the delta for the current time-step by combining with state derivative
-    let delta_t = af::mul(&af::add(&ltex.state_derivatives[0]
-                                   , &delta_v, false), &dz, false);
+    let mut delta_t = af::mul(&af::add(&ltex.state_derivatives[0]
+                                       , &delta_v, false), &dz, false);
+
+    // backprop through the layer norm of the (recomputed) pre-activation
+    if self.layer_norm {
+      let z_t = self.state_projection(&ltex.inputs[current_unroll - 1]
+                                      , &ltex.recurrences[current_unroll - 1]
+                                      , &ltex.weights[RNNIndex::InputToHidden as usize]
+                                      , &ltex.weights[RNNIndex::HiddenToHidden as usize]
+                                      , &ltex.biases[RNNIndex::InputToHidden as usize]
+                                      , "linear");
+      let (delta_z, dgain, dbias) = layer::layer_norm_backward(&delta_t, &z_t
+                                                               , &ltex.biases[LAYER_NORM_GAIN]
+                                                               , LAYER_NORM_EPSILON);
+      ltex.deltas[5] = af::add(&ltex.deltas[5], &dgain, false); // layer norm gain
+      ltex.deltas[6] = af::add(&ltex.deltas[6], &dbias, false); // layer norm bias
+      delta_t = delta_z;
+    }
 
     let dw = af::matmul(&ltex.inputs[current_unroll - 1], &delta_t       // delta_w = delta_t * a_{t}
                         , af::MatProp::TRANS
@@ -192,4 +266,179 @@ impl Layer for RNN
     // delta_{t-1}
     af::matmul(&delta_t, &ltex.weights[0], af::MatProp::NONE, af::MatProp::TRANS)
   }
+
+  // the sensitivities are not carried through the layer normalization
+  fn as_rtrl(&self) -> Option<&RTRL> {
+    match self.layer_norm {
+      true  => None,
+      false => Some(self),
+    }
+  }
+
+  fn return_sequences(&self) -> bool {
+    self.return_sequences
+  }
+
+  fn is_recurrent(&self) -> bool {
+    true
+  }
+}
+
+impl RTRL for RNN
+{
+  fn rtrl(&self, params: Arc<Mutex<Params>>, delta: &Array) -> Array
+  {
+    // get a handle to the underlying params
+    let mut ltex = params.lock().unwrap();
+    let current_unroll = ltex.current_unroll;
+    assert!(current_unroll > 0
+            , "Cannot call rtrl without at least 1 forward pass");
+
+    let x_t = ltex.inputs[current_unroll - 1].clone();
+    let a_tm1 = ltex.recurrences[current_unroll - 1].clone();
+    let a_t = ltex.recurrences[current_unroll].clone();
+    let weight_i2h = ltex.weights[RNNIndex::InputToHidden as usize].clone();
+    let weight_h2h = ltex.weights[RNNIndex::HiddenToHidden as usize].clone();
+
+    // the output projection is not recurrent, so its gradients are direct
+    let (mut delta_v, dv, db_h2o) = layer::linear_backward(delta, &a_t
+                                                           , &ltex.outputs[current_unroll - 1]
+                                                           , &ltex.activations[1]);
+    delta_v = af::matmul(&delta_v, &ltex.weights[RNNIndex::HiddenToOutput as usize]
+                         , af::MatProp::NONE, af::MatProp::TRANS);
+
+    // (re)allocate the sensitivities [da/dW, da/dU, da/db] if required
+    let batch_size = x_t.dims()[0];
+    let hidden_size = weight_h2h.dims()[0];
+    if ltex.state_derivatives.len() != 3 || ltex.state_derivatives[0].dims()[0] != batch_size {
+      let dtype = x_t.get_type();
+      let param_sizes = vec![weight_i2h.elements() as u64, weight_h2h.elements() as u64, hidden_size];
+      ltex.state_derivatives = param_sizes.iter().map(|k| {
+        utils::constant(Dim4::new(&[batch_size, hidden_size, *k, 1]), dtype, 0.0f32)
+      }).collect();
+    }
+
+    // S_t = dz .* (d(xW + a_{t-1}U + b)/dp + S_{t-1} * U)
+    let dz = activations::get_derivative(&ltex.activations[0], &a_t).unwrap();
+    let direct = vec![layer::linear_sensitivity(&x_t, hidden_size)
+                      , layer::linear_sensitivity(&a_tm1, hidden_size)
+                      , layer::bias_sensitivity(batch_size, hidden_size, x_t.get_type())];
+    for (k, d) in direct.iter().enumerate() {
+      let recurrent = layer::sensitivity_matmul(&ltex.state_derivatives[k], &weight_h2h);
+      ltex.state_derivatives[k] = layer::scale_sensitivity(&dz, &af::add(d, &recurrent, false));
+    }
+
+    // dL/dp = sum_{batch} delta_t . S_t
+    let dw = layer::sensitivity_grad(&delta_v, &ltex.state_derivatives[0], weight_i2h.dims());
+    let du = layer::sensitivity_grad(&delta_v, &ltex.state_derivatives[1], weight_h2h.dims());
+    let db_i2h = layer::sensitivity_grad(&delta_v, &ltex.state_derivatives[2]
+                                         , ltex.biases[RNNIndex::InputToHidden as usize].dims());
+
+    // push in the appropriate gradients
+    ltex.deltas[0] = af::add(&ltex.deltas[0], &dw, false);     // i2h
+    ltex.deltas[1] = af::add(&ltex.deltas[1], &dv, false);     // h2o
+    ltex.deltas[2] = af::add(&ltex.deltas[2], &du, false);     // h2h
+    ltex.deltas[3] = af::add(&ltex.deltas[3], &db_i2h, false); // i2h bias
+    ltex.deltas[4] = af::add(&ltex.deltas[4], &db_h2o, false); // h2o bias
+
+    // update location in vector
+    ltex.current_unroll -= 1;
+
+    // instantaneous delta_{t-1}
+    af::matmul(&af::mul(&delta_v, &dz, false), &weight_i2h, af::MatProp::NONE, af::MatProp::TRANS)
+  }
+}
+
+/// Config of a vanilla RNN layer
+#[derive(Clone, Debug, PartialEq)]
+pub struct RNNConfig {
+  pub input_size: usize,
+  pub hidden_size: usize,
+  pub output_size: usize,
+  pub inner_activation: String,
+  pub outer_activation: String,
+  pub w_init: String,
+  pub b_init: String,
+  pub layer_norm: bool,       // layer normalize the hidden pre-activation
+  pub return_sequences: bool, // only output the last timestep if false
+}
+
+impl RNNConfig {
+  pub fn new(input_size: usize, hidden_size: usize, output_size: usize) -> RNNConfig {
+    RNNConfig {
+      input_size: input_size,
+      hidden_size: hidden_size,
+      output_size: output_size,
+      inner_activation: "tanh".to_string(),
+      outer_activation: "linear".to_string(),
+      w_init: "glorot_uniform".to_string(),
+      b_init: "zeros".to_string(),
+      layer_norm: false,
+      return_sequences: true,
+    }
+  }
+
+  /// Builds the config from the string params accepted by `Model::add`
+  pub fn from_params(params: &HashMap<&str, String>) -> Result<RNNConfig, HALError> {
+    let p = Parser::new("rnn", params);
+    try!(p.check_keys(&["input_size", "hidden_size", "output_size", "inner_activation"
+                        , "outer_activation", "w_init", "b_init", "layer_norm", "return_sequences"]));
+    let mut config = RNNConfig::new(try!(p.required("input_size"))
+                                    , try!(p.required("hidden_size"))
+                                    , try!(p.required("output_size")));
+    config.inner_activation = p.string("inner_activation", config.inner_activation);
+    config.outer_activation = p.string("outer_activation", config.outer_activation);
+    config.w_init = p.string("w_init", config.w_init);
+    config.b_init = p.string("b_init", config.b_init);
+    config.layer_norm = try!(p.optional("layer_norm", config.layer_norm));
+    config.return_sequences = try!(p.optional("return_sequences", config.return_sequences));
+    Ok(config)
+  }
+}
+
+impl LayerConfig for RNNConfig {
+  fn layer_type(&self) -> &'static str { "rnn" }
+  fn input_size(&self) -> usize { self.input_size }
+  fn output_size(&self) -> usize { self.output_size }
+  fn return_sequences(&self) -> bool { self.return_sequences }
+
+  fn validate(&self) -> Result<(), HALError> {
+    let layer = self.layer_type();
+    try!(check_size(layer, "input_size", self.input_size));
+    try!(check_size(layer, "hidden_size", self.hidden_size));
+    try!(check_size(layer, "output_size", self.output_size));
+    try!(check_activation(layer, "inner_activation", &self.inner_activation));
+    try!(check_activation(layer, "outer_activation", &self.outer_activation));
+    try!(check_initialization(layer, "w_init", &self.w_init));
+    check_initialization(layer, "b_init", &self.b_init)
+  }
+
+  fn to_params(&self) -> HashMap<String, String> {
+    to_map(vec![("input_size", self.input_size.to_string())
+                , ("hidden_size", self.hidden_size.to_string())
+                , ("output_size", self.output_size.to_string())
+                , ("inner_activation", self.inner_activation.clone())
+                , ("outer_activation", self.outer_activation.clone())
+                , ("w_init", self.w_init.clone())
+                , ("b_init", self.b_init.clone())
+                , ("layer_norm", self.layer_norm.to_string())
+                , ("return_sequences", self.return_sequences.to_string())])
+  }
+
+  fn build<T: HasAfEnum>(&self, param_manager: &mut ParamManager
+                         , manager: DeviceManager, device: Device) -> Box<Layer>
+  {
+    param_manager.add_rnn::<T>(manager, device
+                               , self.input_size, self.hidden_size, self.output_size
+                               , &self.inner_activation
+                               , &self.outer_activation
+                               , &self.w_init
+                               , &self.b_init
+                               , self.layer_norm);
+    Box::new(RNN{input_size: self.input_size
+                 , hidden_size: self.hidden_size
+                 , output_size: self.output_size
+                 , layer_norm: self.layer_norm
+                 , return_sequences: self.return_sequences})
+  }
 }
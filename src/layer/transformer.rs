@@ -0,0 +1,386 @@
+use af;
+use af::{Array, Dim4, DType, MatProp, HasAfEnum};
+use std::sync::{Arc, Mutex};
+use std::collections::HashMap;
+
+use utils;
+use layer;
+use activations;
+use layer::{Layer};
+use layer::config::{LayerConfig, Parser, check_size, check_epsilon, check_initialization, to_map};
+use error::HALError;
+use device::{Device, DeviceManager};
+use params::{Params, ParamManager, TransformerGenerator};
+
+/// A transformer encoder block (post layer normalization)
+///
+/// Every timestep x_t of the sequence (with the sinusoidal positional
+/// encoding PE(t) added if `positional_encoding` is set) attends over all the
+/// timesteps with multi-head scaled dot-product self-attention, followed by a
+/// position-wise feed-forward network:
+///
+///   y_t = LN_1(x_t + MultiHead(x_t, x, x) W_o + b_o)
+///   o_t = LN_2(y_t + relu(y_t W_1 + b_1) W_2 + b_2)
+///
+/// The projections W_q, W_k & W_v have no bias and every head uses
+/// `size / num_heads` of their columns. As every output depends on the whole
+/// sequence this layer only works on whole sequences (see `Layer::forward_sequence`).
+///
+/// `Params::weights` holds [W_q, W_k, W_v, W_o, W_1, W_2] and `Params::biases`
+/// holds [b_o, b_1, b_2] followed by the gain & bias of LN_1 and LN_2.
+pub struct TransformerEncoder {
+  pub size: usize,
+  pub num_heads: usize,
+  pub ff_size: usize,
+  pub epsilon: f32,
+  pub positional_encoding: bool,
+}
+
+// indices in Params::weights
+const QUERY: usize = 0;
+const KEY: usize = 1;
+const VALUE: usize = 2;
+const OUTPUT: usize = 3;
+const FEED_FORWARD_1: usize = 4;
+const FEED_FORWARD_2: usize = 5;
+
+// indices in Params::biases
+const OUTPUT_BIAS: usize = 0;
+const FEED_FORWARD_1_BIAS: usize = 1;
+const FEED_FORWARD_2_BIAS: usize = 2;
+const LAYER_NORM_1_GAIN: usize = 3;
+const LAYER_NORM_1_BIAS: usize = 4;
+const LAYER_NORM_2_GAIN: usize = 5;
+const LAYER_NORM_2_BIAS: usize = 6;
+const NUM_WEIGHTS: usize = 6;
+
+/// Helper that returns the [1, size] sinusoidal encoding of position t:
+/// PE(t, 2i) = sin(t / 10000^(2i / size)), PE(t, 2i + 1) = cos(t / 10000^(2i / size))
+pub fn positional_encoding(t: usize, size: usize, dtype: DType) -> Array
+{
+  let encoding = (0..size).map(|i| {
+    let angle = t as f32 / 10000f32.powf((i - i % 2) as f32 / size as f32);
+    match i % 2 {
+      0 => angle.sin(),
+      _ => angle.cos(),
+    }
+  }).collect::<Vec<f32>>();
+  utils::cast(&utils::vec_to_array::<f32>(encoding, Dim4::new(&[1, size as u64, 1, 1])), dtype)
+}
+
+/// Helper that joins arrays along the features
+fn join_cols(arrays: &[Array]) -> Array
+{
+  arrays[1..].iter().fold(arrays[0].clone(), |acc, a| af::join(1, &acc, a))
+}
+
+fn matmul(a: &Array, b: &Array) -> Array
+{
+  af::matmul(a, b, MatProp::NONE, MatProp::NONE)
+}
+
+// the intermediate values of a forward pass over a sequence (one entry per timestep)
+struct Pass {
+  inputs: Vec<Array>,     // x_t + PE(t)
+  queries: Vec<Array>,
+  keys: Vec<Array>,
+  values: Vec<Array>,
+  alphas: Vec<Vec<Array>>, // [batch, time] attention weights of every head
+  contexts: Vec<Array>,   // joined heads
+  attended: Vec<Array>,   // contexts W_o + b_o
+  residuals1: Vec<Array>,
+  normed: Vec<Array>,     // y_t
+  hidden: Vec<Array>,     // relu(y_t W_1 + b_1)
+  fed: Vec<Array>,        // hidden W_2 + b_2
+  residuals2: Vec<Array>,
+  outputs: Vec<Array>,
+}
+
+impl TransformerEncoder {
+  fn head_size(&self) -> usize {
+    self.size / self.num_heads
+  }
+
+  // the columns of head h
+  fn head(&self, a: &Array, h: usize) -> Array {
+    let first = (h * self.head_size()) as u64;
+    af::cols(a, first, first + self.head_size() as u64 - 1)
+  }
+
+  // runs the block over the sequence, keeping what the backward pass needs
+  fn run(&self, ltex: &Params, xs: &[Array]) -> Pass {
+    let (w, b) = (&ltex.weights, &ltex.biases);
+    let scale = 1.0 / (self.head_size() as f32).sqrt();
+    let inputs = xs.iter().enumerate().map(|(t, x)| match self.positional_encoding {
+      true  => af::add(x, &positional_encoding(t, self.size, x.get_type()), true),
+      false => x.clone(),
+    }).collect::<Vec<Array>>();
+    let queries = inputs.iter().map(|x| matmul(x, &w[QUERY])).collect::<Vec<Array>>();
+    let keys = inputs.iter().map(|x| matmul(x, &w[KEY])).collect::<Vec<Array>>();
+    let values = inputs.iter().map(|x| matmul(x, &w[VALUE])).collect::<Vec<Array>>();
+
+    // alpha^h_t = softmax_j(q^h_t . k^h_j * scale), context^h_t = sum_j alpha^h_tj * v^h_j
+    let mut alphas = Vec::with_capacity(xs.len());
+    let mut contexts = Vec::with_capacity(xs.len());
+    for q in &queries {
+      let mut head_alphas = Vec::with_capacity(self.num_heads);
+      let mut head_contexts = Vec::with_capacity(self.num_heads);
+      for h in 0..self.num_heads {
+        let q_h = self.head(q, h);
+        let scores = keys.iter().map(|k| af::mul(&af::sum(&af::mul(&q_h, &self.head(k, h), false), 1)
+                                                 , &scale, false)).collect::<Vec<Array>>();
+        let alpha = activations::softmax(&join_cols(&scores));
+        let context = values.iter().enumerate()
+          .map(|(j, v)| af::mul(&self.head(v, h), &af::col(&alpha, j as u64), true))
+          .fold(utils::constant(q_h.dims(), q_h.get_type(), 0.0f32), |acc, c| af::add(&acc, &c, false));
+        head_alphas.push(alpha);
+        head_contexts.push(context);
+      }
+      alphas.push(head_alphas);
+      contexts.push(join_cols(&head_contexts));
+    }
+
+    let attended = contexts.iter().map(|c| layer::linear(c, &w[OUTPUT], Some(&b[OUTPUT_BIAS]), "linear"))
+      .collect::<Vec<Array>>();
+    let residuals1 = inputs.iter().zip(attended.iter()).map(|(x, a)| af::add(x, a, false))
+      .collect::<Vec<Array>>();
+    let normed = residuals1.iter().map(|r| layer::layer_norm(r, &b[LAYER_NORM_1_GAIN], &b[LAYER_NORM_1_BIAS]
+                                                             , self.epsilon)).collect::<Vec<Array>>();
+    let hidden = normed.iter().map(|y| layer::linear(y, &w[FEED_FORWARD_1], Some(&b[FEED_FORWARD_1_BIAS]), "relu"))
+      .collect::<Vec<Array>>();
+    let fed = hidden.iter().map(|h| layer::linear(h, &w[FEED_FORWARD_2], Some(&b[FEED_FORWARD_2_BIAS]), "linear"))
+      .collect::<Vec<Array>>();
+    let residuals2 = normed.iter().zip(fed.iter()).map(|(y, f)| af::add(y, f, false))
+      .collect::<Vec<Array>>();
+    let outputs = residuals2.iter().map(|r| layer::layer_norm(r, &b[LAYER_NORM_2_GAIN], &b[LAYER_NORM_2_BIAS]
+                                                              , self.epsilon)).collect::<Vec<Array>>();
+
+    Pass { inputs: inputs, queries: queries, keys: keys, values: values
+           , alphas: alphas, contexts: contexts, attended: attended
+           , residuals1: residuals1, normed: normed, hidden: hidden, fed: fed
+           , residuals2: residuals2, outputs: outputs }
+  }
+}
+
+impl Layer for TransformerEncoder
+{
+  fn forward(&self, params: Arc<Mutex<Params>>, inputs: &Array, state: Option<&Vec<Array>>) -> (Array, Option<Vec<Array>>)
+  {
+    panic!("a transformer encoder needs the whole sequence, use forward_sequence");
+  }
+
+  fn backward(&self, params: Arc<Mutex<Params>>, delta: &Array) -> Array
+  {
+    panic!("a transformer encoder needs the whole sequence, use backward_sequence");
+  }
+
+  fn forward_sequence(&self, params: Arc<Mutex<Params>>, inputs: &[Array]) -> Vec<Array>
+  {
+    // get a handle to the underlying params
+    let mut ltex = params.lock().unwrap();
+    let outputs = self.run(&ltex, inputs).outputs;
+
+    // parameter manager keeps the output & inputs
+    for (x, a_t) in inputs.iter().zip(outputs.iter()) {
+      let current_unroll = ltex.current_unroll;
+      if ltex.inputs.len() > current_unroll { // store in existing
+        ltex.inputs[current_unroll] = x.clone();
+        ltex.outputs[current_unroll] = a_t.clone();
+      }else{                                  // add new
+        ltex.inputs.push(x.clone());
+        ltex.outputs.push(a_t.clone());
+      }
+
+      // update location in vector
+      ltex.current_unroll += 1;
+    }
+
+    outputs
+  }
+
+  fn backward_sequence(&self, params: Arc<Mutex<Params>>, deltas: &[Array]) -> Vec<Array>
+  {
+    // get a handle to the underlying params
+    let mut ltex = params.lock().unwrap();
+    let current_unroll = ltex.current_unroll;
+    assert!(current_unroll >= deltas.len()
+            , "Cannot call backward pass without a forward pass over the sequence");
+
+    // the intermediate values are recomputed from the stored inputs
+    let xs = ltex.inputs[current_unroll - deltas.len()..current_unroll].to_vec();
+    let pass = self.run(&ltex, &xs);
+    let (w, b) = (ltex.weights.clone(), ltex.biases.clone());
+    let scale = 1.0 / (self.head_size() as f32).sqrt();
+    let mut grads = ltex.deltas.clone();
+    let accumulate = |grads: &mut Vec<Array>, index: usize, grad: &Array| {
+      grads[index] = af::add(&grads[index], grad, false);
+    };
+
+    // back through LN_2, the feed-forward network, LN_1 & the output projection
+    let mut dinputs = Vec::with_capacity(deltas.len());
+    let mut dcontexts = Vec::with_capacity(deltas.len());
+    for t in 0..deltas.len() {
+      let (dresidual2, dgain2, dbias2) = layer::layer_norm_backward(&deltas[t], &pass.residuals2[t]
+                                                                    , &b[LAYER_NORM_2_GAIN], self.epsilon);
+      accumulate(&mut grads, NUM_WEIGHTS + LAYER_NORM_2_GAIN, &dgain2);
+      accumulate(&mut grads, NUM_WEIGHTS + LAYER_NORM_2_BIAS, &dbias2);
+
+      let (dfed, dw2, db2) = layer::linear_backward(&dresidual2, &pass.hidden[t], &pass.fed[t], "linear");
+      accumulate(&mut grads, FEED_FORWARD_2, &dw2);
+      accumulate(&mut grads, NUM_WEIGHTS + FEED_FORWARD_2_BIAS, &db2);
+      let dhidden = af::matmul(&dfed, &w[FEED_FORWARD_2], MatProp::NONE, MatProp::TRANS);
+      let (dz1, dw1, db1) = layer::linear_backward(&dhidden, &pass.normed[t], &pass.hidden[t], "relu");
+      accumulate(&mut grads, FEED_FORWARD_1, &dw1);
+      accumulate(&mut grads, NUM_WEIGHTS + FEED_FORWARD_1_BIAS, &db1);
+      let dnormed = af::add(&dresidual2, &af::matmul(&dz1, &w[FEED_FORWARD_1], MatProp::NONE, MatProp::TRANS), false);
+
+      let (dresidual1, dgain1, dbias1) = layer::layer_norm_backward(&dnormed, &pass.residuals1[t]
+                                                                    , &b[LAYER_NORM_1_GAIN], self.epsilon);
+      accumulate(&mut grads, NUM_WEIGHTS + LAYER_NORM_1_GAIN, &dgain1);
+      accumulate(&mut grads, NUM_WEIGHTS + LAYER_NORM_1_BIAS, &dbias1);
+
+      let (dattended, dwo, dbo) = layer::linear_backward(&dresidual1, &pass.contexts[t], &pass.attended[t], "linear");
+      accumulate(&mut grads, OUTPUT, &dwo);
+      accumulate(&mut grads, NUM_WEIGHTS + OUTPUT_BIAS, &dbo);
+      dcontexts.push(af::matmul(&dattended, &w[OUTPUT], MatProp::NONE, MatProp::TRANS));
+      dinputs.push(dresidual1);
+    }
+
+    // back through the attention of every head:
+    // dalpha_tj = dcontext_t . v_j, dscore_tj = alpha_tj * (dalpha_tj - sum_k alpha_tk * dalpha_tk)
+    let zeros = |a: &Array| utils::constant(a.dims(), a.get_type(), 0.0f32);
+    let mut dqueries = Vec::with_capacity(deltas.len());
+    let mut dkeys = Vec::with_capacity(deltas.len());
+    let mut dvalues = Vec::with_capacity(deltas.len());
+    for h in 0..self.num_heads {
+      let q_h = pass.queries.iter().map(|q| self.head(q, h)).collect::<Vec<Array>>();
+      let k_h = pass.keys.iter().map(|k| self.head(k, h)).collect::<Vec<Array>>();
+      let v_h = pass.values.iter().map(|v| self.head(v, h)).collect::<Vec<Array>>();
+      let mut dq_h = q_h.iter().map(&zeros).collect::<Vec<Array>>();
+      let mut dk_h = k_h.iter().map(&zeros).collect::<Vec<Array>>();
+      let mut dv_h = v_h.iter().map(&zeros).collect::<Vec<Array>>();
+
+      for t in 0..deltas.len() {
+        let alpha = &pass.alphas[t][h];
+        let dcontext = self.head(&dcontexts[t], h);
+        let dalpha = join_cols(&v_h.iter().map(|v| af::sum(&af::mul(&dcontext, v, false), 1)).collect::<Vec<Array>>());
+        let dscore = af::mul(alpha, &af::sub(&dalpha, &af::sum(&af::mul(alpha, &dalpha, false), 1), true), false);
+        for j in 0..deltas.len() {
+          let alpha_j = af::col(alpha, j as u64);
+          let dscore_j = af::mul(&af::col(&dscore, j as u64), &scale, false);
+          dv_h[j] = af::add(&dv_h[j], &af::mul(&dcontext, &alpha_j, true), false);
+          dq_h[t] = af::add(&dq_h[t], &af::mul(&k_h[j], &dscore_j, true), false);
+          dk_h[j] = af::add(&dk_h[j], &af::mul(&q_h[t], &dscore_j, true), false);
+        }
+      }
+
+      dqueries.push(dq_h);
+      dkeys.push(dk_h);
+      dvalues.push(dv_h);
+    }
+
+    // back through the projections, the positional encoding is a constant
+    for t in 0..deltas.len() {
+      for (index, dheads) in vec![(QUERY, &dqueries), (KEY, &dkeys), (VALUE, &dvalues)] {
+        let dprojection = join_cols(&dheads.iter().map(|d| d[t].clone()).collect::<Vec<Array>>());
+        accumulate(&mut grads, index, &af::matmul(&pass.inputs[t], &dprojection, MatProp::TRANS, MatProp::NONE));
+        dinputs[t] = af::add(&dinputs[t], &af::matmul(&dprojection, &w[index], MatProp::NONE, MatProp::TRANS), false);
+      }
+    }
+
+    ltex.deltas = grads;
+    ltex.current_unroll -= deltas.len();
+    dinputs
+  }
+
+  fn is_recurrent(&self) -> bool {
+    true
+  }
+}
+
+/// Config of a transformer encoder block (see `layer::TransformerEncoder`)
+#[derive(Clone, Debug, PartialEq)]
+pub struct TransformerEncoderConfig {
+  pub size: usize,
+  pub num_heads: usize,            // needs to divide size
+  pub ff_size: usize,              // hidden size of the feed-forward network
+  pub epsilon: f32,                // of both layer normalizations
+  pub positional_encoding: bool,   // add the sinusoidal encodings to the inputs
+  pub w_init: String,
+  pub b_init: String,
+}
+
+impl TransformerEncoderConfig {
+  pub fn new(size: usize, num_heads: usize) -> TransformerEncoderConfig {
+    TransformerEncoderConfig {
+      size: size,
+      num_heads: num_heads,
+      ff_size: 4 * size,
+      epsilon: 1e-5,
+      positional_encoding: true,
+      w_init: "glorot_uniform".to_string(),
+      b_init: "zeros".to_string(),
+    }
+  }
+
+  /// Builds the config from the string params accepted by `Model::add`
+  pub fn from_params(params: &HashMap<&str, String>) -> Result<TransformerEncoderConfig, HALError> {
+    let p = Parser::new("transformer_encoder", params);
+    try!(p.check_keys(&["size", "num_heads", "ff_size", "epsilon", "positional_encoding"
+                        , "w_init", "b_init"]));
+    let mut config = TransformerEncoderConfig::new(try!(p.required("size"))
+                                                   , try!(p.optional("num_heads", 1)));
+    config.ff_size = try!(p.optional("ff_size", config.ff_size));
+    config.epsilon = try!(p.optional("epsilon", config.epsilon));
+    config.positional_encoding = try!(p.optional("positional_encoding", config.positional_encoding));
+    config.w_init = p.string("w_init", config.w_init);
+    config.b_init = p.string("b_init", config.b_init);
+    Ok(config)
+  }
+}
+
+impl LayerConfig for TransformerEncoderConfig {
+  fn layer_type(&self) -> &'static str { "transformer_encoder" }
+  fn input_size(&self) -> usize { self.size }
+  fn output_size(&self) -> usize { self.size }
+  fn needs_whole_sequence(&self) -> bool { true }
+
+  fn validate(&self) -> Result<(), HALError> {
+    let layer = self.layer_type();
+    try!(check_size(layer, "size", self.size));
+    try!(check_size(layer, "num_heads", self.num_heads));
+    if self.size % self.num_heads != 0 {
+      return Err(HALError::invalid_param(layer, "num_heads"
+                                         , format!("needs to divide the size of {}, got {}"
+                                                   , self.size, self.num_heads)));
+    }
+    try!(check_size(layer, "ff_size", self.ff_size));
+    try!(check_epsilon(layer, self.epsilon));
+    try!(check_initialization(layer, "w_init", &self.w_init));
+    check_initialization(layer, "b_init", &self.b_init)
+  }
+
+  fn to_params(&self) -> HashMap<String, String> {
+    to_map(vec![("size", self.size.to_string())
+                , ("num_heads", self.num_heads.to_string())
+                , ("ff_size", self.ff_size.to_string())
+                , ("epsilon", self.epsilon.to_string())
+                , ("positional_encoding", self.positional_encoding.to_string())
+                , ("w_init", self.w_init.clone())
+                , ("b_init", self.b_init.clone())])
+  }
+
+  fn build<T: HasAfEnum>(&self, param_manager: &mut ParamManager
+                         , manager: DeviceManager, device: Device) -> Box<Layer>
+  {
+    param_manager.add_transformer_encoder::<T>(manager, device
+                                               , self.size, self.ff_size
+                                               , &self.w_init
+                                               , &self.b_init);
+    Box::new(TransformerEncoder{size: self.size
+                                , num_heads: self.num_heads
+                                , ff_size: self.ff_size
+                                , epsilon: self.epsilon
+                                , positional_encoding: self.positional_encoding})
+  }
+}
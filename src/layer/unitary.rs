@@ -1,13 +1,17 @@
 use num;
 use af;
-use af::{Array, MatProp, Dim4, DType};
+use af::{Array, MatProp, Dim4, DType, HasAfEnum};
 
 use utils;
 use std::sync::{Arc, Mutex};
+use std::collections::HashMap;
 use activations;
 use initializations;
-use params::{Params};
+use error::HALError;
+use device::{Device, DeviceManager};
+use params::{Params, ParamManager, UnitaryGenerator};
 use layer::Layer;
+use layer::config::{LayerConfig, Parser, check_size, check_activation, check_initialization, to_map};
 
 use num::Complex;
 
@@ -15,6 +19,7 @@ use num::Complex;
 pub struct Unitary {
   pub input_size: usize,
   pub output_size: usize,
+  pub return_sequences: bool,
 }
 
 /// Compute the multiplication with the diagonal matrix of phases D
@@ -192,12 +197,11 @@ impl Layer for Unitary
       weight5 = af::div(&weight5, &sqrNorm, true);
       ltex.weights[5] = to_real(weight5.clone());
     }
-    let mut rec_t = to_complex(ltex.recurrences[t].clone());
-
-    rec_t = match state {
-      Some(init_state)    => init_state[0].clone(),
-      None                => rec_t
-    };
+    // a provided state [real, imaginary] overrides the current recurrence
+    if let Some(init_state) = state {
+      ltex.recurrences[t] = init_state[0].clone();
+    }
+    let rec_t = to_complex(ltex.recurrences[t].clone());
 
     // we compute h_t+1 = sigma1(W*h_t + V*x_t + b1) 
     let wh = wh(weight1.clone()
@@ -252,7 +256,7 @@ impl Layer for Unitary
     //println!("{}", &(af::norm(&ltex.recurrences[t], af::NormType::VECTOR_2, 1.,1.)as f32));
     ltex.current_unroll += 1;
 
-    (out.clone(), None)
+    (out.clone(), Some(vec![to_real(new_h.clone())]))
   }
 
 
@@ -511,5 +515,129 @@ impl Layer for Unitary
     ltex.current_unroll -= 1;
     new_delta
   }
+
+  fn return_sequences(&self) -> bool {
+    self.return_sequences
+  }
+
+  fn is_recurrent(&self) -> bool {
+    true
+  }
 }
 
+/// Config of a unitary RNN layer
+#[derive(Clone, Debug, PartialEq)]
+pub struct UnitaryConfig {
+  pub input_size: usize,
+  pub output_size: usize,
+  pub hidden_size: usize,
+  pub o_activation: String,     // activation of Vh + b2
+  pub h_init: String,           // initial hidden state
+  pub v_init: String,           // input to hidden matrix
+  pub phase_init: String,       // diagonal (phase) matrices
+  pub householder_init: String, // householder reflections
+  pub u_init: String,           // hidden to output matrix
+  pub h_bias_init: String,
+  pub o_bias_init: String,
+  pub is_permut_const: bool,    // identity permutation if true
+  pub return_sequences: bool,   // only output the last timestep if false
+}
+
+impl UnitaryConfig {
+  pub fn new(input_size: usize, hidden_size: usize, output_size: usize) -> UnitaryConfig {
+    UnitaryConfig {
+      input_size: input_size,
+      output_size: output_size,
+      hidden_size: hidden_size,
+      o_activation: "linear".to_string(),
+      h_init: "glorot_uniform".to_string(),
+      v_init: "glorot_uniform".to_string(),
+      phase_init: "glorot_uniform".to_string(),
+      householder_init: "glorot_uniform".to_string(),
+      u_init: "glorot_uniform".to_string(),
+      h_bias_init: "zeros".to_string(),
+      o_bias_init: "zeros".to_string(),
+      is_permut_const: false,
+      return_sequences: true,
+    }
+  }
+
+  /// Builds the config from the string params accepted by `Model::add`
+  pub fn from_params(params: &HashMap<&str, String>) -> Result<UnitaryConfig, HALError> {
+    let p = Parser::new("unitary", params);
+    try!(p.check_keys(&["input_size", "output_size", "hidden_size", "o_activation"
+                        , "h_init", "v_init", "phase_init", "householder_init", "u_init"
+                        , "h_bias_init", "o_bias_init", "is_permut_const", "return_sequences"]));
+    let mut config = UnitaryConfig::new(try!(p.required("input_size"))
+                                        , try!(p.required("hidden_size"))
+                                        , try!(p.required("output_size")));
+    config.o_activation = p.string("o_activation", config.o_activation);
+    config.h_init = p.string("h_init", config.h_init);
+    config.v_init = p.string("v_init", config.v_init);
+    config.phase_init = p.string("phase_init", config.phase_init);
+    config.householder_init = p.string("householder_init", config.householder_init);
+    config.u_init = p.string("u_init", config.u_init);
+    config.h_bias_init = p.string("h_bias_init", config.h_bias_init);
+    config.o_bias_init = p.string("o_bias_init", config.o_bias_init);
+    config.is_permut_const = try!(p.optional("is_permut_const", config.is_permut_const));
+    config.return_sequences = try!(p.optional("return_sequences", config.return_sequences));
+    Ok(config)
+  }
+}
+
+impl LayerConfig for UnitaryConfig {
+  fn layer_type(&self) -> &'static str { "unitary" }
+  fn input_size(&self) -> usize { self.input_size }
+  fn output_size(&self) -> usize { self.output_size }
+  fn return_sequences(&self) -> bool { self.return_sequences }
+
+  fn validate(&self) -> Result<(), HALError> {
+    let layer = self.layer_type();
+    try!(check_size(layer, "input_size", self.input_size));
+    try!(check_size(layer, "output_size", self.output_size));
+    try!(check_size(layer, "hidden_size", self.hidden_size));
+    try!(check_activation(layer, "o_activation", &self.o_activation));
+    try!(check_initialization(layer, "h_init", &self.h_init));
+    try!(check_initialization(layer, "v_init", &self.v_init));
+    try!(check_initialization(layer, "phase_init", &self.phase_init));
+    try!(check_initialization(layer, "householder_init", &self.householder_init));
+    try!(check_initialization(layer, "u_init", &self.u_init));
+    try!(check_initialization(layer, "h_bias_init", &self.h_bias_init));
+    check_initialization(layer, "o_bias_init", &self.o_bias_init)
+  }
+
+  fn to_params(&self) -> HashMap<String, String> {
+    to_map(vec![("input_size", self.input_size.to_string())
+                , ("output_size", self.output_size.to_string())
+                , ("hidden_size", self.hidden_size.to_string())
+                , ("o_activation", self.o_activation.clone())
+                , ("h_init", self.h_init.clone())
+                , ("v_init", self.v_init.clone())
+                , ("phase_init", self.phase_init.clone())
+                , ("householder_init", self.householder_init.clone())
+                , ("u_init", self.u_init.clone())
+                , ("h_bias_init", self.h_bias_init.clone())
+                , ("o_bias_init", self.o_bias_init.clone())
+                , ("is_permut_const", self.is_permut_const.to_string())
+                , ("return_sequences", self.return_sequences.to_string())])
+  }
+
+  fn build<T: HasAfEnum>(&self, param_manager: &mut ParamManager
+                         , manager: DeviceManager, device: Device) -> Box<Layer>
+  {
+    param_manager.add_unitary::<T>(manager, device
+                                   , self.input_size, self.output_size, self.hidden_size
+                                   , &self.o_activation
+                                   , &self.h_init
+                                   , &self.v_init
+                                   , &self.phase_init
+                                   , &self.householder_init
+                                   , &self.u_init
+                                   , &self.h_bias_init
+                                   , &self.o_bias_init
+                                   , self.is_permut_const);
+    Box::new(Unitary{input_size: self.input_size
+                     , output_size: self.output_size
+                     , return_sequences: self.return_sequences})
+  }
+}
@@ -20,14 +20,19 @@ pub mod model;
 pub use optimizer::{Optimizer};
 pub mod optimizer;
 
+pub use callback::{Callback};
+pub mod callback;
+
 pub use data::{DataSource, Data, DataParams, Normalize, Shuffle};
 pub mod data;
 
 pub mod params;
 pub mod error;
 pub mod loss;
+pub mod metrics;
 pub mod activations;
 pub mod initializations;
 pub mod plot;
 pub mod utils;
 pub mod device;
+pub mod serialize;
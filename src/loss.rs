@@ -119,7 +119,7 @@ pub fn get_loss(name: &str, pred: &Array, target: &Array) -> Result<f32, HALErro
     "cross_entropy"         => Ok(cross_entropy(pred, target)),
     "binary_cross_entropy"  => Ok(binary_cross_entropy(pred, target)),
     "cross_entropy_softmax" => Ok(cross_entropy_softmax(pred, target)),
-    _                       => Err(HALError::UNKNOWN_LOSS),
+    _                       => Err(HALError::UNKNOWN_LOSS(name.to_string())),
   }
 }
 
@@ -131,7 +131,7 @@ pub fn get_loss_vec(name: &str, pred: &Array, target: &Array) -> Result<Array, H
     "cross_entropy"         => Ok(cross_entropy_vec(pred, target)),
     "binary_cross_entropy"  => Ok(binary_cross_entropy_vec(pred, target)),
     "cross_entropy_softmax" => Ok(cross_entropy_softmax_vec(pred, target)),
-    _                       => Err(HALError::UNKNOWN_LOSS),
+    _                       => Err(HALError::UNKNOWN_LOSS(name.to_string())),
   }
 }
 
@@ -143,6 +143,6 @@ pub fn get_loss_derivative(name: &str, pred: &Array, target: &Array) -> Result<A
     "cross_entropy"         => Ok(cross_entropy_derivative(pred, target)),
     "binary_cross_entropy"  => Ok(binary_cross_entropy_derivative(pred, target)),
     "cross_entropy_softmax" => Ok(cross_entropy_softmax_derivative(pred, target)),
-    _                       => Err(HALError::UNKNOWN_LOSS),
+    _                       => Err(HALError::UNKNOWN_LOSS(name.to_string())),
   }
 }
@@ -0,0 +1,342 @@
+use af;
+use af::{Array, Dim4, DType};
+
+use utils;
+use error::HALError;
+
+/// A metric that is accumulated over (possibly many) minibatches
+///
+/// Predictions & targets are [batch, feature] or [batch, feature, time] arrays.
+/// Classification metrics expect one-hot (or probability) targets and
+/// treat the feature dimension as the class dimension.
+pub trait Metric {
+  /// Accumulates the statistics of a single minibatch
+  fn update(&mut self, pred: &Array, target: &Array);
+
+  /// Returns the value of the metric over everything accumulated so far
+  fn value(&self) -> f32;
+
+  /// Clears all accumulated statistics
+  fn reset(&mut self);
+}
+
+/// Helper to fold the time dimension into the batch dimension
+/// [batch, feature, time] --> [batch * time, feature]
+fn flatten_time(input: &Array) -> Array {
+  let dims = input.dims();
+  af::moddims(&af::reorder(input, Dim4::new(&[0, 2, 1, 3]))
+              , Dim4::new(&[dims[0] * dims[2], dims[1], 1, 1]))
+}
+
+/// Helper to return the argmax class of every row as a host vector
+fn argmax_classes(input: &Array) -> Vec<u32> {
+  let (_, classes) = af::imax(&flatten_time(input), 1);
+  let mut v = vec![0u32; classes.elements() as usize];
+  utils::cast(&classes, DType::U32).host(&mut v);
+  v
+}
+
+/// Helper to count the number of non-zero elements of a boolean array
+fn count_true(input: &Array) -> f64 {
+  af::sum_all(&utils::cast(input, DType::F32)).0
+}
+
+/// Fraction of the rows whose argmax matches the argmax of the target
+#[derive(Default)]
+pub struct Accuracy {
+  correct: f64,
+  total: f64,
+}
+
+impl Metric for Accuracy {
+  fn update(&mut self, pred: &Array, target: &Array) {
+    let (_, pred_classes) = af::imax(&flatten_time(pred), 1);
+    let (_, target_classes) = af::imax(&flatten_time(target), 1);
+    self.correct += count_true(&af::eq(&pred_classes, &target_classes, false));
+    self.total += pred_classes.elements() as f64;
+  }
+
+  fn value(&self) -> f32 {
+    (self.correct / self.total.max(1.0)) as f32
+  }
+
+  fn reset(&mut self) {
+    self.correct = 0.0;
+    self.total = 0.0;
+  }
+}
+
+/// Fraction of the rows where the target class is within the k highest predictions
+pub struct TopKAccuracy {
+  pub k: u64,
+  correct: f64,
+  total: f64,
+}
+
+impl TopKAccuracy {
+  pub fn new(k: u64) -> TopKAccuracy {
+    assert!(k > 0, "top-k accuracy needs k > 0");
+    TopKAccuracy { k: k, correct: 0.0, total: 0.0 }
+  }
+}
+
+impl Metric for TopKAccuracy {
+  fn update(&mut self, pred: &Array, target: &Array) {
+    // the target class is in the top-k if less than k classes score higher than it
+    let pred = flatten_time(pred);
+    let target = flatten_time(target);
+    let (num_rows, num_classes) = (pred.dims()[0], pred.dims()[1]);
+    let (_, target_classes) = af::imax(&target, 1);
+    let class_ids = af::range::<u32>(Dim4::new(&[num_rows, num_classes, 1, 1]), 1);
+    let one_hot = utils::cast(&af::eq(&class_ids
+                                      , &af::tile(&target_classes, Dim4::new(&[1, num_classes, 1, 1]))
+                                      , false), pred.get_type());
+    let target_score = af::sum(&af::mul(&pred, &one_hot, false), 1);
+    let num_higher = af::sum(&utils::cast(&af::gt(&pred, &target_score, true), DType::F32), 1);
+    self.correct += count_true(&af::lt(&num_higher, &(self.k as f32), false));
+    self.total += num_rows as f64;
+  }
+
+  fn value(&self) -> f32 {
+    (self.correct / self.total.max(1.0)) as f32
+  }
+
+  fn reset(&mut self) {
+    self.correct = 0.0;
+    self.total = 0.0;
+  }
+}
+
+/// Fraction of the elements where (pred > threshold) matches (target > 0.5)
+pub struct BinaryAccuracy {
+  pub threshold: f32,
+  correct: f64,
+  total: f64,
+}
+
+impl BinaryAccuracy {
+  pub fn new(threshold: f32) -> BinaryAccuracy {
+    BinaryAccuracy { threshold: threshold, correct: 0.0, total: 0.0 }
+  }
+}
+
+impl Metric for BinaryAccuracy {
+  fn update(&mut self, pred: &Array, target: &Array) {
+    let pred_labels = af::gt(pred, &self.threshold, false);
+    let target_labels = af::gt(target, &0.5f32, false);
+    self.correct += count_true(&af::eq(&pred_labels, &target_labels, false));
+    self.total += pred.elements() as f64;
+  }
+
+  fn value(&self) -> f32 {
+    (self.correct / self.total.max(1.0)) as f32
+  }
+
+  fn reset(&mut self) {
+    self.correct = 0.0;
+    self.total = 0.0;
+  }
+}
+
+/// Confusion matrix of the argmax classes
+///
+/// `counts[target * num_classes + predicted]` holds the number of rows of class `target`
+/// that were predicted as `predicted`. The number of classes is inferred from the
+/// first update. `value` returns the overall accuracy.
+#[derive(Default)]
+pub struct ConfusionMatrix {
+  pub num_classes: usize,
+  pub counts: Vec<u64>,
+}
+
+impl ConfusionMatrix {
+  /// Number of rows of class `target` that were predicted as `predicted`
+  pub fn get(&self, target: usize, predicted: usize) -> u64 {
+    self.counts[target * self.num_classes + predicted]
+  }
+
+  /// Per class precision: tp / (tp + fp)
+  pub fn precision(&self) -> Vec<f32> {
+    (0..self.num_classes).map(|c| {
+      let predicted = (0..self.num_classes).fold(0, |sum, t| sum + self.get(t, c));
+      safe_div(self.get(c, c), predicted)
+    }).collect()
+  }
+
+  /// Per class recall: tp / (tp + fn)
+  pub fn recall(&self) -> Vec<f32> {
+    (0..self.num_classes).map(|c| {
+      let actual = (0..self.num_classes).fold(0, |sum, p| sum + self.get(c, p));
+      safe_div(self.get(c, c), actual)
+    }).collect()
+  }
+
+  /// Per class F1 score: 2 * precision * recall / (precision + recall)
+  pub fn f1(&self) -> Vec<f32> {
+    self.precision().iter().zip(self.recall().iter()).map(|(p, r)| {
+      match p + r > 0.0 {
+        true  => 2.0 * p * r / (p + r),
+        false => 0.0,
+      }
+    }).collect()
+  }
+}
+
+fn safe_div(num: u64, den: u64) -> f32 {
+  match den {
+    0 => 0.0,
+    _ => num as f32 / den as f32,
+  }
+}
+
+impl Metric for ConfusionMatrix {
+  fn update(&mut self, pred: &Array, target: &Array) {
+    if self.counts.len() == 0 {
+      self.num_classes = pred.dims()[1] as usize;
+      self.counts = vec![0; self.num_classes * self.num_classes];
+    }
+    assert!(pred.dims()[1] as usize == self.num_classes
+            , "confusion matrix was built for {} classes, got {}", self.num_classes, pred.dims()[1]);
+
+    for (p, t) in argmax_classes(pred).iter().zip(argmax_classes(target).iter()) {
+      self.counts[*t as usize * self.num_classes + *p as usize] += 1;
+    }
+  }
+
+  fn value(&self) -> f32 {
+    let correct = (0..self.num_classes).fold(0, |sum, c| sum + self.get(c, c));
+    safe_div(correct, self.counts.iter().fold(0, |sum, c| sum + c))
+  }
+
+  fn reset(&mut self) {
+    for c in self.counts.iter_mut() {
+      *c = 0;
+    }
+  }
+}
+
+/// Which per class statistic a `ClassMetric` reports
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ClassMetricKind {
+  Precision,
+  Recall,
+  F1,
+}
+
+/// Per class precision, recall or F1 score
+///
+/// `value` returns the (unweighted) mean over the classes while
+/// `per_class` returns the score of every class
+pub struct ClassMetric {
+  pub kind: ClassMetricKind,
+  pub confusion: ConfusionMatrix,
+}
+
+impl ClassMetric {
+  pub fn new(kind: ClassMetricKind) -> ClassMetric {
+    ClassMetric { kind: kind, confusion: ConfusionMatrix::default() }
+  }
+
+  pub fn per_class(&self) -> Vec<f32> {
+    match self.kind {
+      ClassMetricKind::Precision => self.confusion.precision(),
+      ClassMetricKind::Recall    => self.confusion.recall(),
+      ClassMetricKind::F1        => self.confusion.f1(),
+    }
+  }
+}
+
+impl Metric for ClassMetric {
+  fn update(&mut self, pred: &Array, target: &Array) {
+    self.confusion.update(pred, target);
+  }
+
+  fn value(&self) -> f32 {
+    let scores = self.per_class();
+    scores.iter().fold(0.0, |sum, s| sum + s) / (scores.len().max(1) as f32)
+  }
+
+  fn reset(&mut self) {
+    self.confusion.reset();
+  }
+}
+
+/// Mean absolute error
+#[derive(Default)]
+pub struct MeanAbsoluteError {
+  sum: f64,
+  count: f64,
+}
+
+impl Metric for MeanAbsoluteError {
+  fn update(&mut self, pred: &Array, target: &Array) {
+    self.sum += af::sum_all(&af::abs(&af::sub(pred, target, false))).0;
+    self.count += pred.elements() as f64;
+  }
+
+  fn value(&self) -> f32 {
+    (self.sum / self.count.max(1.0)) as f32
+  }
+
+  fn reset(&mut self) {
+    self.sum = 0.0;
+    self.count = 0.0;
+  }
+}
+
+/// Root mean squared error
+#[derive(Default)]
+pub struct RootMeanSquaredError {
+  sum_squares: f64,
+  count: f64,
+}
+
+impl Metric for RootMeanSquaredError {
+  fn update(&mut self, pred: &Array, target: &Array) {
+    let diff = af::sub(pred, target, false);
+    self.sum_squares += af::sum_all(&af::mul(&diff, &diff, false)).0;
+    self.count += pred.elements() as f64;
+  }
+
+  fn value(&self) -> f32 {
+    (self.sum_squares / self.count.max(1.0)).sqrt() as f32
+  }
+
+  fn reset(&mut self) {
+    self.sum_squares = 0.0;
+    self.count = 0.0;
+  }
+}
+
+/// Helper to provide a metric from a string
+///
+/// Available metrics: accuracy, top_<k>_accuracy (eg: top_5_accuracy),
+/// binary_accuracy (threshold of 0.5), precision, recall, f1,
+/// confusion_matrix, mae & rmse
+pub fn get_metric(name: &str) -> Result<Box<Metric>, HALError> {
+  match name {
+    "accuracy"         => Ok(Box::new(Accuracy::default())),
+    "binary_accuracy"  => Ok(Box::new(BinaryAccuracy::new(0.5))),
+    "precision"        => Ok(Box::new(ClassMetric::new(ClassMetricKind::Precision))),
+    "recall"           => Ok(Box::new(ClassMetric::new(ClassMetricKind::Recall))),
+    "f1"               => Ok(Box::new(ClassMetric::new(ClassMetricKind::F1))),
+    "confusion_matrix" => Ok(Box::new(ConfusionMatrix::default())),
+    "mae"              => Ok(Box::new(MeanAbsoluteError::default())),
+    "rmse"             => Ok(Box::new(RootMeanSquaredError::default())),
+    _                  => {
+      // top_<k>_accuracy
+      let k = name.trim_left_matches("top_").trim_right_matches("_accuracy");
+      match (name.starts_with("top_") && name.ends_with("_accuracy"), k.parse::<u64>()) {
+        (true, Ok(k)) if k > 0 => Ok(Box::new(TopKAccuracy::new(k))),
+        _                      => Err(HALError::UNKNOWN_METRIC(name.to_string())),
+      }
+    },
+  }
+}
+
+/// Helper to compute a metric on a single minibatch from a string
+pub fn compute_metric(name: &str, pred: &Array, target: &Array) -> Result<f32, HALError> {
+  let mut metric = try!(get_metric(name));
+  metric.update(pred, target);
+  Ok(metric.value())
+}
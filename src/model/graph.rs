@@ -0,0 +1,733 @@
+use af;
+use af::{Array, Dim4, HasAfEnum};
+use std::cmp::max;
+use num::Zero;
+use itertools::Zip;
+use std::collections::{HashMap, VecDeque};
+
+use loss;
+use utils;
+use error::HALError;
+use layer::{Layer, LayerConfig, DenseConfig, RNNConfig, LSTMConfig, GRUConfig, UnitaryConfig, Conv2DConfig
+            , PoolType, Pool2DConfig, GlobalAvgPool2DConfig, DropoutConfig
+            , BatchNormConfig, LayerNormConfig, EmbeddingConfig, AttentionConfig
+            , NTMConfig, FullUnitaryConfig};
+use data::DataSource;
+use device::{Device, DeviceManager};
+use model;
+use model::Model;
+use optimizer::Optimizer;
+use params::ParamManager;
+
+/// How a merge node combines its inputs
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MergeMode {
+  Concat,   // joined along the feature dimension
+  Add,      // elementwise sum
+  Multiply, // elementwise product
+}
+
+enum NodeKind {
+  Input,
+  Layer(usize), // index of the layer (& its params)
+  Merge(MergeMode),
+}
+
+struct Node {
+  name: String,
+  kind: NodeKind,
+  inputs: Vec<String>,
+  input_size: usize,  // expected feature size of the input (layers only)
+  output_size: usize, // feature size of the output (merges are resolved when sorting)
+}
+
+/// A model whose layers form a directed acyclic graph
+///
+/// Every node has a unique name and consumes the outputs of the nodes named
+/// as its inputs: input nodes are fed from the model inputs, layer nodes
+/// consume exactly one node and merge nodes combine several nodes.
+/// Nodes can be added in any order, they are run in topological order.
+/// `set_outputs` is called once all the nodes were added, it validates the
+/// graph (see `get_order`) which is required to run it.
+///
+/// `Model` works on a single input and target array, thus the input nodes
+/// read consecutive feature columns of the model input (in the order in which
+/// they were added) and the outputs are joined along the feature dimension
+/// (in the order provided to `set_outputs`). The loss is computed separately
+/// for every output (on its columns of the target) and summed.
+pub struct Graph {
+  nodes: Vec<Node>,
+  layers: Vec<Box<Layer>>,
+  outputs: Vec<String>,
+  order: Option<Vec<usize>>,         // cached topological order
+  activations: Vec<Vec<Array>>,      // node activations of every forwarded timestep
+  param_manager: ParamManager,
+  optimizer: Box<Optimizer>,
+  manager: DeviceManager,
+  loss: String,
+  device: Device,
+  training: bool,
+}
+
+impl Drop for Graph {
+  fn drop(&mut self) {
+    self.manager.swap_device(self.device);
+  }
+}
+
+impl Model for Graph {
+  fn new(manager: DeviceManager
+         , optimizer: Box<Optimizer>
+         , loss: &str
+         , device: Device) -> Graph {
+    Graph {
+      nodes: Vec::new(),
+      layers: Vec::new(),
+      outputs: Vec::new(),
+      order: None,
+      activations: Vec::new(),
+      param_manager: ParamManager::default(),
+      optimizer: optimizer,
+      manager: manager,
+      loss: loss.to_string(),
+      device: device,
+      training: true,
+    }
+  }
+
+  /// Adds a new node to the graph
+  ///
+  /// Every node needs a `name` param. Layer nodes (dense, rnn, lstm, gru & unitary)
+  /// take the params of `Sequential::add` plus an `inputs` param naming the node
+  /// they consume. Merge nodes (concat, add & multiply) take a comma separated
+  /// list of node names as `inputs` and input nodes (input) take a `size` param.
+  ///
+  /// # Parameters
+  ///
+  /// - `layer` is the type of node to add
+  /// - `params` is a hashmap of params for the provided node
+  fn add<T: HasAfEnum>(&mut self, layer: &str
+                       , params: HashMap<&str, String>) -> Result<(), HALError>
+  {
+    let mut params = params;
+    let name = try!(take_param(&mut params, layer, "name"));
+    match layer {
+      "input" => {
+        let size = try!(take_param(&mut params, layer, "size"));
+        let size = try!(size.parse::<usize>().map_err(|_| {
+          HALError::invalid_param(layer, "size", format!("could not parse '{}'", size))
+        }));
+        self.add_input(&name, size)
+      },
+      "concat" | "add" | "multiply" => {
+        let mode = match layer {
+          "concat" => MergeMode::Concat,
+          "add"    => MergeMode::Add,
+          _        => MergeMode::Multiply,
+        };
+        let inputs = try!(take_param(&mut params, layer, "inputs"));
+        let inputs: Vec<&str> = inputs.split(',').map(|i| i.trim()).collect();
+        self.add_merge(&name, mode, &inputs)
+      },
+      _ => {
+        let input = try!(take_param(&mut params, layer, "inputs"));
+        match layer {
+          "dense"             => self.add_node::<T, _>(&name, &try!(DenseConfig::from_params(&params)), &input),
+          "rnn"               => self.add_node::<T, _>(&name, &try!(RNNConfig::from_params(&params)), &input),
+          "lstm"              => self.add_node::<T, _>(&name, &try!(LSTMConfig::from_params(&params)), &input),
+          "gru"               => self.add_node::<T, _>(&name, &try!(GRUConfig::from_params(&params)), &input),
+          "unitary"           => self.add_node::<T, _>(&name, &try!(UnitaryConfig::from_params(&params)), &input),
+          "full_unitary"      => self.add_node::<T, _>(&name, &try!(FullUnitaryConfig::from_params(&params)), &input),
+          "conv2d"            => self.add_node::<T, _>(&name, &try!(Conv2DConfig::from_params(&params)), &input),
+          "max_pool2d"        => self.add_node::<T, _>(&name, &try!(Pool2DConfig::from_params(PoolType::Max, &params)), &input),
+          "avg_pool2d"        => self.add_node::<T, _>(&name, &try!(Pool2DConfig::from_params(PoolType::Average, &params)), &input),
+          "global_avg_pool2d" => self.add_node::<T, _>(&name, &try!(GlobalAvgPool2DConfig::from_params(&params)), &input),
+          "dropout"           => self.add_node::<T, _>(&name, &try!(DropoutConfig::from_params(&params)), &input),
+          "batch_norm"        => self.add_node::<T, _>(&name, &try!(BatchNormConfig::from_params(&params)), &input),
+          "layer_norm"        => self.add_node::<T, _>(&name, &try!(LayerNormConfig::from_params(&params)), &input),
+          "embedding"         => self.add_node::<T, _>(&name, &try!(EmbeddingConfig::from_params(&params)), &input),
+          "attention"         => self.add_node::<T, _>(&name, &try!(AttentionConfig::from_params(&params)), &input),
+          "ntm"               => self.add_node::<T, _>(&name, &try!(NTMConfig::from_params(&params)), &input),
+          _                   => Err(HALError::UNKNOWN_LAYER(layer.to_string())),
+        }
+      },
+    }
+  }
+
+  fn set_training(&mut self, training: bool) {
+    self.training = training;
+  }
+
+  fn info(&self) {
+    println!("");
+    self.optimizer.info();
+    println!("loss:           {}\nnum_nodes:      {}\nnum_layers:     {}"
+             , self.loss, self.nodes.len(), self.layers.len());
+  }
+
+  /// Calculate the forward pass of all the nodes
+  ///
+  /// # Parameters
+  ///
+  /// - `inputs` is an array of activations [batch, feature, time] holding the
+  ///   columns of every input node
+  /// - `src_device` is the source device that the data is coming from
+  /// - `dest_device` is the destination device that the data should go to
+  ///
+  /// # Return Values
+  ///
+  /// Vector of the (joined) outputs of the graph per timestep
+  fn forward<T>(&mut self, inputs: &Array
+                , src_device: Device
+                , dest_device: Device) -> Vec<Array>
+    where T: HasAfEnum + Zero + Clone
+  {
+    let order = self.validated_order();
+
+    // check & swap if the backend matches to runtime one (if not already)
+    let activ = self.manager.swap_array_backend::<T>(&inputs, src_device, self.device);
+
+    let bptt_unroll = max(activ.dims()[2], 1);
+    let mut outputs = Vec::with_capacity(bptt_unroll as usize);
+    self.param_manager.set_all_training(self.training);
+    for t in 0..bptt_unroll {
+      let activations = self.step(&order, &af::slice(&activ, t));
+      outputs.push(self.join_outputs(&activations));
+      self.activations.push(activations);
+    }
+
+    // return to the dest device
+    outputs.iter().map(|o| self.manager.swap_array_backend::<T>(o, self.device, dest_device)).collect()
+  }
+
+  /// Fit's model to provided data
+  ///
+  /// See `Sequential::fit` for the parameters
+  fn fit<T, E>(&mut self, source: &T, src_device: Device
+               , epochs: u64, batch_size: u64, bptt_interval: Option<u64>
+               , loss_indices: Option<&Vec<bool>>, verbose: bool) -> Result<Vec<f32>, HALError>
+    where T: DataSource, E: HasAfEnum + Zero + Clone
+  {
+    // some simple data validity checks
+    let data_params = source.info();
+    let idims = data_params.input_dims;
+    let tdims = data_params.target_dims;
+    if batch_size == 0 {
+      return Err(HALError::invalid_param("fit", "batch_size", "needs to be greater than 0".to_string()));
+    }
+    try!(self.manager.check_device(src_device));
+    try!(self.manager.check_device(self.device));
+    if self.order.is_none() {
+      return Err(HALError::invalid_param("graph", "outputs"
+                                         , "set_outputs needs to be called before fitting".to_string()));
+    }
+    try!(model::verify_bptt(bptt_interval, loss_indices, max(idims[2], 1)));
+    let (input_size, output_size) = (self.input_size(), self.output_size());
+    if idims[1] != input_size as u64 {
+      return Err(HALError::SHAPE_MISMATCH {
+        context: "graph inputs".to_string(),
+        expected: Dim4::new(&[idims[0], input_size as u64, idims[2], idims[3]]),
+        actual: idims,
+      });
+    }
+    if tdims[0] != idims[0] || tdims[1] != output_size as u64 || tdims[2] != idims[2] {
+      return Err(HALError::SHAPE_MISMATCH {
+        context: "graph targets".to_string(),
+        expected: Dim4::new(&[idims[0], output_size as u64, idims[2], tdims[3]]),
+        actual: tdims,
+      });
+    }
+
+    let iters = data_params.num_samples as u64 / batch_size as u64;
+    println!("\ntrain samples: {:?} | target samples: {:?} | batch size: {}"
+             , idims, tdims, batch_size);
+    println!("epochs: {} | iterations[per epoch]: {}", epochs, iters);
+
+    let mut lossvec = Vec::<f32>::new();
+    let compute_device = self.device.clone();
+    self.param_manager.clear_all_state_derivatives();
+
+    for epoch in 0..epochs {
+      for iter in 0..iters {
+        if verbose {
+          print!("\n[epoch: {}][iter: {}] ", epoch, iter);
+        }
+
+        let (batch_input, batch_target) = try!(model::next_minibatch::<T, E>(&self.manager, compute_device, source
+                                                                             , src_device, batch_size));
+        try!(self.check_inputs(&batch_input));
+        let current_loss_vec = model::bptt_minibatch::<_, E>(self, compute_device, &batch_input, &batch_target
+                                                              , bptt_interval, loss_indices);
+        self.optimizer.update(&mut self.param_manager, batch_size as u64);
+
+        // cache and print loss (if verbose)
+        if verbose {
+          let loss_sum = current_loss_vec.iter().fold(0f32, |sum, val| sum + val);
+          let avg_loss = loss_sum / current_loss_vec.len() as f32 ;
+          print!("{} ", avg_loss);
+        }
+        lossvec.extend(current_loss_vec);
+      }
+    }
+
+    self.manager.swap_device(src_device); // return to src device
+    Ok(lossvec)
+  }
+
+  /// Calculate the gradients of all the layers and return the loss vector
+  ///
+  /// Every timestep is run through the nodes in reverse topological order,
+  /// the deltas of nodes consumed by several nodes are summed.
+  ///
+  /// # Parameters
+  ///
+  /// - `predictions` are the model predictions
+  /// - `targets` are the true targets (holding the columns of every output)
+  /// - `loss_indices` are the optional indices of losses to use while computing the gradient
+  ///
+  /// # Return Values
+  ///
+  /// Vector of losses (summed over the outputs)
+  fn backward(&mut self, predictions: &Vec<Array>, targets: &Array, loss_indices: Option<&Vec<bool>>) -> Vec<f32> {
+    let order = self.validated_order();
+
+    // setup the optimizer parameters (if not already setup)
+    self.optimizer.setup(self.param_manager.get_all_dims());
+    let mut loss_vec = Vec::with_capacity(predictions.len());
+
+    // every backward pass starts without any future state derivatives
+    self.param_manager.zero_all_state_derivatives();
+
+    if let Some(li) = loss_indices {
+      assert!(li.len() == predictions.len()
+              , "loss indices need to be of the same size as the predictions");
+    }
+
+    for ind in (0..predictions.len()).rev() {
+      let activations = self.activations.pop()
+        .expect("Cannot call backward pass without at least 1 forward pass");
+      let tar = af::slice(&targets, ind as u64);
+      let use_loss = loss_indices.map_or(true, |li| li[ind]);
+
+      // the loss (& its derivative) of every output on its target columns
+      let mut deltas: Vec<Option<Array>> = vec![None; self.nodes.len()];
+      let mut loss_sum = 0f32;
+      let mut offset = 0;
+      for output in self.outputs.iter() {
+        let index = self.node_index(output).unwrap();
+        let pred = &activations[index];
+        let size = pred.dims()[1];
+        let delta = match use_loss {
+          false => utils::constant(pred.dims(), pred.get_type(), 0.0f32),
+          true  => {
+            let out_tar = af::cols(&tar, offset, offset + size - 1);
+            loss_sum += loss::get_loss(&self.loss, pred, &out_tar).unwrap();
+            loss::get_loss_derivative(&self.loss, pred, &out_tar).unwrap()
+          },
+        };
+        accumulate(&mut deltas[index], delta);
+        offset += size;
+      }
+      if use_loss {
+        loss_vec.push(loss_sum);
+      }
+
+      for &index in order.iter().rev() {
+        // nodes that do not lead to an output still need to unwind their state
+        let delta = match deltas[index].take() {
+          Some(d) => d,
+          None    => utils::constant(activations[index].dims(), activations[index].get_type(), 0.0f32),
+        };
+        let node = &self.nodes[index];
+        let input_indices: Vec<usize> = node.inputs.iter().map(|i| self.node_index(i).unwrap()).collect();
+        match node.kind {
+          NodeKind::Input                   => (),
+          NodeKind::Layer(layer)            => {
+            let d = self.layers[layer].backward(self.param_manager.get_params(layer), &delta);
+            accumulate(&mut deltas[input_indices[0]], d);
+          },
+          NodeKind::Merge(MergeMode::Add)   => {
+            for &i in input_indices.iter() {
+              accumulate(&mut deltas[i], delta.clone());
+            }
+          },
+          NodeKind::Merge(MergeMode::Concat) => {
+            let mut col = 0;
+            for &i in input_indices.iter() {
+              let size = activations[i].dims()[1];
+              accumulate(&mut deltas[i], af::cols(&delta, col, col + size - 1));
+              col += size;
+            }
+          },
+          NodeKind::Merge(MergeMode::Multiply) => {
+            // d(x_1 * ... * x_n)/dx_i = prod_{j != i} x_j
+            for (&i, pos) in Zip::new((input_indices.iter(), 0..input_indices.len())) {
+              let others = Zip::new((input_indices.iter(), 0..input_indices.len()))
+                .filter(|&(_, p)| p != pos)
+                .fold(delta.clone(), |acc, (&j, _)| af::mul(&acc, &activations[j], false));
+              accumulate(&mut deltas[i], others);
+            }
+          },
+        }
+      }
+    }
+
+    loss_vec
+  }
+
+  /// Runs inference on the provided inputs
+  ///
+  /// Unlike `forward`, the state recorded by the layers is rolled back
+  /// afterwards, so the training state is left untouched.
+  ///
+  /// # Parameters
+  ///
+  /// - `inputs` is an array of activations [batch, feature, time] on the model's device
+  ///
+  /// # Return Values
+  ///
+  /// Array of (joined) graph outputs [batch, feature, time]
+  fn predict(&self, inputs: &Array) -> Array
+  {
+    let order = self.validated_order();
+
+    self.manager.swap_device(self.device);
+    let snapshot = self.param_manager.get_all_params();
+    self.param_manager.reset_all_unrolls();
+    self.param_manager.set_all_training(false);
+
+    let bptt_unroll = max(inputs.dims()[2], 1);
+    let mut outputs = Vec::with_capacity(bptt_unroll as usize);
+    for t in 0..bptt_unroll {
+      let activations = self.step(&order, &af::slice(inputs, t));
+      outputs.push(self.join_outputs(&activations));
+    }
+
+    // roll back everything the forward pass recorded
+    self.param_manager.set_all_params(snapshot);
+
+    let first = outputs[0].clone();
+    outputs[1..].iter().fold(first, |acc, o| af::join(2, &acc, o))
+  }
+
+  /// Evaluates the model on the test set of the provided source
+  ///
+  /// See `Sequential::evaluate`
+  fn evaluate<T, E>(&self, source: &T, src_device: Device
+                    , batch_size: u64, metrics: &[&str]) -> Result<(f32, HashMap<String, f32>), HALError>
+    where T: DataSource, E: HasAfEnum + Zero + Clone
+  {
+    model::evaluate_source::<T, E, _>(&self.manager, self.device, &self.loss
+                                      , source, src_device, batch_size, metrics
+                                      , |inputs| self.predict(inputs))
+  }
+}
+
+impl Graph {
+  /// Adds an input node reading `size` feature columns of the model input
+  pub fn add_input(&mut self, name: &str, size: usize) -> Result<(), HALError>
+  {
+    if size == 0 {
+      return Err(HALError::invalid_param("input", "size", "needs to be greater than 0".to_string()));
+    }
+    self.push_node(Node {
+      name: name.to_string(),
+      kind: NodeKind::Input,
+      inputs: Vec::new(),
+      input_size: size,
+      output_size: size,
+    })
+  }
+
+  /// Adds a layer node consuming the output of the node named `input`
+  ///
+  /// The layer is validated & built as in `Sequential::add_layer`
+  pub fn add_node<T: HasAfEnum, C: LayerConfig>(&mut self, name: &str, config: &C
+                                                , input: &str) -> Result<(), HALError>
+  {
+    try!(self.check_name(name));
+    try!(config.validate());
+    if !config.return_sequences() {
+      return Err(HALError::invalid_param(config.layer_type(), "return_sequences"
+                                         , "graph nodes run per timestep and need every output".to_string()));
+    }
+    if config.needs_whole_sequence() {
+      return Err(HALError::invalid_param(config.layer_type(), "sequence"
+                                         , "graph nodes run per timestep, not over the whole sequence".to_string()));
+    }
+    let layer = config.build::<T>(&mut self.param_manager, self.manager.clone(), self.device);
+    self.layers.push(layer);
+    let layer_index = self.layers.len() - 1;
+    self.push_node(Node {
+      name: name.to_string(),
+      kind: NodeKind::Layer(layer_index),
+      inputs: vec![input.to_string()],
+      input_size: config.input_size(),
+      output_size: config.output_size(),
+    })
+  }
+
+  /// Adds a merge node combining the outputs of the nodes named in `inputs`
+  pub fn add_merge(&mut self, name: &str, mode: MergeMode, inputs: &[&str]) -> Result<(), HALError>
+  {
+    if inputs.len() < 2 {
+      return Err(HALError::invalid_param("merge", "inputs", format!("{} needs at least 2 inputs", name)));
+    }
+    self.push_node(Node {
+      name: name.to_string(),
+      kind: NodeKind::Merge(mode),
+      inputs: inputs.iter().map(|i| i.to_string()).collect(),
+      input_size: 0,
+      output_size: 0,
+    })
+  }
+
+  /// Sets the nodes whose outputs are the outputs of the graph
+  ///
+  /// This validates the graph (see `get_order`), thus every node needs
+  /// to have been added before. The outputs are not set if the graph is invalid
+  pub fn set_outputs(&mut self, outputs: &[&str]) -> Result<(), HALError>
+  {
+    if outputs.len() == 0 {
+      return Err(HALError::invalid_param("graph", "outputs", "need at least one output".to_string()));
+    }
+    self.outputs = outputs.iter().map(|o| o.to_string()).collect();
+    self.order = None;
+    match self.sorted() {
+      Ok(_)  => Ok(()),
+      Err(e) => {
+        self.outputs.clear();
+        Err(e)
+      },
+    }
+  }
+
+  /// Returns the total number of input features (of all input nodes)
+  pub fn input_size(&self) -> usize
+  {
+    self.nodes.iter().filter(|n| match n.kind { NodeKind::Input => true, _ => false })
+      .fold(0, |sum, n| sum + n.output_size)
+  }
+
+  /// Returns the total number of output features (of all output nodes)
+  ///
+  /// The sizes of merge nodes are only known once the graph has been sorted
+  pub fn output_size(&self) -> usize
+  {
+    self.outputs.iter().filter_map(|o| self.node_index(o))
+      .fold(0, |sum, i| sum + self.nodes[i].output_size)
+  }
+
+  /// Returns the node names in the order in which they are run
+  pub fn get_order(&mut self) -> Result<Vec<String>, HALError>
+  {
+    let order = try!(self.sorted());
+    Ok(order.iter().map(|&i| self.nodes[i].name.clone()).collect())
+  }
+
+  fn check_name(&self, name: &str) -> Result<(), HALError>
+  {
+    match self.node_index(name) {
+      Some(_) => Err(HALError::invalid_param("graph", "name", format!("a node named {} already exists", name))),
+      None    => Ok(()),
+    }
+  }
+
+  fn push_node(&mut self, node: Node) -> Result<(), HALError>
+  {
+    if self.outputs.len() > 0 {
+      return Err(HALError::invalid_param("graph", &node.name
+                                         , "nodes need to be added before the outputs are set".to_string()));
+    }
+    try!(self.check_name(&node.name));
+    self.nodes.push(node);
+    self.order = None;
+    Ok(())
+  }
+
+  fn node_index(&self, name: &str) -> Option<usize>
+  {
+    self.nodes.iter().position(|n| n.name == name)
+  }
+
+  // returns the order cached by set_outputs, the graph can't be run without it
+  fn validated_order(&self) -> Vec<usize>
+  {
+    self.order.clone().expect("the graph outputs need to be set (see Graph::set_outputs) before running it")
+  }
+
+  // returns the cached topological order (sorting & resolving merge sizes if needed)
+  fn sorted(&mut self) -> Result<Vec<usize>, HALError>
+  {
+    if let Some(ref order) = self.order {
+      return Ok(order.clone());
+    }
+
+    let order = try!(self.topological_order());
+
+    // resolve & verify the feature sizes along the graph
+    for &index in order.iter() {
+      let input_sizes: Vec<usize> = self.nodes[index].inputs.iter()
+        .map(|i| self.nodes[self.node_index(i).unwrap()].output_size).collect();
+      let node = &mut self.nodes[index];
+      match node.kind {
+        NodeKind::Input                      => (),
+        NodeKind::Layer(_)                   => {
+          if input_sizes[0] != node.input_size {
+            return Err(HALError::invalid_param("graph", &node.name
+                                               , format!("input_size is {} but {} provides {} features"
+                                                         , node.input_size, node.inputs[0], input_sizes[0])));
+          }
+        },
+        NodeKind::Merge(MergeMode::Concat)   => node.output_size = input_sizes.iter().fold(0, |sum, s| sum + s),
+        NodeKind::Merge(_)                   => {
+          if input_sizes.iter().any(|&s| s != input_sizes[0]) {
+            return Err(HALError::invalid_param("graph", &node.name
+                                               , format!("elementwise merges need equally sized inputs, got {:?}"
+                                                         , input_sizes)));
+          }
+          node.output_size = input_sizes[0];
+        },
+      }
+    }
+
+    self.order = Some(order.clone());
+    Ok(order)
+  }
+
+  // Kahn's algorithm over the named inputs (ties are broken by insertion order)
+  fn topological_order(&self) -> Result<Vec<usize>, HALError>
+  {
+    if self.outputs.len() == 0 {
+      return Err(HALError::invalid_param("graph", "outputs", "no outputs were set".to_string()));
+    }
+    for output in self.outputs.iter() {
+      if self.node_index(output).is_none() {
+        return Err(HALError::invalid_param("graph", "outputs", format!("unknown node {}", output)));
+      }
+    }
+
+    let mut num_pending = vec![0; self.nodes.len()];
+    let mut consumers: Vec<Vec<usize>> = vec![Vec::new(); self.nodes.len()];
+    for (node, index) in Zip::new((self.nodes.iter(), 0..self.nodes.len())) {
+      for input in node.inputs.iter() {
+        match self.node_index(input) {
+          Some(i) => consumers[i].push(index),
+          None    => return Err(HALError::invalid_param("graph", &node.name
+                                                        , format!("unknown input node {}", input))),
+        };
+        num_pending[index] += 1;
+      }
+    }
+
+    let mut ready: VecDeque<usize> = (0..self.nodes.len()).filter(|&i| num_pending[i] == 0).collect();
+    let mut order = Vec::with_capacity(self.nodes.len());
+    while let Some(index) = ready.pop_front() {
+      order.push(index);
+      for &consumer in consumers[index].iter() {
+        num_pending[consumer] -= 1;
+        if num_pending[consumer] == 0 {
+          ready.push_back(consumer);
+        }
+      }
+    }
+
+    if order.len() != self.nodes.len() {
+      let cyclic: Vec<&str> = (0..self.nodes.len()).filter(|&i| num_pending[i] > 0)
+        .map(|i| self.nodes[i].name.as_str()).collect();
+      return Err(HALError::invalid_param("graph", "inputs", format!("the nodes {:?} form a cycle", cyclic)));
+    }
+    Ok(order)
+  }
+
+  // validates the columns of every input node with the layers it feeds
+  fn check_inputs(&self, inputs: &Array) -> Result<(), HALError>
+  {
+    let mut input_col = 0;
+    for node in self.nodes.iter() {
+      if let NodeKind::Input = node.kind {
+        let size = node.output_size as u64;
+        let columns = af::cols(inputs, input_col, input_col + size - 1);
+        for consumer in self.nodes.iter() {
+          match consumer.kind {
+            NodeKind::Layer(layer) if consumer.inputs[0] == node.name => {
+              try!(self.layers[layer].check_inputs(&columns))
+            },
+            _ => (),
+          }
+        }
+        input_col += size;
+      }
+    }
+    Ok(())
+  }
+
+  // forwards a single timestep through every node, returning all node activations
+  fn step(&self, order: &Vec<usize>, inputs: &Array) -> Vec<Array>
+  {
+    let mut activations: Vec<Option<Array>> = vec![None; self.nodes.len()];
+    let mut input_col = 0;
+    for node in self.nodes.iter() {
+      if let NodeKind::Input = node.kind {
+        let index = self.node_index(&node.name).unwrap();
+        let size = node.output_size as u64;
+        activations[index] = Some(af::cols(inputs, input_col, input_col + size - 1));
+        input_col += size;
+      }
+    }
+
+    for &index in order.iter() {
+      let node = &self.nodes[index];
+      let node_inputs: Vec<Array> = node.inputs.iter()
+        .map(|i| activations[self.node_index(i).unwrap()].clone().unwrap()).collect();
+      let activation = match node.kind {
+        NodeKind::Input                        => continue,
+        NodeKind::Layer(layer)                 => {
+          let (a, _) = self.layers[layer].forward(self.param_manager.get_params(layer)
+                                                  , &node_inputs[0], None);
+          a
+        },
+        NodeKind::Merge(MergeMode::Concat)     => join_features(&node_inputs),
+        NodeKind::Merge(MergeMode::Add)        => node_inputs[1..].iter()
+          .fold(node_inputs[0].clone(), |acc, x| af::add(&acc, x, false)),
+        NodeKind::Merge(MergeMode::Multiply)   => node_inputs[1..].iter()
+          .fold(node_inputs[0].clone(), |acc, x| af::mul(&acc, x, false)),
+      };
+      activations[index] = Some(activation);
+    }
+
+    activations.into_iter().map(|a| a.unwrap()).collect()
+  }
+
+  // joins the activations of the output nodes along the feature dimension
+  fn join_outputs(&self, activations: &Vec<Array>) -> Array
+  {
+    let outputs: Vec<Array> = self.outputs.iter()
+      .map(|o| activations[self.node_index(o).unwrap()].clone()).collect();
+    join_features(&outputs)
+  }
+}
+
+// adds a delta to the (possibly not yet set) delta of a node
+fn accumulate(delta: &mut Option<Array>, value: Array)
+{
+  *delta = Some(match delta.take() {
+    Some(d) => af::add(&d, &value, false),
+    None    => value,
+  });
+}
+
+// joins arrays along the feature dimension
+fn join_features(arrays: &[Array]) -> Array
+{
+  arrays[1..].iter().fold(arrays[0].clone(), |acc, a| af::join(1, &acc, a))
+}
+
+// removes a required string param
+fn take_param(params: &mut HashMap<&str, String>, layer: &str, field: &str) -> Result<String, HALError>
+{
+  match params.remove(field) {
+    Some(v) => Ok(v),
+    None    => Err(HALError::invalid_param(layer, field, "missing required param".to_string())),
+  }
+}
@@ -1,10 +1,21 @@
 pub use self::sequential::Sequential;
 mod sequential;
 
+pub use self::graph::{Graph, MergeMode};
+mod graph;
+
+use af;
 use num::Zero;
-use af::{Array, HasAfEnum};
+use af::{Array, Dim4, HasAfEnum};
+use std::cmp::max;
 use std::collections::HashMap;
+use itertools::Zip;
+
+use loss;
+use metrics;
+use metrics::Metric;
 
+use error::HALError;
 use device::{Device, DeviceManager};
 use data::{DataSource};
 use optimizer::Optimizer;
@@ -17,7 +28,7 @@ pub trait Model {
 
   fn fit<T, E>(&mut self, source: &T, src_device: Device
                , epochs: u64, batch_size: u64, bptt_interval: Option<u64>
-               , loss_indices: Option<&Vec<bool>>, verbose: bool) -> Vec<f32>
+               , loss_indices: Option<&Vec<bool>>, verbose: bool) -> Result<Vec<f32>, HALError>
     where T: DataSource, E: HasAfEnum + Zero + Clone;
 
   fn forward<T>(&mut self, inputs: &Array
@@ -27,6 +38,153 @@ pub trait Model {
 
   fn backward(&mut self, predictions: &Vec<Array>, targets: &Array, loss_indices: Option<&Vec<bool>>) -> Vec<f32>;
 
-  fn add<T: HasAfEnum>(&mut self, layer: &str, params: HashMap<&str, String>);
+  fn predict(&self, inputs: &Array) -> Array;
+
+  fn evaluate<T, E>(&self, source: &T, src_device: Device
+                    , batch_size: u64, metrics: &[&str]) -> Result<(f32, HashMap<String, f32>), HALError>
+    where T: DataSource, E: HasAfEnum + Zero + Clone;
+
+  fn add<T: HasAfEnum>(&mut self, layer: &str, params: HashMap<&str, String>) -> Result<(), HALError>;
+
+  /// Selects the mode of the mode dependent layers (eg: dropout) used by `forward`
+  ///
+  /// Models start in training mode; `predict` & `evaluate` always run in inference mode
+  fn set_training(&mut self, training: bool);
+
   fn info(&self);
 }
+
+/// Helper that runs `Model::evaluate` given the model's `predict`
+///
+/// Iterates over `num_test / batch_size` test minibatches, averaging the loss
+/// (per timestep, as in fit) and accumulating the requested metrics over them
+fn evaluate_source<T, E, F>(manager: &DeviceManager, device: Device, loss_name: &str
+                            , source: &T, src_device: Device, batch_size: u64
+                            , metrics: &[&str], predict: F) -> Result<(f32, HashMap<String, f32>), HALError>
+  where T: DataSource, E: HasAfEnum + Zero + Clone, F: Fn(&Array) -> Array
+{
+  if batch_size == 0 {
+    return Err(HALError::invalid_param("evaluate", "batch_size", "needs to be greater than 0".to_string()));
+  }
+  let num_batches = max(source.info().num_test / batch_size, 1);
+
+  let mut loss_sum = 0f32;
+  let mut loss_count = 0;
+  let mut accumulators: Vec<Box<Metric>> = Vec::with_capacity(metrics.len());
+  for name in metrics.iter() {
+    accumulators.push(try!(metrics::get_metric(name)));
+  }
+  for _ in 0..num_batches {
+    manager.swap_device(src_device);
+    let minibatch = source.get_test_iter(batch_size);
+    let batch_input = manager.swap_array_backend::<E>(&minibatch.input.into_inner()
+                                                      , src_device
+                                                      , device);
+    let batch_target = manager.swap_array_backend::<E>(&minibatch.target.into_inner()
+                                                       , src_device
+                                                       , device);
+    let predictions = predict(&batch_input);
+
+    // a model that does not return sequences only predicts the last targets
+    let (num_steps, num_targets) = (predictions.dims()[2], batch_target.dims()[2]);
+    let batch_target = af::slices(&batch_target, num_targets - num_steps, num_targets - 1);
+    for t in 0..max(num_steps, 1) {
+      loss_sum += try!(loss::get_loss(loss_name, &af::slice(&predictions, t)
+                                      , &af::slice(&batch_target, t)));
+      loss_count += 1;
+    }
+    for metric in accumulators.iter_mut() {
+      metric.update(&predictions, &batch_target);
+    }
+  }
+
+  manager.swap_device(src_device); // return to src device
+  let metric_values = Zip::new((metrics.iter(), accumulators.iter()))
+    .map(|(name, metric)| (name.to_string(), metric.value()))
+    .collect();
+  Ok((loss_sum / loss_count as f32, metric_values))
+}
+
+/// Helper that pulls the next training minibatch of `source` onto the compute `device`
+///
+/// Returns the [inputs, targets] of the minibatch
+fn next_minibatch<T, E>(manager: &DeviceManager, device: Device, source: &T
+                        , src_device: Device, batch_size: u64) -> Result<(Array, Array), HALError>
+  where T: DataSource, E: HasAfEnum + Zero + Clone
+{
+  // extract part of the array onto the GPU
+  manager.swap_device(src_device);
+  let minibatch = source.get_train_iter(batch_size);
+  try!(check_batch_rows("minibatch inputs", minibatch.input.borrow().dims(), batch_size));
+  try!(check_batch_rows("minibatch targets", minibatch.target.borrow().dims(), batch_size));
+  let batch_input = manager.swap_array_backend::<E>(&minibatch.input.into_inner()
+                                                    , src_device
+                                                    , device);
+  let batch_target = manager.swap_array_backend::<E>(&minibatch.target.into_inner()
+                                                     , src_device
+                                                     , device);
+  Ok((batch_input, batch_target))
+}
+
+/// Helper that runs `Model::forward` & `Model::backward` over a minibatch
+///
+/// If `bptt_interval` is specified the minibatch is sliced into `bptt_interval`
+/// long sequences (truncated BPTT), the model updates its parameters after that.
+/// Returns the losses of the last slice
+fn bptt_minibatch<M, E>(model: &mut M, device: Device, batch_input: &Array, batch_target: &Array
+                        , bptt_interval: Option<u64>, loss_indices: Option<&Vec<bool>>) -> Vec<f32>
+  where M: Model, E: HasAfEnum + Zero + Clone
+{
+  let mut current_loss_vec = Vec::new();
+  if let Some(bptt_interval) = bptt_interval {
+    let num_seqs = batch_input.dims()[2]/bptt_interval;
+    for seq in 0..num_seqs {
+      let (begin, end) = (seq * bptt_interval, (seq + 1) * bptt_interval);
+      let bptt_input_slice = af::slices(batch_input, begin, end-1);
+      let bptt_target_slice = af::slices(batch_target, begin, end-1);
+      let a_t = model.forward::<E>(&bptt_input_slice, device, device);
+      current_loss_vec = model.backward(&a_t, &bptt_target_slice, loss_indices);
+    }
+  }else{
+    let a_t = model.forward::<E>(batch_input, device, device);
+    current_loss_vec = model.backward(&a_t, batch_target, loss_indices);
+  }
+  current_loss_vec
+}
+
+/// Helper that checks the truncated BPTT interval & the loss indices before fitting
+///
+/// `num_predictions` is the number of predictions of every backward pass without
+/// truncation (the sequence length or 1 for a model that only returns the last timestep)
+fn verify_bptt(bptt_interval: Option<u64>, loss_indices: Option<&Vec<bool>>
+               , num_predictions: u64) -> Result<(), HALError>
+{
+  let num_predictions = match bptt_interval {
+    Some(0)             => {
+      return Err(HALError::invalid_param("fit", "bptt_interval", "needs to be greater than 0".to_string()));
+    },
+    Some(bptt_interval) => bptt_interval,
+    None                => num_predictions,
+  };
+
+  match loss_indices {
+    Some(li) if li.len() as u64 != num_predictions => {
+      Err(HALError::invalid_param("fit", "loss_indices"
+                                  , format!("{} indices provided for {} predictions", li.len(), num_predictions)))
+    },
+    _ => Ok(()),
+  }
+}
+
+// checks that an array holds batch_size rows
+fn check_batch_rows(context: &str, dims: Dim4, batch_size: u64) -> Result<(), HALError>
+{
+  match dims[0] == batch_size {
+    true  => Ok(()),
+    false => Err(HALError::SHAPE_MISMATCH {
+      context: context.to_string(),
+      expected: Dim4::new(&[batch_size, dims[1], dims[2], dims[3]]),
+      actual: dims,
+    }),
+  }
+}
@@ -1,38 +1,85 @@
 use af;
-use af::{Array, Backend, HasAfEnum};
-use std::cmp::max;
-use num::Zero;
+use af::{Array, Backend, DType, HasAfEnum};
+use std::cmp::{max, min};
+use num::{Complex, Zero};
 use itertools::Zip;
 use std::default::Default;
+use std::mem;
 use std::collections::HashMap;
 
 use loss;
 use utils;
-use layer::{Layer, Dense, RNN, Unitary};//, LSTM};
-use data::{DataSource};
+use callback::Callback;
+use error::HALError;
+use layer::{Layer, LayerConfig, DenseConfig, RNNConfig, LSTMConfig, GRUConfig, UnitaryConfig, Conv2DConfig
+            , PoolType, Pool2DConfig, GlobalAvgPool2DConfig, DropoutConfig
+            , BatchNormConfig, LayerNormConfig, EmbeddingConfig
+            , BidirectionalMerge, BidirectionalConfig, AttentionConfig
+            , TransformerEncoderConfig, NTMConfig, ACTConfig, FullUnitaryConfig};
+use data::{DataSource, DataParams};
 use device::{Device, DeviceManager, DeviceManagerFactory};
+use model;
 use model::Model;
 use optimizer::{Optimizer, SGD};
-use params::{ParamManager, DenseGenerator, LSTMGenerator, RNNGenerator, UnitaryGenerator};
+use serialize;
+use serialize::{ArrayRecord, CheckpointRecord, LayerRecord, ModelRecord, OptimizerRecord};
+use params::{ParamManager, Params};
+
+/// How the gradients of a minibatch are computed while fitting
+#[derive(Clone, Copy)]
+enum Unroll {
+  BPTT(Option<u64>), // backprop through time (truncated to the optional interval)
+  RTRL,              // real-time recurrent learning (one timestep at a time)
+}
+
+// the constructor arguments of a layer, kept around for serialization
+struct LayerSpec {
+  layer_type: String,
+  params: HashMap<String, String>,
+  num_optional: usize,  // optional arrays allocated at construction (eg: permutations)
+  whole_sequence: bool, // the layer can't be run one timestep at a time
+}
 
 pub struct Sequential {
   layers: Vec<Box<Layer>>,
+  layer_specs: Vec<LayerSpec>,
   param_manager: ParamManager,
   optimizer: Box<Optimizer>,
   manager: DeviceManager,
   loss: String,
   device: Device,
+  checkpoint_path: Option<String>,
+  checkpoint_interval: u64,
+  validate: bool,
+  validation_patience: Option<u64>,
+  validation_losses: Vec<f32>,
+  best_validation: Option<(f32, Vec<Array>)>, // best validation loss & the params that produced it
+  epochs_without_improvement: u64,
+  callbacks: Vec<Box<Callback>>,
+  stop_requested: bool,
+  training: bool,
 }
 
 impl Default for Sequential {
   fn default() -> Sequential {
     Sequential {
       layers: Vec::new(),
+      layer_specs: Vec::new(),
       param_manager: ParamManager::default(),
       optimizer: Box::new(SGD::default()),
       manager: DeviceManagerFactory::new(),
       loss: "mse".to_string(),
       device: Device{ backend: Backend::DEFAULT, id: 0 },
+      checkpoint_path: None,
+      checkpoint_interval: 0,
+      validate: false,
+      validation_patience: None,
+      validation_losses: Vec::new(),
+      best_validation: None,
+      epochs_without_improvement: 0,
+      callbacks: Vec::new(),
+      stop_requested: false,
+      training: true,
     }
   }
 }
@@ -50,98 +97,72 @@ impl Model for Sequential {
          , device: Device) -> Sequential {
     Sequential {
       layers: Vec::new(),
+      layer_specs: Vec::new(),
       param_manager: ParamManager::default(),
       manager: manager,
       loss: loss.to_string(),
       optimizer: optimizer,
       device: device,
+      checkpoint_path: None,
+      checkpoint_interval: 0,
+      validate: false,
+      validation_patience: None,
+      validation_losses: Vec::new(),
+      best_validation: None,
+      epochs_without_improvement: 0,
+      callbacks: Vec::new(),
+      stop_requested: false,
+      training: true,
     }
   }
 
   /// Adds a new layer to the sequential model
   ///
   /// Given a layer type and provided parameters this function
-  /// will add the required parameters to the sequential model
+  /// will add the required parameters to the sequential model.
+  /// This is a thin adapter over `Sequential::add_layer`: the params
+  /// are parsed into the matching config (missing params take the
+  /// config defaults).
   ///
   /// # Parameters
   ///
   /// - `layer` is the type of layer to add
   /// - `params` is a hashmap of params for the provided layer
+  ///
+  /// # Return Values
+  ///
+  /// `HALError::UNKNOWN_LAYER` or `HALError::INVALID_PARAM` if the layer can not be built
   fn add<T: HasAfEnum>(&mut self, layer: &str
-                       , params: HashMap<&str, String>)
+                       , params: HashMap<&str, String>) -> Result<(), HALError>
   {
-    //TODO: Error handling for hashmap
-    let input_size = params.get("input_size").unwrap().parse::<u64>().unwrap() as usize;
-    let output_size = params.get("output_size").unwrap().parse::<u64>().unwrap() as usize;
     match layer {
-      "dense" => {
-        self.param_manager.add_dense::<T>(self.manager.clone(), self.device
-                                          , input_size, output_size
-                                          , params.get("activation").unwrap()
-                                          , params.get("w_init").unwrap()
-                                          , params.get("b_init").unwrap());
-        self.layers.push(Box::new(Dense{input_size: input_size
-                                        , output_size: output_size}));
-      },
-      "rnn" => {
-        let hidden_size = params.get("hidden_size").unwrap().parse::<u64>().unwrap() as usize;
-        self.param_manager.add_rnn::<T>(self.manager.clone(), self.device
-                                        , input_size, hidden_size, output_size
-                                        , params.get("inner_activation").unwrap()
-                                        , params.get("outer_activation").unwrap()
-                                        , params.get("w_init").unwrap()
-                                        , params.get("b_init").unwrap());
-        self.layers.push(Box::new(RNN{input_size: input_size
-                                      , hidden_size: hidden_size
-                                      , output_size: output_size}));
-      }
-      // "lstm"  => {
-      //   self.param_manager.add_lstm::<T>(self.manager.clone(), self.device
-      //                               , input_size, output_size
-      //                               , params.get("input_activation").unwrap()
-      //                               , params.get("outer_activation").unwrap()
-      //                               , params.get("w_init").unwrap()
-      //                               , params.get("w_recurrent_init").unwrap()
-      //                               , params.get("forget_b_init").unwrap()
-      //                               , params.get("b_init").unwrap());
-      //   self.layers.push(Box::new(LSTM{input_size: input_size
-      //                                  , output_size: output_size}));
-      // },
-      
-      "unitary" => { 
-          let hidden_size = params.get("hidden_size").unwrap().parse::<u64>().unwrap() as usize;
-          self.param_manager.add_unitary::<T>(self.manager.clone(), self.device
-                                            , input_size, output_size, hidden_size 
-
-                                            // activations for Ux + Wh + b1 and for Vh + b2
-                                            , params.get("o_activation").unwrap()
-
-                                            // init hidden state values
-                                            , params.get("h_init").unwrap()
-
-                                            // init values for input2hidden matrix params
-                                            , params.get("v_init").unwrap()
-                                            
-                                            // init values for unitary matrices params
-                                            , params.get("phase_init").unwrap()
-                                            , params.get("householder_init").unwrap()
-
-                                            // init values for hidden2output matrix params
-                                            , params.get("u_init").unwrap()
-                                            
-                                            // init biases values
-                                            , params.get("h_bias_init").unwrap()
-                                            , params.get("o_bias_init").unwrap()
-                                            , params.get("is_permut_const").unwrap().parse::<bool>().unwrap()
-                                            ); 
-            self.layers.push(Box::new(Unitary{input_size: input_size
-                                        , output_size: output_size}));
-     }
-
-      _  => panic!("Error unknown layer type"),
+      "dense"             => self.add_layer::<T, _>(&try!(DenseConfig::from_params(&params))),
+      "rnn"               => self.add_layer::<T, _>(&try!(RNNConfig::from_params(&params))),
+      "lstm"              => self.add_layer::<T, _>(&try!(LSTMConfig::from_params(&params))),
+      "gru"               => self.add_layer::<T, _>(&try!(GRUConfig::from_params(&params))),
+      "unitary"           => self.add_layer::<T, _>(&try!(UnitaryConfig::from_params(&params))),
+      "full_unitary"      => self.add_layer::<T, _>(&try!(FullUnitaryConfig::from_params(&params))),
+      "conv2d"            => self.add_layer::<T, _>(&try!(Conv2DConfig::from_params(&params))),
+      "max_pool2d"        => self.add_layer::<T, _>(&try!(Pool2DConfig::from_params(PoolType::Max, &params))),
+      "avg_pool2d"        => self.add_layer::<T, _>(&try!(Pool2DConfig::from_params(PoolType::Average, &params))),
+      "global_avg_pool2d" => self.add_layer::<T, _>(&try!(GlobalAvgPool2DConfig::from_params(&params))),
+      "dropout"           => self.add_layer::<T, _>(&try!(DropoutConfig::from_params(&params))),
+      "batch_norm"        => self.add_layer::<T, _>(&try!(BatchNormConfig::from_params(&params))),
+      "layer_norm"        => self.add_layer::<T, _>(&try!(LayerNormConfig::from_params(&params))),
+      "embedding"         => self.add_layer::<T, _>(&try!(EmbeddingConfig::from_params(&params))),
+      "attention"         => self.add_layer::<T, _>(&try!(AttentionConfig::from_params(&params))),
+      "ntm"               => self.add_layer::<T, _>(&try!(NTMConfig::from_params(&params))),
+      "transformer_encoder" => self.add_layer::<T, _>(&try!(TransformerEncoderConfig::from_params(&params))),
+      "bidirectional"     => self.add_bidirectional::<T>(params),
+      "act"               => self.add_act::<T>(params),
+      _                   => Err(HALError::UNKNOWN_LAYER(layer.to_string())),
     }
   }
 
+  fn set_training(&mut self, training: bool) {
+    self.training = training;
+  }
+
   //TODO: convert to log crate w/ hashmap
   fn info(&self) {
     println!("");
@@ -174,24 +195,25 @@ impl Model for Sequential {
     // if dim[3] > 1 we assume we have an RNN
     // we will need to unwind at least once for non RNNs
     let bptt_unroll = max(activ.dims()[2], 1);
-    let mut activate;
+    self.param_manager.set_all_training(self.training);
 
-    for t in 0..bptt_unroll {
-      activate = af::slice(&activ, t);
-      for i in 0..self.layers.len() {
-        let (a, _) = self.layers[i].forward(self.param_manager.get_params(i)
-                                            , &activate, None);
-        activate = a;
-      }
+    // every layer sees the whole sequence (eg: for bidirectional layers)
+    let mut activations: Vec<Array> = (0..bptt_unroll).map(|t| af::slice(&activ, t)).collect();
+    for i in 0..self.layers.len() {
+      activations = self.layers[i].forward_sequence(self.param_manager.get_params(i), &activations);
     }
 
     // TODO: Parameterize
     // zero the states
     // self.param_manager.zero_all_states(None);
 
-    // return the collected outputs of the last layer
+    // return the collected outputs of the last layer, only the
+    // last timestep reaches it if a layer does not return sequences
     let last_index = self.layers.len() - 1;
-    let mut outputs = self.param_manager.get_outputs(last_index);
+    let mut outputs = match self.layers.iter().all(|layer| layer.return_sequences()) {
+      true  => self.param_manager.get_outputs(last_index),
+      false => activations,
+    };
 
     // return to the dest device
     for i in 0..outputs.len() {
@@ -218,26 +240,265 @@ impl Model for Sequential {
   ///
   /// # Return Values
   ///
-  /// Vector of losses
+  /// Vector of losses or an error if the model, data or devices are inconsistent
   fn fit<T, E>(&mut self, source: &T, src_device: Device
                , epochs: u64, batch_size: u64, bptt_interval: Option<u64>
-               , loss_indices: Option<&Vec<bool>>, verbose: bool) -> Vec<f32>
+               , loss_indices: Option<&Vec<bool>>, verbose: bool) -> Result<Vec<f32>, HALError>
     where T: DataSource, E: HasAfEnum + Zero + Clone
   {
-    // some simple data validity checks
-    let data_params = source.info();
+    self.validation_losses.clear();
+    self.best_validation = None;
+    self.epochs_without_improvement = 0;
+    self.fit_from::<T, E>(source, src_device, epochs, batch_size, Unroll::BPTT(bptt_interval)
+                          , loss_indices, verbose, 0, 0)
+  }
+
+
+  /// Calculate the layer gradients and return the loss vector
+  ///
+  /// Given predictions and output data, this function computes all the gradients for all
+  /// of the trainable parameters in the layers
+  ///
+  /// # Parameters
+  ///
+  /// - `predictions` are the model predictions
+  /// - `targets` are the true targets
+  /// - `loss_indices` are the optional indices of losses to use while computing the gradient
+  ///
+  /// # Return Values
+  ///
+  /// Vector of losses
+  fn backward(&mut self, predictions: &Vec<Array>, targets: &Array, loss_indices: Option<&Vec<bool>>) -> Vec<f32> {
+    // setup the optimizer parameters (if not already setup)
+    self.optimizer.setup(self.param_manager.get_all_dims());
+    let mut loss_vec = Vec::with_capacity(predictions.len());
+
+    // every backward pass starts without any future state derivatives
+    self.param_manager.zero_all_state_derivatives();
+
+    // the auxiliary losses of the layers (eg: the ponder cost of ACT) are
+    // part of the reported loss, the layers add their gradients themselves
+    // (the ones of the timesteps past the predictions are reported with the last one)
+    let mut auxiliary = vec![0.0f32; predictions.len()];
+    for i in 0..self.layers.len() {
+      if let Some(costs) = self.layers[i].auxiliary_loss(self.param_manager.get_params(i)) {
+        for (t, cost) in costs.iter().enumerate() {
+          auxiliary[min(t, predictions.len() - 1)] += *cost;
+        }
+      }
+    }
+
+    // the predictions of a model that does not return sequences
+    // are the last timesteps, thus they match the last targets
+    let offset = targets.dims()[2] as usize - predictions.len();
+    let mut deltas = Vec::with_capacity(predictions.len());
+    for (pred, ind) in Zip::new((predictions.iter().rev(), (0..predictions.len()).rev()))
+    {
+      let tar = af::slice(&targets, (offset + ind) as u64);
+
+      // handle loss indices that are not to be allowed
+      let delta = match loss_indices {
+        Some(li) => {
+          assert!(li.len() == predictions.len()
+                  , "loss indices need to be of the same size as the predictions");
+          match li[ind] {
+            false => utils::constant(tar.dims(), tar.get_type(), 0.0f32),
+            true  => {
+              loss_vec.push(loss::get_loss(&self.loss, pred, &tar).unwrap() + auxiliary[ind]);
+              loss::get_loss_derivative(&self.loss, pred, &tar).unwrap()
+            },
+          }
+        },
+        None     => {
+          loss_vec.push(loss::get_loss(&self.loss, pred, &tar).unwrap() + auxiliary[ind]);
+          loss::get_loss_derivative(&self.loss, pred, &tar).unwrap()
+        },
+      };
+      deltas.push(delta);
+    }
+
+    // backpropagate the deltas of the whole sequence (in time order) through every layer
+    deltas.reverse();
+    for i in (0..self.layers.len()).rev() {
+      deltas = self.layers[i].backward_sequence(self.param_manager.get_params(i), &deltas);
+    }
+
+    loss_vec
+  }
+
+  /// Runs inference on the provided inputs
+  ///
+  /// Unlike `forward`, the inputs/outputs/states recorded by the layers
+  /// are rolled back afterwards, so the training state is left untouched.
+  /// The prediction starts from the current recurrent states of the model.
+  ///
+  /// # Parameters
+  ///
+  /// - `inputs` is an array of activations [batch, feature, time] on the model's device
+  ///
+  /// # Return Values
+  ///
+  /// Array of model outputs [batch, feature, time]
+  fn predict(&self, inputs: &Array) -> Array
+  {
+    self.manager.swap_device(self.device);
+    let snapshot = self.param_manager.get_all_params();
+    self.param_manager.reset_all_unrolls();
+    self.param_manager.set_all_training(false);
+
+    let bptt_unroll = max(inputs.dims()[2], 1);
+    let mut outputs: Vec<Array> = (0..bptt_unroll).map(|t| af::slice(inputs, t)).collect();
+    for i in 0..self.layers.len() {
+      outputs = self.layers[i].forward_sequence(self.param_manager.get_params(i), &outputs);
+    }
+
+    // roll back everything the forward pass recorded
+    self.param_manager.set_all_params(snapshot);
+
+    let first = outputs[0].clone();
+    outputs[1..].iter().fold(first, |acc, o| af::join(2, &acc, o))
+  }
+
+  /// Evaluates the model on the test set of the provided source
+  ///
+  /// Iterates over `num_test / batch_size` test minibatches, averaging
+  /// the loss and accumulating the requested metrics over them
+  ///
+  /// # Parameters
+  ///
+  /// - `source` is the datasource
+  /// - `src_device` is the source device of the data
+  /// - `batch_size` is the minibatch size
+  /// - `metrics` are the names of the metrics to compute (see `metrics::get_metric`)
+  ///
+  /// # Return Values
+  ///
+  /// The average loss and a map of metric names to their average values. An
+  /// unknown metric or a zero `batch_size` is reported as an error
+  fn evaluate<T, E>(&self, source: &T, src_device: Device
+                    , batch_size: u64, metrics: &[&str]) -> Result<(f32, HashMap<String, f32>), HALError>
+    where T: DataSource, E: HasAfEnum + Zero + Clone
+  {
+    model::evaluate_source::<T, E, _>(&self.manager, self.device, &self.loss
+                                      , source, src_device, batch_size, metrics
+                                      , |inputs| self.predict(inputs))
+  }
+}
+
+impl Sequential {
+  /// Adds a new layer to the sequential model from a typed config
+  ///
+  /// The config is validated before any parameter is allocated,
+  /// so the model is left untouched if an error is returned.
+  ///
+  /// # Parameters
+  ///
+  /// - `config` is the config of the layer to add (eg: `DenseConfig`)
+  ///
+  /// # Return Values
+  ///
+  /// `HALError::INVALID_PARAM` describing the first bad field (if any)
+  pub fn add_layer<T: HasAfEnum, C: LayerConfig>(&mut self, config: &C) -> Result<(), HALError>
+  {
+    try!(config.validate());
+    let layer = config.build::<T>(&mut self.param_manager, self.manager.clone(), self.device);
+    self.layers.push(layer);
+
+    // cache the constructor params so that the model can be serialized
+    let layer_index = self.layers.len() - 1;
+    self.layer_specs.push(LayerSpec {
+      layer_type: config.layer_type().to_string(),
+      params: config.to_params(),
+      num_optional: self.param_manager.get_optionals(layer_index).len(),
+      whole_sequence: config.needs_whole_sequence(),
+    });
+    Ok(())
+  }
+
+  /// Helper that adds a bidirectional layer from the string params of `Model::add`
+  ///
+  /// `layer` names the wrapped recurrent layer and `merge` the merge mode
+  /// (concat by default), the remaining params are the ones of the wrapped layer
+  fn add_bidirectional<T: HasAfEnum>(&mut self, mut params: HashMap<&str, String>) -> Result<(), HALError>
+  {
+    let layer = try!(params.remove("layer").ok_or(
+      HALError::invalid_param("bidirectional", "layer", "missing required param".to_string())));
+    let merge = match params.remove("merge") {
+      Some(name) => try!(BidirectionalMerge::from_name(&name)),
+      None       => BidirectionalMerge::Concat,
+    };
+
+    match &layer[..] {
+      "rnn"     => self.add_layer::<T, _>(&BidirectionalConfig::new(try!(RNNConfig::from_params(&params)), merge)),
+      "lstm"    => self.add_layer::<T, _>(&BidirectionalConfig::new(try!(LSTMConfig::from_params(&params)), merge)),
+      "gru"     => self.add_layer::<T, _>(&BidirectionalConfig::new(try!(GRUConfig::from_params(&params)), merge)),
+      "unitary" => self.add_layer::<T, _>(&BidirectionalConfig::new(try!(UnitaryConfig::from_params(&params)), merge)),
+      _         => Err(HALError::invalid_param("bidirectional", "layer"
+                                               , format!("needs a recurrent layer, got '{}'", layer))),
+    }
+  }
+
+  /// Helper that adds an ACT wrapper from the string params of `Model::add`
+  ///
+  /// `layer` names the wrapped cell (rnn or unitary) and `input_size` is the one of
+  /// the wrapper, the params of `ACTConfig` are picked out and the rest go to the cell
+  fn add_act<T: HasAfEnum>(&mut self, mut params: HashMap<&str, String>) -> Result<(), HALError>
+  {
+    let layer = try!(params.remove("layer").ok_or(
+      HALError::invalid_param("act", "layer", "missing required param".to_string())));
+    let mut act_params = HashMap::new();
+    for key in &["max_steps", "ponder_cost", "epsilon", "halting_w_init", "halting_b_init"] {
+      if let Some(value) = params.remove(key) {
+        act_params.insert(*key, value);
+      }
+    }
+
+    // the cell also reads the first pondering step flag
+    if let Some(input_size) = params.remove("input_size") {
+      let input_size = try!(input_size.parse::<usize>().map_err(|_| HALError::invalid_param(
+        "act", "input_size", format!("could not parse '{}'", input_size))));
+      params.insert("input_size", (input_size + 1).to_string());
+    }
+
+    match &layer[..] {
+      "rnn"     => self.add_layer::<T, _>(&try!(ACTConfig::from_params(try!(RNNConfig::from_params(&params)), &act_params))),
+      "unitary" => self.add_layer::<T, _>(&try!(ACTConfig::from_params(try!(UnitaryConfig::from_params(&params)), &act_params))),
+      _         => Err(HALError::invalid_param("act", "layer"
+                                               , format!("needs an rnn or unitary cell, got '{}'", layer))),
+    }
+  }
+
+  /// Helper that runs some simple data validity checks before fitting
+  ///
+  /// Returns the number of iterations per epoch
+  fn verify_fit_params(&self, data_params: &DataParams, src_device: Device
+                       , epochs: u64, batch_size: u64) -> Result<u64, HALError>
+  {
     let idims = data_params.input_dims;
     let tdims = data_params.target_dims;
+    if batch_size == 0 {
+      return Err(HALError::invalid_param("fit", "batch_size", "needs to be greater than 0".to_string()));
+    }
     let iters =  data_params.num_samples as u64 / batch_size as u64;
     println!("\ntrain samples: {:?} | target samples: {:?} | batch size: {}"
              , idims, tdims, batch_size);
     println!("epochs: {} | iterations[per epoch]: {}", epochs, iters);
-    assert!(idims[0] == tdims[0]
-            , "batch sizes for inputs and targets much be equal");
-    assert!(idims[2] == tdims[2]
-            , "sequence lengths for inputs and targets must be equal");
-    assert!(self.layers.len() > 0
-            , "Need at least one layer to fit!");
+    try!(self.manager.check_device(src_device));
+    try!(self.manager.check_device(self.device));
+    if idims[0] != tdims[0] || idims[2] != tdims[2] {
+      return Err(HALError::SHAPE_MISMATCH {
+        context: "batch size & sequence length of the targets".to_string(),
+        expected: idims,
+        actual: tdims,
+      });
+    }
+    if self.layers.len() == 0 {
+      return Err(HALError::invalid_param("sequential", "layers", "need at least one layer to fit".to_string()));
+    }
+    if self.validate && data_params.num_validation.is_none() {
+      return Err(HALError::invalid_param("sequential", "validation"
+                                         , "the data source does not provide a validation set".to_string()));
+    }
 
     // verify that last layer is of logits type when using
     // softmax_crossentropy or binary_crossentropy
@@ -246,71 +507,96 @@ impl Model for Sequential {
     {
       let last_layer_index = self.layers.len() - 1;
       let last_layer_activations = self.param_manager.get_activations(last_layer_index);
-      let last_activation = last_layer_activations.last().unwrap();
-      assert!(last_activation == "ones" || last_activation == "linear",
-              "Erroneous results expected while using cross_entropy_* \
-               loss and non-logit units in the last layer: {}"
-              , last_activation);
+      let last_activation = last_layer_activations.last().map(|a| a.as_str()).unwrap_or("linear");
+      if last_activation != "ones" && last_activation != "linear" {
+        return Err(HALError::invalid_param("sequential", "loss"
+                                           , format!("erroneous results expected while using {} \
+                                                      loss and non-logit units in the last layer: {}"
+                                                     , self.loss, last_activation)));
+      }
     }
 
+    Ok(iters)
+  }
+
+  /// Helper that checks the unroll & loss indices against the model and the data
+  ///
+  /// The loss indices select among the predictions of every backward pass: one per
+  /// timestep (of a truncated bptt slice) or a single one if the model only returns
+  /// the last timestep
+  fn verify_unroll(&self, data_params: &DataParams, unroll: Unroll
+                   , loss_indices: Option<&Vec<bool>>) -> Result<(), HALError>
+  {
+    let bptt_interval = match unroll {
+      Unroll::BPTT(bptt_interval) => bptt_interval,
+      Unroll::RTRL                => None,
+    };
+    match self.layers.iter().all(|layer| layer.return_sequences()) {
+      true                            => model::verify_bptt(bptt_interval, loss_indices
+                                                            , max(data_params.input_dims[2], 1)),
+      false if bptt_interval.is_some() => {
+        Err(HALError::invalid_param("fit", "bptt_interval"
+                                    , "truncated bptt needs the output of every timestep".to_string()))
+      },
+      false                           => model::verify_bptt(None, loss_indices, 1),
+    }
+  }
+
+  /// Fit's model to provided data starting from a given position
+  ///
+  /// This is the training loop used by `fit` & `fit_rtrl` (which start at [0, 0])
+  /// and by `resume` (which starts where the checkpoint stopped)
+  ///
+  /// # Parameters
+  ///
+  /// - `unroll` is how the gradients of every minibatch are computed
+  /// - `start_epoch` is the epoch to start from
+  /// - `start_iter` is the iteration (within `start_epoch`) to start from
+  ///
+  /// See `fit` for the remaining parameters
+  fn fit_from<T, E>(&mut self, source: &T, src_device: Device
+                    , epochs: u64, batch_size: u64, unroll: Unroll
+                    , loss_indices: Option<&Vec<bool>>, verbose: bool
+                    , start_epoch: u64, start_iter: u64) -> Result<Vec<f32>, HALError>
+    where T: DataSource, E: HasAfEnum + Zero + Clone
+  {
+    // some simple data validity checks
+    let data_params = source.info();
+    let iters = try!(self.verify_fit_params(&data_params, src_device, epochs, batch_size));
+    try!(self.verify_unroll(&data_params, unroll, loss_indices));
 
     // loss vector current loss
     let mut lossvec = Vec::<f32>::new();
-    let compute_device = self.device.clone();
+    self.param_manager.clear_all_state_derivatives();
+
+    self.stop_requested = false;
+    self.run_callbacks(|callback, model| callback.on_train_begin(model));
 
     // iterate epoch times over the number of batch iterations
-    for epoch in 0..epochs {
-      for iter in 0..iters {
-        // ensure we are on the original device device
-        self.manager.swap_device(src_device);
+    'epochs: for epoch in start_epoch..epochs {
+      let epoch_start = lossvec.len();
+      self.run_callbacks(|callback, model| callback.on_epoch_begin(model, epoch));
+
+      let first_iter = match epoch == start_epoch {
+        true  => start_iter,
+        false => 0,
+      };
+      for iter in first_iter..iters {
+        self.run_callbacks(|callback, model| callback.on_batch_begin(model, epoch, iter));
 
         if verbose {
           print!("\n[epoch: {}][iter: {}] ", epoch, iter);
         }
 
-        // extract part of the array onto the GPU
-        self.manager.swap_device(src_device);
-        let minibatch = source.get_train_iter(batch_size);
-        assert!(minibatch.input.borrow().dims()[0] == batch_size
-                , "Ensure that input dims are of batch rows");
-        assert!(minibatch.target.borrow().dims()[0] == batch_size
-                , "Ensure that target dims are of batch rows");
-        let batch_input = self.manager.swap_array_backend::<E>(&minibatch.input.into_inner()
-                                                          , src_device
-                                                          , compute_device);
-        let batch_target = self.manager.swap_array_backend::<E>(&minibatch.target.into_inner()
-                                                           , src_device
-                                                           , compute_device);
-
-        /*
-        {
-            let param = self.param_manager.get_params(0);
-            let ltex = param.lock().unwrap();
-            af::print(&ltex.weights[0]);
-            af::print(&ltex.deltas[0]);
-        }
-        */
-        // if bptt_interval is specified we slice our minibatch
-        // into bptt_interval number of slices and then forward pass on it
-        let mut current_loss_vec = Vec::new();
-        if let Some(bptt_interval) = bptt_interval {
-          let num_seqs = idims[2]/bptt_interval;
-          let start: Vec<_>  = (0..num_seqs).map(|x| x * bptt_interval).collect();
-          let finish: Vec<_> = (1..num_seqs+1).map(|x| x * bptt_interval).collect();
-          for (begin, end) in Zip::new((start, finish)) //TODO: fix when .step_by() becomes stable
-          {
-            let bptt_input_slice = af::slices(&batch_input, begin, end-1);
-            let bptt_target_slice = af::slices(&batch_target, begin, end-1);
-            let a_t = self.forward::<E>(&bptt_input_slice, compute_device, compute_device);
-            current_loss_vec = self.backward(&a_t, &bptt_target_slice, loss_indices);
-          }
-        }else{
-          let a_t = self.forward::<E>(&batch_input, compute_device, compute_device);
-          current_loss_vec = self.backward(&a_t, &batch_target, loss_indices);
-        }
-
-        self.optimizer.update(&mut self.param_manager, batch_size as u64);
-
+        let (batch_input, batch_target) = try!(model::next_minibatch::<T, E>(&self.manager, self.device, source
+                                                                             , src_device, batch_size));
+        try!(self.layers[0].check_inputs(&batch_input));
+        let current_loss_vec = match unroll {
+          Unroll::BPTT(bptt_interval) => self.train_bptt::<E>(&batch_input, &batch_target, bptt_interval
+                                                              , loss_indices, batch_size),
+          Unroll::RTRL                => self.train_rtrl(&batch_input, &batch_target
+                                                         , loss_indices, batch_size),
+        };
 
         // cache and print loss (if verbose)
         if verbose {
@@ -318,64 +604,515 @@ impl Model for Sequential {
           let avg_loss = loss_sum / current_loss_vec.len() as f32 ;
           print!("{} ", avg_loss);
         }
+        let batch_start = lossvec.len();
         lossvec.extend(current_loss_vec);
+        self.run_callbacks(|callback, model| callback.on_batch_end(model, epoch, iter
+                                                                  , &lossvec[batch_start..]));
+
+        // checkpoint every checkpoint_interval iterations (the end of the epoch is handled below)
+        if self.checkpoint_interval > 0 && (iter + 1) % self.checkpoint_interval == 0 && iter + 1 < iters {
+          try!(self.checkpoint(source, epoch, iter + 1));
+        }
+
+        if self.stop_requested {
+          println!("\ntraining stopped by a callback at [epoch: {}][iter: {}]", epoch, iter);
+          break 'epochs;
+        }
+      }
+
+      // evaluate on the validation set and track the best params
+      let mut stop = false;
+      if self.validate {
+        let validation_loss = try!(self.validation_loss::<T, E>(source, src_device, batch_size));
+        self.validation_losses.push(validation_loss);
+        if verbose {
+          print!("\n[epoch: {}] validation loss: {} ", epoch, validation_loss);
+        }
+
+        match self.best_validation.as_ref().map_or(true, |&(best_loss, _)| validation_loss < best_loss) {
+          true  => {
+            let params = self.param_manager.get_all_arrays().iter().map(|p| p.copy()).collect();
+            self.best_validation = Some((validation_loss, params));
+            self.epochs_without_improvement = 0;
+          },
+          false => self.epochs_without_improvement += 1,
+        };
+
+        if let Some(patience) = self.validation_patience {
+          stop = self.epochs_without_improvement >= patience;
+        }
+      }
+
+      if self.checkpoint_path.is_some() {
+        try!(self.checkpoint(source, epoch + 1, 0));
+      }
+
+      self.run_callbacks(|callback, model| callback.on_epoch_end(model, epoch, &lossvec[epoch_start..]));
+
+      if stop {
+        println!("\nno validation improvement for {} epochs, stopping at epoch {}"
+                 , self.epochs_without_improvement, epoch);
+        break;
+      }
+
+      if self.stop_requested {
+        println!("\ntraining stopped by a callback at epoch {}", epoch);
+        break;
       }
     }
 
+    // restore the best performing params
+    if let Some((_, params)) = self.best_validation.take() {
+      self.param_manager.set_all_arrays(params);
+    }
+
+    self.run_callbacks(|callback, model| callback.on_train_end(model, &lossvec));
+
     //utils::write_csv::<f32>("loss.csv", &lossvec);
     self.manager.swap_device(src_device); // return to src device
-    lossvec
+    Ok(lossvec)
   }
 
-
-  /// Calculate the layer gradients and return the loss vector
+  /// Fit's model to provided data using real-time recurrent learning
   ///
-  /// Given predictions and output data, this function computes all the gradients for all
-  /// of the trainable parameters in the layers
+  /// Rather than unrolling the whole sequence and running BPTT, every timestep
+  /// is forwarded, differentiated and applied to the parameters immediately.
+  /// Recurrent layers carry their parameter sensitivities from step to step
+  /// (see `layer::RTRL`), thus the unrolled history is never stored and
+  /// the memory required per step is constant. Every recurrent layer needs to
+  /// implement RTRL (see `Layer::is_recurrent`), the other layers fall back to
+  /// their (single step) backward pass. Validation & checkpoints work as in `fit`,
+  /// however `resume` continues with BPTT.
   ///
   /// # Parameters
   ///
-  /// - `predictions` are the model predictions
-  /// - `targets` are the true targets
-  /// - `loss_indices` are the optional indices of losses to use while computing the gradient
+  /// - `source` is the datasource
+  /// - `src_device` is the source device of the data
+  /// - `epochs` is the number of epochs to run the training loop for
+  /// - `batch_size` is the minibatch size
+  /// - `loss_indices` are the timesteps whose loss is used to update the parameters
+  /// - `verbose` specifies whether or not to print verbose details during training
   ///
   /// # Return Values
   ///
-  /// Vector of losses
-  fn backward(&mut self, predictions: &Vec<Array>, targets: &Array, loss_indices: Option<&Vec<bool>>) -> Vec<f32> {
-    // setup the optimizer parameters (if not already setup)
+  /// Vector of losses or an error if the model, data or devices are inconsistent.
+  /// `HALError::INVALID_PARAM` if a recurrent layer does not implement RTRL
+  pub fn fit_rtrl<T, E>(&mut self, source: &T, src_device: Device
+                        , epochs: u64, batch_size: u64
+                        , loss_indices: Option<&Vec<bool>>, verbose: bool) -> Result<Vec<f32>, HALError>
+    where T: DataSource, E: HasAfEnum + Zero + Clone
+  {
+    if !self.layers.iter().all(|layer| layer.return_sequences()) {
+      return Err(HALError::invalid_param("fit_rtrl", "return_sequences"
+                                         , "rtrl needs the output of every timestep".to_string()));
+    }
+    for (layer, spec) in Zip::new((self.layers.iter(), self.layer_specs.iter())) {
+      if spec.whole_sequence {
+        return Err(HALError::invalid_param("fit_rtrl", "layers"
+                                           , format!("the {} layer needs the whole sequence", spec.layer_type)));
+      }
+      if layer.is_recurrent() && layer.as_rtrl().is_none() {
+        return Err(HALError::invalid_param("fit_rtrl", "layers"
+                                           , format!("the {} layer does not support rtrl", spec.layer_type)));
+      }
+    }
+
     self.optimizer.setup(self.param_manager.get_all_dims());
-    let mut loss_vec = Vec::with_capacity(predictions.len());
+    self.validation_losses.clear();
+    self.best_validation = None;
+    self.epochs_without_improvement = 0;
+    self.fit_from::<T, E>(source, src_device, epochs, batch_size, Unroll::RTRL
+                          , loss_indices, verbose, 0, 0)
+  }
 
-    for (pred, ind) in Zip::new((predictions.iter().rev(), (0..predictions.len()).rev()))
-    {
-      let tar = af::slice(&targets, ind as u64);
-      let last_index = self.layers.len();
+  /// Helper that runs BPTT over a minibatch and updates the parameters
+  ///
+  /// Returns the loss of every timestep (of the last truncated slice)
+  fn train_bptt<E>(&mut self, batch_input: &Array, batch_target: &Array, bptt_interval: Option<u64>
+                   , loss_indices: Option<&Vec<bool>>, batch_size: u64) -> Vec<f32>
+    where E: HasAfEnum + Zero + Clone
+  {
+    let device = self.device;
+    let current_loss_vec = model::bptt_minibatch::<_, E>(self, device, batch_input, batch_target
+                                                          , bptt_interval, loss_indices);
+    self.optimizer.update(&mut self.param_manager, batch_size as u64);
+    current_loss_vec
+  }
+
+  /// Helper that runs RTRL over a minibatch, updating the parameters at every timestep
+  ///
+  /// Returns the loss of every timestep that is part of the loss indices
+  fn train_rtrl(&mut self, batch_input: &Array, batch_target: &Array
+                , loss_indices: Option<&Vec<bool>>, batch_size: u64) -> Vec<f32>
+  {
+    let seq_len = max(batch_input.dims()[2], 1);
+
+    let mut current_loss_vec = Vec::new();
+    self.param_manager.set_all_training(self.training);
+    for t in 0..seq_len {
+      // forward a single timestep through all the layers
+      let mut activate = af::slice(batch_input, t);
+      for i in 0..self.layers.len() {
+        let (a, _) = self.layers[i].forward(self.param_manager.get_params(i)
+                                            , &activate, None);
+        activate = a;
+      }
 
       // handle loss indices that are not to be allowed
-      let mut delta = match loss_indices {
-        Some(li) => {
-          assert!(li.len() == predictions.len()
-                  , "loss indices need to be of the same size as the predictions");
-          match li[ind] {
-            false => utils::constant(tar.dims(), tar.get_type(), 0.0f32),
-            true  => {
-              loss_vec.push(loss::get_loss(&self.loss, pred, &tar).unwrap());
-              loss::get_loss_derivative(&self.loss, pred, &tar).unwrap()
-            },
-          }
-        },
-        None     => {
-          loss_vec.push(loss::get_loss(&self.loss, pred, &tar).unwrap());
-          loss::get_loss_derivative(&self.loss, pred, &tar).unwrap()
+      let tar = af::slice(batch_target, t);
+      let use_loss = match loss_indices {
+        Some(li) => li[t as usize],
+        None     => true,
+      };
+      let mut delta = match use_loss {
+        false => utils::constant(tar.dims(), tar.get_type(), 0.0f32),
+        true  => {
+          current_loss_vec.push(loss::get_loss(&self.loss, &activate, &tar).unwrap());
+          loss::get_loss_derivative(&self.loss, &activate, &tar).unwrap()
         },
       };
 
-      for i in (0..last_index).rev() {
-        delta = self.layers[i].backward(self.param_manager.get_params(i), &delta);
+      // the sensitivities are always carried forward, even without a loss
+      for i in (0..self.layers.len()).rev() {
+        let params = self.param_manager.get_params(i);
+        delta = match self.layers[i].as_rtrl() {
+          Some(rtrl) => rtrl.rtrl(params, &delta),
+          None       => self.layers[i].backward(params, &delta),
+        };
+      }
+
+      if use_loss {
+        self.optimizer.update(&mut self.param_manager, batch_size as u64);
       }
     }
 
-    loss_vec
+    current_loss_vec
+  }
+
+  /// Registers a callback that is run from within `fit` & `fit_rtrl`
+  ///
+  /// Callbacks are run in the order in which they were added
+  pub fn add_callback(&mut self, callback: Box<Callback>)
+  {
+    self.callbacks.push(callback);
+  }
+
+  /// Requests that fitting stops at the end of the current minibatch
+  ///
+  /// Meant to be called from within a `Callback`
+  pub fn stop_training(&mut self)
+  {
+    self.stop_requested = true;
+  }
+
+  /// Returns the optimizer (eg: to modify the learning rate from a `Callback`)
+  pub fn get_optimizer_mut(&mut self) -> &mut Box<Optimizer>
+  {
+    &mut self.optimizer
+  }
+
+  /// Helper to run `f` on every registered callback
+  fn run_callbacks<F>(&mut self, mut f: F)
+    where F: FnMut(&mut Box<Callback>, &mut Sequential)
+  {
+    // the callbacks are moved out so that they can borrow the model mutably
+    let mut callbacks = mem::replace(&mut self.callbacks, Vec::new());
+    for callback in callbacks.iter_mut() {
+      f(callback, self);
+    }
+
+    // keep the callbacks that were registered from within a hook
+    callbacks.extend(mem::replace(&mut self.callbacks, Vec::new()));
+    self.callbacks = callbacks;
+  }
+
+  /// Enables periodic checkpointing while fitting
+  ///
+  /// A checkpoint (model params, recurrent states, optimizer state and the
+  /// epoch/iteration position) is written every `interval` iterations as
+  /// well as at the end of every epoch. Use `resume` to continue from it.
+  ///
+  /// # Parameters
+  ///
+  /// - `path` is the checkpoint file (overwritten on every checkpoint)
+  /// - `interval` is the number of iterations between checkpoints (0 for epoch ends only)
+  pub fn set_checkpoint(&mut self, path: &str, interval: u64)
+  {
+    self.checkpoint_path = Some(path.to_string());
+    self.checkpoint_interval = interval;
+  }
+
+  /// Enables evaluation on the validation set at the end of every epoch
+  ///
+  /// The params with the lowest validation loss are restored once fitting ends.
+  ///
+  /// # Parameters
+  ///
+  /// - `patience` is the number of epochs without improvement after
+  ///   which fitting stops early (None to never stop early)
+  pub fn set_validation(&mut self, patience: Option<u64>)
+  {
+    self.validate = true;
+    self.validation_patience = patience;
+  }
+
+  /// Returns the per-epoch validation losses of the last fit
+  pub fn get_validation_losses(&self) -> Vec<f32>
+  {
+    self.validation_losses.clone()
+  }
+
+  /// Returns a copy of the params of a layer
+  ///
+  /// Useful to inspect what a layer recorded during the last forward
+  /// pass (eg: `layer::Attention::attention_weights`)
+  pub fn get_layer_params(&self, layer_index: usize) -> Params
+  {
+    self.param_manager.get_params(layer_index).lock().unwrap().clone()
+  }
+
+  /// Helper to compute the average loss over the validation set
+  ///
+  /// Uses `predict`, thus the training state of the layers is unaffected
+  fn validation_loss<T, E>(&self, source: &T, src_device: Device, batch_size: u64) -> Result<f32, HALError>
+    where T: DataSource, E: HasAfEnum + Zero + Clone
+  {
+    let num_validation = try!(source.info().num_validation.ok_or(HALError::invalid_param(
+      "sequential", "validation", "the data source does not provide a validation set".to_string())));
+    let num_batches = max(num_validation / batch_size, 1);
+    let compute_device = self.device.clone();
+
+    let mut loss_sum = 0f32;
+    let mut loss_count = 0;
+    for _ in 0..num_batches {
+      self.manager.swap_device(src_device);
+      let minibatch = match source.get_validation_iter(batch_size) {
+        Some(m) => m,
+        None    => break,
+      };
+      let batch_input = self.manager.swap_array_backend::<E>(&minibatch.input.into_inner()
+                                                             , src_device
+                                                             , compute_device);
+      let batch_target = self.manager.swap_array_backend::<E>(&minibatch.target.into_inner()
+                                                              , src_device
+                                                              , compute_device);
+      let predictions = self.predict(&batch_input);
+      for t in 0..max(predictions.dims()[2], 1) {
+        loss_sum += loss::get_loss(&self.loss, &af::slice(&predictions, t)
+                                   , &af::slice(&batch_target, t)).unwrap();
+        loss_count += 1;
+      }
+    }
+
+    if loss_count == 0 {
+      return Err(HALError::invalid_param("sequential", "validation"
+                                         , "the data source did not return any validation batches".to_string()));
+    }
+    Ok(loss_sum / loss_count as f32)
+  }
+
+  /// Saves the model to a json file
+  ///
+  /// Writes the loss, the layer list along with each layer's constructor
+  /// params and all of the weights, biases & construction-time optional arrays
+  ///
+  /// # Parameters
+  ///
+  /// - `path` is the destination file
+  ///
+  /// # Return Values
+  ///
+  /// `HALError::IO_ERROR` if the file could not be written
+  pub fn save(&self, path: &str) -> Result<(), HALError>
+  {
+    serialize::write_json(path, &self.model_record())
+  }
+
+  /// Loads a model that was previously saved with `save`
+  ///
+  /// The layers are re-created on the provided device (which does not need to be
+  /// of the same backend as the one used while saving) using the stored dtypes.
+  /// The returned model uses a default SGD optimizer.
+  ///
+  /// # Parameters
+  ///
+  /// - `path` is the source file
+  /// - `manager` is the device manager
+  /// - `device` is the device to place the model on
+  pub fn load(path: &str, manager: DeviceManager, device: Device) -> Result<Sequential, HALError>
+  {
+    try!(manager.check_device(device));
+    let record: ModelRecord = try!(serialize::read_json(path));
+    let mut model = Sequential::new(manager, Box::new(SGD::default()), &record.loss, device);
+
+    // parameter free layers (eg: pooling) take the dtype of the layer before them
+    let mut dtype = DType::F32;
+    for layer in record.layers.iter() {
+      if let Some(w) = layer.weights.first() {
+        dtype = try!(serialize::str_to_dtype(&w.dtype));
+      }
+      let params: HashMap<&str, String> = layer.params.iter().map(|(k, v)| (k.as_str(), v.clone())).collect();
+      try!(match dtype {
+        DType::F32 => model.add::<f32>(&layer.layer_type, params),
+        DType::F64 => model.add::<f64>(&layer.layer_type, params),
+        DType::C32 => model.add::<Complex<f32>>(&layer.layer_type, params),
+        DType::C64 => model.add::<Complex<f64>>(&layer.layer_type, params),
+        dtype      => Err(HALError::invalid_param(&layer.layer_type, "dtype"
+                                                  , format!("unsupported layer dtype {:?}", dtype))),
+      });
+    }
+
+    // overwrite the freshly initialized params with the stored ones
+    try!(model.restore_params(&record));
+    Ok(model)
+  }
+
+  /// Resumes fitting from a checkpoint written during `fit` (see `set_checkpoint`)
+  ///
+  /// The model needs to have been built with the same layers and optimizer type
+  /// as the one that wrote the checkpoint. The params, recurrent states,
+  /// optimizer state, data source position and early stopping state (validation
+  /// losses, best params & patience counter) are restored and the training
+  /// loop continues from the stored epoch & iteration. The data source needs
+  /// to support `DataSource::set_position`.
+  ///
+  /// # Parameters
+  ///
+  /// - `path` is the checkpoint file
+  ///
+  /// See `fit` for the remaining parameters
+  ///
+  /// # Return Values
+  ///
+  /// Vector of losses (of the resumed iterations only)
+  pub fn resume<T, E>(&mut self, path: &str, source: &T, src_device: Device
+                      , epochs: u64, batch_size: u64, bptt_interval: Option<u64>
+                      , loss_indices: Option<&Vec<bool>>, verbose: bool) -> Result<Vec<f32>, HALError>
+    where T: DataSource, E: HasAfEnum + Zero + Clone
+  {
+    let record: CheckpointRecord = try!(serialize::read_json(path));
+    try!(self.restore_params(&record.model));
+
+    self.manager.swap_device(self.device);
+    if record.recurrences.len() != self.layers.len() {
+      return Err(HALError::invalid_param("checkpoint", "recurrences"
+                                         , format!("checkpoint has {} layers, model has {}"
+                                                   , record.recurrences.len(), self.layers.len())));
+    }
+    for (recurrences, layer_num) in Zip::new((record.recurrences.iter(), 0..self.layers.len())) {
+      self.param_manager.set_recurrences(layer_num, try!(serialize::to_arrays(recurrences)));
+    }
+    try!(self.optimizer.set_state(try!(record.optimizer.to_state())));
+    try!(source.set_position(record.source_epoch, record.source_iter));
+
+    // early stopping continues with the patience & best params it had
+    self.validation_losses = record.validation_losses.clone();
+    self.epochs_without_improvement = record.epochs_without_improvement;
+    self.best_validation = match record.best_loss {
+      Some(best_loss) => Some((best_loss, try!(serialize::to_arrays(&record.best_params)))),
+      None            => None,
+    };
+
+    println!("resuming from {} at [epoch: {}][iter: {}]", path, record.epoch, record.iter);
+    self.fit_from::<T, E>(source, src_device, epochs, batch_size, Unroll::BPTT(bptt_interval)
+                          , loss_indices, verbose, record.epoch, record.iter)
+  }
+
+  /// Writes a checkpoint to the configured checkpoint path
+  ///
+  /// `epoch` & `iter` are the position that training should resume from
+  fn checkpoint<T: DataSource>(&self, source: &T, epoch: u64, iter: u64) -> Result<(), HALError>
+  {
+    if let Some(ref path) = self.checkpoint_path {
+      self.manager.swap_device(self.device);
+      let recurrences: Vec<Vec<ArrayRecord>> = (0..self.layers.len()).map(|layer_num| {
+        self.param_manager.get_recurrences(layer_num).iter()
+          .map(|r| ArrayRecord::from_array(r)).collect()
+      }).collect();
+      let (source_epoch, source_iter) = source.get_position();
+      let (best_loss, best_params) = match self.best_validation {
+        Some((best_loss, ref params)) => (Some(best_loss)
+                                          , params.iter().map(|p| ArrayRecord::from_array(p)).collect()),
+        None                          => (None, Vec::new()),
+      };
+
+      try!(serialize::write_json(path, &CheckpointRecord {
+        model: self.model_record(),
+        recurrences: recurrences,
+        optimizer: OptimizerRecord::from_state(&self.optimizer.get_state()),
+        epoch: epoch,
+        iter: iter,
+        source_epoch: source_epoch,
+        source_iter: source_iter,
+        validation_losses: self.validation_losses.clone(),
+        best_loss: best_loss,
+        best_params: best_params,
+        epochs_without_improvement: self.epochs_without_improvement,
+      }));
+    }
+    Ok(())
+  }
+
+  /// Helper to gather the serializable form of the model
+  fn model_record(&self) -> ModelRecord
+  {
+    self.manager.swap_device(self.device);
+    let mut layers = Vec::with_capacity(self.layer_specs.len());
+    for (spec, layer_num) in Zip::new((self.layer_specs.iter(), 0..self.layer_specs.len())) {
+      let to_records = |arrays: Vec<Array>| -> Vec<ArrayRecord> {
+        arrays.iter().map(|a| ArrayRecord::from_array(a)).collect()
+      };
+
+      // optional arrays past the construction-time ones are per-unroll caches
+      let mut optional = self.param_manager.get_optionals(layer_num);
+      optional.truncate(spec.num_optional);
+
+      layers.push(LayerRecord {
+        layer_type: spec.layer_type.clone(),
+        params: spec.params.clone(),
+        weights: to_records(self.param_manager.get_weights(layer_num)),
+        biases: to_records(self.param_manager.get_biases(layer_num)),
+        optional: to_records(optional),
+      });
+    }
+
+    ModelRecord {
+      loss: self.loss.clone(),
+      layers: layers,
+    }
+  }
+
+  /// Helper to overwrite the params of the (already built) layers with stored ones
+  fn restore_params(&mut self, record: &ModelRecord) -> Result<(), HALError>
+  {
+    if record.layers.len() != self.layers.len() {
+      return Err(HALError::invalid_param("model record", "layers"
+                                         , format!("stored model has {} layers, model has {}"
+                                                   , record.layers.len(), self.layers.len())));
+    }
+    self.manager.swap_device(self.device);
+
+    for (layer, layer_num) in Zip::new((record.layers.iter(), 0..record.layers.len())) {
+      if layer.layer_type != self.layer_specs[layer_num].layer_type
+        || layer.weights.len() != self.param_manager.num_weights(layer_num)
+        || layer.biases.len() != self.param_manager.num_biases(layer_num)
+        || layer.optional.len() > self.param_manager.get_optionals(layer_num).len()
+      {
+        return Err(HALError::invalid_param("model record", "layers"
+                                           , format!("stored params of layer {} do not match a {} layer"
+                                                     , layer_num, self.layer_specs[layer_num].layer_type)));
+      }
+      self.param_manager.set_weights(layer_num, try!(serialize::to_arrays(&layer.weights)));
+      self.param_manager.set_biases(layer_num, try!(serialize::to_arrays(&layer.biases)));
+
+      // keep any per-unroll caches, only the construction-time arrays are stored
+      let mut optional = self.param_manager.get_optionals(layer_num);
+      for (arr, i) in Zip::new((try!(serialize::to_arrays(&layer.optional)), 0..layer.optional.len())) {
+        optional[i] = arr;
+      }
+      self.param_manager.set_optionals(layer_num, optional);
+    }
+    Ok(())
   }
 }
+
@@ -5,9 +5,10 @@ use std::collections::HashMap;
 use std::default::Default;
 
 use optimizer;
-use params::ParamManager;
+use params::{ParamManager, UpdateRows};
 use initializations;
-use optimizer::Optimizer;
+use error::HALError;
+use optimizer::{Optimizer, OptimizerState};
 
 #[allow(non_snake_case)]
 pub struct Adam {
@@ -75,34 +76,81 @@ impl Optimizer for Adam {
     // all arrays are returned as [W0, b0, .. WN, bN, ..] (note this is per layer)
     // deltas are returned in the same way
     let num_params = self.vt.len();
-    for (arr, delta, vt_i, mt_i, ind) in Zip::new((parameter_manager.get_all_arrays().iter()   // weights + biases
-                                                   , parameter_manager.get_all_deltas().iter() // deltas of above
-                                                   , self.vt.iter_mut()                        // vt
-                                                   , self.mt.iter_mut()                        // mt
-                                                   , 0..num_params))                           // current index
+    let unitary = parameter_manager.get_all_unitary_flags();
+    for (arr, delta, rows, vt_i, mt_i, ind) in Zip::new((parameter_manager.get_all_arrays().iter()   // weights + biases
+                                                         , parameter_manager.get_all_deltas().iter() // deltas of above
+                                                         , parameter_manager.get_all_update_rows()   // rows to update
+                                                         , self.vt.iter_mut()                        // vt
+                                                         , self.mt.iter_mut()                        // mt
+                                                         , 0..num_params))                           // current index
     {
+      // the moments of the sparse tables without a gradient are not decayed
+      if let UpdateRows::Skip = rows {
+        continue;
+      }
+
       let grad_update = match self.clip_grad > 0.0 {
-        false => delta.clone(),
-        true  => optimizer::clip_grads(&delta, self.clip_grad),
+        false => optimizer::gather_rows(&rows, &delta),
+        true  => optimizer::gather_rows(&rows, &optimizer::clip_grads(&delta, self.clip_grad)),
       };
 
-      *mt_i = af::add(&af::mul(&self.beta1, mt_i, false)
-                      , &af::mul(&(1.0 - self.beta1), &grad_update, false)
-                      , false);
-      *vt_i = af::add(&af::mul(&self.beta2, vt_i, false)
-                      , &af::mul(&(1.0 - self.beta2), &af::mul(&grad_update, &grad_update, false), false)
-                      , false);
-      let mhat_i = af::div(mt_i, &(1.0 - self.beta1), false);
-      let vhat_i = af::div(vt_i, &(1.0 - self.beta2), false);
+      // (only computed on the touched rows of sparse tables)
+      let updated_mt = af::add(&af::mul(&self.beta1, &optimizer::gather_rows(&rows, mt_i), false)
+                               , &af::mul(&(1.0 - self.beta1), &grad_update, false)
+                               , false);
+      let updated_vt = af::add(&af::mul(&self.beta2, &optimizer::gather_rows(&rows, vt_i), false)
+                               , &af::mul(&(1.0 - self.beta2), &af::mul(&grad_update, &grad_update, false), false)
+                               , false);
+      *mt_i = optimizer::scatter_rows(&rows, &updated_mt, mt_i);
+      *vt_i = optimizer::scatter_rows(&rows, &updated_vt, vt_i);
+      let mhat_i = af::div(&updated_mt, &(1.0 - self.beta1), false);
+      let vhat_i = af::div(&updated_vt, &(1.0 - self.beta2), false);
       let update = af::mul(&self.learning_rate, &af::div(&mhat_i, &af::add(&af::sqrt(&vhat_i), &self.eps, false), false)
                            , false);
 
-      parameter_manager.set_array_from_index(af::sub(arr, &update, false), ind);
+      // unitary matrices move along the Stiefel manifold in the direction of the update instead
+      let updated = match unitary[ind] {
+        true  => optimizer::unitary_update(arr, &update, 1.0),
+        false => optimizer::scatter_rows(&rows, &af::sub(&optimizer::gather_rows(&rows, arr), &update, false), arr),
+      };
+      parameter_manager.set_array_from_index(updated, ind);
     }
 
     // zero out the deltas
     parameter_manager.zero_all_deltas();
-    parameter_manager.zero_all_state_derivatives();
+  }
+
+  fn get_learning_rate(&self) -> f32 {
+    self.learning_rate
+  }
+
+  fn set_learning_rate(&mut self, learning_rate: f32) {
+    self.learning_rate = learning_rate;
+  }
+
+  fn get_state(&self) -> OptimizerState {
+    // beta1 is decayed by lambda on every update
+    let mut scalars = HashMap::new();
+    scalars.insert("beta1".to_string(), self.beta1);
+    let mut arrays = HashMap::new();
+    arrays.insert("mt".to_string(), self.mt.clone());
+    arrays.insert("vt".to_string(), self.vt.clone());
+    OptimizerState {
+      name: self.name.clone(),
+      iter: self.iter,
+      scalars: scalars,
+      arrays: arrays,
+    }
+  }
+
+  fn set_state(&mut self, state: OptimizerState) -> Result<(), HALError> {
+    try!(state.check_name(&self.name));
+    let (beta1, mt, vt) = (try!(state.scalar("beta1")), try!(state.arrays("mt")), try!(state.arrays("vt")));
+    self.iter = state.iter;
+    self.beta1 = beta1;
+    self.mt = mt;
+    self.vt = vt;
+    Ok(())
   }
 
   fn info(&self){
@@ -5,26 +5,79 @@ pub use self::adam::Adam;
 mod adam;
 
 use af;
-use af::{Array, Dim4, NormType};
+use af::{Array, Dim4, Indexer, MatProp, NormType};
 use std::collections::HashMap;
 
 use utils;
 use error::HALError;
-use params::ParamManager;
+use params::{ParamManager, UpdateRows};
+
+/// Snapshot of everything an optimizer mutates while training
+///
+/// This is what is needed (along with the model params) to
+/// resume training from a checkpoint
+#[derive(Clone)]
+pub struct OptimizerState {
+  pub name: String,
+  pub iter: u64,
+  pub scalars: HashMap<String, f32>,
+  pub arrays: HashMap<String, Vec<Array>>,
+}
+
+impl OptimizerState {
+  /// Checks that the state was saved by an optimizer of the given name
+  pub fn check_name(&self, name: &str) -> Result<(), HALError> {
+    match self.name == name {
+      true  => Ok(()),
+      false => Err(HALError::invalid_param(name, "name"
+                                           , format!("cannot restore the state of {}", self.name))),
+    }
+  }
+
+  /// Returns the scalar saved under key
+  pub fn scalar(&self, key: &str) -> Result<f32, HALError> {
+    match self.scalars.get(key) {
+      Some(v) => Ok(*v),
+      None    => Err(HALError::invalid_param(&self.name, key, "missing from the optimizer state".to_string())),
+    }
+  }
+
+  /// Returns the arrays saved under key
+  pub fn arrays(&self, key: &str) -> Result<Vec<Array>, HALError> {
+    match self.arrays.get(key) {
+      Some(v) => Ok(v.clone()),
+      None    => Err(HALError::invalid_param(&self.name, key, "missing from the optimizer state".to_string())),
+    }
+  }
+}
 
 pub trait Optimizer {
   fn new(params: &HashMap<&str, &str>) -> Self where Self: Sized;
   //fn setup(&mut self, w_dim: Vec<Dim4>, b_dim: Vec<Dim4>);
   fn setup(&mut self, dims: Vec<Dim4>);
   fn update(&mut self, parameter_manager: &mut ParamManager, batch_size: u64);
+  fn get_learning_rate(&self) -> f32;
+  fn set_learning_rate(&mut self, learning_rate: f32);
+  fn get_state(&self) -> OptimizerState;
+  fn set_state(&mut self, state: OptimizerState) -> Result<(), HALError>;
   fn info(&self);
 }
 
+/// Helper to build an optimizer from a string & its params
+///
+/// Every param read by the optimizer's `new` needs to be provided,
+/// otherwise an INVALID_PARAM error naming the field is returned
 pub fn get_optimizer(name: &str, params: &HashMap<&str, &str>) -> Result<Box<Optimizer>, HALError>{
   match name.to_lowercase().as_str() {
-    "sgd"  => Ok(Box::new(SGD::new(params))),
-    "adam" => Ok(Box::new(Adam::new(params))),
-    _     => Err(HALError::UNKNOWN),
+    "sgd"  => {
+      try!(check_params("sgd", params, &["learning_rate", "momemtum", "decay", "clip_grad"], &["nesterov"]));
+      Ok(Box::new(SGD::new(params)))
+    },
+    "adam" => {
+      try!(check_params("adam", params, &["learning_rate", "beta1", "beta2", "eps", "lambda", "clip_grad"], &[]));
+      Ok(Box::new(Adam::new(params)))
+    },
+    _     => Err(HALError::UNKNOWN_OPTIMIZER(name.to_string())),
   }
 }
 
@@ -32,10 +85,77 @@ pub fn get_optimizer_with_defaults(name: &str) -> Result<Box<Optimizer>, HALErro
   match name.to_lowercase().as_str() {
     "sgd" =>  Ok(Box::new(SGD::default())),
     "adam" => Ok(Box::new(Adam::default())),
-    _     => Err(HALError::UNKNOWN),
+    _     => Err(HALError::UNKNOWN_OPTIMIZER(name.to_string())),
+  }
+}
+
+// checks that the float & bool params are present and parse
+fn check_params(name: &str, params: &HashMap<&str, &str>
+                , floats: &[&str], bools: &[&str]) -> Result<(), HALError>
+{
+  let fields = floats.iter().map(|f| (f, true)).chain(bools.iter().map(|b| (b, false)));
+  for (field, is_float) in fields {
+    let parses = match params.get(field) {
+      None    => return Err(HALError::invalid_param(name, field, "missing required param".to_string())),
+      Some(v) => match is_float {
+        true  => v.parse::<f32>().is_ok(),
+        false => v.parse::<bool>().is_ok(),
+      },
+    };
+    if !parses {
+      return Err(HALError::invalid_param(name, field, format!("could not parse '{}'", params[field])));
+    }
+  }
+  Ok(())
+}
+
+/// Helper that gathers the rows of an array (or of its optimizer state) that are updated
+///
+/// `rows` are returned by `ParamManager::get_all_update_rows`, thus the
+/// update of a sparse table is only computed on the rows of the seen tokens
+pub fn gather_rows(rows: &UpdateRows, arr: &Array) -> Array {
+  match *rows {
+    UpdateRows::Only(ref r) => af::lookup(arr, r, 0),
+    _                       => arr.clone(),
   }
 }
 
+/// Helper that writes the updated rows gathered by `gather_rows` back into the array
+pub fn scatter_rows(rows: &UpdateRows, updated: &Array, current: &Array) -> Array {
+  match *rows {
+    UpdateRows::Only(ref r) => {
+      let mut indices = Indexer::new();
+      indices.set_index(r, 0, None);
+      af::assign_gen(current, &indices, updated)
+    },
+    _                       => updated.clone(),
+  }
+}
+
+/// Helper that takes a gradient step on a unitary matrix along the Stiefel manifold
+///
+/// `w` is a [n, 2n] (real, imaginary) unitary matrix and `grad` the gradient of
+/// both its parts, G = dL/dRe(W) + i dL/dIm(W). The Cayley transform of the
+/// skew-Hermitian A = G W^H - W G^H keeps the update unitary (Wisdom et al., 2016):
+///
+///   W' = (I + lr / 2 A)^-1 (I - lr / 2 A) W
+///
+/// Any descent direction keeps A skew-Hermitian, thus the optimizers pass their
+/// own step (eg: the SGD velocity or the Adam update) with a learning rate of 1
+pub fn unitary_update(w: &Array, grad: &Array, learning_rate: f32) -> Array {
+  let n = w.dims()[0];
+  let complex = |a: &Array| af::cplx2(&af::cols(a, 0, n - 1), &af::cols(a, n, 2 * n - 1), false);
+  let (w_c, g_c) = (complex(w), complex(grad));
+  let a = af::sub(&af::matmul(&g_c, &w_c, MatProp::NONE, MatProp::CTRANS)
+                  , &af::matmul(&w_c, &g_c, MatProp::NONE, MatProp::CTRANS), false);
+  let step = af::mul(&a, &(learning_rate / 2.0), false);
+  let identity = af::diag_create(&utils::constant(Dim4::new(&[n, 1, 1, 1]), w_c.get_type(), 1.0f32), 0);
+  let updated = af::solve(&af::add(&identity, &step, false)
+                          , &af::matmul(&af::sub(&identity, &step, false), &w_c, MatProp::NONE, MatProp::NONE)
+                          , MatProp::NONE);
+  utils::cast(&af::join(1, &af::real(&updated), &af::imag(&updated)), w.get_type())
+}
+
 pub fn clip_grads(input: &Array, rescale: f32) -> Array {
   let norm = af::norm(input, NormType::VECTOR_2, 0f64, 0f64) as f32;
   let scale = rescale / norm.max(rescale);
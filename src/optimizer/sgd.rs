@@ -4,10 +4,11 @@ use itertools::Zip;
 use std::collections::HashMap;
 use std::default::Default;
 
-use params::ParamManager;
+use params::{ParamManager, UpdateRows};
 use initializations;
 use optimizer;
-use optimizer::Optimizer;
+use error::HALError;
+use optimizer::{Optimizer, OptimizerState};
 
 #[allow(non_snake_case)]
 pub struct SGD {
@@ -67,11 +68,18 @@ impl Optimizer for SGD {
     // all arrays are returned as [W0, b0, .. WN, bN, ..] (note this is per layer)
     // deltas are returned in the same way
     let num_params = self.velocity.len();
-    for (arr, delta, velocity, ind) in Zip::new((parameter_manager.get_all_arrays().iter()   // weights + biases
-                                                 , parameter_manager.get_all_deltas().iter() // deltas of above
-                                                 , self.velocity.iter_mut()                  // velocity of above
-                                                 , 0..num_params))                           // current index
+    let unitary = parameter_manager.get_all_unitary_flags();
+    for (arr, delta, rows, velocity, ind) in Zip::new((parameter_manager.get_all_arrays().iter()   // weights + biases
+                                                       , parameter_manager.get_all_deltas().iter() // deltas of above
+                                                       , parameter_manager.get_all_update_rows()   // rows to update
+                                                       , self.velocity.iter_mut()                  // velocity of above
+                                                       , 0..num_params))                           // current index
     {
+      // the sparse tables without a gradient are left untouched
+      if let UpdateRows::Skip = rows {
+        continue;
+      }
+
       let grad_update = match self.clip_grad > 0.0 {
         false => delta.clone(),
         true  => optimizer::clip_grads(&delta, self.clip_grad),
@@ -79,15 +87,49 @@ impl Optimizer for SGD {
 
       // v   = momemtum * v + learning_rate * d_w (or d_b)
       // p   = p - v
-      *velocity = af::add(&af::mul(&self.momemtum, velocity, false),
-                          &af::mul(&alpha, &grad_update, false), false);
+      // (only computed on the touched rows of sparse tables)
+      let updated_velocity = af::add(&af::mul(&self.momemtum, &optimizer::gather_rows(&rows, velocity), false),
+                                     &af::mul(&alpha, &optimizer::gather_rows(&rows, &grad_update), false), false);
+      *velocity = optimizer::scatter_rows(&rows, &updated_velocity, velocity);
       assert!(velocity.dims().get() == arr.dims().get());
-      parameter_manager.set_array_from_index(af::sub(arr, velocity, false), ind);
+
+      // unitary matrices move along the Stiefel manifold in the direction of v instead
+      let updated = match unitary[ind] {
+        true  => optimizer::unitary_update(arr, velocity, 1.0),
+        false => optimizer::scatter_rows(&rows, &af::sub(&optimizer::gather_rows(&rows, arr), &updated_velocity, false)
+                                         , arr),
+      };
+      parameter_manager.set_array_from_index(updated, ind);
     }
 
     // zero out the deltas
     parameter_manager.zero_all_deltas();
-    parameter_manager.zero_all_state_derivatives();
+  }
+
+  fn get_learning_rate(&self) -> f32 {
+    self.learning_rate
+  }
+
+  fn set_learning_rate(&mut self, learning_rate: f32) {
+    self.learning_rate = learning_rate;
+  }
+
+  fn get_state(&self) -> OptimizerState {
+    let mut arrays = HashMap::new();
+    arrays.insert("velocity".to_string(), self.velocity.clone());
+    OptimizerState {
+      name: self.name.clone(),
+      iter: self.iter,
+      scalars: HashMap::new(),
+      arrays: arrays,
+    }
+  }
+
+  fn set_state(&mut self, state: OptimizerState) -> Result<(), HALError> {
+    try!(state.check_name(&self.name));
+    self.velocity = try!(state.arrays("velocity"));
+    self.iter = state.iter;
+    Ok(())
   }
 
   fn info(&self){
@@ -1,3 +1,4 @@
+use af;
 use af::{Array, Dim4, HasAfEnum, DType};
 use std::default::Default;
 use num::Complex;
@@ -77,6 +78,14 @@ macro_rules! get_param_func {
     )
 }
 
+/// The rows of an array that the optimizers update (see `ParamManager::get_all_update_rows`)
+#[derive(Clone)]
+pub enum UpdateRows {
+  All,         // dense arrays take the whole update
+  Only(Array), // the (u32) rows of a sparse table that received a gradient
+  Skip,        // a sparse table without a gradient since the last update
+}
+
 #[derive(Clone)]
 pub struct Params {
   pub layer_type: String,
@@ -91,6 +100,10 @@ pub struct Params {
   pub state_derivatives: Vec<Array>,
   pub current_unroll: usize,
   pub optional: Vec<Array>,
+  pub training: bool, // whether mode dependent layers (eg: dropout) run in training mode
+  pub sparse: bool,   // weights[0] is a lookup table, only rows with a gradient are updated
+  pub touched_rows: Option<Array>, // the (u32) rows of a sparse table with a gradient since the last update
+  pub unitary: bool,  // weights[0] is a [n, 2n] (real, imaginary) unitary matrix, updated along the Stiefel manifold
 }
 
 pub struct ParamManager {
@@ -173,6 +186,10 @@ impl ParamManager {
       state_derivatives: Vec::new(),
       current_unroll: 0,
       optional: optional,
+      training: true,
+      sparse: false,
+      touched_rows: None,
+      unitary: false,
     })));
   }
 
@@ -232,6 +249,19 @@ impl ParamManager {
           f(&mut layer.lock().unwrap());
         }
 
+      // copies the params of every layer (the arrays themselves are reference
+      // counted & copy on write) so that they can be restored with set_all_params
+      pub fn get_all_params(&self) -> Vec<Params> {
+        self.layer_storage.iter().map(|layer| layer.lock().unwrap().clone()).collect()
+      }
+
+      pub fn set_all_params(&self, params: Vec<Params>) {
+        assert!(self.layer_storage.len() == params.len());
+        for (layer, p) in self.layer_storage.iter().zip(params.into_iter()) {
+          *layer.lock().unwrap() = p;
+        }
+      }
+
       pub fn get_all_arrays(&self) -> Vec<Array> {
         let mut p = Vec::new();
         for layer_num in 0..self.num_layers() {
@@ -308,6 +338,35 @@ impl ParamManager {
         d
       }
 
+      // the rows updated by the optimizer, aligned with get_all_arrays:
+      // only the touched rows of sparse tables, all the rows otherwise
+      pub fn get_all_update_rows(&self) -> Vec<UpdateRows> {
+        let mut rows = Vec::new();
+        for layer in &self.layer_storage {
+          let ltex = layer.lock().unwrap();
+          for i in 0..(ltex.weights.len() + ltex.biases.len()) {
+            rows.push(match (ltex.sparse && i == 0, &ltex.touched_rows) {
+              (true, &Some(ref touched)) => UpdateRows::Only(touched.clone()),
+              (true, &None)              => UpdateRows::Skip,
+              (false, _)                 => UpdateRows::All,
+            });
+          }
+        }
+        rows
+      }
+
+      // whether the arrays (aligned with get_all_arrays) need to stay unitary
+      pub fn get_all_unitary_flags(&self) -> Vec<bool> {
+        let mut flags = Vec::new();
+        for layer in &self.layer_storage {
+          let ltex = layer.lock().unwrap();
+          for i in 0..(ltex.weights.len() + ltex.biases.len()) {
+            flags.push(ltex.unitary && i == 0);
+          }
+        }
+        flags
+      }
+
       pub fn zero_all_deltas(&self) {
         for layer_num in 0..self.num_layers() {
           for delta_num in 0..self.num_arrays(layer_num) {
@@ -318,6 +377,11 @@ impl ParamManager {
             self.set_delta(layer_num, delta_num, zero_tensor);
           }
         }
+
+        // the sparse tables start over without any touched rows
+        for layer in &self.layer_storage {
+          layer.lock().unwrap().touched_rows = None;
+        }
       }
 
       pub fn zero_all_state_derivatives(&self) {
@@ -333,6 +397,28 @@ impl ParamManager {
       }
 
 
+      // drops the state derivatives of all layers so that they are
+      // re-allocated (with the current batch size) on the next backward/rtrl pass
+      pub fn clear_all_state_derivatives(&self) {
+        for layer_num in 0..self.num_layers() {
+          self.set_state_derivatives(layer_num, Vec::new());
+        }
+      }
+
+      // rewinds all layers to the first unroll (eg: after a forward only pass)
+      pub fn reset_all_unrolls(&self) {
+        for layer in self.layer_storage.iter() {
+          layer.lock().unwrap().current_unroll = 0;
+        }
+      }
+
+      // switches mode dependent layers (eg: dropout) between training & inference
+      pub fn set_all_training(&self, training: bool) {
+        for layer in self.layer_storage.iter() {
+          layer.lock().unwrap().training = training;
+        }
+      }
+
       pub fn zero_all_states(&self, default_state: Option<Array>)
       {
         for layer_num in 0..self.num_layers() {
@@ -537,7 +623,8 @@ pub trait RNNGenerator {
                            , inner_activation: &str
                            , outer_activation: &str
                            , w_init: &str
-                           , b_init: &str);
+                           , b_init: &str
+                           , layer_norm: bool);
 }
 
 pub enum LSTMIndex {
@@ -555,6 +642,25 @@ pub enum RNNIndex {
   HiddenToHidden,
 }
 
+pub enum GRUIndex {
+  Update=0,   // z_t
+  Reset,      // r_t
+  Hidden,     // hh_t
+}
+
+pub trait GRUGenerator {
+  fn add_gru<T: HasAfEnum>(&mut self
+                           , manager: DeviceManager
+                           , device: Device
+                           , input_size: usize
+                           , output_size: usize
+                           , inner_activation: &str
+                           , outer_activation: &str
+                           , w_init: &str
+                           , w_recurrent_init: &str
+                           , b_init: &str);
+}
+
 pub trait LSTMGenerator {
   fn add_lstm<T: HasAfEnum>(&mut self
                             , manager: DeviceManager
@@ -588,6 +694,130 @@ pub trait UnitaryGenerator {
                                , is_permut_const: bool);
 }
 
+pub trait ConvGenerator {
+  fn add_conv2d<T: HasAfEnum>(&mut self
+                              , manager: DeviceManager
+                              , device: Device
+                              , input_dims: (usize, usize, usize) // [height, width, channels]
+                              , filters: usize
+                              , kernel: (usize, usize)            // [height, width]
+                              , activation: &str
+                              , w_init: &str
+                              , b_init: &str);
+}
+
+pub trait PoolGenerator {
+  fn add_pool2d<T: HasAfEnum>(&mut self
+                              , manager: DeviceManager
+                              , device: Device
+                              , layer_type: &str                  // max_pool2d or avg_pool2d
+                              , input_dims: (usize, usize, usize) // [height, width, channels]
+                              , window: (usize, usize)            // [height, width]
+                              , stride: (usize, usize));          // [height, width]
+
+  fn add_global_pool2d<T: HasAfEnum>(&mut self
+                                     , manager: DeviceManager
+                                     , device: Device
+                                     , layer_type: &str);
+}
+
+pub trait DropoutGenerator {
+  fn add_dropout<T: HasAfEnum>(&mut self
+                               , manager: DeviceManager
+                               , device: Device);
+}
+
+pub trait EmbeddingGenerator {
+  fn add_embedding<T: HasAfEnum>(&mut self
+                                 , manager: DeviceManager
+                                 , device: Device
+                                 , vocab_size: usize
+                                 , output_size: usize
+                                 , w_init: &str
+                                 , pretrained: Option<&[f32]>); // column major [vocab_size, output_size]
+}
+
+pub trait NormGenerator {
+  fn add_batch_norm<T: HasAfEnum>(&mut self
+                                  , manager: DeviceManager
+                                  , device: Device
+                                  , size: usize
+                                  , gamma_init: &str
+                                  , beta_init: &str);
+
+  fn add_layer_norm<T: HasAfEnum>(&mut self
+                                  , manager: DeviceManager
+                                  , device: Device
+                                  , size: usize
+                                  , gain_init: &str
+                                  , bias_init: &str);
+}
+
+pub trait AttentionGenerator {
+  fn add_attention<T: HasAfEnum>(&mut self
+                                 , manager: DeviceManager
+                                 , device: Device
+                                 , size: usize
+                                 , attention_size: Option<usize> // of the additive score, None for dot products
+                                 , w_init: &str
+                                 , b_init: &str);
+}
+
+pub trait TransformerGenerator {
+  fn add_transformer_encoder<T: HasAfEnum>(&mut self
+                                           , manager: DeviceManager
+                                           , device: Device
+                                           , size: usize
+                                           , ff_size: usize
+                                           , w_init: &str
+                                           , b_init: &str);
+}
+
+pub trait NTMGenerator {
+  fn add_ntm<T: HasAfEnum>(&mut self
+                           , manager: DeviceManager
+                           , device: Device
+                           , input_size: usize
+                           , output_size: usize
+                           , controller_size: usize
+                           , recurrent_controller: bool
+                           , memory_size: usize   // number of memory slots
+                           , memory_width: usize  // size of every slot
+                           , outer_activation: &str
+                           , w_init: &str
+                           , b_init: &str);
+}
+
+pub trait BidirectionalGenerator {
+  fn add_bidirectional<T: HasAfEnum>(&mut self
+                                     , manager: DeviceManager
+                                     , device: Device
+                                     , forward: &Params    // of the forward direction
+                                     , backward: &Params); // of the backward direction
+}
+
+pub trait ACTGenerator {
+  fn add_act<T: HasAfEnum>(&mut self
+                           , manager: DeviceManager
+                           , device: Device
+                           , cell: &Params          // of the wrapped cell
+                           , state_size: usize      // read by the halting unit
+                           , w_init: &str
+                           , b_init: &str);
+}
+
+pub trait FullUnitaryGenerator {
+  fn add_full_unitary<T: HasAfEnum>(&mut self
+                                    , manager: DeviceManager
+                                    , device: Device
+                                    , input_size: usize
+                                    , hidden_size: usize
+                                    , output_size: usize
+                                    , outer_activation: &str
+                                    , w_init: &str
+                                    , b_init: &str);
+}
+
 /** Custom Layer Impls **/
 
 impl DenseGenerator for ParamManager {
@@ -618,7 +848,8 @@ impl RNNGenerator for ParamManager {
                            , inner_activation: &str
                            , outer_activation: &str
                            , w_init: &str
-                           , b_init: &str)
+                           , b_init: &str
+                           , layer_norm: bool)
   {
     let recurrent_dims = (hidden_size, hidden_size);
     let input_dims = (input_size, hidden_size);
@@ -630,7 +861,13 @@ impl RNNGenerator for ParamManager {
     let weights = vec![(w_init, input_dims)         // input 2 hidden
       , (w_init, output_dims)      // hidden to output
       , (w_init, recurrent_dims)]; // hidden to hidden
-    let biases = vec![(b_init, input_bias_dims), (b_init, output_bias_dims)];
+    let mut biases = vec![(b_init, input_bias_dims), (b_init, output_bias_dims)];
+
+    // the layer norm gain & bias of the pre-activation are kept after the biases
+    if layer_norm {
+      biases.push(("ones", (1, hidden_size)));
+      biases.push(("zeros", (1, hidden_size)));
+    }
 
     self.add::<T>(manager, device, "rnn"
                   , weights                                            // weight dims
@@ -641,6 +878,44 @@ impl RNNGenerator for ParamManager {
   }
 }
 
+impl GRUGenerator for ParamManager {
+  fn add_gru<T: HasAfEnum>(&mut self
+                           , manager: DeviceManager
+                           , device: Device
+                           , input_size: usize
+                           , output_size: usize
+                           , inner_activation: &str
+                           , outer_activation: &str
+                           , w_init: &str
+                           , w_recurrent_init: &str
+                           , b_init: &str)
+  {
+    let input_dims = (input_size, output_size);
+    let recurrent_dims = (output_size, output_size);
+    let bias_dims = (output_size, 1);
+
+    // W_z, W_r, W_h, U_z, U_r, U_h
+    let weights = vec![(w_init, input_dims)
+      , (w_init, input_dims)
+      , (w_init, input_dims)
+      , (w_recurrent_init, recurrent_dims)
+      , (w_recurrent_init, recurrent_dims)
+      , (w_recurrent_init, recurrent_dims)];
+
+    // b_z, b_r, b_h
+    let biases = vec![(b_init, bias_dims); 3];
+
+    // the hidden states and the activated gates are batch dependent
+    // and thus are allocated during the forward pass
+    self.add::<T>(manager, device, "gru"
+                  , weights
+                  , biases
+                  , vec![inner_activation, outer_activation]
+                  , None
+                  , None);
+  }
+}
+
 impl LSTMGenerator for ParamManager {
   fn add_lstm<T: HasAfEnum>(&mut self
                             , manager: DeviceManager
@@ -658,25 +933,31 @@ impl LSTMGenerator for ParamManager {
     let input_dims = (input_size, output_size);
     let recurrent_dims = (output_size, output_size);
     let bias_dims = (output_size, 1);
+
     // W_i, W_f, W_o, W_ct, U_i, U_f, U_o, U_ct
+    let weights = vec![(w_init, input_dims)
+      , (w_init, input_dims)
+      , (w_init, input_dims)
+      , (w_init, input_dims)
+      , (w_recurrent_init, recurrent_dims)
+      , (w_recurrent_init, recurrent_dims)
+      , (w_recurrent_init, recurrent_dims)
+      , (w_recurrent_init, recurrent_dims)];
+
+    // b_i, b_f, b_o, b_ct
+    let biases = vec![(b_init, bias_dims)
+      , (forget_b_init, bias_dims)
+      , (b_init, bias_dims)
+      , (b_init, bias_dims)];
+
+    // the [h, c] states and the activated gates are batch dependent
+    // and thus are allocated during the forward pass
     self.add::<T>(manager, device, "lstm"
-                  , vec![(w_init, input_dims)
-                  , (w_init, input_dims)
-                  , (w_init, input_dims)
-                  , (w_init, input_dims)
-                  , (w_recurrent_init, recurrent_dims)
-                  , (w_recurrent_init, recurrent_dims)
-                  , (w_recurrent_init, recurrent_dims)
-                  , (w_recurrent_init, recurrent_dims)]
-                  , vec![(b_init, bias_dims)
-                  , (forget_b_init, bias_dims)
-                  , (b_init, bias_dims)
-                  , (b_init, bias_dims)]
+                  , weights
+                  , biases
                   , vec![inner_activation, outer_activation]
-                  , Some(vec![("zeros", bias_dims); 6])   // i_f_o_ct_c_h @ t-1
-                  , Some(vec![("zeros", input_dims)       // dW
-                         , ("zeros", recurrent_dims) // dU
-                         , ("zeros", bias_dims)]));  // db
+                  , None
+                  , None);
   }
 }
 
@@ -752,3 +1033,341 @@ impl<'a> UnitaryGenerator for ParamManager {
     layer.lock().unwrap().optional.push(utils::vec_to_array::<u32>(permut_inv, dims));
   }
 }
+
+impl DropoutGenerator for ParamManager {
+  fn add_dropout<T: HasAfEnum>(&mut self
+                               , manager: DeviceManager
+                               , device: Device)
+  {
+    // the masks are batch dependent and thus are allocated during the forward pass
+    self.add::<T>(manager, device, "dropout"
+                  , Vec::new(), Vec::new(), Vec::new()
+                  , None, None);
+  }
+}
+
+impl EmbeddingGenerator for ParamManager {
+  fn add_embedding<T: HasAfEnum>(&mut self
+                                 , manager: DeviceManager
+                                 , device: Device
+                                 , vocab_size: usize
+                                 , output_size: usize
+                                 , w_init: &str
+                                 , pretrained: Option<&[f32]>)
+  {
+    // a single [vocab_size, output_size] table, one row per token
+    self.add::<T>(manager, device, "embedding"
+                  , vec![(w_init, (vocab_size, output_size))]
+                  , Vec::new()
+                  , Vec::new()
+                  , None, None);
+
+    let layer = self.layer_storage.last().unwrap().clone();
+    let mut ltex = layer.lock().unwrap();
+    ltex.sparse = true;
+    if let Some(table) = pretrained {
+      let dims = Dim4::new(&[vocab_size as u64, output_size as u64, 1, 1]);
+      ltex.weights[0] = utils::cast(&utils::vec_to_array::<f32>(table.to_vec(), dims), T::get_af_dtype());
+    }
+  }
+}
+
+impl NormGenerator for ParamManager {
+  fn add_batch_norm<T: HasAfEnum>(&mut self
+                                  , manager: DeviceManager
+                                  , device: Device
+                                  , size: usize
+                                  , gamma_init: &str
+                                  , beta_init: &str)
+  {
+    // gamma & beta are [1, size] so that they broadcast over the batch;
+    // the running mean & variance are kept (and serialized) as optionals
+    self.add::<T>(manager, device, "batch_norm"
+                  , vec![(gamma_init, (1, size))]
+                  , vec![(beta_init, (1, size))]
+                  , Vec::new()
+                  , None
+                  , Some(vec![("zeros", (1, size)), ("ones", (1, size))]));
+  }
+
+  fn add_layer_norm<T: HasAfEnum>(&mut self
+                                  , manager: DeviceManager
+                                  , device: Device
+                                  , size: usize
+                                  , gain_init: &str
+                                  , bias_init: &str)
+  {
+    self.add::<T>(manager, device, "layer_norm"
+                  , vec![(gain_init, (1, size))]
+                  , vec![(bias_init, (1, size))]
+                  , Vec::new()
+                  , None, None);
+  }
+}
+
+impl AttentionGenerator for ParamManager {
+  fn add_attention<T: HasAfEnum>(&mut self
+                                 , manager: DeviceManager
+                                 , device: Device
+                                 , size: usize
+                                 , attention_size: Option<usize>
+                                 , w_init: &str
+                                 , b_init: &str)
+  {
+    // the dot product score has no params, the additive one
+    // keeps [W_q, W_k, v] as weights & b as a [1, attention_size] bias;
+    // the attention weights are batch dependent and allocated in the forward pass
+    let (weights, biases) = match attention_size {
+      Some(a) => (vec![(w_init, (size, a)), (w_init, (size, a)), (w_init, (a, 1))]
+                  , vec![(b_init, (1, a))]),
+      None    => (Vec::new(), Vec::new()),
+    };
+    self.add::<T>(manager, device, "attention"
+                  , weights, biases
+                  , Vec::new()
+                  , None, None);
+  }
+}
+
+impl TransformerGenerator for ParamManager {
+  fn add_transformer_encoder<T: HasAfEnum>(&mut self
+                                           , manager: DeviceManager
+                                           , device: Device
+                                           , size: usize
+                                           , ff_size: usize
+                                           , w_init: &str
+                                           , b_init: &str)
+  {
+    // [W_q, W_k, W_v, W_o, W_1, W_2] & [b_o, b_1, b_2] followed by
+    // the gain & bias of both layer normalizations
+    self.add::<T>(manager, device, "transformer_encoder"
+                  , vec![(w_init, (size, size)), (w_init, (size, size)), (w_init, (size, size))
+                         , (w_init, (size, size)), (w_init, (size, ff_size)), (w_init, (ff_size, size))]
+                  , vec![(b_init, (size, 1)), (b_init, (ff_size, 1)), (b_init, (size, 1))
+                         , ("ones", (1, size)), ("zeros", (1, size))
+                         , ("ones", (1, size)), ("zeros", (1, size))]
+                  , Vec::new()
+                  , None, None);
+  }
+}
+
+impl NTMGenerator for ParamManager {
+  fn add_ntm<T: HasAfEnum>(&mut self
+                           , manager: DeviceManager
+                           , device: Device
+                           , input_size: usize
+                           , output_size: usize
+                           , controller_size: usize
+                           , recurrent_controller: bool
+                           , memory_size: usize
+                           , memory_width: usize
+                           , outer_activation: &str
+                           , w_init: &str
+                           , b_init: &str)
+  {
+    // the controller reads [x_t, r_{t-1}] and emits [k, beta, g, s (3), gamma]
+    // for the read & write heads followed by the erase & add vectors
+    let heads_size = 4 * memory_width + 12;
+    let mut weights = vec![(w_init, (input_size + memory_width, controller_size)) // controller
+                           , (w_init, (controller_size, heads_size))              // heads
+                           , (w_init, (controller_size + memory_width, output_size))]; // output
+    if recurrent_controller {
+      weights.push((w_init, (controller_size, controller_size)));
+    }
+
+    // the packed [memory, read & write weightings, read vector, controller] state
+    // is allocated (with the batch size) on the first forward pass
+    self.add::<T>(manager, device, "ntm"
+                  , weights
+                  , vec![(b_init, (controller_size, 1)), (b_init, (heads_size, 1)), (b_init, (output_size, 1))]
+                  , vec![outer_activation]
+                  , None, None);
+  }
+}
+
+impl BidirectionalGenerator for ParamManager {
+  fn add_bidirectional<T: HasAfEnum>(&mut self
+                                     , manager: DeviceManager
+                                     , device: Device
+                                     , forward: &Params
+                                     , backward: &Params)
+  {
+    // the arrays were allocated by the layers of both directions, they are
+    // laid out as [W_fwd, W_bwd], [b_fwd, b_bwd] & [o_fwd, o_bwd]
+    let activations = forward.activations.iter().map(|a| a.as_str()).collect::<Vec<&str>>();
+    self.add::<T>(manager, device, "bidirectional"
+                  , Vec::new(), Vec::new()
+                  , activations
+                  , None, None);
+
+    let num_weights = forward.weights.len();
+    let layer = self.layer_storage.last().unwrap().clone();
+    let mut ltex = layer.lock().unwrap();
+    ltex.weights = forward.weights.iter().chain(backward.weights.iter()).cloned().collect();
+    ltex.biases = forward.biases.iter().chain(backward.biases.iter()).cloned().collect();
+    ltex.deltas = forward.deltas[..num_weights].iter().chain(backward.deltas[..num_weights].iter())
+      .chain(forward.deltas[num_weights..].iter()).chain(backward.deltas[num_weights..].iter())
+      .cloned().collect();
+    ltex.optional = forward.optional.iter().chain(backward.optional.iter()).cloned().collect();
+  }
+}
+
+impl ACTGenerator for ParamManager {
+  fn add_act<T: HasAfEnum>(&mut self
+                           , manager: DeviceManager
+                           , device: Device
+                           , cell: &Params
+                           , state_size: usize
+                           , w_init: &str
+                           , b_init: &str)
+  {
+    // the halting unit is allocated here, the arrays of the cell are laid out
+    // before it as [W_cell, W_h], [b_cell, b_h] along with the cell optionals
+    let activations = cell.activations.iter().map(|a| a.as_str()).collect::<Vec<&str>>();
+    self.add::<T>(manager, device, "act"
+                  , vec![(w_init, (state_size, 1))]
+                  , vec![(b_init, (1, 1))]
+                  , activations
+                  , None, None);
+
+    let num_weights = cell.weights.len();
+    let layer = self.layer_storage.last().unwrap().clone();
+    let mut ltex = layer.lock().unwrap();
+    let (halting_weight, halting_bias) = (ltex.weights[0].clone(), ltex.biases[0].clone());
+    let (dhalting_weight, dhalting_bias) = (ltex.deltas[0].clone(), ltex.deltas[1].clone());
+    ltex.weights = cell.weights.iter().cloned().chain(Some(halting_weight)).collect();
+    ltex.biases = cell.biases.iter().cloned().chain(Some(halting_bias)).collect();
+    ltex.deltas = cell.deltas[..num_weights].iter().cloned().chain(Some(dhalting_weight))
+      .chain(cell.deltas[num_weights..].iter().cloned()).chain(Some(dhalting_bias))
+      .collect();
+    ltex.optional = cell.optional.clone();
+  }
+}
+
+impl FullUnitaryGenerator for ParamManager {
+  fn add_full_unitary<T: HasAfEnum>(&mut self
+                                    , manager: DeviceManager
+                                    , device: Device
+                                    , input_size: usize
+                                    , hidden_size: usize
+                                    , output_size: usize
+                                    , outer_activation: &str
+                                    , w_init: &str
+                                    , b_init: &str)
+  {
+    // complex matrices are [real, imaginary] along the columns,
+    // the recurrent matrix is replaced by a random unitary below
+    self.add::<T>(manager, device, "full_unitary"
+                  , vec![("zeros", (hidden_size, 2*hidden_size))    // recurrent [unitary]
+                         , (w_init, (input_size, 2*hidden_size))   // input to hidden
+                         , (w_init, (2*hidden_size, output_size))] // hidden to output
+                  , vec![(b_init, (1, hidden_size))                // modReLU bias
+                         , (b_init, (output_size, 1))]
+                  , vec![outer_activation]
+                  , None
+                  , None);
+
+    let layer = self.layer_storage.last().unwrap().clone();
+    let mut ltex = layer.lock().unwrap();
+    ltex.weights[0] = random_unitary::<T>(hidden_size);
+    ltex.unitary = true;
+  }
+}
+
+impl ConvGenerator for ParamManager {
+  fn add_conv2d<T: HasAfEnum>(&mut self
+                              , manager: DeviceManager
+                              , device: Device
+                              , input_dims: (usize, usize, usize)
+                              , filters: usize
+                              , kernel: (usize, usize)
+                              , activation: &str
+                              , w_init: &str
+                              , b_init: &str)
+  {
+    let kernel_size = kernel.0 * kernel.1 * input_dims.2;
+
+    // a single [kernel_size, filters] matrix shared across all positions
+    self.add::<T>(manager, device, "conv2d"
+                  , vec![(w_init, (kernel_size, filters))]
+                  , vec![(b_init, (filters, 1))]
+                  , vec![activation]
+                  , None
+                  , None);
+  }
+}
+
+impl PoolGenerator for ParamManager {
+  fn add_pool2d<T: HasAfEnum>(&mut self
+                              , manager: DeviceManager
+                              , device: Device
+                              , layer_type: &str
+                              , input_dims: (usize, usize, usize)
+                              , window: (usize, usize)
+                              , stride: (usize, usize))
+  {
+    // pooling has no trainable params, only the window indices
+    self.add::<T>(manager, device, layer_type
+                  , Vec::new(), Vec::new(), Vec::new()
+                  , None, None);
+
+    // each tap k of the window at output feature q (position & channel)
+    // reads input feature indices[q + num_outputs * k]
+    let indices = patch_indices(input_dims, window, stride);
+    let dims = Dim4::new(&[indices.len() as u64, 1, 1, 1]);
+    let layer = self.layer_storage.last().unwrap().clone();
+    layer.lock().unwrap().optional.push(utils::vec_to_array::<u32>(indices, dims));
+  }
+
+  fn add_global_pool2d<T: HasAfEnum>(&mut self
+                                     , manager: DeviceManager
+                                     , device: Device
+                                     , layer_type: &str)
+  {
+    self.add::<T>(manager, device, layer_type
+                  , Vec::new(), Vec::new(), Vec::new()
+                  , None, None);
+  }
+}
+
+/// Helper that returns a random [n, 2n] (real, imaginary) unitary matrix
+///
+/// This is the Q of the QR decomposition of a complex gaussian matrix
+fn random_unitary<T: HasAfEnum>(n: usize) -> Array {
+  let dims = Dim4::new(&[n as u64, n as u64, 1, 1]);
+  let real = initializations::normal::<T>(dims, 1.0f32);
+  let (q, _, _) = af::qr(&af::cplx2(&real, &initializations::normal::<T>(dims, 1.0f32), false));
+  utils::cast(&af::join(1, &af::real(&q), &af::imag(&q)), real.get_type())
+}
+
+/// Helper that builds the indices of the input features covered by a pooling window
+///
+/// The features are the column major flattening of [height, width, channels] and
+/// the outputs are the [positions, channels]. Entry [o + num_outputs * k] is the
+/// feature read by tap k of output o.
+fn patch_indices(input_dims: (usize, usize, usize), window: (usize, usize)
+                 , stride: (usize, usize)) -> Vec<u32>
+{
+  let (height, width, channels) = input_dims;
+  let output_height = (height - window.0) / stride.0 + 1;
+  let output_width = (width - window.1) / stride.1 + 1;
+  let num_positions = output_height * output_width;
+  let num_outputs = num_positions * channels;
+
+  let mut indices: Vec<u32> = vec![0; num_outputs * window.0 * window.1];
+  for c in 0..channels {
+    for kx in 0..window.1 {
+      for ky in 0..window.0 {
+        for ox in 0..output_width {
+          for oy in 0..output_height {
+            let (y, x) = (oy * stride.0 + ky, ox * stride.1 + kx);
+            let o = oy + output_height * ox + num_positions * c;
+            let k = ky + window.0 * kx;
+            indices[o + num_outputs * k] = (y + height * x + height * width * c) as u32;
+          }
+        }
+      }
+    }
+  }
+  indices
+}
@@ -0,0 +1,207 @@
+use af;
+use af::{Array, Dim4, DType};
+use std::fs;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::collections::HashMap;
+use rustc_serialize::{json, Encodable, Decodable};
+
+use utils;
+use error::HALError;
+use optimizer::OptimizerState;
+
+/// Host side copy of an Array
+///
+/// The values are stored as f64 (real & imaginary parts) along with the
+/// original dtype and dims so that the array can be rebuilt on any backend
+#[derive(RustcEncodable, RustcDecodable, Clone, Debug)]
+pub struct ArrayRecord {
+  pub dims: Vec<u64>,
+  pub dtype: String,
+  pub real: Vec<f64>,
+  pub imag: Vec<f64>,
+}
+
+/// Serialized form of a single layer: its constructor params and its parameters
+#[derive(RustcEncodable, RustcDecodable, Clone, Debug)]
+pub struct LayerRecord {
+  pub layer_type: String,
+  pub params: HashMap<String, String>,
+  pub weights: Vec<ArrayRecord>,
+  pub biases: Vec<ArrayRecord>,
+  pub optional: Vec<ArrayRecord>,
+}
+
+/// Serialized form of a model
+#[derive(RustcEncodable, RustcDecodable, Clone, Debug)]
+pub struct ModelRecord {
+  pub loss: String,
+  pub layers: Vec<LayerRecord>,
+}
+
+/// Serialized form of an optimizer's state
+#[derive(RustcEncodable, RustcDecodable, Clone, Debug)]
+pub struct OptimizerRecord {
+  pub name: String,
+  pub iter: u64,
+  pub scalars: HashMap<String, f32>,
+  pub arrays: HashMap<String, Vec<ArrayRecord>>,
+}
+
+/// Serialized form of a training checkpoint
+///
+/// `epoch` & `iter` are the position (in the fit loop) to resume from while
+/// `source_epoch` & `source_iter` are the position of the data source.
+/// The remaining fields are the early stopping state of the run
+#[derive(RustcEncodable, RustcDecodable, Clone, Debug)]
+pub struct CheckpointRecord {
+  pub model: ModelRecord,
+  pub recurrences: Vec<Vec<ArrayRecord>>,
+  pub optimizer: OptimizerRecord,
+  pub epoch: u64,
+  pub iter: u64,
+  pub source_epoch: u64,
+  pub source_iter: u64,
+  pub validation_losses: Vec<f32>,
+  pub best_loss: Option<f32>,
+  pub best_params: Vec<ArrayRecord>,
+  pub epochs_without_improvement: u64,
+}
+
+impl ArrayRecord {
+  pub fn from_array(input: &Array) -> ArrayRecord {
+    let dtype = input.get_type();
+    let (real, imag) = match dtype {
+      DType::C32 | DType::C64 => (utils::array_to_vec(&utils::cast(&af::real(input), DType::F64))
+                                  , utils::array_to_vec(&utils::cast(&af::imag(input), DType::F64))),
+      _                       => (utils::array_to_vec(&utils::cast(input, DType::F64)), Vec::new()),
+    };
+
+    ArrayRecord {
+      dims: input.dims().get().to_vec(),
+      dtype: dtype_to_str(dtype).to_string(),
+      real: real,
+      imag: imag,
+    }
+  }
+
+  /// Rebuilds the array on the current device
+  ///
+  /// Returns `HALError::INVALID_PARAM` if the record is inconsistent (eg: a corrupt file)
+  pub fn to_array(&self) -> Result<Array, HALError> {
+    if self.dims.len() != 4 {
+      return Err(HALError::invalid_param("array record", "dims"
+                                         , format!("needs 4 dims, got {}", self.dims.len())));
+    }
+    let dims = Dim4::new(&[self.dims[0], self.dims[1], self.dims[2], self.dims[3]]);
+    let dtype = try!(str_to_dtype(&self.dtype));
+    let complex = dtype == DType::C32 || dtype == DType::C64;
+    let num_imag = if complex { dims.elements() as usize } else { 0 };
+    if self.real.len() != dims.elements() as usize || self.imag.len() != num_imag {
+      return Err(HALError::invalid_param("array record", "real"
+                                         , format!("{} values do not fill {:?}", self.real.len(), dims)));
+    }
+
+    let real = utils::raw_to_array::<f64>(&self.real[..], dims);
+    Ok(match complex {
+      true  => {
+        let imag = utils::raw_to_array::<f64>(&self.imag[..], dims);
+        utils::cast(&af::cplx2(&real, &imag, false), dtype)
+      },
+      false => utils::cast(&real, dtype),
+    })
+  }
+}
+
+impl OptimizerRecord {
+  pub fn from_state(state: &OptimizerState) -> OptimizerRecord {
+    OptimizerRecord {
+      name: state.name.clone(),
+      iter: state.iter,
+      scalars: state.scalars.clone(),
+      arrays: state.arrays.iter()
+        .map(|(k, v)| (k.clone(), v.iter().map(|a| ArrayRecord::from_array(a)).collect()))
+        .collect(),
+    }
+  }
+
+  /// Rebuilds the optimizer state on the current device
+  pub fn to_state(&self) -> Result<OptimizerState, HALError> {
+    let mut arrays = HashMap::new();
+    for (k, v) in self.arrays.iter() {
+      arrays.insert(k.clone(), try!(to_arrays(v)));
+    }
+    Ok(OptimizerState {
+      name: self.name.clone(),
+      iter: self.iter,
+      scalars: self.scalars.clone(),
+      arrays: arrays,
+    })
+  }
+}
+
+/// Helper that rebuilds a list of arrays, failing on the first inconsistent record
+pub fn to_arrays(records: &[ArrayRecord]) -> Result<Vec<Array>, HALError> {
+  let mut arrays = Vec::with_capacity(records.len());
+  for record in records {
+    arrays.push(try!(record.to_array()));
+  }
+  Ok(arrays)
+}
+
+pub fn dtype_to_str(dtype: DType) -> &'static str {
+  match dtype {
+    DType::F32 => "f32",
+    DType::F64 => "f64",
+    DType::C32 => "c32",
+    DType::C64 => "c64",
+    DType::B8  => "b8",
+    DType::S32 => "s32",
+    DType::U32 => "u32",
+    DType::U8  => "u8",
+    DType::S64 => "s64",
+    DType::U64 => "u64",
+    DType::S16 => "s16",
+    DType::U16 => "u16",
+  }
+}
+
+pub fn str_to_dtype(name: &str) -> Result<DType, HALError> {
+  Ok(match name {
+    "f32" => DType::F32,
+    "f64" => DType::F64,
+    "c32" => DType::C32,
+    "c64" => DType::C64,
+    "b8"  => DType::B8,
+    "s32" => DType::S32,
+    "u32" => DType::U32,
+    "u8"  => DType::U8,
+    "s64" => DType::S64,
+    "u64" => DType::U64,
+    "s16" => DType::S16,
+    "u16" => DType::U16,
+    _     => return Err(HALError::invalid_param("array record", "dtype", format!("unknown dtype '{}'", name))),
+  })
+}
+
+/// Helper to write an encodable struct to a json file
+pub fn write_json<T: Encodable>(path: &str, record: &T) -> Result<(), HALError> {
+  let encoded = try!(json::encode(record).map_err(|e| HALError::io_error(path, e)));
+
+  // write to a temporary file first so that an interrupted
+  // write never clobbers a previously valid file
+  let tmp_path = format!("{}.tmp", path);
+  {
+    let mut f = try!(File::create(&tmp_path).map_err(|e| HALError::io_error(&tmp_path, e)));
+    try!(f.write_all(encoded.as_bytes()).map_err(|e| HALError::io_error(&tmp_path, e)));
+  }
+  fs::rename(&tmp_path, path).map_err(|e| HALError::io_error(path, e))
+}
+
+/// Helper to read a decodable struct from a json file
+pub fn read_json<T: Decodable>(path: &str) -> Result<T, HALError> {
+  let mut file = try!(File::open(path).map_err(|e| HALError::io_error(path, e)));
+  let mut contents = String::new();
+  try!(file.read_to_string(&mut contents).map_err(|e| HALError::io_error(path, e)));
+  json::decode(&contents).map_err(|e| HALError::io_error(path, e))
+}
@@ -293,23 +293,22 @@ pub fn write_csv<T>(filename: &str, v: &Vec<T>)
 }
 
 // Helper to read a csv file to a vector
-pub fn read_csv<T>(filename: &str) -> Vec<T>
+pub fn read_csv<T>(filename: &str) -> Result<Vec<T>, HALError>
   where T: std::str::FromStr, <T as std::str::FromStr>::Err: std::fmt::Debug
 {
   let mut retval: Vec<T> = Vec::new();
-  let rdr = csv::Reader::from_file(Path::new(filename));
-  match rdr {
-    Ok(mut reader) => {
-      for row in reader.records() {
-        let row = row.unwrap();
-        for value in row {
-          retval.push(value.parse::<T>().unwrap());
-        }
-      }
-    },
-    Err(e)     => panic!("error reader from csv file {} : {}", filename, e),
+  let mut reader = try!(csv::Reader::from_file(Path::new(filename))
+                        .map_err(|e| HALError::io_error(filename, e)));
+  for row in reader.records() {
+    let row = try!(row.map_err(|e| HALError::io_error(filename, e)));
+    for value in row {
+      match value.parse::<T>() {
+        Ok(v)  => retval.push(v),
+        Err(e) => return Err(HALError::io_error(filename, format!("could not parse '{}': {:?}", value, e))),
+      };
+    }
   }
-  retval
+  Ok(retval)
 }
 
 // Generic Normalizer
@@ -346,53 +345,45 @@ pub fn scale(src: &Array, low: f32, high: f32) -> Array {
           , &low, false)
 }
 
-fn _read_gzip_filename(entire_file: &Vec<u8>) -> String {
-  let d = match GzDecoder::new(&entire_file[..]) {
-    Err(e) => panic!("Could not read gzip header: {}", e),
-    Ok(dc) => dc,
-  };
-  str::from_utf8(d.header().filename().unwrap())
-    .unwrap().to_owned()
+fn _read_gzip_filename(src: &str, entire_file: &Vec<u8>) -> Result<String, HALError> {
+  let d = try!(GzDecoder::new(&entire_file[..]).map_err(|e| HALError::io_error(src, e)));
+  match d.header().filename().map(|name| str::from_utf8(name)) {
+    Some(Ok(name)) => Ok(name.to_owned()),
+    _              => Err(HALError::io_error(src, "gzip header does not contain a valid filename")),
+  }
 }
 
-fn _ungzip_to_file(dest: &str, entire_file: &Vec<u8>) -> bool{
-  let mut d = match GzDecoder::new(&entire_file[..]) {
-    Err(e) => panic!("Could not read gzip {}: {}", dest, e),
-    Ok(dc) => dc,
-  };
-
+fn _ungzip_to_file(src: &str, dest: &str, entire_file: &Vec<u8>) -> Result<(), HALError> {
+  let mut d = try!(GzDecoder::new(&entire_file[..]).map_err(|e| HALError::io_error(src, e)));
   let mut body = Vec::new();
-  d.read_to_end(&mut body).unwrap();
-  let mut f = match File::create(dest){
-    Err(e) => panic!("cannot create file {}: {}", dest, e),
-    Ok(f)  => f,
-  };
-  f.write_all(&body[..]).unwrap();
-  true
+  try!(d.read_to_end(&mut body).map_err(|e| HALError::io_error(src, e)));
+  let mut f = try!(File::create(dest).map_err(|e| HALError::io_error(dest, e)));
+  f.write_all(&body[..]).map_err(|e| HALError::io_error(dest, e))
 }
 
-pub fn ungzip(src: &str) {
-  let mut file = match File::open(src){
-    Err(e) => panic!("could not open {}, {}", src, e),
-    Ok(f)  => f,
-  };
+/// Decompresses a gzip file to the filename stored in its header
+pub fn ungzip(src: &str) -> Result<(), HALError> {
+  let mut file = try!(File::open(src).map_err(|e| HALError::io_error(src, e)));
   let mut entire_file = Vec::new();
-  file.read_to_end(&mut entire_file).unwrap();
+  try!(file.read_to_end(&mut entire_file).map_err(|e| HALError::io_error(src, e)));
 
   // get the filename
-  let name = _read_gzip_filename(&entire_file);
+  let name = try!(_read_gzip_filename(src, &entire_file));
   print!("ungzip'ing {} from {}...", name, src);
 
   // write to dest
-  _ungzip_to_file(&name, &entire_file);
+  try!(_ungzip_to_file(src, &name, &entire_file));
   println!("...completed");
+  Ok(())
 }
 
-pub fn untar(src: &str, dest: &str){
+pub fn untar(src: &str, dest: &str) -> Result<(), HALError> {
   print!("Untarring {}...", src);
-  let mut ar = Archive::new(File::open(src).unwrap());
-  ar.unpack(dest).unwrap();
+  let file = try!(File::open(src).map_err(|e| HALError::io_error(src, e)));
+  let mut ar = Archive::new(file);
+  try!(ar.unpack(dest).map_err(|e| HALError::io_error(dest, e)));
   println!("...complete");
+  Ok(())
 }
 
 /// Pull a file from URL to a location destination
@@ -400,22 +391,20 @@ pub fn untar(src: &str, dest: &str){
 /// # Parameters
 /// - `url` is the location to pull data from
 /// - `dest` is the destination file location
-pub fn download(url: &str, dest: &str) {
+pub fn download(url: &str, dest: &str) -> Result<(), HALError> {
   print!("Downloading {} to {}...", url, dest);
   let client = Client::new();
-  let mut res = client.get(url)
-                      .header(Connection::close())
-                      .send().unwrap();
+  let mut res = try!(client.get(url)
+                     .header(Connection::close())
+                     .send().map_err(|e| HALError::io_error(url, e)));
   let mut body = Vec::new();
-  res.read_to_end(&mut body).unwrap();
+  try!(res.read_to_end(&mut body).map_err(|e| HALError::io_error(url, e)));
 
-  let mut f = match File::create(dest){
-    Err(e) => panic!("cannot open file {}: {}", dest, e),
-    Ok(f)  => f,
-  };
-  f.write_all(&body[..]).unwrap();
+  let mut f = try!(File::create(dest).map_err(|e| HALError::io_error(dest, e)));
+  try!(f.write_all(&body[..]).map_err(|e| HALError::io_error(dest, e)));
   println!("...complete: read {} Mb"
            , body.len() as f32/(1024.0*1024.0));
+  Ok(())
 }
 
 /// Returns true if the file exists (and is a file)
@@ -1,18 +1,30 @@
-extern crate hal;
+#[macro_use] extern crate hal;
 extern crate arrayfire as af;
 extern crate itertools;
 extern crate rand;
 #[macro_use] extern crate timeit;
 
 use std::env;
+use std::fs::File;
+use std::io::Write;
+use std::rc::Rc;
+use std::cell::RefCell;
+use std::sync::{Arc, Mutex};
 use af::{Array, Dim4, Backend, DType};
 use itertools::Zip;
 use rand::distributions::{IndependentSample, Range};
 
-use hal::{utils, activations, initializations, loss};
+use hal::{utils, activations, initializations, loss, metrics};
+use hal::metrics::Metric;
+use hal::{Model, Callback};
+use hal::model::{Sequential, Graph, MergeMode};
+use hal::optimizer;
+use hal::optimizer::{Optimizer, SGD, Adam, get_optimizer, get_optimizer_with_defaults};
+use hal::data::{Data, DataSource, DataParams, SinSource, AddingProblemSource, CopyingProblemSource};
 use hal::layer;
-use hal::layer::{Layer};
-use hal::params::{DenseGenerator, RNNGenerator, UnitaryGenerator, ParamManager};
+use hal::layer::{Layer, LayerConfig};
+use hal::params::{DenseGenerator, RNNGenerator, LSTMGenerator, GRUGenerator, UnitaryGenerator, ParamManager, Params
+                  , UpdateRows};
 use hal::device::{DeviceManagerFactory, Device};
 use hal::error::HALError;
 
@@ -224,12 +236,24 @@ where F: FnMut(&ParamManager, Box<Layer>)
       input_size: input_size,
       hidden_size: hdims.unwrap()[1] as usize,
       output_size: output_size,
+      layer_norm: false,
+      return_sequences: true,
+    }),
+    "lstm"  => Box::new(layer::LSTM {
+      input_size: input_size,
+      output_size: output_size,
+      return_sequences: true,
+    }),
+    "gru"  => Box::new(layer::GRU {
+      input_size: input_size,
+      output_size: output_size,
+      return_sequences: true,
     }),
     "unitary" => Box::new(layer::Unitary {
       input_size: input_size,
       output_size: output_size,
+      return_sequences: true,
     }),
-    //todo: lstm, etc
     _      => panic!("unknown layer type specified"),
   };
 
@@ -249,6 +273,26 @@ where F: FnMut(&ParamManager, Box<Layer>)
                                    , activation // inner activation
                                    , activation // outer activation
                                    , w_init
+                                   , b_init
+                                   , false);
+    }
+    "lstm"  => {
+      param_manager.add_lstm::<f64>(device_manager, device
+                                    , input_size, output_size
+                                    , activation // inner activation
+                                    , activation // outer activation
+                                    , w_init
+                                    , w_init     // recurrent weight init
+                                    , b_init     // forget bias init
+                                    , b_init);
+    }
+    "gru"  => {
+      param_manager.add_gru::<f64>(device_manager, device
+                                   , input_size, output_size
+                                   , activation // inner activation
+                                   , activation // outer activation
+                                   , w_init
+                                   , w_init     // recurrent weight init
                                    , b_init);
     }
     "unitary" => { 
@@ -269,7 +313,6 @@ where F: FnMut(&ParamManager, Box<Layer>)
       //let h_t = utils::constant(hdims, DType::F64, 0.5f32);
       //param_manager.set_recurrences(0, vec![h_t]);
     }
-    //todo: lstm, etc
     _      => panic!("unknown layer type specified"),
   };
 
@@ -297,9 +340,13 @@ pub fn layer_forward_helper(layer_type: &str, idims: Dim4, hdims: Option<Dim4>
 
                   // make it such that we are within an unrolling [for rnn types]
                   let h_t = match &layer_type.to_lowercase()[..] {
-                    "rnn" | "unitary"  => {
+                    "rnn" | "gru" | "unitary"  => {
                       vec![utils::constant(hdims.unwrap(), DType::F64, 0.5f32)]
                     },
+                    "lstm" => { // [h_{t-1}, c_{t-1}]
+                      vec![utils::constant(hdims.unwrap(), DType::F64, 0.5f32)
+                           , utils::constant(hdims.unwrap(), DType::F64, 0.5f32)]
+                    },
                     _     => vec![utils::constant(odims, DType::F64, 0.5f32)],
                   };
 
@@ -350,9 +397,13 @@ pub fn layer_backward_helper(layer_type: &str, idims: Dim4, hdims: Option<Dim4>
 
                   // make it such that we are within an unrolling [for rnn types]
                   let h_t = match &layer_type.to_lowercase()[..] {
-                    "rnn" => {
+                    "rnn" | "gru" => {
                       vec![initializations::uniform::<f64>(hdims.unwrap(), -0.5, 0.5)]
                     },
+                    "lstm" => { // [h_{t-1}, c_{t-1}]
+                      vec![initializations::uniform::<f64>(hdims.unwrap(), -0.5, 0.5)
+                           , initializations::uniform::<f64>(hdims.unwrap(), -0.5, 0.5)]
+                    },
                     // XXX: refactor later
                     _  => vec![utils::constant(odims, DType::F64, 0.0f32)],
                   };
@@ -465,6 +516,22 @@ fn rnn_forward(){
   });
 }
 
+#[test]
+fn rnn_batch_size_change(){
+  let device_manager = DeviceManagerFactory::new();
+  let device = Device{backend: Backend::DEFAULT, id: 0};
+  let mut param_manager = ParamManager::default();
+  let layer = layer::RNNConfig::new(3, 4, 4).build::<f64>(&mut param_manager, device_manager, device);
+  let params = param_manager.get_params(0);
+
+  // a new sequence with a different batch size starts from a zero state
+  let (activ, _) = layer.forward(params.clone(), &utils::constant(Dim4::new(&[2, 3, 1, 1]), DType::F64, 1.0f32), None);
+  assert!(activ.dims()[0] == 2);
+  params.lock().unwrap().current_unroll = 0;
+  let (activ, _) = layer.forward(params.clone(), &utils::constant(Dim4::new(&[5, 3, 1, 1]), DType::F64, 1.0f32), None);
+  assert!(activ.dims()[0] == 5 && params.lock().unwrap().recurrences[0].dims()[0] == 5);
+}
+
 #[test]
 fn rnn_backward() {
   timeit! ({
@@ -480,6 +547,74 @@ fn rnn_backward() {
   });
 }
 
+#[test]
+/// With linear activations and unit weights every gate is identical:
+///     z   = sum(x) + sum(h_{t-1}) = 6.44 + 2.5 = 8.94
+///     c_t = z * z + z * c_{t-1}   = 84.3936
+///     h_t = z * c_t               = 754.478784
+fn lstm_forward(){
+  timeit!({
+    let idims = Dim4::new(&[1, 5, 1, 1]);
+    let odims = Dim4::new(&[1, 5, 1, 1]);
+    let hdims = Dim4::new(&[1, 5, 1, 1]);
+    layer_forward_helper("lstm", idims, Some(hdims), odims, "l2", 1e-4
+                         , "linear"                                      // activation
+                         , "ones"                                        // weight init
+                         , "zeros"                                       // bias init
+                         , vec![-0.01, 0.00, 1.10, 2.20, 3.15]           //input
+                         , vec![754.478784, 754.478784, 754.478784, 754.478784, 754.478784]); //target
+  });
+}
+
+#[test]
+fn lstm_backward() {
+  timeit! ({
+    let idims = Dim4::new(&[1, 4, 1, 1]); // single time slice
+    let odims = Dim4::new(&[1, 4, 1, 1]); // single time slice
+    let hdims = Dim4::new(&[1, 4, 1, 1]); // single time slice
+    layer_backward_helper("lstm", idims, Some(hdims), odims
+                          , "l2"              // loss
+                          , 1e-3              // eps for numerical grad
+                          , "tanh"            // activation [used for inner and outer]
+                          , "glorot_uniform"  // weight init
+                          , "glorot_uniform");// bias init
+  });
+}
+
+#[test]
+/// With linear activations and unit weights both gates are identical:
+///     z_t  = r_t = sum(x) + sum(h_{t-1})    = 6.44 + 2.5 = 8.94
+///     hh_t = sum(x) + sum(r_t .* h_{t-1})   = 6.44 + 22.35 = 28.79
+///     h_t  = (1 - z_t) * h_{t-1} + z_t * hh_t = 253.4126
+fn gru_forward(){
+  timeit!({
+    let idims = Dim4::new(&[1, 5, 1, 1]);
+    let odims = Dim4::new(&[1, 5, 1, 1]);
+    let hdims = Dim4::new(&[1, 5, 1, 1]);
+    layer_forward_helper("gru", idims, Some(hdims), odims, "l2", 1e-4
+                         , "linear"                                      // activation
+                         , "ones"                                        // weight init
+                         , "zeros"                                       // bias init
+                         , vec![-0.01, 0.00, 1.10, 2.20, 3.15]           //input
+                         , vec![253.4126, 253.4126, 253.4126, 253.4126, 253.4126]); //target
+  });
+}
+
+#[test]
+fn gru_backward() {
+  timeit! ({
+    let idims = Dim4::new(&[1, 4, 1, 1]); // single time slice
+    let odims = Dim4::new(&[1, 4, 1, 1]); // single time slice
+    let hdims = Dim4::new(&[1, 4, 1, 1]); // single time slice
+    layer_backward_helper("gru", idims, Some(hdims), odims
+                          , "l2"              // loss
+                          , 1e-3              // eps for numerical grad
+                          , "tanh"            // activation [used for inner and outer]
+                          , "glorot_uniform"  // weight init
+                          , "glorot_uniform");// bias init
+  });
+}
+
 #[test]
 fn unitary_forward() {
   let idims = Dim4::new(&[1, 10, 1, 1]);
@@ -505,3 +640,1617 @@ fn unitary_backward() {
                         , " ");
 }
 
+
+/// helper to run the gradient check that matches the smoothness of the layer
+fn check_gradient<F>(fn_closure: F, arr: &Array, eps: f64, grad: &Array, smooth: bool)
+  where F : Fn(&Array) -> f64
+{
+  let rel = match smooth {
+    true  => utils::verify_gradient_smooth(fn_closure, arr, eps, grad),
+    false => utils::verify_gradient_kinks(fn_closure, arr, eps, grad),
+  };
+  rel.unwrap();
+}
+
+/// test the parameter & input gradients of a layer built from its config
+pub fn config_backward_helper<C: LayerConfig>(config: &C, batch_size: u64, loss: &str
+                                              , eps: f64, smooth: bool)
+{
+  let mut param_manager = ParamManager::default();
+  let device_manager = DeviceManagerFactory::new();
+  let device = Device{backend: Backend::DEFAULT, id: 0};
+  config.validate().unwrap();
+  let layer = config.build::<f64>(&mut param_manager, device_manager, device);
+  let params = param_manager.get_params(0);
+
+  let idims = Dim4::new(&[batch_size, config.input_size() as u64, 1, 1]);
+  let odims = Dim4::new(&[batch_size, config.output_size() as u64, 1, 1]);
+  let x = initializations::uniform::<f64>(idims, -0.5f32, 0.5f32);
+  let targets = initializations::uniform::<f64>(odims, -0.5f32, 0.5f32);
+
+  // run a forward and then bkwd pass to extract the gradients
+  let (activ, _) = layer.forward(params.clone(), &x, None);
+  let delta = loss::get_loss_derivative(loss, &activ, &targets).unwrap();
+  let dx = layer.backward(params.clone(), &delta);
+  let grads = param_manager.get_all_deltas();
+  let num_params = param_manager.num_arrays(0);
+
+  for (arr, grad, ind) in Zip::new((param_manager.get_all_arrays().iter(), grads, 0..num_params)) {
+    println!("\nTesting gradient of array with {:?} dims", arr.dims());
+    check_gradient(|i: &Array| {
+      params.lock().unwrap().current_unroll = 0;
+      param_manager.set_array_from_index(i.clone(), ind);
+      let (fwd_pass, _) = layer.forward(params.clone(), &x, None);
+      loss::get_loss(loss, &fwd_pass, &targets).unwrap() as f64
+    }, &arr.copy(), eps, &grad, smooth);
+    param_manager.set_array_from_index(arr.clone(), ind);
+  }
+
+  println!("\nTesting gradient of the {:?} input", idims);
+  check_gradient(|i: &Array| {
+    params.lock().unwrap().current_unroll = 0;
+    let (fwd_pass, _) = layer.forward(params.clone(), i, None);
+    loss::get_loss(loss, &fwd_pass, &targets).unwrap() as f64
+  }, &x, eps, &dx, smooth);
+}
+
+/// helper that runs a sequence through a layer starting from a fresh (zero) state
+fn unroll_forward(layer: &Box<Layer>, params: Arc<Mutex<Params>>, xs: &[Array]) -> Vec<Array>
+{
+  {
+    let mut ltex = params.lock().unwrap();
+    ltex.current_unroll = 0;
+    ltex.inputs.clear();
+    ltex.outputs.clear();
+    ltex.recurrences.clear();
+    ltex.state_derivatives.clear();
+  }
+  layer.forward_sequence(params, xs)
+}
+
+/// test the parameter & first input gradients of a layer unrolled over several timesteps
+pub fn config_unroll_helper<C: LayerConfig>(config: &C, batch_size: u64, seq_len: u64, loss: &str
+                                            , eps: f64, smooth: bool)
+{
+  let mut param_manager = ParamManager::default();
+  let device_manager = DeviceManagerFactory::new();
+  let device = Device{backend: Backend::DEFAULT, id: 0};
+  config.validate().unwrap();
+  let layer = config.build::<f64>(&mut param_manager, device_manager, device);
+  let params = param_manager.get_params(0);
+
+  let idims = Dim4::new(&[batch_size, config.input_size() as u64, 1, 1]);
+  let odims = Dim4::new(&[batch_size, config.output_size() as u64, 1, 1]);
+  let xs: Vec<Array> = (0..seq_len).map(|_| initializations::uniform::<f64>(idims, -0.5f32, 0.5f32)).collect();
+  let targets: Vec<Array> = (0..seq_len).map(|_| initializations::uniform::<f64>(odims, -0.5f32, 0.5f32)).collect();
+  let seq_loss = |outputs: Vec<Array>| {
+    Zip::new((outputs.iter(), targets.iter()))
+      .fold(0f64, |sum, (o, t)| sum + loss::get_loss(loss, o, t).unwrap() as f64)
+  };
+
+  // backprop through the whole unroll
+  let outputs = unroll_forward(&layer, params.clone(), &xs);
+  let deltas: Vec<Array> = Zip::new((outputs.iter(), targets.iter()))
+    .map(|(o, t)| loss::get_loss_derivative(loss, o, t).unwrap()).collect();
+  let dx = layer.backward_sequence(params.clone(), &deltas);
+  let grads = param_manager.get_all_deltas();
+  let num_params = param_manager.num_arrays(0);
+
+  for (arr, grad, ind) in Zip::new((param_manager.get_all_arrays().iter(), grads, 0..num_params)) {
+    println!("\nTesting unrolled gradient of array with {:?} dims", arr.dims());
+    check_gradient(|i: &Array| {
+      param_manager.set_array_from_index(i.clone(), ind);
+      seq_loss(unroll_forward(&layer, params.clone(), &xs))
+    }, &arr.copy(), eps, &grad, smooth);
+    param_manager.set_array_from_index(arr.clone(), ind);
+  }
+
+  println!("\nTesting unrolled gradient of the first {:?} input", idims);
+  check_gradient(|i: &Array| {
+    let mut perturbed = xs.clone();
+    perturbed[0] = i.clone();
+    seq_loss(unroll_forward(&layer, params.clone(), &perturbed))
+  }, &xs[0], eps, &dx[0], smooth);
+}
+
+/// test that the RTRL gradients of a layer match BPTT over a single timestep
+/// and the numerical gradients of the whole sequence loss over several timesteps
+pub fn config_rtrl_helper<C: LayerConfig>(config: &C, batch_size: u64, seq_len: u64, loss: &str, eps: f64)
+{
+  let mut param_manager = ParamManager::default();
+  let device_manager = DeviceManagerFactory::new();
+  let device = Device{backend: Backend::DEFAULT, id: 0};
+  config.validate().unwrap();
+  let layer = config.build::<f64>(&mut param_manager, device_manager, device);
+  let rtrl = layer.as_rtrl().unwrap();
+  let params = param_manager.get_params(0);
+
+  let idims = Dim4::new(&[batch_size, config.input_size() as u64, 1, 1]);
+  let odims = Dim4::new(&[batch_size, config.output_size() as u64, 1, 1]);
+  let xs: Vec<Array> = (0..seq_len).map(|_| initializations::uniform::<f64>(idims, -0.5f32, 0.5f32)).collect();
+  let targets: Vec<Array> = (0..seq_len).map(|_| initializations::uniform::<f64>(odims, -0.5f32, 0.5f32)).collect();
+  let seq_loss = |outputs: Vec<Array>| {
+    Zip::new((outputs.iter(), targets.iter()))
+      .fold(0f64, |sum, (o, t)| sum + loss::get_loss(loss, o, t).unwrap() as f64)
+  };
+
+  // a single timestep from a fresh state: rtrl & bptt compute the same gradients
+  let outputs = unroll_forward(&layer, params.clone(), &xs[..1]);
+  let delta = loss::get_loss_derivative(loss, &outputs[0], &targets[0]).unwrap();
+  layer.backward_sequence(params.clone(), &[delta.clone()]);
+  let bptt_grads = param_manager.get_all_deltas();
+  param_manager.zero_all_deltas();
+  unroll_forward(&layer, params.clone(), &xs[..1]);
+  rtrl.rtrl(params.clone(), &delta);
+  for (bptt_grad, rtrl_grad) in Zip::new((bptt_grads.iter(), param_manager.get_all_deltas().iter())) {
+    let diff = af::max_all(&af::abs(&af::sub(bptt_grad, rtrl_grad, false))).0;
+    assert!(diff < 1e-9, "rtrl & bptt gradients of {:?} differ by {}", rtrl_grad.dims(), diff);
+  }
+
+  // several timesteps: every step adds the gradient of its loss w.r.t. the whole history
+  param_manager.zero_all_deltas();
+  unroll_forward(&layer, params.clone(), &[]);
+  for (x, target) in Zip::new((xs.iter(), targets.iter())) {
+    let (output, _) = layer.forward(params.clone(), x, None);
+    rtrl.rtrl(params.clone(), &loss::get_loss_derivative(loss, &output, target).unwrap());
+  }
+  let grads = param_manager.get_all_deltas();
+  let num_params = param_manager.num_arrays(0);
+
+  for (arr, grad, ind) in Zip::new((param_manager.get_all_arrays().iter(), grads, 0..num_params)) {
+    println!("\nTesting rtrl gradient of array with {:?} dims", arr.dims());
+    check_gradient(|i: &Array| {
+      param_manager.set_array_from_index(i.clone(), ind);
+      seq_loss(unroll_forward(&layer, params.clone(), &xs))
+    }, &arr.copy(), eps, &grad, true);
+    param_manager.set_array_from_index(arr.clone(), ind);
+  }
+}
+
+#[test]
+fn conv2d_forward() {
+  let device_manager = DeviceManagerFactory::new();
+  let device = Device{backend: Backend::DEFAULT, id: 0};
+
+  // a 3x3 image holding 1..9 in column major order
+  let x = Array::new::<f64>(&[1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0, 9.0], Dim4::new(&[1, 9, 1, 1]));
+  let sum_kernel = layer::Conv2DConfig { w_init: "ones".to_string()
+                                         , ..layer::Conv2DConfig::new((3, 3, 1), 1, (2, 2)) };
+  let padded = layer::Conv2DConfig { stride: (2, 2), padding: (1, 1), ..sum_kernel.clone() };
+  for (config, expected) in vec![(sum_kernel, vec![12.0, 16.0, 24.0, 28.0])
+                                 , (padded, vec![1.0, 5.0, 11.0, 28.0])] {
+    let mut param_manager = ParamManager::default();
+    config.validate().unwrap();
+    assert!(config.output_size() == 4);
+    let layer = config.build::<f64>(&mut param_manager, device_manager.clone(), device);
+    let (activ, _) = layer.forward(param_manager.get_params(0), &x, None);
+    assert!(utils::array_to_vec(&activ) == expected
+            , "conv2d output {:?} differs from {:?}", utils::array_to_vec(&activ), expected);
+  }
+
+  // kernels that do not fit & zero strides are rejected
+  let too_wide = layer::Conv2DConfig::new((3, 3, 1), 1, (2, 4));
+  match too_wide.validate() {
+    Err(HALError::INVALID_PARAM{ref field, ..}) => assert!(field == "kernel_width"),
+    _ => panic!("expected an invalid param error for kernel_width"),
+  };
+  let zero_stride = layer::Conv2DConfig { stride: (0, 1), ..layer::Conv2DConfig::new((3, 3, 1), 1, (2, 2)) };
+  assert!(zero_stride.validate().is_err());
+
+  // convolutions stack with dense layers in a sequential model
+  let mut model = Sequential::new(device_manager.clone(), Box::new(SGD::default()), "l2", device);
+  model.add::<f32>("conv2d", hashmap!["input_height"     => 6.to_string()
+                                      , "input_width"    => 6.to_string()
+                                      , "input_channels" => 2.to_string()
+                                      , "filters"        => 4.to_string()
+                                      , "kernel_height"  => 3.to_string()
+                                      , "kernel_width"   => 3.to_string()
+                                      , "stride_height"  => 2.to_string()
+                                      , "stride_width"   => 2.to_string()
+                                      , "padding_height" => 1.to_string()
+                                      , "padding_width"  => 1.to_string()
+                                      , "activation"     => "relu".to_string()]).unwrap();
+  model.add::<f32>("dense", hashmap!["input_size" => (3 * 3 * 4).to_string()
+                                     , "output_size" => 5.to_string()]).unwrap();
+  let input = initializations::uniform::<f32>(Dim4::new(&[2, 72, 3, 1]), -1.0, 1.0);
+  assert!(model.predict(&input).dims().get() == &[2, 5, 3, 1]);
+}
+
+#[test]
+fn conv2d_backward() {
+  // multiple channels & filters, uneven kernel, stride & padding
+  let config = layer::Conv2DConfig { stride: (1, 2), padding: (1, 0), activation: "tanh".to_string()
+                                     , b_init: "glorot_uniform".to_string()
+                                     , ..layer::Conv2DConfig::new((4, 4, 2), 3, (3, 2)) };
+  assert!(config.output_dims() == (4, 2));
+  config_backward_helper(&config, 2, "l2", 1e-4, true);
+}
+
+#[test]
+fn pooling_forward() {
+  let device_manager = DeviceManagerFactory::new();
+  let device = Device{backend: Backend::DEFAULT, id: 0};
+
+  // two 4x4 channels holding 1..32 in column major order
+  let values: Vec<f64> = (1..33).map(|v| v as f64).collect();
+  let x = utils::vec_to_array::<f64>(values, Dim4::new(&[1, 32, 1, 1]));
+  let max_pool = layer::Pool2DConfig::new(layer::PoolType::Max, (4, 4, 2), (2, 2));
+  let avg_pool = layer::Pool2DConfig::new(layer::PoolType::Average, (4, 4, 2), (2, 2));
+  let global_pool = layer::GlobalAvgPool2DConfig::new((4, 4, 2));
+  assert!(max_pool.output_size() == 8 && global_pool.output_size() == 2);
+
+  let mut param_manager = ParamManager::default();
+  let layers = vec![max_pool.build::<f64>(&mut param_manager, device_manager.clone(), device)
+                    , avg_pool.build::<f64>(&mut param_manager, device_manager.clone(), device)
+                    , global_pool.build::<f64>(&mut param_manager, device_manager.clone(), device)];
+  let expected = vec![vec![6.0, 8.0, 14.0, 16.0, 22.0, 24.0, 30.0, 32.0]
+                      , vec![3.5, 5.5, 11.5, 13.5, 19.5, 21.5, 27.5, 29.5]
+                      , vec![8.5, 24.5]];
+  for (layer, truth, index) in Zip::new((layers.iter(), expected.iter(), 0..3)) {
+    let (activ, _) = layer.forward(param_manager.get_params(index), &x, None);
+    assert!(utils::array_to_vec(&activ) == *truth
+            , "pooling output {:?} differs from {:?}", utils::array_to_vec(&activ), truth);
+  }
+
+  // the max pool gradient is routed to the max of every window only
+  let dx = layers[0].backward(param_manager.get_params(0), &utils::constant(Dim4::new(&[1, 8, 1, 1]), DType::F64, 1.0));
+  let routed: Vec<usize> = utils::array_to_vec(&dx).iter().enumerate()
+    .filter(|&(_, d)| *d == 1.0).map(|(i, _)| i).collect();
+  assert!(routed == vec![5, 7, 13, 15, 21, 23, 29, 31]);
+  assert!(af::sum_all(&dx).0 == 8.0);
+
+  // windows that do not fit are rejected
+  match layer::Pool2DConfig::new(layer::PoolType::Max, (4, 4, 1), (5, 2)).validate() {
+    Err(HALError::INVALID_PARAM{ref component, ref field, ..}) => assert!(component == "max_pool2d" && field == "window_height"),
+    _ => panic!("expected an invalid param error for window_height"),
+  };
+
+  // conv -> pool -> dense in a sequential model
+  let mut model = Sequential::new(device_manager.clone(), Box::new(SGD::default()), "l2", device);
+  model.add::<f32>("conv2d", hashmap!["input_height"     => 8.to_string()
+                                      , "input_width"    => 8.to_string()
+                                      , "filters"        => 3.to_string()
+                                      , "kernel_height"  => 3.to_string()
+                                      , "kernel_width"   => 3.to_string()
+                                      , "padding_height" => 1.to_string()
+                                      , "padding_width"  => 1.to_string()
+                                      , "activation"     => "relu".to_string()]).unwrap();
+  model.add::<f32>("max_pool2d", hashmap!["input_height"     => 8.to_string()
+                                          , "input_width"    => 8.to_string()
+                                          , "input_channels" => 3.to_string()
+                                          , "window_height"  => 2.to_string()
+                                          , "window_width"   => 2.to_string()]).unwrap();
+  model.add::<f32>("global_avg_pool2d", hashmap!["input_height"     => 4.to_string()
+                                                 , "input_width"    => 4.to_string()
+                                                 , "input_channels" => 3.to_string()]).unwrap();
+  model.add::<f32>("dense", hashmap!["input_size" => 3.to_string()
+                                     , "output_size" => 2.to_string()]).unwrap();
+  let input = initializations::uniform::<f32>(Dim4::new(&[2, 64, 2, 1]), -1.0, 1.0);
+  let outputs = model.forward::<f32>(&input, device, device);
+  assert!(outputs.len() == 2 && outputs[0].dims().get() == &[2, 2, 1, 1]);
+  model.backward(&outputs, &af::constant(0.0f32, Dim4::new(&[2, 2, 2, 1])), None);
+}
+
+#[test]
+fn pooling_backward() {
+  // overlapping windows accumulate the gradients of every window they are in
+  let max_pool = layer::Pool2DConfig { stride: (1, 1)
+                                       , ..layer::Pool2DConfig::new(layer::PoolType::Max, (4, 3, 2), (2, 2)) };
+  config_backward_helper(&max_pool, 2, "l2", 1e-4, false);
+  let avg_pool = layer::Pool2DConfig { stride: (2, 1)
+                                       , ..layer::Pool2DConfig::new(layer::PoolType::Average, (4, 3, 2), (2, 2)) };
+  config_backward_helper(&avg_pool, 2, "l2", 1e-4, false);
+  config_backward_helper(&layer::GlobalAvgPool2DConfig::new((3, 2, 4)), 2, "l2", 1e-4, false);
+
+  // repeated taps are summed & unread features get zero
+  let dpatches = Array::new::<f64>(&[1.0, 2.0, 3.0, 4.0, 5.0], Dim4::new(&[1, 5, 1, 1]));
+  let indices = utils::vec_to_array::<u32>(vec![2, 0, 3, 2, 0], Dim4::new(&[5, 1, 1, 1]));
+  assert!(utils::array_to_vec(&layer::fold_patches(&dpatches, &indices, 5)) == vec![7.0, 0.0, 5.0, 3.0, 0.0]);
+}
+
+#[test]
+fn dropout() {
+  let device_manager = DeviceManagerFactory::new();
+  let device = Device{backend: Backend::DEFAULT, id: 0};
+  let mut param_manager = ParamManager::default();
+  let config = layer::DropoutConfig::new(1000, 0.25);
+  config.validate().unwrap();
+  let layer = config.build::<f64>(&mut param_manager, device_manager.clone(), device);
+  let params = param_manager.get_params(0);
+
+  // kept units are scaled by 1 / (1 - rate) & roughly `rate` of them are dropped
+  let dims = Dim4::new(&[4, 1000, 1, 1]);
+  let x = utils::constant(dims, DType::F64, 1.0);
+  let (activ, _) = layer.forward(params.clone(), &x, None);
+  let values = utils::array_to_vec(&activ);
+  assert!(values.iter().all(|&v| v == 0.0 || (v - 1.0 / 0.75).abs() < 1e-6));
+  let dropped = values.iter().filter(|&&v| v == 0.0).count() as f64 / values.len() as f64;
+  assert!((dropped - 0.25).abs() < 0.05, "dropped {} of the units", dropped);
+
+  // the gradient goes through the same mask
+  let delta = initializations::uniform::<f64>(dims, -1.0, 1.0);
+  let dx = layer.backward(params.clone(), &delta);
+  let masked = af::mul(&delta, &activ, false);
+  assert!(af::max_all(&af::abs(&af::sub(&dx, &masked, false))).0 < 1e-9);
+
+  // inference mode is the identity
+  params.lock().unwrap().training = false;
+  let (activ, _) = layer.forward(params.clone(), &x, None);
+  assert!(utils::array_to_vec(&activ) == utils::array_to_vec(&x));
+
+  // rates outside of [0, 1) are rejected
+  match layer::DropoutConfig::new(10, 1.0).validate() {
+    Err(HALError::INVALID_PARAM{ref component, ref field, ..}) => assert!(component == "dropout" && field == "rate"),
+    _ => panic!("expected an invalid param error for rate"),
+  };
+  assert!(layer::DropoutConfig::new(10, -0.1).validate().is_err());
+}
+
+#[test]
+fn batch_norm_forward() {
+  let device_manager = DeviceManagerFactory::new();
+  let device = Device{backend: Backend::DEFAULT, id: 0};
+  let mut param_manager = ParamManager::default();
+  let config = layer::BatchNormConfig { momentum: 0.0, ..layer::BatchNormConfig::new(3) };
+  config.validate().unwrap();
+  let layer = config.build::<f64>(&mut param_manager, device_manager.clone(), device);
+  let params = param_manager.get_params(0);
+
+  // every feature is normalized over the batch in training mode
+  let x = utils::vec_to_array::<f64>(vec![1.0, 2.0, 3.0, 4.0, -2.0, 0.0, 2.0, 4.0, 10.0, 10.0, 10.0, 10.0]
+                                     , Dim4::new(&[4, 3, 1, 1]));
+  let (training, _) = layer.forward(params.clone(), &x, None);
+  let mean = utils::array_to_vec(&af::mean(&training, 0));
+  let var = utils::array_to_vec(&af::var(&training, true, 0));
+  assert!(mean.iter().all(|m| m.abs() < 1e-9));
+  assert!((var[0] - 1.0).abs() < 1e-4 && (var[1] - 1.0).abs() < 1e-4 && var[2] == 0.0);
+
+  // the running statistics track the batch (momentum of 0) & are used in inference mode
+  let optional = param_manager.get_optionals(0);
+  assert!(utils::array_to_vec(&optional[0]) == vec![2.5, 1.0, 10.0]);
+  assert!(utils::array_to_vec(&optional[1]) == vec![1.25, 5.0, 0.0]);
+  params.lock().unwrap().training = false;
+  let (inference, _) = layer.forward(params.clone(), &x, None);
+  assert!(af::max_all(&af::abs(&af::sub(&training, &inference, false))).0 < 1e-9);
+  assert!(utils::array_to_vec(&param_manager.get_optionals(0)[0]) == vec![2.5, 1.0, 10.0]);
+
+  // bad momentums are rejected
+  match layer::BatchNormConfig { momentum: 1.0, ..layer::BatchNormConfig::new(3) }.validate() {
+    Err(HALError::INVALID_PARAM{ref component, ref field, ..}) => assert!(component == "batch_norm" && field == "momentum"),
+    _ => panic!("expected an invalid param error for momentum"),
+  };
+
+  // a dense stack with batch normalization trains in a sequential model
+  let mut model = Sequential::new(device_manager.clone(), Box::new(SGD::default()), "l2", device);
+  model.add::<f32>("dense", hashmap!["input_size" => 8.to_string()
+                                     , "output_size" => 16.to_string()]).unwrap();
+  model.add::<f32>("batch_norm", hashmap!["size" => 16.to_string()
+                                          , "momentum" => 0.5.to_string()]).unwrap();
+  model.add::<f32>("dense", hashmap!["input_size" => 16.to_string()
+                                     , "output_size" => 8.to_string()
+                                     , "activation" => "tanh".to_string()]).unwrap();
+  let source = SinSource::new(8, 4, DType::F32, 32, false, false);
+  let loss = model.fit::<SinSource, f32>(&source, device, 2, 4, None, None, false).unwrap();
+  assert!(loss.iter().all(|l| l.is_finite()));
+}
+
+#[test]
+fn batch_norm_backward() {
+  let config = layer::BatchNormConfig { gamma_init: "glorot_uniform".to_string()
+                                        , beta_init: "glorot_uniform".to_string()
+                                        , ..layer::BatchNormConfig::new(5) };
+  config_backward_helper(&config, 4, "l2", 1e-4, true);
+}
+
+#[test]
+fn layer_norm_forward() {
+  let device_manager = DeviceManagerFactory::new();
+  let device = Device{backend: Backend::DEFAULT, id: 0};
+  let mut param_manager = ParamManager::default();
+  let config = layer::LayerNormConfig::new(4);
+  config.validate().unwrap();
+  let layer = config.build::<f64>(&mut param_manager, device_manager.clone(), device);
+
+  // every sample is normalized over its features
+  let x = initializations::uniform::<f64>(Dim4::new(&[3, 4, 1, 1]), -5.0, 5.0);
+  let (activ, _) = layer.forward(param_manager.get_params(0), &x, None);
+  let mean = utils::array_to_vec(&af::mean(&activ, 1));
+  let var = utils::array_to_vec(&af::var(&activ, true, 1));
+  assert!(mean.iter().all(|m| m.abs() < 1e-9));
+  assert!(var.iter().all(|v| (v - 1.0).abs() < 1e-4));
+
+  // a non positive epsilon is rejected
+  match layer::LayerNormConfig { epsilon: 0.0, ..layer::LayerNormConfig::new(4) }.validate() {
+    Err(HALError::INVALID_PARAM{ref component, ref field, ..}) => assert!(component == "layer_norm" && field == "epsilon"),
+    _ => panic!("expected an invalid param error for epsilon"),
+  };
+
+  // a layer normalized rnn trains in a sequential model
+  let mut model = Sequential::new(device_manager.clone(), Box::new(SGD::default()), "l2", device);
+  model.add::<f32>("rnn", hashmap!["input_size"    => 1.to_string()
+                                   , "hidden_size" => 16.to_string()
+                                   , "output_size" => 1.to_string()
+                                   , "layer_norm"  => "true".to_string()]).unwrap();
+  let source = SinSource::new(1, 4, DType::F32, 32, false, false);
+  let loss = model.fit::<SinSource, f32>(&source, device, 2, 4, None, None, false).unwrap();
+  assert!(loss.iter().all(|l| l.is_finite()));
+}
+
+#[test]
+fn layer_norm_backward() {
+  let config = layer::LayerNormConfig { gain_init: "glorot_uniform".to_string()
+                                        , bias_init: "glorot_uniform".to_string()
+                                        , ..layer::LayerNormConfig::new(6) };
+  config_backward_helper(&config, 3, "l2", 1e-4, true);
+
+  // the gain & bias of the hidden pre-activation are learnt through the whole unroll
+  let rnn = layer::RNNConfig { layer_norm: true, b_init: "glorot_uniform".to_string()
+                               , ..layer::RNNConfig::new(3, 5, 2) };
+  config_unroll_helper(&rnn, 2, 4, "l2", 1e-4, true);
+  config_unroll_helper(&layer::RNNConfig { layer_norm: true, ..layer::RNNConfig::new(4, 4, 3) }
+                       , 2, 3, "l2", 1e-4, true);
+}
+
+#[test]
+fn embedding() {
+  let device_manager = DeviceManagerFactory::new();
+  let device = Device{backend: Backend::DEFAULT, id: 0};
+  let mut param_manager = ParamManager::default();
+
+  // row i of the pretrained table is [i, 10 + i]
+  let config = layer::EmbeddingConfig { pretrained: Some(vec![0.0, 1.0, 2.0, 3.0, 10.0, 11.0, 12.0, 13.0])
+                                        , ..layer::EmbeddingConfig::new(4, 2) };
+  config.validate().unwrap();
+  let layer = config.build::<f64>(&mut param_manager, device_manager.clone(), device);
+  let params = param_manager.get_params(0);
+
+  // every token is mapped to its row
+  let tokens = utils::vec_to_array::<f64>(vec![2.0, 0.0, 2.0], Dim4::new(&[3, 1, 1, 1]));
+  let (activ, _) = layer.forward(params.clone(), &tokens, None);
+  assert!(utils::array_to_vec(&activ) == vec![2.0, 0.0, 2.0, 12.0, 10.0, 12.0]);
+
+  // the deltas are summed onto the rows of the tokens only
+  let delta = utils::vec_to_array::<f64>(vec![1.0, 3.0, 5.0, 2.0, 4.0, 6.0], Dim4::new(&[3, 2, 1, 1]));
+  let dx = layer.backward(params.clone(), &delta);
+  assert!(dx.dims().get() == tokens.dims().get());
+  assert!(utils::array_to_vec(&param_manager.get_deltas(0)[0]) == vec![3.0, 0.0, 6.0, 0.0, 4.0, 0.0, 8.0, 0.0]);
+  match param_manager.get_all_update_rows()[0] {
+    UpdateRows::Only(ref rows) => assert!(utils::array_to_vec(rows) == vec![0.0, 2.0]),
+    _ => panic!("expected the touched rows of the table"),
+  };
+
+  // and the optimizer leaves the rows of unseen tokens untouched
+  let mut optimizer = Adam::default();
+  optimizer.setup(param_manager.get_all_dims());
+  optimizer.update(&mut param_manager, 3);
+  let table = utils::array_to_vec(&param_manager.get_weights(0)[0]);
+  assert!(table[1] == 1.0 && table[3] == 3.0 && table[5] == 11.0 && table[7] == 13.0);
+  assert!(table[0] != 0.0 && table[2] != 2.0 && table[4] != 10.0 && table[6] != 12.0);
+  match param_manager.get_all_update_rows()[0] {
+    UpdateRows::Skip => (),
+    _ => panic!("expected the touched rows to be reset by the update"),
+  };
+
+  // tokens outside of the vocabulary read a zero vector & are rejected when fitting
+  let invalid = utils::vec_to_array::<f64>(vec![-1.0, 4.0, 1.0], Dim4::new(&[3, 1, 1, 1]));
+  let (activ, _) = layer.forward(params.clone(), &invalid, None);
+  let expected_row = utils::array_to_vec(&param_manager.get_weights(0)[0]);
+  assert!(utils::array_to_vec(&activ) == vec![0.0, 0.0, expected_row[1], 0.0, 0.0, expected_row[5]]);
+  layer.backward(params.clone(), &delta);
+  match param_manager.get_all_update_rows()[0] {
+    UpdateRows::Only(ref rows) => assert!(utils::array_to_vec(rows) == vec![0.0, 1.0]),
+    _ => panic!("expected the touched rows of the table"),
+  };
+  assert!(utils::array_to_vec(&param_manager.get_deltas(0)[0]) == vec![0.0, 5.0, 0.0, 0.0, 0.0, 6.0, 0.0, 0.0]);
+  for tokens in vec![invalid, utils::vec_to_array::<f64>(vec![0.5, 1.0, 2.0], Dim4::new(&[3, 1, 1, 1]))] {
+    match layer.check_inputs(&tokens) {
+      Err(HALError::INVALID_PARAM{ref component, ref field, ..}) => assert!(component == "embedding" && field == "inputs"),
+      _ => panic!("expected an invalid param error for inputs"),
+    };
+  }
+  assert!(layer.check_inputs(&tokens).is_ok());
+
+  // the pretrained table needs to match the vocabulary
+  match layer::EmbeddingConfig { pretrained: Some(vec![0.0; 7]), ..layer::EmbeddingConfig::new(4, 2) }.validate() {
+    Err(HALError::INVALID_PARAM{ref component, ref field, ..}) => assert!(component == "embedding" && field == "pretrained"),
+    _ => panic!("expected an invalid param error for pretrained"),
+  };
+
+  // token sequences of [batch, 1, time] feed a recurrent layer
+  let mut model = Sequential::new(device_manager.clone(), Box::new(SGD::default()), "l2", device);
+  model.add::<f32>("embedding", hashmap!["vocab_size" => 5.to_string()
+                                         , "output_size" => 4.to_string()]).unwrap();
+  model.add::<f32>("rnn", hashmap!["input_size"    => 4.to_string()
+                                   , "hidden_size" => 8.to_string()
+                                   , "output_size" => 2.to_string()]).unwrap();
+  let sequence = utils::vec_to_array::<f32>(vec![1.0, 4.0, 0.0, 0.0, 3.0, 2.0], Dim4::new(&[2, 1, 3, 1]));
+  let outputs = model.forward::<f32>(&sequence, device, device);
+  assert!(outputs.len() == 3 && outputs[0].dims().get() == &[2, 2, 1, 1]);
+  model.backward(&outputs, &af::constant(0.0f32, Dim4::new(&[2, 2, 3, 1])), None);
+}
+
+#[test]
+fn bidirectional() {
+  let device_manager = DeviceManagerFactory::new();
+  let device = Device{backend: Backend::DEFAULT, id: 0};
+
+  // with the same weights in both directions a palindrome is read the same way
+  // forward & backward: the forward half at t matches the backward half at T - 1 - t
+  let rnn = layer::RNNConfig { w_init: "ones".to_string(), ..layer::RNNConfig::new(3, 4, 2) };
+  let x0 = initializations::uniform::<f64>(Dim4::new(&[2, 3, 1, 1]), -0.5f32, 0.5f32);
+  let x1 = initializations::uniform::<f64>(Dim4::new(&[2, 3, 1, 1]), -0.5f32, 0.5f32);
+  let xs = vec![x0.clone(), x1, x0];
+  let mut merged = Vec::new();
+  for merge in vec![layer::BidirectionalMerge::Concat, layer::BidirectionalMerge::Sum] {
+    let config = layer::BidirectionalConfig::new(rnn.clone(), merge);
+    config.validate().unwrap();
+    let mut param_manager = ParamManager::default();
+    let layer = config.build::<f64>(&mut param_manager, device_manager.clone(), device);
+    let outputs = unroll_forward(&layer, param_manager.get_params(0), &xs);
+    assert!(outputs.len() == 3 && outputs[0].dims() == Dim4::new(&[2, config.output_size() as u64, 1, 1]));
+    assert!(param_manager.num_arrays(0) == 2 * 5);
+    merged.push(outputs);
+  }
+  let (concat, sum) = (&merged[0], &merged[1]);
+  assert!(concat[0].dims()[1] == 4 && sum[0].dims()[1] == 2);
+  let half = |a: &Array, i: u64| utils::array_to_vec(&af::cols(a, 2 * i, 2 * i + 1));
+  for t in 0..3 {
+    assert!(Zip::new((half(&concat[t], 0).iter(), half(&concat[2 - t], 1).iter()))
+            .all(|(f, b)| (f - b).abs() < 1e-9), "directions of timestep {} differ", t);
+    let expected = af::add(&af::cols(&concat[t], 0, 1), &af::cols(&concat[t], 2, 3), false);
+    assert!(Zip::new((utils::array_to_vec(&expected).iter(), utils::array_to_vec(&sum[t]).iter()))
+            .all(|(e, s)| (e - s).abs() < 1e-9), "summed outputs of timestep {} differ", t);
+  }
+
+  // only recurrent layers can be wrapped
+  match layer::BidirectionalConfig::new(layer::DenseConfig::new(3, 2), layer::BidirectionalMerge::Sum).validate() {
+    Err(HALError::INVALID_PARAM{ref field, ..}) => assert!(field == "layer"),
+    _ => panic!("expected an invalid param error for layer"),
+  };
+
+  // the gradients flow through both directions
+  config_unroll_helper(&layer::BidirectionalConfig::new(layer::RNNConfig::new(3, 4, 2)
+                                                        , layer::BidirectionalMerge::Concat)
+                       , 2, 4, "l2", 1e-4, true);
+  config_unroll_helper(&layer::BidirectionalConfig::new(layer::GRUConfig::new(3, 2)
+                                                        , layer::BidirectionalMerge::Sum)
+                       , 2, 3, "l2", 1e-4, true);
+
+  // the wrapped layer is picked by name in a sequential model
+  let mut model = Sequential::new(device_manager.clone(), Box::new(SGD::default()), "l2", device);
+  model.add::<f32>("bidirectional", hashmap!["layer"         => "lstm".to_string()
+                                             , "input_size"  => 4.to_string()
+                                             , "output_size" => 3.to_string()]).unwrap();
+  model.add::<f32>("dense", hashmap!["input_size" => 6.to_string()
+                                     , "output_size" => 2.to_string()]).unwrap();
+  match model.add::<f32>("bidirectional", hashmap!["layer" => "rnn".to_string()
+                                                   , "merge" => "max".to_string()]) {
+    Err(HALError::INVALID_PARAM{ref field, ..}) => assert!(field == "merge"),
+    _ => panic!("expected an invalid param error for merge"),
+  };
+
+  let input = initializations::uniform::<f32>(Dim4::new(&[4, 4, 5, 1]), -1.0, 1.0);
+  let outputs = model.forward::<f32>(&input, device, device);
+  assert!(outputs.len() == 5 && outputs[4].dims() == Dim4::new(&[4, 2, 1, 1]));
+  let prediction = utils::array_to_vec(&utils::cast(&model.predict(&input), DType::F64));
+  let joined = outputs[1..].iter().fold(outputs[0].clone(), |acc, o| af::join(2, &acc, o));
+  let forward = utils::array_to_vec(&utils::cast(&joined, DType::F64));
+  assert!(Zip::new((prediction.iter(), forward.iter())).all(|(p, f)| (p - f).abs() < 1e-5));
+  let loss = model.backward(&outputs, &af::constant(0.0f32, Dim4::new(&[4, 2, 5, 1])), None);
+  assert!(loss.len() == 5 && loss.iter().all(|l| l.is_finite()));
+}
+
+#[test]
+fn attention() {
+  let device_manager = DeviceManagerFactory::new();
+  let device = Device{backend: Backend::DEFAULT, id: 0};
+
+  // the first timestep can only attend to itself, the later ones
+  // spread a distribution over all the timesteps seen so far
+  let xs: Vec<Array> = (0..4).map(|_| initializations::uniform::<f64>(Dim4::new(&[3, 5, 1, 1]), -1.0, 1.0)).collect();
+  for score in vec![layer::AttentionScore::Dot, layer::AttentionScore::Additive] {
+    let config = layer::AttentionConfig::new(5, score);
+    config.validate().unwrap();
+    let mut param_manager = ParamManager::default();
+    let layer = config.build::<f64>(&mut param_manager, device_manager.clone(), device);
+    let outputs = unroll_forward(&layer, param_manager.get_params(0), &xs);
+    assert!(utils::array_to_vec(&outputs[0]) == utils::array_to_vec(&xs[0]));
+
+    let weights = layer::Attention::attention_weights(&param_manager.get_params(0).lock().unwrap());
+    assert!(weights.len() == 4);
+    for (t, alpha) in weights.iter().enumerate() {
+      assert!(alpha.dims() == Dim4::new(&[3, t as u64 + 1, 1, 1]));
+      assert!(utils::array_to_vec(&af::sum(alpha, 1)).iter().all(|s| (s - 1.0).abs() < 1e-9));
+    }
+  }
+
+  // gradients flow into the query & every attended timestep
+  config_unroll_helper(&layer::AttentionConfig::new(4, layer::AttentionScore::Dot), 2, 4, "l2", 1e-4, true);
+  let additive = layer::AttentionConfig { attention_size: 3, b_init: "glorot_uniform".to_string()
+                                          , ..layer::AttentionConfig::new(4, layer::AttentionScore::Additive) };
+  config_unroll_helper(&additive, 2, 4, "l2", 1e-4, true);
+
+  // without causal masking every timestep attends over the whole sequence
+  let encoder = layer::AttentionConfig { causal: false, ..layer::AttentionConfig::new(5, layer::AttentionScore::Dot) };
+  assert!(encoder.needs_whole_sequence()
+          && !layer::AttentionConfig::new(5, layer::AttentionScore::Dot).needs_whole_sequence());
+  let mut param_manager = ParamManager::default();
+  let layer = encoder.build::<f64>(&mut param_manager, device_manager.clone(), device);
+  unroll_forward(&layer, param_manager.get_params(0), &xs);
+  let weights = layer::Attention::attention_weights(&param_manager.get_params(0).lock().unwrap());
+  assert!(weights.len() == 4 && weights.iter().all(|alpha| alpha.dims() == Dim4::new(&[3, 4, 1, 1])));
+  config_unroll_helper(&layer::AttentionConfig { causal: false, ..layer::AttentionConfig::new(4, layer::AttentionScore::Dot) }
+                       , 2, 4, "l2", 1e-4, true);
+  config_unroll_helper(&layer::AttentionConfig { causal: false, ..additive }, 2, 4, "l2", 1e-4, true);
+
+  // attending over the outputs of a recurrent layer in a sequential model
+  let mut model = Sequential::new(device_manager.clone(), Box::new(SGD::default()), "l2", device);
+  model.add::<f32>("lstm", hashmap!["input_size" => 2.to_string()
+                                    , "output_size" => 6.to_string()]).unwrap();
+  model.add::<f32>("attention", hashmap!["size" => 6.to_string()
+                                         , "score" => "additive".to_string()]).unwrap();
+  model.add::<f32>("dense", hashmap!["input_size" => 6.to_string()
+                                     , "output_size" => 1.to_string()]).unwrap();
+  match model.add::<f32>("attention", hashmap!["size" => 6.to_string()
+                                               , "score" => "cosine".to_string()]) {
+    Err(HALError::INVALID_PARAM{ref field, ..}) => assert!(field == "score"),
+    _ => panic!("expected an invalid param error for score"),
+  };
+
+  let input = initializations::uniform::<f32>(Dim4::new(&[4, 2, 5, 1]), -1.0, 1.0);
+  let outputs = model.forward::<f32>(&input, device, device);
+  let weights = layer::Attention::attention_weights(&model.get_layer_params(1));
+  assert!(weights.len() == 5 && weights[4].dims() == Dim4::new(&[4, 5, 1, 1]));
+  let loss = model.backward(&outputs, &af::constant(0.0f32, Dim4::new(&[4, 1, 5, 1])), None);
+  assert!(loss.len() == 5 && loss.iter().all(|l| l.is_finite()));
+
+  // predict rolls the weights back, an inference mode forward pass keeps them
+  model.predict(&input);
+  assert!(layer::Attention::attention_weights(&model.get_layer_params(1)).is_empty());
+  model.set_training(false);
+  let inferred = model.forward::<f32>(&input, device, device);
+  let weights = layer::Attention::attention_weights(&model.get_layer_params(1));
+  assert!(weights.len() == 5 && weights[4].dims() == Dim4::new(&[4, 5, 1, 1]));
+  assert!(utils::array_to_vec(&inferred[4]) == utils::array_to_vec(&af::slice(&model.predict(&input), 4)));
+}
+
+#[test]
+fn transformer_encoder() {
+  let device_manager = DeviceManagerFactory::new();
+  let device = Device{backend: Backend::DEFAULT, id: 0};
+
+  // sin on the even & cos on the odd features
+  assert!(utils::array_to_vec(&layer::positional_encoding(0, 4, DType::F64)) == vec![0.0, 1.0, 0.0, 1.0]);
+  let encoding = utils::array_to_vec(&layer::positional_encoding(3, 4, DType::F64));
+  assert!((encoding[0] - 3f64.sin()).abs() < 1e-6 && (encoding[3] - 0.03f64.cos()).abs() < 1e-6);
+
+  // the heads split the features
+  match layer::TransformerEncoderConfig::new(8, 3).validate() {
+    Err(HALError::INVALID_PARAM{ref field, ..}) => assert!(field == "num_heads"),
+    _ => panic!("expected an invalid param error for num_heads"),
+  };
+
+  // every output depends on the whole sequence
+  let config = layer::TransformerEncoderConfig { ff_size: 6, b_init: "glorot_uniform".to_string()
+                                                 , ..layer::TransformerEncoderConfig::new(4, 2) };
+  config_unroll_helper(&config, 2, 3, "l2", 1e-4, false);
+  config_unroll_helper(&layer::TransformerEncoderConfig { positional_encoding: false
+                                                          , ..layer::TransformerEncoderConfig::new(6, 3) }
+                       , 3, 4, "l2", 1e-4, false);
+
+  // an attention only model on the adding problem
+  let mut model = Sequential::new(device_manager.clone(), Box::new(SGD::default()), "l2", device);
+  model.add::<f32>("dense", hashmap!["input_size" => 1.to_string()
+                                     , "output_size" => 8.to_string()]).unwrap();
+  model.add::<f32>("transformer_encoder", hashmap!["size" => 8.to_string()
+                                                   , "num_heads" => 2.to_string()
+                                                   , "ff_size" => 16.to_string()]).unwrap();
+  model.add::<f32>("dense", hashmap!["input_size" => 8.to_string()
+                                     , "output_size" => 1.to_string()]).unwrap();
+  let source = AddingProblemSource::new(4, 8, DType::F32, 40).unwrap();
+  let loss = model.fit::<AddingProblemSource, f32>(&source, device, 1, 4, None, None, false).unwrap();
+  assert!(loss.iter().all(|l| l.is_finite()));
+
+  // layers that need the whole sequence can't be run one timestep at a time
+  match model.fit_rtrl::<AddingProblemSource, f32>(&source, device, 1, 4, None, false) {
+    Err(HALError::INVALID_PARAM{ref field, ..}) => assert!(field == "layers"),
+    _ => panic!("expected an invalid param error for the layers"),
+  };
+  let mut graph = Graph::new(device_manager.clone(), Box::new(SGD::default()), "l2", device);
+  graph.add_input("x", 4).unwrap();
+  let bidirectional = layer::BidirectionalConfig::new(layer::GRUConfig::new(4, 4), layer::BidirectionalMerge::Sum);
+  let act = layer::ACTConfig::new(layer::RNNConfig::new(5, 4, 4));
+  for result in vec![graph.add_node::<f32, _>("transformer", &config, "x")
+                     , graph.add_node::<f32, _>("bidirectional", &bidirectional, "x")
+                     , graph.add_node::<f32, _>("act", &act, "x")] {
+    match result {
+      Err(HALError::INVALID_PARAM{ref field, ..}) => assert!(field == "sequence"),
+      _ => panic!("expected an invalid param error for the sequence"),
+    };
+  }
+}
+
+#[test]
+fn ntm() {
+  let device_manager = DeviceManagerFactory::new();
+  let device = Device{backend: Backend::DEFAULT, id: 0};
+
+  match layer::NTMConfig { memory_size: 0, ..layer::NTMConfig::new(3, 2, 4, 5) }.validate() {
+    Err(HALError::INVALID_PARAM{ref field, ..}) => assert!(field == "memory_size"),
+    _ => panic!("expected an invalid param error for memory_size"),
+  };
+
+  // the gradients flow through the memory, the head weightings & the controller
+  for controller in vec![layer::NTMController::Dense, layer::NTMController::RNN] {
+    let config = layer::NTMConfig { controller: controller, controller_size: 5
+                                    , b_init: "glorot_uniform".to_string()
+                                    , ..layer::NTMConfig::new(3, 2, 4, 3) };
+    config_unroll_helper(&config, 2, 4, "l2", 1e-4, true);
+  }
+
+  // the copying problem with an RNN controller
+  let mut model = Sequential::new(device_manager.clone(), Box::new(Adam::default()), "cross_entropy_softmax", device);
+  model.add::<f32>("ntm", hashmap!["input_size" => 6.to_string()
+                                   , "output_size" => 6.to_string()
+                                   , "controller" => "rnn".to_string()
+                                   , "controller_size" => 20.to_string()
+                                   , "memory_size" => 8.to_string()
+                                   , "memory_width" => 6.to_string()]).unwrap();
+  match model.add::<f32>("ntm", hashmap!["input_size" => 6.to_string()
+                                         , "output_size" => 6.to_string()
+                                         , "controller" => "lstm".to_string()
+                                         , "memory_size" => 8.to_string()
+                                         , "memory_width" => 6.to_string()]) {
+    Err(HALError::INVALID_PARAM{ref field, ..}) => assert!(field == "controller"),
+    _ => panic!("expected an invalid param error for controller"),
+  };
+
+  let source = CopyingProblemSource::new(6, 8, 3, 8, DType::F32, 40).unwrap();
+  let loss = model.fit::<CopyingProblemSource, f32>(&source, device, 1, 8, None, None, false).unwrap();
+  assert!(loss.iter().all(|l| l.is_finite()));
+}
+
+#[test]
+fn act() {
+  let device_manager = DeviceManagerFactory::new();
+  let device = Device{backend: Backend::DEFAULT, id: 0};
+
+  // only rnn & unitary cells ponder
+  match layer::ACTConfig::new(layer::GRUConfig::new(4, 2)).validate() {
+    Err(HALError::INVALID_PARAM{ref field, ..}) => assert!(field == "layer"),
+    _ => panic!("expected an invalid param error for layer"),
+  };
+  match (layer::ACTConfig { epsilon: 1.5, ..layer::ACTConfig::new(layer::RNNConfig::new(4, 5, 2)) }).validate() {
+    Err(HALError::INVALID_PARAM{ref field, ..}) => assert!(field == "epsilon"),
+    _ => panic!("expected an invalid param error for epsilon"),
+  };
+
+  // the gradients flow through the weighted states & outputs of every pondering step
+  let config = layer::ACTConfig { max_steps: 3, ponder_cost: 0.0
+                                  , ..layer::ACTConfig::new(layer::RNNConfig::new(4, 5, 2)) };
+  config_unroll_helper(&config, 2, 3, "l2", 1e-4, false);
+
+  // the ponder cost sum_t (N(t) + R(t)) only flows into the halting probabilities
+  let config = layer::ACTConfig { max_steps: 4, ponder_cost: 1.0
+                                  , ..layer::ACTConfig::new(layer::RNNConfig::new(3, 4, 2)) };
+  let mut param_manager = ParamManager::default();
+  let layer = config.build::<f64>(&mut param_manager, device_manager.clone(), device);
+  let params = param_manager.get_params(0);
+  let xs: Vec<Array> = (0..3).map(|_| initializations::uniform::<f64>(Dim4::new(&[2, 2, 1, 1]), -0.5, 0.5)).collect();
+  let ponder = |xs: &[Array]| {
+    unroll_forward(&layer, params.clone(), xs);
+    let ltex = params.lock().unwrap();
+    ltex.recurrences[..3].iter().fold(0f64, |sum, r| sum + af::sum_all(r).0)
+  };
+  ponder(&xs);
+  for counts in layer::ACT::ponder_counts(&params.lock().unwrap()) {
+    assert!(utils::array_to_vec(&counts).iter().all(|n| *n >= 1.0 && *n <= 4.0));
+  }
+  let zeros: Vec<Array> = (0..3).map(|_| utils::constant(Dim4::new(&[2, 2, 1, 1]), DType::F64, 0.0f32)).collect();
+  layer.backward_sequence(params.clone(), &zeros);
+  let num_arrays = param_manager.num_arrays(0);
+  for (arr, grad, ind) in Zip::new((param_manager.get_all_arrays().iter(), param_manager.get_all_deltas(), 0..num_arrays)) {
+    check_gradient(|i: &Array| {
+      param_manager.set_array_from_index(i.clone(), ind);
+      ponder(&xs)
+    }, &arr.copy(), 1e-4, &grad, false);
+    param_manager.set_array_from_index(arr.clone(), ind);
+  }
+
+  // the wrapper returns sequences like its cell
+  let config = layer::ACTConfig::new(layer::RNNConfig { return_sequences: false, ..layer::RNNConfig::new(3, 4, 2) });
+  assert!(!config.return_sequences());
+  let mut param_manager = ParamManager::default();
+  let layer = config.build::<f64>(&mut param_manager, device_manager.clone(), device);
+  assert!(!layer.return_sequences());
+  let outputs = unroll_forward(&layer, param_manager.get_params(0), &xs);
+  assert!(outputs.len() == 1 && outputs[0].dims() == Dim4::new(&[2, 2, 1, 1]));
+
+  // pondering on the adding problem, the ponder cost is part of the loss
+  let mut model = Sequential::new(device_manager.clone(), Box::new(Adam::default()), "l2", device);
+  model.add::<f32>("act", hashmap!["layer"         => "rnn".to_string()
+                                   , "input_size"  => 1.to_string()
+                                   , "hidden_size" => 8.to_string()
+                                   , "output_size" => 1.to_string()
+                                   , "max_steps"   => 5.to_string()]).unwrap();
+  match model.add::<f32>("act", hashmap!["layer" => "lstm".to_string()]) {
+    Err(HALError::INVALID_PARAM{ref field, ..}) => assert!(field == "layer"),
+    _ => panic!("expected an invalid param error for layer"),
+  };
+
+  let input = initializations::uniform::<f32>(Dim4::new(&[4, 1, 6, 1]), -1.0, 1.0);
+  let outputs = model.forward::<f32>(&input, device, device);
+  let counts = layer::ACT::ponder_counts(&model.get_layer_params(0));
+  assert!(counts.len() == 6 && counts.iter().all(|c| c.dims() == Dim4::new(&[4, 1, 1, 1])));
+  let targets = af::constant(0.0f32, Dim4::new(&[4, 1, 6, 1]));
+  let loss = model.backward(&outputs, &targets, None);
+  assert!(loss.len() == 6 && loss.iter().all(|l| l.is_finite() && *l >= 0.01 * 4.0));
+
+  let source = AddingProblemSource::new(4, 8, DType::F32, 40).unwrap();
+  let loss = model.fit::<AddingProblemSource, f32>(&source, device, 1, 4, None, None, false).unwrap();
+  assert!(loss.iter().all(|l| l.is_finite()));
+}
+
+#[test]
+fn full_unitary() {
+  let device_manager = DeviceManagerFactory::new();
+  let device = Device{backend: Backend::DEFAULT, id: 0};
+
+  match layer::FullUnitaryConfig { outer_activation: "softsign".to_string(), ..layer::FullUnitaryConfig::new(3, 4, 2) }.validate() {
+    Err(HALError::INVALID_PARAM{ref field, ..}) => assert!(field == "outer_activation"),
+    _ => panic!("expected an invalid param error for outer_activation"),
+  };
+
+  // the gradients flow through the modReLU & the (real, imaginary) parts of every matrix
+  let config = layer::FullUnitaryConfig { b_init: "glorot_uniform".to_string()
+                                          , ..layer::FullUnitaryConfig::new(3, 4, 2) };
+  config_unroll_helper(&config, 2, 3, "l2", 1e-4, false);
+
+  // max |W^H W - I| of a [n, 2n] (real, imaginary) matrix
+  let unitary_error = |w: &Array| {
+    let n = w.dims()[0];
+    let w_c = af::cplx2(&af::cols(w, 0, n - 1), &af::cols(w, n, 2 * n - 1), false);
+    let identity = af::diag_create(&af::constant(1.0f32, Dim4::new(&[n, 1, 1, 1])), 0);
+    let product = af::matmul(&w_c, &w_c, af::MatProp::CTRANS, af::MatProp::NONE);
+    af::max_all(&af::abs(&af::sub(&product, &identity, false))).0
+  };
+
+  // the recurrent matrix starts unitary & stays unitary after a Cayley step
+  let mut param_manager = ParamManager::default();
+  layer::FullUnitaryConfig::new(3, 6, 2).build::<f32>(&mut param_manager, device_manager.clone(), device);
+  let w = param_manager.get_all_arrays()[0].clone();
+  assert!(unitary_error(&w) < 1e-4);
+  let grad = initializations::normal::<f32>(w.dims(), 1.0f32);
+  let updated = optimizer::unitary_update(&w, &grad, 0.1);
+  assert!(unitary_error(&updated) < 1e-4);
+  assert!(af::max_all(&af::abs(&af::sub(&updated, &w, false))).0 > 1e-4);
+
+  // a single (small) step of either optimizer decreases the loss of a fixed batch
+  let optimizers = vec![get_optimizer("sgd", &hashmap!["learning_rate" => "0.01"
+                                                       , "momemtum"    => "0.9"
+                                                       , "decay"       => "0.0"
+                                                       , "nesterov"    => "false"
+                                                       , "clip_grad"   => "0.0"]).unwrap()
+                        , get_optimizer_with_defaults("adam").unwrap()];
+  let xs: Vec<Array> = (0..3).map(|_| initializations::uniform::<f32>(Dim4::new(&[2, 3, 1, 1]), -0.5, 0.5)).collect();
+  let targets: Vec<Array> = (0..3).map(|_| initializations::uniform::<f32>(Dim4::new(&[2, 2, 1, 1]), -0.5, 0.5)).collect();
+  for mut optimizer in optimizers {
+    let mut param_manager = ParamManager::default();
+    let layer = layer::FullUnitaryConfig::new(3, 6, 2).build::<f32>(&mut param_manager, device_manager.clone(), device);
+    let params = param_manager.get_params(0);
+    let seq_loss = |outputs: Vec<Array>| {
+      Zip::new((outputs.iter(), targets.iter()))
+        .fold(0f32, |sum, (o, t)| sum + loss::get_loss("l2", o, t).unwrap())
+    };
+
+    let outputs = unroll_forward(&layer, params.clone(), &xs);
+    let initial_loss = seq_loss(outputs.clone());
+    let deltas: Vec<Array> = Zip::new((outputs.iter(), targets.iter()))
+      .map(|(o, t)| loss::get_loss_derivative("l2", o, t).unwrap()).collect();
+    layer.backward_sequence(params.clone(), &deltas);
+    optimizer.setup(param_manager.get_all_dims());
+    optimizer.update(&mut param_manager, 2);
+
+    let updated_loss = seq_loss(unroll_forward(&layer, params.clone(), &xs));
+    assert!(updated_loss < initial_loss, "the step did not decrease the loss: {} -> {}", initial_loss, updated_loss);
+    assert!(unitary_error(&param_manager.get_all_arrays()[0]) < 1e-4);
+  }
+
+  // the copying problem, the recurrent matrix stays unitary while training
+  let mut model = Sequential::new(device_manager.clone(), Box::new(SGD::default()), "cross_entropy_softmax", device);
+  model.add::<f32>("full_unitary", hashmap!["input_size" => 6.to_string()
+                                            , "hidden_size" => 16.to_string()
+                                            , "output_size" => 6.to_string()]).unwrap();
+  let source = CopyingProblemSource::new(6, 8, 3, 8, DType::F32, 40).unwrap();
+  let loss = model.fit::<CopyingProblemSource, f32>(&source, device, 2, 8, None, None, false).unwrap();
+  assert!(loss.iter().all(|l| l.is_finite()));
+  assert!(unitary_error(&model.get_layer_params(0).weights[0]) < 1e-3);
+}
+
+#[test]
+fn return_sequences() {
+  let device_manager = DeviceManagerFactory::new();
+  let device = Device{backend: Backend::DEFAULT, id: 0};
+
+  let gru = layer::GRUConfig::from_params(&hashmap!["input_size" => 3.to_string()
+                                                    , "output_size" => 2.to_string()
+                                                    , "return_sequences" => "false".to_string()]).unwrap();
+  assert!(!gru.return_sequences && gru.to_params()["return_sequences"] == "false");
+
+  // only the last output gets a delta, the earlier timesteps get theirs through the state
+  let rnn = layer::RNNConfig { return_sequences: false, ..layer::RNNConfig::new(3, 4, 2) };
+  config_unroll_helper(&rnn, 2, 4, "l2", 1e-4, true);
+  config_unroll_helper(&gru, 2, 4, "l2", 1e-4, true);
+  config_unroll_helper(&layer::BidirectionalConfig::new(gru.clone(), layer::BidirectionalMerge::Concat)
+                       , 2, 4, "l2", 1e-4, true);
+  let act = layer::ACTConfig { max_steps: 3, ponder_cost: 0.0
+                               , ..layer::ACTConfig::new(layer::RNNConfig { return_sequences: false
+                                                                            , ..layer::RNNConfig::new(4, 5, 2) }) };
+  config_unroll_helper(&act, 2, 3, "l2", 1e-4, false);
+
+  // graph nodes run per timestep
+  let mut graph = Graph::new(device_manager.clone(), Box::new(SGD::default()), "l2", device);
+  graph.add_input("x", 3).unwrap();
+  match graph.add_node::<f32, _>("h", &rnn, "x") {
+    Err(HALError::INVALID_PARAM{ref field, ..}) => assert!(field == "return_sequences"),
+    _ => panic!("expected an invalid param error for return_sequences"),
+  };
+
+  // a stacked sequence classifier without loss indices: the second
+  // recurrent layer only passes its last output to the dense layer
+  let mut model = Sequential::new(device_manager.clone(), Box::new(Adam::default()), "l2", device);
+  model.add::<f32>("lstm", hashmap!["input_size" => 1.to_string()
+                                    , "output_size" => 8.to_string()]).unwrap();
+  model.add::<f32>("gru", hashmap!["input_size" => 8.to_string()
+                                   , "output_size" => 8.to_string()
+                                   , "return_sequences" => "false".to_string()]).unwrap();
+  model.add::<f32>("dense", hashmap!["input_size" => 8.to_string()
+                                     , "output_size" => 1.to_string()]).unwrap();
+
+  let input = initializations::uniform::<f32>(Dim4::new(&[4, 1, 6, 1]), -1.0, 1.0);
+  let outputs = model.forward::<f32>(&input, device, device);
+  assert!(outputs.len() == 1 && outputs[0].dims() == Dim4::new(&[4, 1, 1, 1]));
+  let targets = af::constant(0.0f32, Dim4::new(&[4, 1, 6, 1]));
+  assert!(model.backward(&outputs, &targets, None).len() == 1);
+
+  let source = AddingProblemSource::new(4, 8, DType::F32, 40).unwrap();
+  let loss = model.fit::<AddingProblemSource, f32>(&source, device, 1, 4, None, None, false).unwrap();
+  assert!(loss.len() == 10 && loss.iter().all(|l| l.is_finite()));
+  let (test_loss, _) = model.evaluate::<AddingProblemSource, f32>(&source, device, 4, &[]).unwrap();
+  assert!(test_loss.is_finite());
+
+  // a single prediction per sequence: no truncated bptt & a single loss index
+  match model.fit::<AddingProblemSource, f32>(&source, device, 1, 4, Some(4), None, false) {
+    Err(HALError::INVALID_PARAM{ref field, ..}) => assert!(field == "bptt_interval"),
+    _ => panic!("expected an invalid param error for bptt_interval"),
+  };
+  match model.fit::<AddingProblemSource, f32>(&source, device, 1, 4, None, Some(&vec![true; 8]), false) {
+    Err(HALError::INVALID_PARAM{ref field, ..}) => assert!(field == "loss_indices"),
+    _ => panic!("expected an invalid param error for loss_indices"),
+  };
+  assert!(model.fit::<AddingProblemSource, f32>(&source, device, 1, 4, None, Some(&vec![true]), false).is_ok());
+}
+
+#[test]
+fn rtrl() {
+  config_rtrl_helper(&layer::RNNConfig::new(3, 4, 2), 2, 4, "l2", 1e-4);
+  config_rtrl_helper(&layer::LSTMConfig::new(3, 4), 2, 4, "l2", 1e-4);
+}
+
+#[test]
+fn sequential_fit_rtrl() {
+  let device_manager = DeviceManagerFactory::new();
+  let device = Device{backend: Backend::DEFAULT, id: 0};
+  let (size, batch_size) = (4, 4);
+  let build = || Sequential::new(device_manager.clone(), Box::new(SGD::default()), "l2", device);
+  let rnn = layer::RNNConfig { w_init: "ones".to_string(), ..layer::RNNConfig::new(size, size, size) };
+  let lstm = layer::LSTMConfig { w_init: "ones".to_string(), w_recurrent_init: "ones".to_string()
+                                 , ..layer::LSTMConfig::new(size, size) };
+
+  // a single minibatch of a single timestep: rtrl takes the same step as bptt
+  let (mut bptt, mut rtrl) = (build(), build());
+  bptt.add_layer::<f32, _>(&rnn).unwrap();
+  bptt.add_layer::<f32, _>(&lstm).unwrap();
+  rtrl.add_layer::<f32, _>(&rnn).unwrap();
+  rtrl.add_layer::<f32, _>(&lstm).unwrap();
+  let source = SinSource::new(size as u64, batch_size, DType::F32, batch_size, false, false);
+  let bptt_loss = bptt.fit::<SinSource, f32>(&source, device, 1, batch_size, None, None, false).unwrap();
+  let source = SinSource::new(size as u64, batch_size, DType::F32, batch_size, false, false);
+  let rtrl_loss = rtrl.fit_rtrl::<SinSource, f32>(&source, device, 1, batch_size, None, false).unwrap();
+  assert!(bptt_loss.len() == 1 && bptt_loss == rtrl_loss);
+  for layer_index in 0..2 {
+    let (bptt_params, rtrl_params) = (bptt.get_layer_params(layer_index), rtrl.get_layer_params(layer_index));
+    for (b, r) in Zip::new((bptt_params.weights.iter().chain(bptt_params.biases.iter())
+                            , rtrl_params.weights.iter().chain(rtrl_params.biases.iter()))) {
+      assert!(af::max_all(&af::abs(&af::sub(b, r, false))).0 < 1e-5);
+    }
+  }
+
+  // recurrent layers without rtrl & layer normalized RNNs are rejected up front
+  let source = SinSource::new(size as u64, batch_size, DType::F32, 4 * batch_size, false, false);
+  let mut gru = build();
+  gru.add_layer::<f32, _>(&layer::GRUConfig::new(size, size)).unwrap();
+  let mut unitary = build();
+  unitary.add_layer::<f32, _>(&layer::UnitaryConfig::new(size, size, size)).unwrap();
+  let mut layer_norm = build();
+  layer_norm.add_layer::<f32, _>(&layer::RNNConfig { layer_norm: true, ..rnn.clone() }).unwrap();
+  for model in vec![gru, unitary, layer_norm].iter_mut() {
+    match model.fit_rtrl::<SinSource, f32>(&source, device, 1, batch_size, None, false) {
+      Err(HALError::INVALID_PARAM{ref field, ..}) => assert!(field == "layers"),
+      _ => panic!("expected an invalid param error for the layers"),
+    };
+  }
+}
+
+#[test]
+fn sequential_training_mode() {
+  let device_manager = DeviceManagerFactory::new();
+  let device = Device{backend: Backend::DEFAULT, id: 0};
+  let mut model = Sequential::new(device_manager.clone(), Box::new(SGD::default()), "l2", device);
+  model.add::<f32>("dense", hashmap!["input_size" => 8.to_string()
+                                     , "output_size" => 64.to_string()
+                                     , "activation" => "tanh".to_string()]).unwrap();
+  model.add::<f32>("dropout", hashmap!["size" => 64.to_string()
+                                       , "rate" => 0.5.to_string()]).unwrap();
+  model.add::<f32>("dense", hashmap!["input_size" => 64.to_string()
+                                     , "output_size" => 8.to_string()]).unwrap();
+
+  // predict always runs in inference mode and is thus deterministic
+  let input = initializations::uniform::<f32>(Dim4::new(&[4, 8, 1, 1]), -1.0, 1.0);
+  let prediction = utils::array_to_vec(&utils::cast(&model.predict(&input), DType::F64));
+  assert!(prediction == utils::array_to_vec(&utils::cast(&model.predict(&input), DType::F64)));
+
+  // forward drops units in training mode ...
+  let diff = |outputs: &Vec<Array>| {
+    let forward = utils::array_to_vec(&utils::cast(&outputs[0], DType::F64));
+    Zip::new((forward.iter(), prediction.iter())).fold(0.0f64, |m, (f, p)| m.max((f - p).abs()))
+  };
+  let training = model.forward::<f32>(&input, device, device);
+  assert!(diff(&training) > 1e-3);
+
+  // ... and matches predict in inference mode (the backward pass rewinds the unroll)
+  model.backward(&training, &af::constant(0.0f32, Dim4::new(&[4, 8, 1, 1])), None);
+  model.set_training(false);
+  let inference = model.forward::<f32>(&input, device, device);
+  assert!(inference.len() == 1 && diff(&inference) < 1e-6);
+  model.backward(&inference, &af::constant(0.0f32, Dim4::new(&[4, 8, 1, 1])), None);
+
+  // fitting with dropout runs in training mode
+  model.set_training(true);
+  let source = SinSource::new(8, 4, DType::F32, 32, false, false);
+  let loss = model.fit::<SinSource, f32>(&source, device, 1, 4, None, None, false).unwrap();
+  assert!(loss.iter().all(|l| l.is_finite()));
+}
+
+#[test]
+fn sequential_save_load() {
+  let device_manager = DeviceManagerFactory::new();
+  let device = Device{backend: Backend::DEFAULT, id: 0};
+  let mut model = Box::new(Sequential::new(device_manager.clone()
+                                           , Box::new(SGD::default())
+                                           , "l2"
+                                           , device));
+  model.add::<f64>("dense", hashmap!["activation"    => "tanh".to_string()
+                                     , "input_size"  => 4.to_string()
+                                     , "output_size" => 6.to_string()
+                                     , "w_init"      => "glorot_uniform".to_string()
+                                     , "b_init"      => "glorot_uniform".to_string()]).unwrap();
+  model.add::<f64>("lstm", hashmap!["inner_activation"   => "sigmoid".to_string()
+                                    , "outer_activation" => "tanh".to_string()
+                                    , "input_size"       => 6.to_string()
+                                    , "output_size"      => 3.to_string()
+                                    , "w_init"           => "glorot_uniform".to_string()
+                                    , "w_recurrent_init" => "glorot_uniform".to_string()
+                                    , "forget_b_init"    => "ones".to_string()
+                                    , "b_init"           => "zeros".to_string()]).unwrap();
+
+  // [batch, feature, time]
+  let input = initializations::uniform::<f64>(Dim4::new(&[2, 4, 3, 1]), -1.0, 1.0);
+  let original = model.forward::<f64>(&input, device, device);
+
+  let path = env::temp_dir().join("hal_sequential_save_load.json");
+  let path = path.to_str().unwrap();
+  model.save(path).unwrap();
+  let mut loaded = Sequential::load(path, device_manager.clone(), device).unwrap();
+  let restored = loaded.forward::<f64>(&input, device, device);
+
+  assert!(original.len() == restored.len());
+  for (o, r) in Zip::new((original.iter(), restored.iter())) {
+    assert!(o.get_type() == r.get_type());
+    let diff = af::max_all(&af::abs(&af::sub(o, r, false))).0;
+    assert!(diff < 1e-9, "restored model output differs by {}", diff);
+  }
+
+  // missing & corrupt files are reported instead of aborting
+  let missing = env::temp_dir().join("hal_sequential_missing.json");
+  match Sequential::load(missing.to_str().unwrap(), device_manager.clone(), device) {
+    Err(HALError::IO_ERROR{..}) => (),
+    _ => panic!("expected an I/O error for a missing file"),
+  };
+  let corrupt = env::temp_dir().join("hal_sequential_corrupt.json");
+  File::create(&corrupt).unwrap().write_all(b"{\"loss\": \"l2\", \"layers\": [").unwrap();
+  match Sequential::load(corrupt.to_str().unwrap(), device_manager.clone(), device) {
+    Err(HALError::IO_ERROR{..}) => (),
+    _ => panic!("expected an I/O error for a corrupt file"),
+  };
+  match model.save(env::temp_dir().join("hal_missing_dir").join("model.json").to_str().unwrap()) {
+    Err(HALError::IO_ERROR{..}) => (),
+    _ => panic!("expected an I/O error for a missing directory"),
+  };
+}
+
+#[test]
+fn layer_config_validation() {
+  // missing params take the config defaults
+  let dense = layer::DenseConfig::from_params(&hashmap!["input_size"  => 4.to_string()
+                                                        , "output_size" => 6.to_string()]).unwrap();
+  assert!(dense == layer::DenseConfig::new(4, 6));
+  assert!(dense.validate().is_ok());
+
+  // typos, missing sizes & unparsable values are reported with the field name
+  let typo = layer::DenseConfig::from_params(&hashmap!["input_size"  => 4.to_string()
+                                                       , "output_size" => 6.to_string()
+                                                       , "w_inti"      => "zeros".to_string()]);
+  match typo {
+    Err(HALError::INVALID_PARAM{ref component, ref field, ..}) => assert!(component == "dense" && field == "w_inti"),
+    _ => panic!("expected an invalid param error for w_inti"),
+  };
+  match layer::RNNConfig::from_params(&hashmap!["input_size"  => 4.to_string()
+                                                , "output_size" => 6.to_string()]) {
+    Err(HALError::INVALID_PARAM{ref field, ..}) => assert!(field == "hidden_size"),
+    _ => panic!("expected an invalid param error for hidden_size"),
+  };
+  match layer::UnitaryConfig::from_params(&hashmap!["input_size"  => 1.to_string()
+                                                    , "hidden_size" => 8.to_string()
+                                                    , "output_size" => 1.to_string()
+                                                    , "is_permut_const" => "yes".to_string()]) {
+    Err(HALError::INVALID_PARAM{ref field, ..}) => assert!(field == "is_permut_const"),
+    _ => panic!("expected an invalid param error for is_permut_const"),
+  };
+
+  // unknown names and empty layers are caught by validate
+  let lstm = layer::LSTMConfig { outer_activation: "tanhh".to_string(), ..layer::LSTMConfig::new(4, 3) };
+  match lstm.validate() {
+    Err(HALError::INVALID_PARAM{ref field, ..}) => assert!(field == "outer_activation"),
+    _ => panic!("expected an invalid param error for outer_activation"),
+  };
+  let gru = layer::GRUConfig { b_init: "zero".to_string(), ..layer::GRUConfig::new(4, 3) };
+  assert!(gru.validate().is_err());
+  assert!(layer::DenseConfig::new(0, 3).validate().is_err());
+
+  // an invalid config leaves the model untouched
+  let device_manager = DeviceManagerFactory::new();
+  let device = Device{backend: Backend::DEFAULT, id: 0};
+  let mut model = Sequential::new(device_manager, Box::new(SGD::default()), "mse", device);
+  assert!(model.add_layer::<f32, _>(&lstm).is_err());
+  model.add_layer::<f32, _>(&layer::GRUConfig::new(4, 3)).unwrap();
+  let input = initializations::uniform::<f32>(Dim4::new(&[2, 4, 3, 1]), -1.0, 1.0);
+  let outputs = model.forward::<f32>(&input, device, device);
+  assert!(outputs.len() == 3 && outputs[0].dims()[1] == 3);
+}
+
+#[test]
+fn structured_errors() {
+  let device_manager = DeviceManagerFactory::new();
+  let device = Device{backend: Backend::DEFAULT, id: 0};
+  let mut model = Sequential::new(device_manager, Box::new(SGD::default()), "mse", device);
+  match model.add::<f32>("desne", hashmap!["input_size" => 4.to_string(), "output_size" => 4.to_string()]) {
+    Err(HALError::UNKNOWN_LAYER(ref name)) => assert!(name == "desne"),
+    _ => panic!("expected an unknown layer error"),
+  };
+
+  // fitting an empty model or on an unavailable device fails before training
+  let source = SinSource::new(4, 2, DType::F32, 8, false, false);
+  assert!(model.fit::<SinSource, f32>(&source, device, 1, 2, None, None, false).is_err());
+  model.add::<f32>("dense", hashmap!["input_size" => 4.to_string(), "output_size" => 4.to_string()]).unwrap();
+  let missing = Device{backend: Backend::DEFAULT, id: 1024};
+  match model.fit::<SinSource, f32>(&source, missing, 1, 2, None, None, false) {
+    Err(HALError::DEVICE_UNAVAILABLE(d)) => assert!(d == missing),
+    _ => panic!("expected a device unavailable error"),
+  };
+
+  match initializations::get_initialization::<f32>("orthogonal", Dim4::new(&[2, 2, 1, 1])) {
+    Err(HALError::UNKNOWN_INITIALIZATION(ref name)) => assert!(name == "orthogonal"),
+    _ => panic!("expected an unknown initialization error"),
+  };
+  match get_optimizer("rmsprop", &hashmap!["learning_rate" => "0.1"]) {
+    Err(HALError::UNKNOWN_OPTIMIZER(ref name)) => assert!(name == "rmsprop"),
+    _ => panic!("expected an unknown optimizer error"),
+  };
+  match get_optimizer("sgd", &hashmap!["learning_rate" => "0.1"]) {
+    Err(HALError::INVALID_PARAM{ref component, ref field, ..}) => assert!(component == "sgd" && field == "momemtum"),
+    _ => panic!("expected an invalid param error for momemtum"),
+  };
+  match AddingProblemSource::new(2, 6, DType::F32, 8) {
+    Err(HALError::INVALID_PARAM{ref field, ..}) => assert!(field == "bptt_unroll"),
+    _ => panic!("expected an invalid param error for bptt_unroll"),
+  };
+  match utils::read_csv::<f32>("/nonexistent/hal.csv") {
+    Err(HALError::IO_ERROR{ref path, ..}) => assert!(path == "/nonexistent/hal.csv"),
+    _ => panic!("expected an io error"),
+  };
+}
+
+#[test]
+fn graph_model() {
+  let device_manager = DeviceManagerFactory::new();
+  let device = Device{backend: Backend::DEFAULT, id: 0};
+  let build = || {
+    let optimizer = get_optimizer("sgd", &hashmap!["learning_rate" => "0.001", "momemtum" => "0.0"
+                                                   , "decay" => "0.0", "nesterov" => "false"
+                                                   , "clip_grad" => "0.0"]).unwrap();
+    let mut graph = Graph::new(device_manager.clone(), optimizer, "mse", device);
+    let dense = |input_size: usize, output_size: usize, activation: &str| {
+      layer::DenseConfig { activation: activation.to_string(), w_init: "ones".to_string()
+                           , ..layer::DenseConfig::new(input_size, output_size) }
+    };
+
+    // out = dense(concat(x, x + tanh(x W1)) * sigmoid(concat(...) W2)), nodes out of order
+    graph.add_node::<f32, _>("out", &dense(8, 4, "linear"), "gated").unwrap();
+    graph.add_input("x", 4).unwrap();
+    graph.add_node::<f32, _>("hidden", &dense(4, 4, "tanh"), "x").unwrap();
+    graph.add_merge("residual", MergeMode::Add, &["x", "hidden"]).unwrap();
+    graph.add_merge("joined", MergeMode::Concat, &["x", "residual"]).unwrap();
+    graph.add_node::<f32, _>("gate", &dense(8, 8, "sigmoid"), "joined").unwrap();
+    graph.add_merge("gated", MergeMode::Multiply, &["joined", "gate"]).unwrap();
+    graph.set_outputs(&["out"]).unwrap();
+    graph
+  };
+
+  let mut graph = build();
+  assert!(graph.get_order().unwrap() == vec!["x", "hidden", "residual", "joined", "gate", "gated", "out"]);
+
+  // compare against the same computation done by hand
+  let x = initializations::uniform::<f32>(Dim4::new(&[2, 4, 1, 1]), -1.0, 1.0);
+  let row_sum = |a: &Array, cols: u64| af::tile(&af::sum(a, 1), Dim4::new(&[1, cols, 1, 1]));
+  let residual = af::add(&x, &af::tanh(&row_sum(&x, 4)), false);
+  let joined = af::join(1, &x, &residual);
+  let gated = af::mul(&joined, &activations::sigmoid(&row_sum(&joined, 8)), false);
+  let expected = row_sum(&gated, 4);
+  let out = graph.forward::<f32>(&x, device, device);
+  assert!(out.len() == 1);
+  assert!(af::max_all(&af::abs(&af::sub(&out[0], &expected, false))).0 < 1e-5);
+  assert!(af::max_all(&af::abs(&af::sub(&graph.predict(&x), &expected, false))).0 < 1e-5);
+  graph.backward(&out, &af::constant(0.0f32, Dim4::new(&[2, 4, 1, 1])), None);
+
+  // training on a deterministic source with a deterministic init reduces the loss
+  let source = SinSource::new(4, 4, DType::F32, 32, false, false);
+  let loss = build().fit::<SinSource, f32>(&source, device, 5, 4, None, None, false).unwrap();
+  assert!(loss.len() == 5 * 8);
+  assert!(loss[loss.len() - 1] < loss[0], "graph loss did not decrease: {:?}", loss);
+
+  // nodes can't be added once the outputs are set
+  match graph.add_input("y", 4) {
+    Err(HALError::INVALID_PARAM{ref field, ..}) => assert!(field == "y"),
+    _ => panic!("expected an invalid param error for node y"),
+  };
+
+  // unknown inputs, cycles & mismatched sizes are reported when setting the outputs
+  let mut cyclic = Graph::new(device_manager.clone(), Box::new(SGD::default()), "mse", device);
+  cyclic.add_input("x", 4).unwrap();
+  cyclic.add_merge("a", MergeMode::Add, &["x", "b"]).unwrap();
+  cyclic.add_merge("b", MergeMode::Add, &["x", "a"]).unwrap();
+  assert!(cyclic.set_outputs(&["b"]).is_err());
+  let mut mismatched = Graph::new(device_manager.clone(), Box::new(SGD::default()), "mse", device);
+  mismatched.add::<f32>("input", hashmap!["name" => "x".to_string(), "size" => 3.to_string()]).unwrap();
+  mismatched.add::<f32>("dense", hashmap!["name" => "d".to_string(), "inputs" => "x".to_string()
+                                          , "input_size" => 4.to_string()
+                                          , "output_size" => 2.to_string()]).unwrap();
+  match mismatched.set_outputs(&["d"]) {
+    Err(HALError::INVALID_PARAM{ref field, ..}) => assert!(field == "d"),
+    _ => panic!("expected an invalid param error for node d"),
+  };
+
+  // an unfinished graph can't be fitted
+  let source = SinSource::new(3, 4, DType::F32, 32, false, false);
+  match mismatched.fit::<SinSource, f32>(&source, device, 1, 4, None, None, false) {
+    Err(HALError::INVALID_PARAM{ref field, ..}) => assert!(field == "outputs"),
+    _ => panic!("expected an invalid param error for the outputs"),
+  };
+}
+
+#[test]
+fn sequential_checkpoint_resume() {
+  let device_manager = DeviceManagerFactory::new();
+  let device = Device{backend: Backend::DEFAULT, id: 0};
+  let (input_size, batch_size, num_samples, epochs) = (8, 4, 32, 2);
+  let iters = (num_samples / batch_size) as usize;
+  let build = || {
+    let mut model = Sequential::new(device_manager.clone()
+                                    , Box::new(Adam::default())
+                                    , "l2"
+                                    , device);
+    model.add::<f32>("dense", hashmap!["activation"    => "tanh".to_string()
+                                       , "input_size"  => input_size.to_string()
+                                       , "output_size" => input_size.to_string()
+                                       , "w_init"      => "ones".to_string()
+                                       , "b_init"      => "zeros".to_string()]).unwrap();
+    model
+  };
+
+  // uninterrupted run
+  let source = SinSource::new(input_size, batch_size, DType::F32, num_samples, false, false);
+  let full_loss = build().fit::<SinSource, f32>(&source, device, epochs, batch_size
+                                                , None, None, false).unwrap();
+
+  // run a single epoch (checkpointing at its end) and resume from it
+  let path = env::temp_dir().join("hal_sequential_checkpoint.json");
+  let path = path.to_str().unwrap();
+  let source = SinSource::new(input_size, batch_size, DType::F32, num_samples, false, false);
+  let mut interrupted = build();
+  interrupted.set_checkpoint(path, 3);
+  let first_loss = interrupted.fit::<SinSource, f32>(&source, device, 1, batch_size
+                                                      , None, None, false).unwrap();
+
+  let source = SinSource::new(input_size, batch_size, DType::F32, num_samples, false, false);
+  let resumed_loss = build().resume::<SinSource, f32>(path, &source, device, epochs, batch_size
+                                                      , None, None, false).unwrap();
+
+  assert!(first_loss.len() == iters && resumed_loss.len() == full_loss.len() - iters);
+  assert!(first_loss[..] == full_loss[..iters]);
+  assert!(resumed_loss[..] == full_loss[iters..]
+          , "resumed losses {:?} differ from {:?}", resumed_loss, &full_loss[iters..]);
+
+  // the adam state can't be restored into another optimizer
+  let mut sgd_model = Sequential::new(device_manager.clone(), Box::new(SGD::default()), "l2", device);
+  sgd_model.add::<f32>("dense", hashmap!["input_size"    => input_size.to_string()
+                                         , "output_size" => input_size.to_string()]).unwrap();
+  match sgd_model.resume::<SinSource, f32>(path, &source, device, epochs, batch_size, None, None, false) {
+    Err(HALError::INVALID_PARAM{ref component, ref field, ..}) => assert!(component == "SGD" && field == "name"),
+    _ => panic!("expected an invalid param error for the optimizer name"),
+  };
+}
+
+/// Wraps a SinSource but always hands out the same validation minibatch
+struct FixedValidationSource {
+  source: SinSource,
+  validation: Data,
+}
+
+impl FixedValidationSource {
+  fn new(source: SinSource, batch_size: u64) -> FixedValidationSource {
+    let validation = source.get_train_iter(batch_size);
+    FixedValidationSource {
+      source: source,
+      validation: validation,
+    }
+  }
+}
+
+impl DataSource for FixedValidationSource {
+  fn info(&self) -> DataParams {
+    self.source.info()
+  }
+
+  fn get_train_iter(&self, num_batch: u64) -> Data {
+    self.source.get_train_iter(num_batch)
+  }
+
+  fn get_test_iter(&self, num_batch: u64) -> Data {
+    self.source.get_test_iter(num_batch)
+  }
+
+  fn get_validation_iter(&self, _num_batch: u64) -> Option<Data> {
+    Some(Data {
+      input: RefCell::new(Box::new(self.validation.input.borrow().copy())),
+      target: RefCell::new(Box::new(self.validation.target.borrow().copy())),
+    })
+  }
+}
+
+#[test]
+fn sequential_early_stopping() {
+  let device_manager = DeviceManagerFactory::new();
+  let device = Device{backend: Backend::DEFAULT, id: 0};
+  let (input_size, batch_size, num_samples) = (8, 4, 32);
+
+  // a zero learning rate on a fixed validation batch never improves the validation loss
+  let optimizer = get_optimizer("sgd", &hashmap!["learning_rate" => "0.0"
+                                                 , "momemtum"    => "0.0"
+                                                 , "decay"       => "0.0"
+                                                 , "nesterov"    => "false"
+                                                 , "clip_grad"   => "0.0"]).unwrap();
+  let mut model = Sequential::new(device_manager.clone(), optimizer, "l2", device);
+  model.add::<f32>("dense", hashmap!["activation"    => "tanh".to_string()
+                                     , "input_size"  => input_size.to_string()
+                                     , "output_size" => input_size.to_string()
+                                     , "w_init"      => "ones".to_string()
+                                     , "b_init"      => "zeros".to_string()]).unwrap();
+  model.set_validation(Some(1));
+
+  let source = FixedValidationSource::new(SinSource::new(input_size, batch_size, DType::F32
+                                                         , num_samples, false, false), batch_size);
+  let loss = model.fit::<FixedValidationSource, f32>(&source, device, 10, batch_size, None, None, false).unwrap();
+
+  // the first epoch sets the best loss, the second one has no improvement
+  let iters = (num_samples / batch_size) as usize;
+  let validation_losses = model.get_validation_losses();
+  assert!(loss.len() == 2 * iters, "expected to stop after 2 epochs, ran {} iterations", loss.len());
+  assert!(validation_losses.len() == 2 && validation_losses[0] == validation_losses[1]);
+
+  // the source can't be rewound, thus it can't be resumed from a checkpoint
+  match source.set_position(1, 0) {
+    Err(HALError::INVALID_PARAM{ref field, ..}) => assert!(field == "position"),
+    _ => panic!("expected an invalid param error for the position"),
+  };
+}
+
+/// Records the hooks that were called and stops training after `stop_after` batches
+struct RecordingCallback {
+  events: Rc<RefCell<Vec<String>>>,
+  stop_after: usize,
+  num_batches: usize,
+}
+
+impl Callback for RecordingCallback {
+  fn on_train_begin(&mut self, _model: &mut Sequential) {
+    self.events.borrow_mut().push("train_begin".to_string());
+  }
+
+  fn on_epoch_begin(&mut self, _model: &mut Sequential, epoch: u64) {
+    self.events.borrow_mut().push(format!("epoch_begin {}", epoch));
+  }
+
+  fn on_epoch_end(&mut self, model: &mut Sequential, epoch: u64, _losses: &[f32]) {
+    self.events.borrow_mut().push(format!("epoch_end {}", epoch));
+
+    // simple step learning rate schedule
+    let lr = model.get_optimizer_mut().get_learning_rate();
+    model.get_optimizer_mut().set_learning_rate(lr * 0.5);
+  }
+
+  fn on_batch_begin(&mut self, _model: &mut Sequential, epoch: u64, iter: u64) {
+    self.events.borrow_mut().push(format!("batch_begin {} {}", epoch, iter));
+  }
+
+  fn on_batch_end(&mut self, model: &mut Sequential, epoch: u64, iter: u64, losses: &[f32]) {
+    assert!(losses.len() == 1);
+    self.events.borrow_mut().push(format!("batch_end {} {}", epoch, iter));
+    self.num_batches += 1;
+    if self.num_batches == self.stop_after {
+      model.stop_training();
+    }
+  }
+
+  fn on_train_end(&mut self, _model: &mut Sequential, losses: &[f32]) {
+    self.events.borrow_mut().push(format!("train_end {}", losses.len()));
+  }
+}
+
+#[test]
+fn sequential_callbacks() {
+  let device_manager = DeviceManagerFactory::new();
+  let device = Device{backend: Backend::DEFAULT, id: 0};
+  let (input_size, batch_size, num_samples) = (8, 4, 8);
+
+  let mut model = Sequential::new(device_manager.clone(), Box::new(SGD::default()), "l2", device);
+  model.add::<f32>("dense", hashmap!["activation"    => "tanh".to_string()
+                                     , "input_size"  => input_size.to_string()
+                                     , "output_size" => input_size.to_string()
+                                     , "w_init"      => "glorot_uniform".to_string()
+                                     , "b_init"      => "zeros".to_string()]).unwrap();
+  let events = Rc::new(RefCell::new(Vec::new()));
+  model.add_callback(Box::new(RecordingCallback{ events: events.clone(), stop_after: 3, num_batches: 0 }));
+
+  // 2 iterations per epoch, stopped during the second epoch
+  let source = SinSource::new(input_size, batch_size, DType::F32, num_samples, false, false);
+  let loss = model.fit::<SinSource, f32>(&source, device, 10, batch_size, None, None, false).unwrap();
+  assert!(loss.len() == 3);
+  assert!(*events.borrow() == vec!["train_begin", "epoch_begin 0"
+                                   , "batch_begin 0 0", "batch_end 0 0"
+                                   , "batch_begin 0 1", "batch_end 0 1"
+                                   , "epoch_end 0", "epoch_begin 1"
+                                   , "batch_begin 1 0", "batch_end 1 0"
+                                   , "train_end 3"]);
+  assert!(model.get_optimizer_mut().get_learning_rate() == SGD::default().learning_rate * 0.5);
+}
+
+#[test]
+fn sequential_predict_evaluate() {
+  let device_manager = DeviceManagerFactory::new();
+  let device = Device{backend: Backend::DEFAULT, id: 0};
+  let mut model = Sequential::new(device_manager.clone(), Box::new(SGD::default()), "l2", device);
+  model.add::<f32>("lstm", hashmap!["inner_activation"   => "sigmoid".to_string()
+                                    , "outer_activation" => "tanh".to_string()
+                                    , "input_size"       => 8.to_string()
+                                    , "output_size"      => 8.to_string()
+                                    , "w_init"           => "glorot_uniform".to_string()
+                                    , "w_recurrent_init" => "glorot_uniform".to_string()
+                                    , "forget_b_init"    => "ones".to_string()
+                                    , "b_init"           => "zeros".to_string()]).unwrap();
+
+  // predicting twice gives the same result as no state is carried over
+  let input = initializations::uniform::<f32>(Dim4::new(&[4, 8, 3, 1]), -1.0, 1.0);
+  let first = model.predict(&input);
+  let second = model.predict(&input);
+  assert!(first.dims().get() == &[4, 8, 3, 1]);
+  assert!(utils::array_to_vec(&utils::cast(&first, DType::F64))
+          == utils::array_to_vec(&utils::cast(&second, DType::F64)));
+
+  // and it matches the (state recording) forward pass from the same initial state
+  let forward = model.forward::<f32>(&input, device, device);
+  for (t, f) in forward.iter().enumerate() {
+    let diff = af::max_all(&af::abs(&af::sub(&af::slice(&first, t as u64), f, false))).0;
+    assert!(diff < 1e-6);
+  }
+
+  let source = SinSource::new(8, 4, DType::F32, 32, false, false);
+  let (loss, metrics) = model.evaluate::<SinSource, f32>(&source, device, 4, &["mae", "rmse"]).unwrap();
+  assert!(loss.is_finite() && loss >= 0.0);
+  assert!(metrics.len() == 2 && metrics["mae"] >= 0.0 && metrics["rmse"] >= metrics["mae"]);
+
+  // unknown metrics & empty minibatches are reported instead of panicking
+  match model.evaluate::<SinSource, f32>(&source, device, 4, &["mae", "top_0_accuracy"]) {
+    Err(HALError::UNKNOWN_METRIC(ref name)) => assert!(name == "top_0_accuracy"),
+    _ => panic!("expected an unknown metric error"),
+  };
+  match model.evaluate::<SinSource, f32>(&source, device, 0, &["mae"]) {
+    Err(HALError::INVALID_PARAM{ref field, ..}) => assert!(field == "batch_size"),
+    _ => panic!("expected an invalid param error for batch_size"),
+  };
+}
+
+/// helper to build a [rows, cols] f32 array from row-major values
+fn metric_array(rows: u64, cols: u64, values: &[f32]) -> Array {
+  af::transpose(&utils::raw_to_array::<f32>(values, Dim4::new(&[cols, rows, 1, 1])), false)
+}
+
+#[test]
+fn classification_metrics() {
+  // 4 samples, 3 classes
+  let pred = metric_array(4, 3, &[0.7, 0.2, 0.1
+                                  , 0.1, 0.3, 0.6
+                                  , 0.2, 0.5, 0.3
+                                  , 0.5, 0.1, 0.4]);
+  let target = metric_array(4, 3, &[1.0, 0.0, 0.0
+                                    , 0.0, 1.0, 0.0
+                                    , 0.0, 1.0, 0.0
+                                    , 0.0, 0.0, 1.0]);
+  assert!(metrics::compute_metric("accuracy", &pred, &target).unwrap() == 0.5);
+  assert!(metrics::compute_metric("top_2_accuracy", &pred, &target).unwrap() == 1.0);
+  assert!(metrics::compute_metric("top_1_accuracy", &pred, &target).unwrap() == 0.5);
+
+  let mut confusion = metrics::ConfusionMatrix::default();
+  confusion.update(&pred, &target);
+  assert!(confusion.counts == vec![1, 0, 0
+                                   , 0, 1, 1
+                                   , 1, 0, 0]);
+  assert!(confusion.precision() == vec![0.5, 1.0, 0.0]);
+  assert!(confusion.recall() == vec![1.0, 0.5, 0.0]);
+  let f1 = confusion.f1();
+  assert!((f1[0] - 2.0/3.0).abs() < 1e-6 && (f1[1] - 2.0/3.0).abs() < 1e-6 && f1[2] == 0.0);
+
+  // accumulating two halves is the same as a single batch
+  let mut acc = metrics::get_metric("recall").unwrap();
+  acc.update(&af::rows(&pred, 0, 1), &af::rows(&target, 0, 1));
+  acc.update(&af::rows(&pred, 2, 3), &af::rows(&target, 2, 3));
+  assert!((acc.value() - 0.5).abs() < 1e-6);
+
+  let binary_pred = metric_array(1, 4, &[0.2, 0.7, 0.55, 0.9]);
+  let binary_target = metric_array(1, 4, &[0.0, 1.0, 0.0, 1.0]);
+  assert!(metrics::compute_metric("binary_accuracy", &binary_pred, &binary_target).unwrap() == 0.75);
+  let mut strict = metrics::BinaryAccuracy::new(0.6);
+  strict.update(&binary_pred, &binary_target);
+  assert!(strict.value() == 1.0);
+
+  match metrics::get_metric("top_0_accuracy") {
+    Err(HALError::UNKNOWN_METRIC(_)) => (),
+    _                                => panic!("expected an unknown metric error"),
+  };
+}
+
+#[test]
+fn regression_metrics() {
+  let pred = metric_array(2, 2, &[1.0, 2.0, 3.0, 4.0]);
+  let target = metric_array(2, 2, &[1.0, 1.0, 1.0, 1.0]);
+  assert!(metrics::compute_metric("mae", &pred, &target).unwrap() == 1.5);
+
+  // accumulated rmse is computed over all elements (not averaged per batch)
+  let mut rmse = metrics::get_metric("rmse").unwrap();
+  rmse.update(&af::rows(&pred, 0, 0), &af::rows(&target, 0, 0));
+  rmse.update(&af::rows(&pred, 1, 1), &af::rows(&target, 1, 1));
+  assert!((rmse.value() - 3.5f32.sqrt()).abs() < 1e-6);
+  rmse.reset();
+  rmse.update(&target, &target);
+  assert!(rmse.value() == 0.0);
+}